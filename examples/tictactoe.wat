@@ -28,8 +28,8 @@
 
     ;; Sets a piece in the board. No error checking is done here
     (func $setPiece (param $row i32) (param $col i32) (param $piece i32)
-        local.get $col
         local.get $row
+        local.get $col
         call $offsetForPosition
         local.get $piece
         i32.store
@@ -38,9 +38,9 @@
     ;; Places the current player's piece in the given location
     ;; advances to next player
     (func $takeTurn (param $row i32) (param $col i32)
-        global.get $currentTurn
-        local.get $col
         local.get $row
+        local.get $col
+        global.get $currentTurn
         call $setPiece
         call $advanceTurn
     )
@@ -48,8 +48,8 @@
     ;; Retrieves the value of the piece at a given position on
     ;; the board. No error checking done here.
     (func $getPiece (param $row i32) (param $col i32) (result i32)
-        local.get $col
         local.get $row
+        local.get $col
         call $offsetForPosition
         i32.load
     )
@@ -87,9 +87,9 @@
                 local.set $c
                 block 
                     loop $iterate_col
-                        global.get $EMPTY
-                        local.get $c
                         local.get $r
+                        local.get $c
+                        global.get $EMPTY
                         call $setPiece
 
                         local.get $c
@@ -0,0 +1,492 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::interpret::{
+    ast::ast::{BlockTable, ExportType, Label, Mod},
+    decoder::decode_wasm,
+    host::{HostAction, HostFunc},
+    parser::parse_source,
+    runtime::{Evaluator, InterruptHandle, RunState},
+    trace::Tracer,
+    trap::Trap,
+    validate::validate,
+};
+pub use crate::interpret::ast::ast::{FuncType, ValType};
+pub use crate::interpret::runtime::{Frame, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The text or binary isn't a well-formed module
+    Parse(String),
+    /// The module is well-formed but fails validation
+    Invalid(String),
+    /// An import isn't provided or doesn't have the expected type
+    Link(String),
+    /// An invocation names a missing export or has the wrong arguments
+    Invoke(String),
+    /// Execution trapped, with the backtrace of the guest functions it was in, innermost first
+    Trap(Trap, Vec<Frame>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(message) => write!(f, "parse error: {}", message),
+            Error::Invalid(message) => write!(f, "{}", message),
+            Error::Link(message) => write!(f, "link error: {}", message),
+            Error::Invoke(message) => write!(f, "{}", message),
+            Error::Trap(trap, backtrace) => match backtrace.first() {
+                Some(frame) => write!(f, "trap: {} {}", trap, frame),
+                None => write!(f, "trap: {}", trap),
+            },
+        }
+    }
+}
+
+impl Error {
+    /// The functions a trap happened in, innermost first, empty for other errors and for
+    /// traps outside guest code
+    pub fn backtrace(&self) -> &[Frame] {
+        match self {
+            Error::Trap(_, backtrace) => backtrace,
+            _ => &[],
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The type of an export as seen from outside the module
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExternType {
+    Func(FuncType),
+    Global { ty: ValType, mutable: bool },
+    Memory { pages: u32 },
+    Table { size: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportDesc {
+    pub name: String,
+    pub ty: ExternType,
+}
+
+/// A parsed and validated module, instantiate it with `Instance::new`
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub(crate) module: Mod,
+    pub(crate) blks_table: BlockTable,
+}
+
+impl Module {
+    pub fn from_wat(source: &str) -> Result<Module, Error> {
+        let (module, blks_table) = parse_source(source).map_err(Error::Parse)?;
+        Module::validated(module, blks_table)
+    }
+
+    pub fn from_wasm(bytes: &[u8]) -> Result<Module, Error> {
+        let (module, blks_table) = decode_wasm(bytes).map_err(Error::Parse)?;
+        Module::validated(module, blks_table)
+    }
+
+    /// Reads a `.wat` or `.wasm` file, the binary format is recognised by its magic number
+    pub fn from_file(path: &str) -> Result<Module, Error> {
+        let bytes = std::fs::read(path).map_err(|error| Error::Parse(format!("can't read {}: {}", path, error)))?;
+        if bytes.starts_with(b"\0asm") {
+            return Module::from_wasm(&bytes);
+        }
+        match String::from_utf8(bytes) {
+            Ok(source) => {
+                let mut module = Module::from_wat(&source)?;
+                module.module.source_map.file = Some(path.to_string());
+                Ok(module)
+            }
+            Err(_) => Err(Error::Parse(format!("{} is neither text nor binary wasm", path))),
+        }
+    }
+
+    fn validated(module: Mod, blks_table: BlockTable) -> Result<Module, Error> {
+        validate(&module, &blks_table).map_err(|error| Error::Invalid(error.to_string()))?;
+        Ok(Module { module, blks_table })
+    }
+
+    /// Every export sorted by name
+    pub fn exports(&self) -> Vec<ExportDesc> {
+        exports_of(&self.module)
+    }
+}
+
+pub(crate) fn exports_of(module: &Mod) -> Vec<ExportDesc> {
+    let mut exports: Vec<ExportDesc> = module
+        .exports
+        .iter()
+        .map(|(name, export)| ExportDesc {
+            name: name.clone(),
+            ty: extern_type(module, &export.export_type, &export.export_ref),
+        })
+        .collect();
+    exports.sort_by(|a, b| a.name.cmp(&b.name));
+    exports
+}
+
+pub(crate) fn extern_type(module: &Mod, export_type: &ExportType, export_ref: &Label) -> ExternType {
+    match export_type {
+        ExportType::FUNCTION => ExternType::Func(module.funcs[fn_index(module, export_ref)].ty.clone()),
+        ExportType::GLOBAL => {
+            let global = &module.globals[module.get_global(export_ref)];
+            ExternType::Global {
+                ty: global.ty,
+                mutable: global.mutable,
+            }
+        }
+        ExportType::MEMORY => ExternType::Memory {
+            pages: module.memory.as_ref().map_or(0, |mem| mem.initial_capacity),
+        },
+        ExportType::TABLE => ExternType::Table {
+            size: module.table.as_ref().map_or(0, |table| table.initial_size),
+        },
+    }
+}
+
+pub(crate) fn fn_index(module: &Mod, label: &Label) -> usize {
+    match label {
+        Label::REF(name) => module.get_fn_idx(name),
+        Label::U32(idx) => *idx,
+    }
+}
+
+pub(crate) fn export_of(module: &Mod, name: &str) -> Option<ExternType> {
+    let export = module.exports.get(name)?;
+    Some(extern_type(module, &export.export_type, &export.export_ref))
+}
+
+pub(crate) fn func_type_of(module: &Mod, name: &str) -> Result<FuncType, Error> {
+    match export_of(module, name) {
+        Some(ExternType::Func(ty)) => Ok(ty),
+        Some(ty) => Err(Error::Invoke(format!("export {} is not a function, it is a {:?}", name, ty))),
+        None => Err(Error::Invoke(format!("no such function export {}", name))),
+    }
+}
+
+/// The index of an exported global, `None` if there's no global export by that name
+pub(crate) fn global_index(module: &Mod, name: &str) -> Option<usize> {
+    let export = module.exports.get(name)?;
+    if export.export_type != ExportType::GLOBAL {
+        return None;
+    }
+    Some(module.get_global(&export.export_ref))
+}
+
+/// The index of an exported function after checking the arguments against its signature
+fn export_fn_index(evaluator: &Evaluator, name: &str, args: &[Value]) -> Result<usize, Error> {
+    let ty = func_type_of(&evaluator.module, name)?;
+    let types: Vec<ValType> = args.iter().map(|arg| arg.ty()).collect();
+    if types != ty.params {
+        return Err(Error::Invoke(format!(
+            "{} expects arguments {:?}, saw {:?}",
+            name, ty.params, types
+        )));
+    }
+    Ok(fn_index(&evaluator.module, &evaluator.module.exports[name].export_ref))
+}
+
+/// Calls an exported function after checking the arguments against its signature
+pub(crate) fn invoke_export(evaluator: &mut Evaluator, name: &str, args: &[Value]) -> Result<Vec<Value>, Error> {
+    let fn_idx = export_fn_index(evaluator, name, args)?;
+    // an interrupt requested while nothing ran is meant for an earlier invocation
+    evaluator.take_interrupt();
+    evaluator.invoke(fn_idx, args.to_vec()).map_err(|trap| trap_error(evaluator, trap))
+}
+
+/// The error for a trap of the evaluator's last run, with the functions it trapped in
+pub(crate) fn trap_error(evaluator: &mut Evaluator, trap: Trap) -> Error {
+    let mut backtrace = std::mem::take(&mut evaluator.backtrace);
+    backtrace.reverse();
+    Error::Trap(trap, backtrace)
+}
+
+/// What a host function sees of the instance calling it
+pub struct Caller<'a> {
+    evaluator: &'a mut Evaluator,
+}
+
+impl Caller<'_> {
+    pub fn memory(&self) -> &[u8] {
+        &self.evaluator.memory.bytes
+    }
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.evaluator.memory.bytes
+    }
+}
+
+/// Host functions and global values that satisfy a module's imports
+#[derive(Debug, Clone, Default)]
+pub struct Imports {
+    pub(crate) funcs: HashMap<(String, String), HostFunc>,
+    pub(crate) globals: HashMap<(String, String), Value>,
+}
+
+impl Imports {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn func<F>(&mut self, module: &str, name: &str, ty: FuncType, func: F) -> &mut Self
+    where
+        F: Fn(&mut Caller, &[Value]) -> Result<Vec<Value>, Trap> + Send + Sync + 'static,
+    {
+        let host_fn = HostFunc::new(ty, move |evaluator, args| {
+            let mut caller = Caller { evaluator };
+            func(&mut caller, args).map(HostAction::Return)
+        });
+        self.funcs.insert((module.to_string(), name.to_string()), host_fn);
+        self
+    }
+
+    /// Like `func` for a host function that may suspend the guest by returning `HostAction::Yield`,
+    /// a call that reaches it has to be started and driven with `Instance::run_for`
+    pub fn yielding_func<F>(&mut self, module: &str, name: &str, ty: FuncType, func: F) -> &mut Self
+    where
+        F: Fn(&mut Caller, &[Value]) -> Result<HostAction, Trap> + Send + Sync + 'static,
+    {
+        let host_fn = HostFunc::new(ty, move |evaluator, args| func(&mut Caller { evaluator }, args));
+        self.funcs.insert((module.to_string(), name.to_string()), host_fn);
+        self
+    }
+
+    pub fn global(&mut self, module: &str, name: &str, value: Value) -> &mut Self {
+        self.globals.insert((module.to_string(), name.to_string()), value);
+        self
+    }
+}
+
+/// A module with its own memory, globals and table, ready to be invoked
+#[derive(Debug)]
+pub struct Instance {
+    evaluator: Evaluator,
+}
+
+impl Instance {
+    /// Resolves the imports, initialises globals and segments and runs the start function
+    pub fn new(module: &Module, imports: &Imports) -> Result<Instance, Error> {
+        let parsed = &module.module;
+        let mut imported_globals = Vec::new();
+        let mut host_funcs = Vec::new();
+        for import in parsed.imports.iter() {
+            let key = (import.module.clone(), import.name.clone());
+            match import.import_type {
+                ExportType::FUNCTION => {
+                    let expected = &parsed.funcs[import.import_ref].ty;
+                    match imports.funcs.get(&key) {
+                        Some(host_fn) if host_fn.ty == *expected => host_funcs.push(Some(host_fn.clone())),
+                        Some(host_fn) => {
+                            return Err(Error::Link(format!(
+                                "incompatible import type for {}.{}: expected {:?}, saw {:?}",
+                                import.module, import.name, expected, host_fn.ty
+                            )))
+                        }
+                        None => return Err(Error::Link(format!("unknown import {}.{}", import.module, import.name))),
+                    }
+                }
+                ExportType::GLOBAL => {
+                    let global = &parsed.globals[import.import_ref];
+                    match imports.globals.get(&key) {
+                        Some(value) if value.ty() == global.ty => imported_globals.push(*value),
+                        Some(_) => {
+                            return Err(Error::Link(format!(
+                                "incompatible import type for {}.{}: expected {}",
+                                import.module, import.name, global.ty
+                            )))
+                        }
+                        None => return Err(Error::Link(format!("unknown import {}.{}", import.module, import.name))),
+                    }
+                    host_funcs.push(None);
+                }
+                // memories and tables can't be provided through `Imports`, only the `Linker` shares them
+                ExportType::MEMORY | ExportType::TABLE => {
+                    return Err(Error::Link(format!("unknown import {}.{}", import.module, import.name)))
+                }
+            }
+        }
+        let mut evaluator = Evaluator::instantiate(parsed.clone(), module.blks_table.clone(), &imported_globals);
+        evaluator.host_funcs = host_funcs;
+        evaluator.init_segments().map_err(|trap| Error::Trap(trap, Vec::new()))?;
+        if let Some(start) = parsed.start {
+            if let Err(trap) = evaluator.invoke(start, Vec::new()) {
+                return Err(trap_error(&mut evaluator, trap));
+            }
+        }
+        Ok(Instance { evaluator })
+    }
+
+    /// Calls an exported function with arguments matching its signature
+    pub fn invoke(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>, Error> {
+        self.check_idle()?;
+        invoke_export(&mut self.evaluator, name, args)
+    }
+
+    fn check_idle(&self) -> Result<(), Error> {
+        if self.is_paused() {
+            return Err(Error::Invoke(String::from("a call is paused, finish or abort it first")));
+        }
+        Ok(())
+    }
+
+    /// Calls an exported function without running it, `step` or `run_for` then execute it
+    pub fn start(&mut self, name: &str, args: &[Value]) -> Result<(), Error> {
+        self.check_idle()?;
+        let fn_idx = export_fn_index(&self.evaluator, name, args)?;
+        if self.evaluator.module.funcs[fn_idx].import.is_some() {
+            return Err(Error::Invoke(format!("{} is a host function, it can't be stepped", name)));
+        }
+        self.evaluator.take_interrupt();
+        self.evaluator.add_parameters(args.to_vec());
+        self.evaluator.call(&Label::U32(fn_idx));
+        Ok(())
+    }
+
+    /// Executes the next opcode of the started call, returning its results once it returns.
+    /// A trap abandons the call
+    pub fn step(&mut self) -> Result<Option<Vec<Value>>, Error> {
+        self.run_for(1)
+    }
+
+    /// Executes at most `n_steps` opcodes of the started call, returning its results once it returns.
+    /// `None` when the steps ran out or a host function suspended the call, see `resume`
+    pub fn run_for(&mut self, n_steps: usize) -> Result<Option<Vec<Value>>, Error> {
+        if !self.is_paused() {
+            return Err(Error::Invoke(String::from("no call is paused")));
+        }
+        match self.evaluator.run_for(n_steps) {
+            RunState::Finished(results) => Ok(Some(results)),
+            RunState::Trapped(trap) => Err(trap_error(&mut self.evaluator, trap)),
+            RunState::Yielded => Ok(None),
+        }
+    }
+
+    /// Whether a host function yielded and the started call waits for `resume`
+    pub fn is_suspended(&self) -> bool {
+        self.evaluator.is_suspended()
+    }
+
+    /// Hands the results of the host function that yielded to the guest, `run_for` then carries on
+    pub fn resume(&mut self, results: Vec<Value>) -> Result<(), Error> {
+        self.evaluator.resume(results).map_err(Error::Invoke)
+    }
+
+    /// Whether a started call hasn't returned yet
+    pub fn is_paused(&self) -> bool {
+        !self.evaluator.calls.is_empty()
+    }
+
+    /// Abandons the started call, memory and globals keep what it changed
+    pub fn abort(&mut self) {
+        self.evaluator.abandon();
+    }
+
+    /// The functions of the started call, the outermost first
+    pub fn frames(&self) -> Vec<Frame> {
+        self.evaluator.frames()
+    }
+
+    /// The locals, parameters first, of the `frame`th function counted like `frames`
+    pub fn frame_locals(&self, frame: usize) -> Option<&[Value]> {
+        Some(&self.evaluator.calls.get(frame)?.locals)
+    }
+
+    /// The operand stack of the `frame`th function counted like `frames`
+    pub fn frame_stack(&self, frame: usize) -> Option<&[Value]> {
+        self.evaluator.frame_stack(frame)
+    }
+
+    pub fn func_type(&self, name: &str) -> Result<FuncType, Error> {
+        func_type_of(&self.evaluator.module, name)
+    }
+
+    pub fn export(&self, name: &str) -> Option<ExternType> {
+        export_of(&self.evaluator.module, name)
+    }
+
+    /// Every export sorted by name
+    pub fn exports(&self) -> Vec<ExportDesc> {
+        exports_of(&self.evaluator.module)
+    }
+
+    /// The linear memory, `None` if the module has none
+    pub fn memory(&self) -> Option<&[u8]> {
+        self.evaluator.module.memory.as_ref()?;
+        Some(&self.evaluator.memory.bytes)
+    }
+
+    pub fn memory_mut(&mut self) -> Option<&mut [u8]> {
+        self.evaluator.module.memory.as_ref()?;
+        Some(&mut self.evaluator.memory.bytes)
+    }
+
+    /// The current value of an exported global
+    pub fn global(&self, name: &str) -> Option<Value> {
+        Some(self.evaluator.globals[global_index(&self.evaluator.module, name)?])
+    }
+
+    /// Changes an exported mutable global, the value must have the global's type
+    pub fn set_global(&mut self, name: &str, value: Value) -> Result<(), Error> {
+        let module = &self.evaluator.module;
+        let global_idx = global_index(module, name).ok_or(Error::Invoke(format!("no such global export {}", name)))?;
+        let global = &module.globals[global_idx];
+        if !global.mutable {
+            return Err(Error::Invoke(format!("global {} is immutable", name)));
+        }
+        if value.ty() != global.ty {
+            return Err(Error::Invoke(format!(
+                "global {} has type {}, saw a {}",
+                name,
+                global.ty,
+                value.ty()
+            )));
+        }
+        self.evaluator.globals[global_idx] = value;
+        Ok(())
+    }
+
+    /// Meters the following calls, running dry traps with `OutOfFuel` and leaves the call paused
+    /// so that it carries on with `run_for` once fuel is added
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.evaluator.set_fuel(fuel);
+    }
+
+    /// Tops up the fuel, which also starts metering if it was off
+    pub fn add_fuel(&mut self, fuel: u64) {
+        self.evaluator.add_fuel(fuel);
+    }
+
+    /// The fuel left, `None` when execution isn't metered
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.evaluator.remaining_fuel()
+    }
+
+    pub fn disable_fuel(&mut self) {
+        self.evaluator.disable_fuel();
+    }
+
+    /// Lets another thread or a signal handler stop the running invocation with a trap
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.evaluator.interrupt_handle()
+    }
+
+    /// Logs every opcode of the following invocations to `tracer`, `None` stops tracing
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.evaluator.tracer = tracer;
+    }
+
+    /// Writes memory and globals to a snapshot file, with the started call when one is paused,
+    /// see `load_snapshot`
+    pub fn save_snapshot(&self, path: &str) -> Result<(), String> {
+        self.evaluator.save_snapshot(path, self.is_paused())
+    }
+
+    /// Restores memory and globals saved from an instance of the same source, a call that was
+    /// paused when the snapshot was taken is paused again and any current one is dropped
+    pub fn load_snapshot(&mut self, path: &str) -> Result<(), String> {
+        self.evaluator.load_snapshot(path)
+    }
+}
@@ -0,0 +1,259 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+
+use interperter::{run_wast, Error, Imports, Instance, Module, Tracer, Trap, ValType, Value, Wasi};
+
+pub const USAGE: &str = "usage: interperter FILE
+       interperter repl FILE [--script SCRIPT] [TRACE...]
+       interperter wast FILE...
+       interperter run FILE [--invoke NAME [ARGS...]] [--dir HOST:GUEST]... [TRACE...] [-- WASI_ARGS...]
+TRACE: --trace                logs every executed opcode to stderr
+       --trace-only FUNCTION  traces only this function, may be repeated
+       --trace-file FILE      writes a compact trace to FILE instead";
+
+pub enum Command {
+    Repl {
+        file: String,
+        script: Option<String>,
+        trace: TraceOptions,
+    },
+    Run(RunOptions),
+    Wast {
+        files: Vec<String>,
+    },
+}
+
+pub struct RunOptions {
+    pub file: String,
+    pub invoke: Option<(String, Vec<String>)>, // export name and its arguments
+    pub dirs: Vec<(String, String)>,           // host directory and the guest path it is preopened as
+    pub wasi_args: Vec<String>,                // after the program name
+    pub trace: TraceOptions,
+}
+
+/// The `--trace` options, giving functions or a file turns tracing on as well
+#[derive(Default)]
+pub struct TraceOptions {
+    pub enabled: bool,
+    pub functions: Vec<String>,
+    pub file: Option<String>,
+}
+
+impl TraceOptions {
+    /// The tracer these options ask for, `None` when tracing is off
+    pub fn tracer(&self) -> Result<Option<Tracer>, String> {
+        let mut tracer = match &self.file {
+            Some(path) => trace_file(path)?,
+            None if self.enabled || !self.functions.is_empty() => Tracer::new(io::stderr()),
+            None => return Ok(None),
+        };
+        for function in self.functions.iter() {
+            tracer.only(function);
+        }
+        Ok(Some(tracer))
+    }
+}
+
+/// A tracer writing the compact format to a new file at `path`
+pub fn trace_file(path: &str) -> Result<Tracer, String> {
+    let file = File::create(path).map_err(|error| format!("can't create {}: {}", path, error))?;
+    Ok(Tracer::compact(BufWriter::new(file)))
+}
+
+/// Reads a trace option and its value from `args`, false when `flag` isn't one
+fn parse_trace<'a>(
+    flag: &str,
+    args: &mut impl Iterator<Item = &'a String>,
+    trace: &mut TraceOptions,
+) -> Result<bool, String> {
+    match flag {
+        "--trace" => trace.enabled = true,
+        "--trace-only" => trace.functions.push(args.next().ok_or("--trace-only expects a function name")?.clone()),
+        "--trace-file" => trace.file = Some(args.next().ok_or("--trace-file expects a file name")?.clone()),
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Parses the command line without the program name
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    match args {
+        [file] if file != "run" && file != "repl" => Ok(Command::Repl {
+            file: file.clone(),
+            script: None,
+            trace: TraceOptions::default(),
+        }),
+        [run, rest @ ..] if run == "run" => parse_run(rest).map(Command::Run),
+        [repl, rest @ ..] if repl == "repl" => parse_repl(rest),
+        [wast, files @ ..] if wast == "wast" && !files.is_empty() => Ok(Command::Wast { files: files.to_vec() }),
+        [] => Err(format!("expected a file name\n{}", USAGE)),
+        _ => Err(format!("unexpected arguments {}\n{}", args[1..].join(" "), USAGE)),
+    }
+}
+
+fn parse_repl(args: &[String]) -> Result<Command, String> {
+    let (mut file, mut script, mut trace) = (None, None, TraceOptions::default());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--script" => script = Some(args.next().ok_or("--script expects a file name")?.clone()),
+            flag if parse_trace(flag, &mut args, &mut trace)? => {}
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}\n{}", flag, USAGE)),
+            name if file.is_none() => file = Some(name.to_string()),
+            extra => return Err(format!("unexpected argument {}\n{}", extra, USAGE)),
+        }
+    }
+    let file = file.ok_or(format!("expected a file name\n{}", USAGE))?;
+    Ok(Command::Repl { file, script, trace })
+}
+
+fn parse_run(args: &[String]) -> Result<RunOptions, String> {
+    let mut options = RunOptions {
+        file: String::new(),
+        invoke: None,
+        dirs: Vec::new(),
+        wasi_args: Vec::new(),
+        trace: TraceOptions::default(),
+    };
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--" => {
+                options.wasi_args = args.cloned().collect();
+                break;
+            }
+            "--invoke" => {
+                let name = args.next().ok_or("--invoke expects an export name")?;
+                let mut values = Vec::new();
+                // negative numbers start with a single dash, flags with two
+                while let Some(value) = args.next_if(|value| !value.starts_with("--")) {
+                    values.push(value.clone());
+                }
+                options.invoke = Some((name.clone(), values));
+            }
+            "--dir" => {
+                let dir = args.next().ok_or("--dir expects HOST:GUEST")?;
+                let (host, guest) = dir.split_once(':').unwrap_or((dir, dir));
+                options.dirs.push((host.to_string(), guest.to_string()));
+            }
+            flag if parse_trace(flag, &mut args, &mut options.trace)? => {}
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}\n{}", flag, USAGE)),
+            file if options.file.is_empty() => options.file = file.to_string(),
+            extra => return Err(format!("unexpected argument {}\n{}", extra, USAGE)),
+        }
+    }
+    if options.file.is_empty() {
+        return Err(format!("expected a file name\n{}", USAGE));
+    }
+    Ok(options)
+}
+
+/// Reads a value of the given type the way the text format writes constants: integers may be
+/// negative, in hex like `0x2a` and separated with `_`, floats may also be `inf`, `-inf` or `nan`
+pub fn parse_value(ty: ValType, text: &str) -> Result<Value, String> {
+    let parsed = match ty {
+        ValType::I32 => parse_integer(text, 32).map(|n| Value::I32(n as i32)),
+        ValType::I64 => parse_integer(text, 64).map(|n| Value::I64(n as i64)),
+        ValType::F32 => text.replace('_', "").parse().map(Value::F32).ok(),
+        ValType::F64 => text.replace('_', "").parse().map(Value::F64).ok(),
+    };
+    parsed.ok_or(format!("{} is not a valid {}", text, ty))
+}
+
+/// Reads an integer of `bits` bits, anything from the most negative signed value to the
+/// largest unsigned one is accepted, so `0xffffffff` is a valid i32
+pub fn parse_integer(text: &str, bits: u32) -> Option<i128> {
+    let digits = text.replace('_', "");
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, digits.strip_prefix('+').unwrap_or(&digits)),
+    };
+    let magnitude = match digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        Some(hex) if hex.starts_with(|c: char| c.is_ascii_hexdigit()) => i128::from_str_radix(hex, 16).ok()?,
+        Some(_) => return None,
+        None if digits.starts_with(|c: char| c.is_ascii_digit()) => digits.parse::<i128>().ok()?,
+        None => return None,
+    };
+    let n = if negative { -magnitude } else { magnitude };
+    (-(1 << (bits - 1))..1 << bits).contains(&n).then_some(n)
+}
+
+/// Runs the module without the REPL and returns the process exit status: the guest's
+/// `proc_exit` code, otherwise 0 on success and 1 when loading, linking or the call fails
+pub fn run(options: &RunOptions) -> i32 {
+    match try_run(options) {
+        Ok(code) => code,
+        Err(Error::Trap(Trap::Exit(code), _)) => code,
+        Err(error) => {
+            eprintln!("{}", error);
+            1
+        }
+    }
+}
+
+fn try_run(options: &RunOptions) -> Result<i32, Error> {
+    let module = Module::from_file(&options.file)?;
+    let mut wasi = Wasi::new();
+    wasi.arg(&options.file);
+    for arg in options.wasi_args.iter() {
+        wasi.arg(arg);
+    }
+    for (host, guest) in options.dirs.iter() {
+        wasi.preopen_dir(host, guest).map_err(Error::Link)?;
+    }
+    let mut imports = Imports::new();
+    wasi.add_to_imports(&mut imports);
+    let mut instance = Instance::new(&module, &imports)?;
+    instance.set_tracer(options.trace.tracer().map_err(Error::Invoke)?);
+    let (name, args) = match &options.invoke {
+        Some(invoke) => invoke,
+        None => return wasi.start(&mut instance),
+    };
+    let ty = instance.func_type(name)?;
+    if args.len() != ty.params.len() {
+        return Err(Error::Invoke(format!(
+            "{} expects {} arguments, saw {}",
+            name,
+            ty.params.len(),
+            args.len()
+        )));
+    }
+    let values = ty
+        .params
+        .iter()
+        .zip(args.iter())
+        .map(|(ty, arg)| parse_value(*ty, arg))
+        .collect::<Result<Vec<Value>, String>>()
+        .map_err(Error::Invoke)?;
+    for result in instance.invoke(name, &values)? {
+        println!("{}", result);
+    }
+    Ok(0)
+}
+
+/// Runs spec test scripts, printing the failing commands and a summary per file. The exit
+/// status is 1 when a command failed or a script couldn't be read
+pub fn wast(files: &[String]) -> i32 {
+    let mut status = 0;
+    for file in files {
+        let report = std::fs::read_to_string(file)
+            .map_err(|error| format!("can't read {}: {}", file, error))
+            .and_then(|source| run_wast(&source));
+        match report {
+            Ok(report) => {
+                for failure in report.failures.iter() {
+                    println!("{}:{}: {}", file, failure.line, failure.message);
+                }
+                println!("{}: {} passed, {} failed", file, report.passed, report.failures.len());
+                if !report.failures.is_empty() {
+                    status = 1;
+                }
+            }
+            Err(error) => {
+                eprintln!("{}: {}", file, error);
+                status = 1;
+            }
+        }
+    }
+    status
+}
@@ -2,7 +2,6 @@ pub mod ast {
     use std::collections::HashMap;
 
     use crate::interpret::{op::OP, scanner::Scanner};
-    pub const IDX_LIMIT: u8 = 32;
     #[derive(Debug, Clone, PartialEq)]
     pub enum Label {
         REF(String),
@@ -21,6 +20,40 @@ pub mod ast {
             }
         }
     }
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum ValType {
+        I32,
+        I64,
+        F32,
+        F64,
+    }
+    impl ValType {
+        pub fn from_kwd(kwd: &str) -> Option<Self> {
+            match kwd {
+                "i32" => Some(ValType::I32),
+                "i64" => Some(ValType::I64),
+                "f32" => Some(ValType::F32),
+                "f64" => Some(ValType::F64),
+                _ => None,
+            }
+        }
+    }
+    impl std::fmt::Display for ValType {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            match self {
+                ValType::I32 => write!(f, "i32"),
+                ValType::I64 => write!(f, "i64"),
+                ValType::F32 => write!(f, "f32"),
+                ValType::F64 => write!(f, "f64"),
+            }
+        }
+    }
+    /// Signature shared by functions and blocks, `(param t*) (result t*)`
+    #[derive(Debug, Clone, PartialEq, Default)]
+    pub struct FuncType {
+        pub params: Vec<ValType>,
+        pub results: Vec<ValType>,
+    }
     pub type BlockTable = Vec<Vec<Block>>;
 
     #[derive(Debug, Clone)]
@@ -28,43 +61,100 @@ pub mod ast {
         pub id: Option<String>,
         pub is_loop: bool,
         pub next_pc: usize,
-        pub result: bool,
+        pub else_pc: Option<usize>, // only set for an `if` with an `else` arm
+        pub ty: FuncType,
+    }
+    impl Block {
+        /// Number of values a branch to this block carries
+        pub fn label_arity(&self) -> usize {
+            if self.is_loop {
+                self.ty.params.len()
+            } else {
+                self.ty.results.len()
+            }
+        }
     }
+    /// Instructions evaluated once at instantiation, e.g. `(i32.add (global.get $g) (i32.const 4))`
+    pub type ConstExpr = Vec<OP>;
     #[derive(Debug, Clone, PartialEq)]
     pub struct Global {
+        pub ty: ValType,
         pub mutable: bool,
-        pub value: i32,
+        pub init: ConstExpr, // empty for an imported global
+    }
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Table {
+        pub name: Option<String>,
+        pub initial_size: u32,
+    }
+    /// Bytes copied into memory at `offset` when the module is instantiated
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Data {
+        pub offset: Option<ConstExpr>, // None for a passive segment
+        pub bytes: Vec<u8>,
+    }
+    /// Functions written into the table at `offset` when the module is instantiated
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Elem {
+        pub offset: Option<ConstExpr>, // None for a passive segment
+        pub funcs: Vec<Label>,
     }
 
     #[derive(Debug, Clone)]
     pub struct Fn {
         pub name: Option<String>,
-        pub args: u32,
-        pub locals: u32,
+        pub ty: FuncType,
+        pub locals: Vec<ValType>,
         pub code_addr: usize,
-        pub result: bool,
+        pub import: Option<usize>, // index into `Mod::imports` for functions without a body
     }
     impl Fn {
+        #[allow(non_snake_case)]
         pub fn Empty() -> Self {
             Self {
                 name: None,
-                args: 0,
-                locals: 0,
+                ty: FuncType::default(),
+                locals: Vec::new(),
                 code_addr: 0,
-                result: false,
+                import: None,
             }
         }
     }
+    /// Where an opcode was written in the text format, line and column count from 1
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct SourceLoc {
+        pub line: usize,
+        pub col: usize,
+        pub func: usize, // the enclosing function
+    }
+    /// The source location of each opcode of `Mod::code`, empty for binary modules
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct SourceMap {
+        pub file: Option<String>, // set when the module was read from a file
+        pub locs: Vec<SourceLoc>,
+    }
+    impl SourceMap {
+        pub fn get(&self, pc: usize) -> Option<&SourceLoc> {
+            self.locs.get(pc)
+        }
+    }
     #[derive(Debug, Clone)]
     pub struct Mod {
         pub memory: Option<Mem>,
+        pub table: Option<Table>,
+        pub datas: Vec<Data>,
+        pub elems: Vec<Elem>,
+        pub imports: Vec<Import>,
         pub exports: HashMap<String, Export>,
         pub funcs: Vec<Fn>,
         pub funcs_refs: HashMap<String, usize>,
         pub globals_map: HashMap<String, usize>,
         pub globals: Vec<Global>, // globals
+        pub types: Vec<FuncType>,
         pub code: Code,
+        pub source_map: SourceMap,
         pub start: Option<usize>,
+        pub source_hash: u64, // of the text or binary, see `snapshot::source_hash`
     }
     impl Mod {
         pub fn get_fn_idx(&self, fn_id: &str) -> usize {
@@ -79,8 +169,8 @@ pub mod ast {
                 None => panic!("no function of the id {:?}", fn_id),
             }
         }
-        pub fn get_global(&mut self, label: &Label) -> usize {
-            let mut global_idx;
+        pub fn get_global(&self, label: &Label) -> usize {
+            let global_idx;
             match label {
                 Label::REF(name) => {
                     if let Some(idx) = self.globals_map.get(name) {
@@ -97,7 +187,7 @@ pub mod ast {
                     }
                 }
             }
-            return global_idx;
+            global_idx
         }
     }
 
@@ -106,6 +196,7 @@ pub mod ast {
         FUNCTION,
         GLOBAL,
         MEMORY,
+        TABLE,
     }
     #[derive(Debug, Clone, PartialEq)]
     pub struct Export {
@@ -121,19 +212,34 @@ pub mod ast {
         }
     }
 
-    type FnSymTable = Vec<Fn>;
+    /// Imported functions and globals take the lowest indices of their index space
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Import {
+        pub module: String,
+        pub name: String,
+        pub import_type: ExportType,
+        pub import_ref: usize,
+    }
+
     type Code = Vec<OP>;
 
     #[derive(Debug)]
     pub struct Parser {
         pub memory: Option<Mem>,
+        pub table: Option<Table>,
+        pub datas: Vec<Data>,
+        pub elems: Vec<Elem>,
         pub scanner: Scanner,
+        pub imports: Vec<Import>,
         pub exports: HashMap<String, Export>,
         pub functions: Vec<Fn>,
         pub code_memory: Code,
+        pub code_locs: Vec<SourceLoc>, // parallel to `code_memory`
         pub funcs_refs: HashMap<String, usize>,
         pub globals: Vec<Global>,
         pub globals_map: HashMap<String, usize>,
+        pub types: Vec<FuncType>,
+        pub types_map: HashMap<String, usize>,
         pub blks_table: Vec<Vec<Block>>,
         pub blks_stack: Vec<usize>,
     }
@@ -141,13 +247,20 @@ pub mod ast {
         pub fn new(scanner: Scanner) -> Self {
             Self {
                 memory: None,
-                scanner: scanner,
+                table: None,
+                datas: Vec::new(),
+                elems: Vec::new(),
+                scanner,
+                imports: Vec::new(),
                 exports: HashMap::new(),
                 functions: Vec::new(),
                 code_memory: Vec::new(),
+                code_locs: Vec::new(),
                 funcs_refs: HashMap::new(),
                 globals: Vec::new(),
                 globals_map: HashMap::new(),
+                types: Vec::new(),
+                types_map: HashMap::new(),
                 blks_table: Vec::new(),
                 blks_stack: Vec::new(),
             }
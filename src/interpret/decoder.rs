@@ -0,0 +1,543 @@
+use std::collections::HashMap;
+
+use crate::interpret::ast::ast::{
+    Block, BlockTable, ConstExpr, Data, Elem, Export, ExportType, Fn, FuncType, Global, Import, Label, Mem, Mod,
+    SourceMap, Table, ValType,
+};
+use crate::interpret::op::OP::{self, *};
+use crate::interpret::snapshot::source_hash;
+
+const MAGIC: &[u8; 4] = b"\0asm";
+const VERSION: u32 = 1;
+
+/// Reads the binary format into the same module representation `parse_source` builds from text
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn eof(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        match self.bytes.get(self.pos..self.pos + n) {
+            Some(slice) => {
+                self.pos += n;
+                Ok(slice)
+            }
+            None => Err(String::from("unexpected end of binary")),
+        }
+    }
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+    fn leb_u32(&mut self) -> Result<u32, String> {
+        let mut result: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            result |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+            if shift >= 35 {
+                return Err(String::from("integer representation too long"));
+            }
+        }
+        u32::try_from(result).map_err(|_| String::from("integer too large"))
+    }
+    fn leb_signed(&mut self, bits: u32) -> Result<i64, String> {
+        let mut result: i64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift < 64 {
+                result |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    result |= -1 << shift;
+                }
+                break;
+            }
+            if shift >= bits + 7 {
+                return Err(String::from("integer representation too long"));
+            }
+        }
+        Ok(result)
+    }
+    fn usize(&mut self) -> Result<usize, String> {
+        Ok(self.leb_u32()? as usize)
+    }
+    fn name(&mut self) -> Result<String, String> {
+        let len = self.usize()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| String::from("malformed UTF-8 encoding"))
+    }
+    fn val_type(&mut self) -> Result<ValType, String> {
+        match self.byte()? {
+            0x7f => Ok(ValType::I32),
+            0x7e => Ok(ValType::I64),
+            0x7d => Ok(ValType::F32),
+            0x7c => Ok(ValType::F64),
+            byte => Err(format!("unknown value type {:#x}", byte)),
+        }
+    }
+    fn val_types(&mut self) -> Result<Vec<ValType>, String> {
+        let count = self.usize()?;
+        (0..count).map(|_| self.val_type()).collect()
+    }
+    /// Returns the initial size, maximums aren't enforced
+    fn limits(&mut self) -> Result<u32, String> {
+        match self.byte()? {
+            0x00 => self.leb_u32(),
+            0x01 => {
+                let initial = self.leb_u32()?;
+                self.leb_u32()?;
+                Ok(initial)
+            }
+            byte => Err(format!("malformed limits flag {:#x}", byte)),
+        }
+    }
+    fn table_type(&mut self) -> Result<u32, String> {
+        match self.byte()? {
+            0x70 => self.limits(),
+            byte => Err(format!("only funcref tables are supported, saw {:#x}", byte)),
+        }
+    }
+    fn global_type(&mut self) -> Result<(ValType, bool), String> {
+        let ty = self.val_type()?;
+        match self.byte()? {
+            0x00 => Ok((ty, false)),
+            0x01 => Ok((ty, true)),
+            byte => Err(format!("malformed mutability {:#x}", byte)),
+        }
+    }
+    fn const_expr(&mut self) -> Result<ConstExpr, String> {
+        let mut expr = Vec::new();
+        loop {
+            let op = match self.byte()? {
+                0x0b => return Ok(expr),
+                0x23 => GLOGET(Label::U32(self.usize()?)),
+                0x41 => I32CONST(self.leb_signed(32)? as i32),
+                0x42 => I64CONST(self.leb_signed(64)?),
+                0x43 => F32CONST(f32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+                0x44 => F64CONST(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+                0x6a => I32ADD,
+                0x6b => I32SUB,
+                0x6c => I32MUL,
+                0x7c => I64ADD,
+                0x7d => I64SUB,
+                0x7e => I64MUL,
+                byte => return Err(format!("opcode {:#x} isn't supported in a constant expression", byte)),
+            };
+            expr.push(op);
+        }
+    }
+}
+
+/// Per function state while decoding a code section entry
+struct Body {
+    blocks: Vec<Block>,
+    open: Vec<usize>,
+}
+
+impl Decoder<'_> {
+    fn block_type(&mut self, types: &[FuncType]) -> Result<FuncType, String> {
+        match self.bytes.get(self.pos) {
+            Some(0x40) => {
+                self.pos += 1;
+                Ok(FuncType::default())
+            }
+            Some(0x7c..=0x7f) => Ok(FuncType {
+                params: Vec::new(),
+                results: vec![self.val_type()?],
+            }),
+            _ => {
+                let type_idx = self.leb_signed(33)?;
+                match usize::try_from(type_idx).ok().and_then(|idx| types.get(idx)) {
+                    Some(ty) => Ok(ty.clone()),
+                    None => Err(format!("unknown type {}", type_idx)),
+                }
+            }
+        }
+    }
+    fn open_block(&mut self, body: &mut Body, is_loop: bool, code: &[OP], types: &[FuncType]) -> Result<usize, String> {
+        let ty = self.block_type(types)?;
+        body.blocks.push(Block {
+            id: None,
+            is_loop,
+            next_pc: if is_loop { code.len() } else { 0 },
+            else_pc: None,
+            ty,
+        });
+        body.open.push(body.blocks.len() - 1);
+        Ok(body.blocks.len() - 1)
+    }
+    /// Branch depths count outwards from the innermost block, the function body is the outermost label
+    fn branch_target(&mut self, body: &Body) -> Result<Option<usize>, String> {
+        let depth = self.usize()?;
+        if depth < body.open.len() {
+            Ok(Some(body.open[body.open.len() - 1 - depth]))
+        } else if depth == body.open.len() {
+            Ok(None)
+        } else {
+            Err(format!("unknown label {}", depth))
+        }
+    }
+    fn memarg(&mut self) -> Result<(), String> {
+        self.leb_u32()?; // alignment is only a hint
+        if self.leb_u32()? != 0 {
+            return Err(String::from("memory access offsets aren't supported"));
+        }
+        Ok(())
+    }
+    /// Decodes instructions up to the `end` of the function body, which becomes a `RET`
+    fn function_body(&mut self, code: &mut Vec<OP>, types: &[FuncType]) -> Result<Vec<Block>, String> {
+        let mut body = Body {
+            blocks: Vec::new(),
+            open: Vec::new(),
+        };
+        loop {
+            let op = match self.byte()? {
+                0x00 => UNR,
+                0x01 => NOP,
+                0x02 => BLK(self.open_block(&mut body, false, code, types)?),
+                0x03 => LOOP(self.open_block(&mut body, true, code, types)?),
+                0x04 => IF(self.open_block(&mut body, false, code, types)?),
+                0x05 => {
+                    let blk_idx = *body.open.last().ok_or("else outside of an if")?;
+                    body.blocks[blk_idx].else_pc = Some(code.len());
+                    ELSE(blk_idx)
+                }
+                0x0b => match body.open.pop() {
+                    Some(blk_idx) => {
+                        if !body.blocks[blk_idx].is_loop {
+                            body.blocks[blk_idx].next_pc = code.len();
+                        }
+                        END
+                    }
+                    None => {
+                        code.push(RET);
+                        return Ok(body.blocks);
+                    }
+                },
+                0x0c => match self.branch_target(&body)? {
+                    Some(blk_idx) => BR(blk_idx),
+                    None => RET,
+                },
+                0x0d => match self.branch_target(&body)? {
+                    Some(blk_idx) => BRIF(blk_idx),
+                    None => return Err(String::from("br_if to the function body isn't supported")),
+                },
+                0x0f => RET,
+                0x10 => CALL(Label::U32(self.usize()?)),
+                0x11 => {
+                    let type_idx = self.usize()?;
+                    if self.byte()? != 0x00 {
+                        return Err(String::from("only table 0 exists in this implementation"));
+                    }
+                    if type_idx >= types.len() {
+                        return Err(format!("unknown type {}", type_idx));
+                    }
+                    CALLIND(type_idx)
+                }
+                0x1a => DROP,
+                0x20 => LOCGET(self.usize()?),
+                0x21 => LOCSET(self.usize()?),
+                0x22 => LOCTEE(self.usize()?),
+                0x23 => GLOGET(Label::U32(self.usize()?)),
+                0x24 => GLOSET(Label::U32(self.usize()?)),
+                0x28 => {
+                    self.memarg()?;
+                    I32LOAD
+                }
+                0x36 => {
+                    self.memarg()?;
+                    I32STORE
+                }
+                0x41 => I32CONST(self.leb_signed(32)? as i32),
+                0x42 => I64CONST(self.leb_signed(64)?),
+                0x43 => F32CONST(f32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+                0x44 => F64CONST(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+                0x45 => I32EQZ,
+                0x46 => I32EQ,
+                0x47 => I32NE,
+                0x48 => I32LTS,
+                0x4a => I32GTS,
+                0x4c => I32LES,
+                0x4e => I32GES,
+                0x6a => I32ADD,
+                0x6b => I32SUB,
+                0x6c => I32MUL,
+                0x6d => I32DIVS,
+                0x6e => I32DIVU,
+                0x6f => I32REMS,
+                0x70 => I32REMU,
+                0x71 => I32AND,
+                0x72 => I32OR,
+                0x73 => I32XOR,
+                0x7c => I64ADD,
+                0x7d => I64SUB,
+                0x7e => I64MUL,
+                byte => return Err(format!("opcode {:#x} isn't supported", byte)),
+            };
+            code.push(op);
+        }
+    }
+}
+
+/// Decodes a `.wasm` binary, only the instructions the text parser knows are accepted
+pub fn decode_wasm(bytes: &[u8]) -> Result<(Mod, BlockTable), String> {
+    let mut decoder = Decoder { bytes, pos: 0 };
+    if decoder.take(4).map_err(|_| String::from("magic header not detected"))? != MAGIC {
+        return Err(String::from("magic header not detected"));
+    }
+    let version = u32::from_le_bytes(decoder.take(4)?.try_into().unwrap());
+    if version != VERSION {
+        return Err(format!("unknown binary version {}", version));
+    }
+    let mut module = Mod {
+        memory: None,
+        table: None,
+        datas: Vec::new(),
+        elems: Vec::new(),
+        imports: Vec::new(),
+        exports: HashMap::new(),
+        funcs: Vec::new(),
+        funcs_refs: HashMap::new(),
+        globals_map: HashMap::new(),
+        globals: Vec::new(),
+        types: Vec::new(),
+        code: Vec::new(),
+        source_map: SourceMap::default(),
+        start: None,
+        source_hash: source_hash(bytes),
+    };
+    let mut blks_table: BlockTable = Vec::new();
+    let mut fn_types: Vec<usize> = Vec::new();
+    let mut last_order = None;
+    while !decoder.eof() {
+        let id = decoder.byte()?;
+        let size = decoder.usize()?;
+        let mut section = Decoder {
+            bytes: decoder.take(size)?,
+            pos: 0,
+        };
+        if id != 0 {
+            let order = section_order(id).ok_or(format!("malformed section id {}", id))?;
+            if last_order.is_some_and(|last| order <= last) {
+                return Err(format!("section {} is out of order", id));
+            }
+            last_order = Some(order);
+        }
+        match id {
+            0 => decode_custom(&mut section, &mut module)?,
+            1 => {
+                for _ in 0..section.usize()? {
+                    if section.byte()? != 0x60 {
+                        return Err(String::from("malformed function type"));
+                    }
+                    let params = section.val_types()?;
+                    let results = section.val_types()?;
+                    module.types.push(FuncType { params, results });
+                }
+            }
+            2 => {
+                for _ in 0..section.usize()? {
+                    let module_name = section.name()?;
+                    let name = section.name()?;
+                    let (import_type, import_ref) = match section.byte()? {
+                        0x00 => {
+                            let type_idx = section.usize()?;
+                            let ty = module.types.get(type_idx).ok_or(format!("unknown type {}", type_idx))?;
+                            let mut function = Fn::Empty();
+                            function.ty = ty.clone();
+                            function.import = Some(module.imports.len());
+                            module.funcs.push(function);
+                            blks_table.push(Vec::new());
+                            (ExportType::FUNCTION, module.funcs.len() - 1)
+                        }
+                        0x01 => {
+                            let initial_size = section.table_type()?;
+                            module.table = Some(Table { name: None, initial_size });
+                            (ExportType::TABLE, 0)
+                        }
+                        0x02 => {
+                            module.memory = Some(Mem::new(None, section.limits()?));
+                            (ExportType::MEMORY, 0)
+                        }
+                        0x03 => {
+                            let (ty, mutable) = section.global_type()?;
+                            module.globals.push(Global { ty, mutable, init: Vec::new() });
+                            (ExportType::GLOBAL, module.globals.len() - 1)
+                        }
+                        byte => return Err(format!("malformed import kind {:#x}", byte)),
+                    };
+                    module.imports.push(Import {
+                        module: module_name,
+                        name,
+                        import_type,
+                        import_ref,
+                    });
+                }
+            }
+            3 => {
+                for _ in 0..section.usize()? {
+                    fn_types.push(section.usize()?);
+                }
+            }
+            4 => {
+                for _ in 0..section.usize()? {
+                    if module.table.is_some() {
+                        return Err(String::from("only one table is allowed per module"));
+                    }
+                    let initial_size = section.table_type()?;
+                    module.table = Some(Table { name: None, initial_size });
+                }
+            }
+            5 => {
+                for _ in 0..section.usize()? {
+                    if module.memory.is_some() {
+                        return Err(String::from("only one memory is allowed per module"));
+                    }
+                    module.memory = Some(Mem::new(None, section.limits()?));
+                }
+            }
+            6 => {
+                for _ in 0..section.usize()? {
+                    let (ty, mutable) = section.global_type()?;
+                    let init = section.const_expr()?;
+                    module.globals.push(Global { ty, mutable, init });
+                }
+            }
+            7 => {
+                for _ in 0..section.usize()? {
+                    let name = section.name()?;
+                    let export_type = match section.byte()? {
+                        0x00 => ExportType::FUNCTION,
+                        0x01 => ExportType::TABLE,
+                        0x02 => ExportType::MEMORY,
+                        0x03 => ExportType::GLOBAL,
+                        byte => return Err(format!("malformed export kind {:#x}", byte)),
+                    };
+                    let export = Export::new(export_type, Label::U32(section.usize()?));
+                    if module.exports.insert(name.clone(), export).is_some() {
+                        return Err(format!("duplicate export name {}", name));
+                    }
+                }
+            }
+            8 => module.start = Some(section.usize()?),
+            9 => {
+                for _ in 0..section.usize()? {
+                    if section.usize()? != 0 {
+                        return Err(String::from("only active element segments of table 0 are supported"));
+                    }
+                    let offset = Some(section.const_expr()?);
+                    let mut funcs = Vec::new();
+                    for _ in 0..section.usize()? {
+                        funcs.push(Label::U32(section.usize()?));
+                    }
+                    module.elems.push(Elem { offset, funcs });
+                }
+            }
+            10 => {
+                let count = section.usize()?;
+                if count != fn_types.len() {
+                    return Err(String::from("function and code section have inconsistent lengths"));
+                }
+                for type_idx in fn_types.iter() {
+                    let size = section.usize()?;
+                    let mut entry = Decoder {
+                        bytes: section.take(size)?,
+                        pos: 0,
+                    };
+                    let mut function = Fn::Empty();
+                    function.ty = module.types.get(*type_idx).ok_or(format!("unknown type {}", type_idx))?.clone();
+                    for _ in 0..entry.usize()? {
+                        let n = entry.usize()?;
+                        let ty = entry.val_type()?;
+                        if function.locals.len() + n > 50_000 {
+                            return Err(String::from("too many locals"));
+                        }
+                        function.locals.extend(std::iter::repeat_n(ty, n));
+                    }
+                    function.code_addr = module.code.len();
+                    blks_table.push(entry.function_body(&mut module.code, &module.types)?);
+                    if !entry.eof() {
+                        return Err(String::from("section size mismatch in function body"));
+                    }
+                    module.funcs.push(function);
+                }
+            }
+            11 => {
+                for _ in 0..section.usize()? {
+                    let offset = match section.usize()? {
+                        0 => Some(section.const_expr()?),
+                        1 => None,
+                        2 => {
+                            section.usize()?;
+                            Some(section.const_expr()?)
+                        }
+                        flag => return Err(format!("malformed data segment flag {}", flag)),
+                    };
+                    let len = section.usize()?;
+                    let bytes = section.take(len)?.to_vec();
+                    module.datas.push(Data { offset, bytes });
+                }
+            }
+            12 => {
+                section.usize()?; // data count, only needed by bulk memory instructions
+            }
+            _ => return Err(format!("malformed section id {}", id)),
+        }
+        if id != 0 && !section.eof() {
+            return Err(format!("section size mismatch in section {}", id));
+        }
+    }
+    if module.funcs.iter().filter(|f| f.import.is_none()).count() != fn_types.len() {
+        return Err(String::from("function and code section have inconsistent lengths"));
+    }
+    Ok((module, blks_table))
+}
+
+/// Where a non-custom section goes in a module, the data count section comes before code and data
+fn section_order(id: u8) -> Option<usize> {
+    [1, 2, 3, 4, 5, 6, 7, 8, 9, 12, 10, 11].iter().position(|section| *section == id)
+}
+
+/// Takes function names from the `name` section, other custom sections are ignored
+fn decode_custom(section: &mut Decoder, module: &mut Mod) -> Result<(), String> {
+    if section.name()? != "name" {
+        return Ok(());
+    }
+    // a malformed name section doesn't invalidate the module
+    let _ = decode_function_names(section, module);
+    Ok(())
+}
+
+fn decode_function_names(section: &mut Decoder, module: &mut Mod) -> Result<(), String> {
+    while !section.eof() {
+        let id = section.byte()?;
+        let size = section.usize()?;
+        let mut subsection = Decoder {
+            bytes: section.take(size)?,
+            pos: 0,
+        };
+        if id != 1 {
+            continue;
+        }
+        for _ in 0..subsection.usize()? {
+            let fn_idx = subsection.usize()?;
+            let name = subsection.name()?;
+            module.funcs_refs.insert(name.clone(), fn_idx);
+            if let Some(function) = module.funcs.get_mut(fn_idx) {
+                function.name = Some(name);
+            }
+        }
+    }
+    Ok(())
+}
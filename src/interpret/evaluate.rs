@@ -1,39 +1,113 @@
-use log::debug;
-
-use crate::interpret::{
-    op::{
-        OP::{self, *},
-    },
-};
+use crate::interpret::op::OP::{self, *};
 
 use super::{
     ast::ast::Label,
-    runtime::{self, set_fn_variables, Evaluator, FnFrame, ValueStack},
+    host::{HostAction, HostFunc},
+    runtime::{set_fn_variables, BlockFrame, Evaluator, FnFrame, FuncRef, RunState, Value},
+    trap::Trap,
 };
 
+/// The opcodes that leave more values on the stack than they take, the others either shrink it
+/// or, like `end` and `br`, only keep values that were already on it
+fn pushes_value(op: &OP) -> bool {
+    matches!(op, I32CONST(_) | I64CONST(_) | F32CONST(_) | F64CONST(_) | LOCGET(_) | GLOGET(_))
+}
+
+/// A call that can't be suspended ran into a host function asking to yield
+fn yielded_outside_run_for() -> Trap {
+    Trap::Host(String::from("a host function yielded, only a call driven by run_for can be suspended"))
+}
+
 impl Evaluator {
-    pub fn run(&mut self) -> Option<i32> {
-        while self.calls.len() != 0 {
-            self.step();
+    /// Runs the pending call to completion and returns all of its results,
+    /// on a trap the call is abandoned so the evaluator can be used again.
+    /// Running out of fuel keeps the call pending, `run` continues it once fuel is added.
+    /// A host function that yields traps, a suspendable call has to be driven by `run_for`
+    pub fn run(&mut self) -> Result<Vec<Value>, Trap> {
+        loop {
+            match self.run_for(usize::MAX) {
+                RunState::Finished(results) => return Ok(results),
+                RunState::Trapped(trap) => return Err(trap),
+                RunState::Yielded if self.is_suspended() => {
+                    self.backtrace = self.frames();
+                    self.abandon();
+                    return Err(yielded_outside_run_for());
+                }
+                RunState::Yielded => {}
+            }
+        }
+    }
+    /// Executes at most `n_steps` opcodes of the pending call, a yielded call
+    /// continues with the next `run_for` (after `resume` if a host function suspended it)
+    pub fn run_for(&mut self, n_steps: usize) -> RunState {
+        if self.is_suspended() {
+            return RunState::Yielded;
+        }
+        let base = match self.calls.first() {
+            Some(frame) => frame.stack_height,
+            None => self.stack.len(),
+        };
+        for _ in 0..n_steps {
+            if self.calls.is_empty() {
+                break;
+            }
+            if let Err(trap) = self.step() {
+                if trap != Trap::OutOfFuel {
+                    self.calls.clear();
+                    self.stack.truncate(base);
+                    self.pc = 0;
+                }
+                return RunState::Trapped(trap);
+            }
+            if self.is_suspended() {
+                return RunState::Yielded;
+            }
         }
-        let result = self.stack.pop();
-        if result.is_some() {
-            return result;
+        if self.calls.is_empty() {
+            RunState::Finished(self.stack.split_off(base.min(self.stack.len())))
+        } else {
+            RunState::Yielded
         }
-        return None;
     }
-    pub fn step(&mut self) {
+    pub fn step(&mut self) -> Result<(), Trap> {
         let next_op = self.next_opcode().clone();
+        if let Some(fuel) = self.fuel {
+            let cost = self.cost_table.cost(&next_op);
+            if fuel < cost {
+                self.backtrace = self.frames();
+                return Err(Trap::OutOfFuel);
+            }
+            self.fuel = Some(fuel - cost);
+        }
+        let (pc, depth) = (self.pc, self.calls.len());
         self.pc += 1;
 
-        self.evaluate_bytecode(next_op);
+        // checked before the opcode runs, so the stack never grows past its limit
+        let evaluated = if pushes_value(&next_op) && self.stack.len() >= self.max_stack_size {
+            Err(Trap::OperandStackExhausted)
+        } else {
+            match self.tracer.clone() {
+                Some(tracer) => self.evaluate_traced(&tracer, pc, next_op),
+                None => self.evaluate_bytecode(next_op),
+            }
+        };
+        if let Err(trap) = evaluated {
+            self.backtrace = self.frames();
+            // the opcode that trapped unless it entered or left a function
+            if self.calls.len() == depth {
+                self.backtrace[depth - 1] = self.frame(depth - 1, pc);
+            }
+            self.backtrace.append(&mut self.callee_frames);
+            return Err(trap);
+        }
+        Ok(())
     }
-    pub fn evaluate_bytecode(&mut self, opcode: OP) {
+    pub fn evaluate_bytecode(&mut self, opcode: OP) -> Result<(), Trap> {
         match opcode {
             I32ADD => {
-                if let Some(rhs) = self.stack.pop() {
-                    if let Some(lhs) = self.stack.pop() {
-                        self.stack.push(lhs + rhs);
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
+                        self.stack.push(Value::I32(lhs.wrapping_add(rhs)));
                     } else {
                         panic!("top of stack isn't type i32, need lhs to add")
                     }
@@ -42,10 +116,10 @@ impl Evaluator {
                 }
             },
             I32MUL => {
-                if let Some(rhs) = self.stack.pop() {
-                    if let Some(lhs) = self.stack.pop() {
-                        let val = lhs * rhs;
-                        self.stack.push(val);
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
+                        let val = lhs.wrapping_mul(rhs);
+                        self.stack.push(Value::I32(val));
                     } else {
                         panic!("top of stack isn't type i32, need lhs to multiply")
                     }
@@ -54,13 +128,16 @@ impl Evaluator {
                 }
             },
             I32DIVS => {
-                if let Some(rhs) = self.stack.pop() {
-                    if rhs == 0{
-                        panic!("rhs is 0 cannot divide by zero")
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if rhs == 0 {
+                        return Err(Trap::IntegerDivideByZero);
                     }
-                    if let Some(lhs) = self.stack.pop() {
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
+                        if lhs == i32::MIN && rhs == -1 {
+                            return Err(Trap::IntegerOverflow);
+                        }
                         let val = lhs / rhs;
-                        self.stack.push(val);
+                        self.stack.push(Value::I32(val));
                     } else {
                         panic!("top of stack isn't type i32, need lhs to divide")
                     }
@@ -69,12 +146,54 @@ impl Evaluator {
                 }
             },
             I32CONST(n) => {
-                self.stack.push(n);
+                self.stack.push(Value::I32(n));
+            },
+            I64CONST(n) => {
+                self.stack.push(Value::I64(n));
+            },
+            F32CONST(n) => {
+                self.stack.push(Value::F32(n));
+            },
+            F64CONST(n) => {
+                self.stack.push(Value::F64(n));
+            },
+            I64SUB => {
+                if let Some(Value::I64(rhs)) = self.stack.pop() {
+                    if let Some(Value::I64(lhs)) = self.stack.pop() {
+                        self.stack.push(Value::I64(lhs.wrapping_sub(rhs)));
+                    } else {
+                        panic!("top of stack isn't type i64, need lhs to subtract")
+                    }
+                } else {
+                    panic!("top of stack isn't type i64, need rhs to subtract")
+                }
+            },
+            I64MUL => {
+                if let Some(Value::I64(rhs)) = self.stack.pop() {
+                    if let Some(Value::I64(lhs)) = self.stack.pop() {
+                        self.stack.push(Value::I64(lhs.wrapping_mul(rhs)));
+                    } else {
+                        panic!("top of stack isn't type i64, need lhs to multiply")
+                    }
+                } else {
+                    panic!("top of stack isn't type i64, need rhs to multiply")
+                }
+            },
+            I64ADD => {
+                if let Some(Value::I64(rhs)) = self.stack.pop() {
+                    if let Some(Value::I64(lhs)) = self.stack.pop() {
+                        self.stack.push(Value::I64(lhs.wrapping_add(rhs)));
+                    } else {
+                        panic!("top of stack isn't type i64, need lhs to add")
+                    }
+                } else {
+                    panic!("top of stack isn't type i64, need rhs to add")
+                }
             },
             I32SUB => {
-                if let Some(rhs) = self.stack.pop() {
-                    if let Some(lhs) = self.stack.pop() {
-                        self.stack.push(lhs - rhs);
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
+                        self.stack.push(Value::I32(lhs.wrapping_sub(rhs)));
                     } else {
                         panic!("top of stack isn't type i32, need lhs to subtract")
                     }
@@ -83,10 +202,10 @@ impl Evaluator {
                 }
             },
             I32AND => {
-                if let Some(rhs) = self.stack.pop() {
-                    if let Some(lhs) = self.stack.pop() {
-                        let val = (lhs != 0 && rhs !=0);
-                        self.stack.push(val as i32);
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
+                        let val = lhs != 0 && rhs != 0;
+                        self.stack.push(Value::I32(val as i32));
                     } else {
                         panic!("top of stack isn't type i32, need lhs for logical and")
                     }
@@ -95,10 +214,10 @@ impl Evaluator {
                 }
             },
             I32OR => {
-                if let Some(rhs) = self.stack.pop() {
-                    if let Some(lhs) = self.stack.pop() {
-                        let val = (lhs != 0 || rhs !=0);
-                        self.stack.push(val as i32);
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
+                        let val = lhs != 0 || rhs != 0;
+                        self.stack.push(Value::I32(val as i32));
                     } else {
                         panic!("top of stack isn't type i32, need lhs for logical and")
                     }
@@ -107,10 +226,10 @@ impl Evaluator {
                 }
             },
             I32LTS => {
-                if let Some(rhs) = self.stack.pop() {
-                    if let Some(lhs) = self.stack.pop() {
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
                         let val = (lhs < rhs) as i32;
-                        self.stack.push(val);
+                        self.stack.push(Value::I32(val));
                     } else {
                         panic!("top of stack isn't type i32, need lhs to compare less than")
                     }
@@ -119,10 +238,10 @@ impl Evaluator {
                 }
             },
             I32EQ => {
-                if let Some(rhs) = self.stack.pop() {
-                    if let Some(lhs) = self.stack.pop() {
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
                         let val = (lhs == rhs) as i32;
-                        self.stack.push(val);
+                        self.stack.push(Value::I32(val));
                     } else {
                         panic!("top of stack isn't type i32, need lhs to compare equal")
                     }
@@ -131,10 +250,10 @@ impl Evaluator {
                 }
             },
             I32GES => {
-                if let Some(rhs) = self.stack.pop() {
-                    if let Some(lhs) = self.stack.pop() {
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
                         let val = (lhs >= rhs) as i32;
-                        self.stack.push(val);
+                        self.stack.push(Value::I32(val));
                     } else {
                         panic!("top of stack isn't type i32, need lhs to compare greater equal")
                     }
@@ -143,10 +262,10 @@ impl Evaluator {
                 }
             },
             I32GTS => {
-                if let Some(rhs) = self.stack.pop() {
-                    if let Some(lhs) = self.stack.pop() {
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
                         let val = (lhs > rhs) as i32;
-                        self.stack.push(val);
+                        self.stack.push(Value::I32(val));
                     } else {
                         panic!("top of stack isn't type i32, need lhs to compare greater ")
                     }
@@ -155,10 +274,10 @@ impl Evaluator {
                 }
             },
             I32LES => {
-                if let Some(rhs) = self.stack.pop() {
-                    if let Some(lhs) = self.stack.pop() {
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
                         let val = (lhs <= rhs) as i32;
-                        self.stack.push(val);
+                        self.stack.push(Value::I32(val));
                     } else {
                         panic!("top of stack isn't type i32, need lhs to compare less equal")
                     }
@@ -167,18 +286,21 @@ impl Evaluator {
                 }
             },
             I32EQZ => {
-                if let Some(n) = self.stack.pop() {
+                if let Some(Value::I32(n)) = self.stack.pop() {
                     let val = (n == 0) as i32;
-                    self.stack.push(val)
+                    self.stack.push(Value::I32(val))
                 } else {
                     panic!("top of stack isn't type i32, need rhs to compare equal")
                 }
             },
             I32REMU => {
-                if let Some(rhs) = self.stack.pop() {
-                    if let Some(lhs) = self.stack.pop() {
-                        let val = lhs % rhs;
-                        self.stack.push(val);
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if rhs == 0 {
+                        return Err(Trap::IntegerDivideByZero);
+                    }
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
+                        let val = (lhs as u32 % rhs as u32) as i32;
+                        self.stack.push(Value::I32(val));
                     } else {
                         panic!("top of stack isn't type i32, need lhs to find remainder")
                     }
@@ -186,8 +308,62 @@ impl Evaluator {
                     panic!("top of stack isn't type i32, need rhs to find remainder")
                 }
             },
+            I32REMS => {
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if rhs == 0 {
+                        return Err(Trap::IntegerDivideByZero);
+                    }
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
+                        // i32::MIN % -1 overflows in Rust, the spec defines it as 0
+                        self.stack.push(Value::I32(lhs.wrapping_rem(rhs)));
+                    } else {
+                        panic!("top of stack isn't type i32, need lhs to find remainder")
+                    }
+                } else {
+                    panic!("top of stack isn't type i32, need rhs to find remainder")
+                }
+            },
+            I32DIVU => {
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if rhs == 0 {
+                        return Err(Trap::IntegerDivideByZero);
+                    }
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
+                        let val = (lhs as u32 / rhs as u32) as i32;
+                        self.stack.push(Value::I32(val));
+                    } else {
+                        panic!("top of stack isn't type i32, need lhs to divide")
+                    }
+                } else {
+                    panic!("top of stack isn't type i32, need rhs to divide")
+                }
+            },
+            I32NE => {
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
+                        let val = (lhs != rhs) as i32;
+                        self.stack.push(Value::I32(val));
+                    } else {
+                        panic!("top of stack isn't type i32, need lhs to compare not equal")
+                    }
+                } else {
+                    panic!("top of stack isn't type i32, need rhs to compare not equal")
+                }
+            },
+            I32XOR => {
+                if let Some(Value::I32(rhs)) = self.stack.pop() {
+                    if let Some(Value::I32(lhs)) = self.stack.pop() {
+                        self.stack.push(Value::I32(lhs ^ rhs));
+                    } else {
+                        panic!("top of stack isn't type i32, need lhs to xor")
+                    }
+                } else {
+                    panic!("top of stack isn't type i32, need rhs to xor")
+                }
+            },
+            NOP => {},
             LOCGET(idx) => {
-                let n: i32 = self.calls[self.calls.len() - 1].locals[idx];
+                let n = self.calls[self.calls.len() - 1].locals[idx];
                 self.stack.push(n);
             },
             LOCSET(idx) => {
@@ -246,75 +422,106 @@ impl Evaluator {
                     panic!("need to see a constant to set global")
                 }
             },
-            BLK(blk_idx) => {
+            BLK(blk_idx) | LOOP(blk_idx) => {
+                self.enter_block(blk_idx);
             },
-            LOOP(blk_idx) => {
+            IF(blk_idx) => {
+                if let Some(Value::I32(condition)) = self.stack.pop() {
+                    self.enter_block(blk_idx);
+                    if condition == 0 {
+                        let blk = &self.blks_table[self.current_fn()][blk_idx];
+                        self.pc = match blk.else_pc {
+                            Some(else_pc) => else_pc + 1,
+                            None => blk.next_pc,
+                        };
+                    }
+                } else {
+                    panic!("should have seen a condition for if");
+                }
+            },
+            ELSE(blk_idx) => {
+                // the then arm finished, skip the else arm and close the if at its end
+                self.pc = self.blks_table[self.current_fn()][blk_idx].next_pc;
             },
             BR(blk_idx) => {
-                let fn_idx = self.calls[self.calls.len() - 1].fn_idx;
-                self.pc = self.blks_table[fn_idx][blk_idx].next_pc;
+                self.branch(blk_idx)?;
             },
             BRIF(blk_idx) => {
-                if let Some(condition) = self.stack.pop() {
-                    if condition <= 0 {
-                        return;
+                if let Some(Value::I32(condition)) = self.stack.pop() {
+                    if condition == 0 {
+                        return Ok(());
                     }
-                    let fn_idx = self.calls[self.calls.len() - 1].fn_idx;
-                    self.pc = self.blks_table[fn_idx][blk_idx].next_pc;
+                    self.branch(blk_idx)?;
                 } else {
                     panic!("should have seen a condition for br_if");
                 }
             },
             END => {
+                let this_fn_idx = self.calls.len() - 1;
+                self.calls[this_fn_idx].blocks.pop();
             },
             CALL(fn_label) => {
-                let mut new_fn_idx;
-                match fn_label {
-                    Label::REF(id) => {
-                        if id.as_str() == "print" {
-                            let val = self.stack.pop();
-                            match val {
-                                Some(val) => {
-                                    println!("{}", val);
-                                    return;
-                                }
-                                None => {}
-                            }
-                        }
-                        new_fn_idx = self.module.get_fn_idx(&id)
+                let new_fn_idx = match fn_label {
+                    Label::REF(id) => self.module.get_fn_idx(&id),
+                    Label::U32(fn_idx) => fn_idx,
+                };
+                self.check_call()?;
+                self.call_fn(new_fn_idx)?;
+            },
+            CALLIND(type_idx) => {
+                if let Some(Value::I32(elem_idx)) = self.stack.pop() {
+                    let func = match self.table.get(elem_idx as u32 as usize) {
+                        Some(Some(func)) => func.clone(),
+                        Some(None) => return Err(Trap::UninitializedElement),
+                        None => return Err(Trap::UndefinedElement),
+                    };
+                    let ty = match &func {
+                        FuncRef::Local(fn_idx) => &self.module.funcs[*fn_idx].ty,
+                        FuncRef::Host(host_fn) => &host_fn.ty,
+                    };
+                    if *ty != self.module.types[type_idx] {
+                        return Err(Trap::IndirectCallTypeMismatch);
                     }
-                    Label::U32(fn_idx) => new_fn_idx = fn_idx,
+                    self.check_call()?;
+                    match func {
+                        FuncRef::Local(fn_idx) => self.call_fn(fn_idx)?,
+                        FuncRef::Host(host_fn) => self.call_host_fn(&host_fn, None)?,
+                    }
+                } else {
+                    panic!("should have seen a table index for call_indirect");
                 }
-                let new_fn = { self.module.funcs.get(new_fn_idx).unwrap() };
-                let new_locals = set_fn_variables(new_fn, &mut self.stack);
-                let new_fn_frame = FnFrame::new(new_fn_idx, new_locals, self.pc);
-                self.pc = new_fn.code_addr;
-                self.calls.push(new_fn_frame);
             },
             RET => {
                 let this_fn_frame = self.calls.pop().unwrap();
+                let arity = self.module.funcs[this_fn_frame.fn_idx].ty.results.len();
+                self.unwind_stack(this_fn_frame.stack_height, arity);
                 self.pc = this_fn_frame.ret;
             },
+            UNR => return Err(Trap::Unreachable),
             DROP => {
                 self.stack.pop();
             },
             I32LOAD => {
-                if let Some(offset) = self.stack.pop() {
-                    let end = offset as usize + 4;
-                    let slice = &self.memory.bytes[offset as usize..end];
+                if let Some(Value::I32(offset)) = self.stack.pop() {
+                    let start = offset as u32 as usize;
+                    let slice = match self.memory.bytes.get(start..start + 4) {
+                        Some(slice) => slice,
+                        None => return Err(Trap::MemoryOutOfBounds),
+                    };
                     let n = i32::from_le_bytes(slice.try_into().unwrap());
-                    self.stack.push(n);
+                    self.stack.push(Value::I32(n));
                 } else {
                     panic!("stack should have an index to access memory from which to load")
                 }
             },
             I32STORE => {
-                if let Some(val) = self.stack.pop() {
-                    if let Some(offset) = self.stack.pop() {
-                        let end = (offset + 4) as usize;
-                        self.memory
-                            .bytes
-                            .splice(offset as usize..end, val.to_le_bytes());
+                if let Some(Value::I32(val)) = self.stack.pop() {
+                    if let Some(Value::I32(offset)) = self.stack.pop() {
+                        let start = offset as u32 as usize;
+                        match self.memory.bytes.get_mut(start..start + 4) {
+                            Some(slice) => slice.copy_from_slice(&val.to_le_bytes()),
+                            None => return Err(Trap::MemoryOutOfBounds),
+                        }
                     } else {
                         panic!("stack should have an index to access memory for which to store")
                     }
@@ -322,14 +529,14 @@ impl Evaluator {
                     panic!("Should have seen a value to store in memory")
                 }
             },
-            _ => panic!("opcode not yet implemented in evaluator"),
         }
+        Ok(())
     }
 
     pub fn call(&mut self, label: &Label) {
-        let mut fn_idx;
+        let fn_idx;
         match label {
-            Label::REF(fn_name) => fn_idx = self.module.get_fn_idx(&fn_name),
+            Label::REF(fn_name) => fn_idx = self.module.get_fn_idx(fn_name),
             Label::U32(idx) => {
                 if *idx < self.module.funcs.len() {
                     fn_idx = *idx
@@ -338,10 +545,143 @@ impl Evaluator {
                 }
             }
         }
-        let new_fn = { self.module.funcs.get(fn_idx).unwrap() };
+        if self.module.funcs[fn_idx].import.is_some() {
+            panic!("imported function {} can only be called from guest code", fn_idx)
+        }
+        let new_fn = &self.module.funcs[fn_idx];
         let new_locals = set_fn_variables(new_fn, &mut self.stack);
-        let new_fn_frame = FnFrame::new(fn_idx, new_locals, self.pc);
+        let new_fn_frame = FnFrame::new(fn_idx, new_locals, self.pc, self.stack.len());
+        self.pc = new_fn.code_addr;
         self.calls.push(new_fn_frame);
+    }
+
+    /// Calls a function to completion, also when it's an import provided by the host
+    pub fn invoke(&mut self, fn_idx: usize, args: Vec<Value>) -> Result<Vec<Value>, Trap> {
+        self.backtrace.clear();
+        if self.module.funcs[fn_idx].import.is_none() {
+            self.add_parameters(args);
+            self.call(&Label::U32(fn_idx));
+            return self.run();
+        }
+        let base = self.stack.len();
+        self.add_parameters(args);
+        if let Err(trap) = self.call_fn(fn_idx) {
+            self.stack.truncate(base);
+            self.backtrace = std::mem::take(&mut self.callee_frames);
+            return Err(trap);
+        }
+        if self.is_suspended() {
+            self.suspended = None;
+            self.stack.truncate(base);
+            return Err(yielded_outside_run_for());
+        }
+        Ok(self.stack.split_off(base))
+    }
+
+    fn check_call(&self) -> Result<(), Trap> {
+        if self.take_interrupt() {
+            return Err(Trap::Interrupted);
+        }
+        if self.calls.len() >= self.max_call_depth {
+            return Err(Trap::CallStackExhausted);
+        }
+        Ok(())
+    }
+
+    fn call_fn(&mut self, fn_idx: usize) -> Result<(), Trap> {
+        if let Some(import_idx) = self.module.funcs[fn_idx].import {
+            return self.call_host(fn_idx, import_idx);
+        }
+        let new_fn = &self.module.funcs[fn_idx];
+        let new_locals = set_fn_variables(new_fn, &mut self.stack);
+        let new_fn_frame = FnFrame::new(fn_idx, new_locals, self.pc, self.stack.len());
         self.pc = new_fn.code_addr;
+        self.calls.push(new_fn_frame);
+        Ok(())
+    }
+
+    fn call_host(&mut self, fn_idx: usize, import_idx: usize) -> Result<(), Trap> {
+        let host_fn = match &self.host_funcs[import_idx] {
+            Some(host_fn) => host_fn.clone(),
+            None => {
+                let import = &self.module.imports[import_idx];
+                return Err(Trap::Host(format!("unresolved import {}.{}", import.module, import.name)));
+            }
+        };
+        self.call_host_fn(&host_fn, Some(fn_idx))
+    }
+
+    /// Runs a host function on the arguments at the top of the stack, `fn_idx` is
+    /// the import it satisfies and `None` for a table element from another instance
+    fn call_host_fn(&mut self, host_fn: &HostFunc, fn_idx: Option<usize>) -> Result<(), Trap> {
+        let n_params = host_fn.ty.params.len();
+        let args = self.stack.split_off(self.stack.len() - n_params);
+        let name = match fn_idx.and_then(|fn_idx| self.module.funcs[fn_idx].import) {
+            Some(import_idx) => {
+                let import = &self.module.imports[import_idx];
+                format!("host function {}.{}", import.module, import.name)
+            }
+            None => String::from("table element"),
+        };
+        match host_fn.call(self, &args)? {
+            HostAction::Return(results) => {
+                let types: Vec<_> = results.iter().map(|value| value.ty()).collect();
+                if types != host_fn.ty.results {
+                    return Err(Trap::Host(format!(
+                        "{} returned {:?}, expected {:?}",
+                        name, types, host_fn.ty.results
+                    )));
+                }
+                if self.stack.len() + results.len() > self.max_stack_size {
+                    return Err(Trap::OperandStackExhausted);
+                }
+                self.stack.extend(results);
+            }
+            HostAction::Yield => match fn_idx {
+                Some(fn_idx) => self.suspended = Some(fn_idx),
+                None => return Err(Trap::Host(format!("{} can't yield", name))),
+            },
+        }
+        Ok(())
+    }
+
+    fn current_fn(&self) -> usize {
+        self.calls[self.calls.len() - 1].fn_idx
+    }
+
+    fn enter_block(&mut self, blk_idx: usize) {
+        let n_params = self.blks_table[self.current_fn()][blk_idx].ty.params.len();
+        let height = self.stack.len().saturating_sub(n_params);
+        let this_fn_idx = self.calls.len() - 1;
+        self.calls[this_fn_idx].blocks.push(BlockFrame { blk_idx, height });
+    }
+
+    /// Keeps the top `arity` values and drops everything else above `height`
+    fn unwind_stack(&mut self, height: usize, arity: usize) {
+        let keep_from = self.stack.len().saturating_sub(arity).max(height);
+        let kept = self.stack.split_off(keep_from);
+        self.stack.truncate(height);
+        self.stack.extend(kept);
+    }
+
+    fn branch(&mut self, blk_idx: usize) -> Result<(), Trap> {
+        let this_fn_idx = self.calls.len() - 1;
+        let blk = &self.blks_table[self.calls[this_fn_idx].fn_idx][blk_idx];
+        let (is_loop, next_pc, arity) = (blk.is_loop, blk.next_pc, blk.label_arity());
+        if is_loop && self.take_interrupt() {
+            return Err(Trap::Interrupted);
+        }
+        let frame = &mut self.calls[this_fn_idx];
+        match frame.blocks.iter().rposition(|b| b.blk_idx == blk_idx) {
+            Some(depth) => {
+                let height = frame.blocks[depth].height;
+                frame.blocks.truncate(depth);
+                self.unwind_stack(height, arity);
+            }
+            None => panic!("branch to block {} which isn't entered", blk_idx),
+        }
+        // a loop label re-enters the loop through its LOOP opcode, a block label resumes after its END
+        self.pc = if is_loop { next_pc } else { next_pc + 1 };
+        Ok(())
     }
 }
@@ -0,0 +1,44 @@
+use std::fmt;
+use std::sync::Arc;
+
+use super::{
+    ast::ast::FuncType,
+    runtime::{Evaluator, Value},
+    trap::Trap,
+};
+
+/// What a host function wants the guest to see after it ran
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostAction {
+    Return(Vec<Value>),
+    /// Suspends the guest, `resume` later supplies the results
+    Yield,
+}
+
+type HostCallback = dyn Fn(&mut Evaluator, &[Value]) -> Result<HostAction, Trap> + Send + Sync;
+
+/// A function implemented by the embedder that satisfies a function import
+#[derive(Clone)]
+pub struct HostFunc {
+    pub ty: FuncType,
+    callback: Arc<HostCallback>,
+}
+impl HostFunc {
+    pub fn new<F>(ty: FuncType, callback: F) -> Self
+    where
+        F: Fn(&mut Evaluator, &[Value]) -> Result<HostAction, Trap> + Send + Sync + 'static,
+    {
+        Self {
+            ty,
+            callback: Arc::new(callback),
+        }
+    }
+    pub fn call(&self, evaluator: &mut Evaluator, args: &[Value]) -> Result<HostAction, Trap> {
+        (self.callback)(evaluator, args)
+    }
+}
+impl fmt::Debug for HostFunc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HostFunc({:?})", self.ty)
+    }
+}
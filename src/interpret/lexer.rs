@@ -1,8 +1,8 @@
 
-use logos::{ Logos, Span};
+use logos::Logos;
 use std::ops::Deref;
 
-#[derive(Logos, Debug, PartialEq)]
+#[derive(Logos, Debug, Clone, PartialEq)]
 #[logos(skip r#";;[ a-zA-z0-9!#$%&`*+-./:<>=?@\^_'()|~]+"#)]
 #[logos(skip r#";[ a-zA-z0-9!#$%&`*+-./:<>=?@\^_'()|~\n]+;"#)]
 #[logos(skip r"[\s\t\n\f]+")]
@@ -13,10 +13,16 @@ pub enum Token {
     #[regex(r#"\$[a-zA-z0-9!#$%&`*+-./:<->=?@\^_'\+|~]+"#,  |lex| lex.slice()[1..].to_owned() )]
     Id(String),
 
-    #[regex(r#"-?[0-9]+"#, |lex| lex.slice().parse::<i32>().unwrap())]
-    Integer(i32),
+    #[regex(r#"[+-]?(0x[0-9a-fA-F][0-9a-fA-F_]*|[0-9][0-9_]*)"#, |lex| parse_integer(lex.slice()))]
+    Integer(i64),
 
-    #[regex(r#""[a-zA-z0-9!#$%&`*+-./:<>=?@\^_'|~]+""#, |lex| lex.slice()[1..lex.slice().len()-1].to_owned() )]
+    #[regex(r#"[+-]?[0-9][0-9_]*\.[0-9_]*([eE][+-]?[0-9][0-9_]*)?"#, |lex| parse_float(lex.slice()))]
+    #[regex(r#"[+-]?[0-9][0-9_]*[eE][+-]?[0-9][0-9_]*"#, |lex| parse_float(lex.slice()))]
+    #[regex(r#"[+-](inf|nan)"#, |lex| parse_float(lex.slice()))]
+    Float(f64),
+
+    // the raw text between the quotes, escapes are checked here and resolved by `unescape`
+    #[regex(r#""([^"\\]|\\.)*""#, |lex| lex_string(lex.slice()) )]
     String(String),
 
     #[token("(")]
@@ -38,25 +44,116 @@ impl Deref for Token {
     }
 }
 
-type Error = (String, Span);
+/// Integers are kept as 64 bits, `i32.const` wraps them and hex literals may use all 64 bits
+pub fn parse_integer(slice: &str) -> Option<i64> {
+    let digits = slice.replace('_', "");
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(rest) => (true, rest.to_owned()),
+        None => (false, digits.trim_start_matches('+').to_owned()),
+    };
+    let magnitude = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<u64>().ok()?,
+    };
+    if negative {
+        Some((magnitude as i64).wrapping_neg())
+    } else {
+        Some(magnitude as i64)
+    }
+}
 
-type Result<T> = std::result::Result<T, Error>;
+fn parse_float(slice: &str) -> Option<f64> {
+    slice.replace('_', "").parse::<f64>().ok()
+}
 
-pub fn get_tokens(program: &str) -> Vec<Token> {
+/// The text between the quotes of a string literal, `None` makes a malformed escape a lex error
+fn lex_string(slice: &str) -> Option<String> {
+    let raw = &slice[1..slice.len() - 1];
+    unescape(raw).ok().map(|_| raw.to_owned())
+}
+
+/// Resolves the escapes of a string literal into the bytes it denotes
+pub fn unescape(raw: &str) -> std::result::Result<Vec<u8>, String> {
+    let raw = raw.as_bytes();
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < raw.len() {
+        if raw[i] != b'\\' || i + 1 == raw.len() {
+            bytes.push(raw[i]);
+            i += 1;
+            continue;
+        }
+        match raw[i + 1] {
+            b'n' => bytes.push(b'\n'),
+            b't' => bytes.push(b'\t'),
+            b'r' => bytes.push(b'\r'),
+            b'u' => {
+                // `\u{hex}`, the braces are required
+                let end = raw[i..].iter().position(|b| *b == b'}').map(|end| i + end);
+                let c = match (raw.get(i + 2), end) {
+                    (Some(b'{'), Some(end)) => std::str::from_utf8(&raw[i + 3..end])
+                        .ok()
+                        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                        .and_then(char::from_u32),
+                    _ => None,
+                };
+                let c = c.ok_or_else(|| {
+                    let escape = &raw[i..end.map_or(raw.len(), |end| end + 1)];
+                    format!("malformed unicode escape {}", String::from_utf8_lossy(escape))
+                })?;
+                let mut buf = [0; 4];
+                bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+                i = end.unwrap() + 1;
+                continue;
+            }
+            c if c.is_ascii_hexdigit() && i + 2 < raw.len() => {
+                let hex = std::str::from_utf8(&raw[i + 1..i + 3]).unwrap();
+                bytes.push(u8::from_str_radix(hex, 16).unwrap_or(0));
+                i += 3;
+                continue;
+            }
+            c => bytes.push(c),
+        }
+        i += 2;
+    }
+    Ok(bytes)
+}
+
+/// Where a token starts in the source, line and column count from 1
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Position {
+    pub line: usize,
+    pub col: usize,
+}
+
+/// The tokens of a program and the position of each
+pub fn get_tokens(program: &str) -> Result<(Vec<Token>, Vec<Position>), String> {
     let mut lexer = Token::lexer(program);
     let mut tokens: Vec<Token> = Vec::new();
+    let mut positions: Vec<Position> = Vec::new();
+    let (mut line, mut line_start, mut scanned) = (1, 0, 0);
 
     while let Some(token) = lexer.next() {
+        let start = lexer.span().start;
+        for (offset, _) in program[scanned..start].match_indices('\n') {
+            line += 1;
+            line_start = scanned + offset + 1;
+        }
+        scanned = start;
         match token {
-            Ok(tok) => tokens.push(tok),
+            Ok(tok) => {
+                tokens.push(tok);
+                positions.push(Position {
+                    line,
+                    col: program[line_start..start].chars().count() + 1,
+                });
+            }
             Err(_) => {
-                panic!(
-                    "syntax error because of an unexpcted token at  {:?}",
-                    lexer.span()
-                );
+                let col = program[line_start..start].chars().count() + 1;
+                return Err(format!("syntax error because of an unexpcted token at {}:{}", line, col));
             }
         }
     }
-    tokens
+    Ok((tokens, positions))
 }
 
@@ -2,6 +2,12 @@ pub mod ast;
 pub mod op;
 pub mod evaluate;
 pub mod runtime;
+pub mod trap;
+pub mod host;
+pub mod snapshot;
+pub mod decoder;
 pub mod parser;
 pub mod scanner;
 pub mod lexer;
+pub mod validate;
+pub mod trace;
@@ -7,7 +7,10 @@ pub enum OP {
     NOP = 0x01,
     BLK(usize) = 0x02,
     LOOP(usize) = 0x03,
+    IF(usize) = 0x04,
+    ELSE(usize) = 0x05,
     CALL(Label) = 0x10,
+    CALLIND(usize) = 0x11, // type index of the expected signature
     END = 0x0b,
     BR(usize) = 0x0C,
     BRIF(usize) = 0x0D,
@@ -22,11 +25,15 @@ pub enum OP {
     I32STORE = 0x36,
     I32CONST(i32) = 0x41,
     I64CONST(i64) = 0x42,
+    F32CONST(f32) = 0x43,
+    F64CONST(f64) = 0x44,
     I32NE = 0x47,
     I32LTS = 0x48,
     I32ADD = 0x6a,
     I32MUL = 0x6c,
     I64ADD = 0x7c,
+    I64SUB = 0x7d,
+    I64MUL = 0x7e,
     I32REMU = 0x70,
     I32REMS = 0x6F,
     I32AND = 0x71,
@@ -42,3 +49,30 @@ pub enum OP {
     I32SUB = 0x6b,
 }
 
+/// The instruction groups of the spec. Checks and costs that apply to a whole group go by the
+/// kind, so an opcode has to be placed in one before it can be validated or metered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpKind {
+    Control,
+    Call,
+    Parametric,
+    Variable,
+    Memory,
+    Numeric,
+}
+
+impl OP {
+    pub fn kind(&self) -> OpKind {
+        use OP::*;
+        match self {
+            UNR | NOP | BLK(_) | LOOP(_) | IF(_) | ELSE(_) | END | BR(_) | BRIF(_) | RET => OpKind::Control,
+            CALL(_) | CALLIND(_) => OpKind::Call,
+            DROP => OpKind::Parametric,
+            LOCGET(_) | LOCSET(_) | LOCTEE(_) | GLOGET(_) | GLOSET(_) => OpKind::Variable,
+            I32LOAD | I32STORE => OpKind::Memory,
+            I32CONST(_) | I64CONST(_) | F32CONST(_) | F64CONST(_) | I32NE | I32LTS | I32ADD | I32MUL | I64ADD
+            | I64SUB | I64MUL | I32REMU | I32REMS | I32AND | I32OR | I32DIVU | I32DIVS | I32EQZ | I32EQ | I32GTS
+            | I32LES | I32GES | I32XOR | I32SUB => OpKind::Numeric,
+        }
+    }
+}
@@ -1,73 +1,158 @@
-use crate::interpret::lexer::{get_tokens, Token};
+use crate::interpret::lexer::{get_tokens, unescape, Position, Token};
 use crate::interpret::ast::ast::{
-    Block, Export, ExportType, Fn, Global, Label, Mem, Mod, Parser,
+    Block, ConstExpr, Data, Elem, Export, ExportType, Fn, FuncType, Global, Import, Label, Mem,
+    Mod, Parser, SourceLoc, SourceMap, Table, ValType,
 };
 use crate::interpret::op::OP;
 use crate::interpret::op::OP::*;
 use crate::interpret::scanner::Scanner;
-use log::debug;
+use crate::interpret::snapshot::source_hash;
 use std::collections::HashMap;
 
-use core::{panic};
-
 impl Parser {
-    pub fn parse_fn(&mut self) -> Fn {
-        let fn_code_addr = self.code_memory.len();
-        let mut name: Option<String> = None;
+    pub fn parse_fn(&mut self, mut import: Option<(String, String)>) -> Result<Fn, String> {
         let mut locals_map: HashMap<String, usize> = HashMap::new();
         let mut function = Fn::Empty();
-        let mut n_locals = 0;
-        function.code_addr = fn_code_addr;
+        let fn_idx = self.functions.len();
+        function.code_addr = self.code_memory.len();
         if let Some(Token::Id(id)) = self.scanner.peek1() {
             function.name = Some(id.clone());
             self.scanner.advance();
         }
-        while self.parse_var("param", &mut locals_map, n_locals) {
-            function.args += 1;
-            n_locals += 1;
+        if import.is_none() {
+            self.parse_inline_exports(ExportType::FUNCTION, fn_idx)?;
+            import = self.parse_inline_import()?;
+        }
+        function.ty = self.parse_typeuse(&mut locals_map)?;
+        if let Some(names) = import {
+            self.add_import(names, ExportType::FUNCTION, fn_idx)?;
+            function.import = Some(self.imports.len() - 1);
+            match self.scanner.get_next_token() {
+                Some(Token::RParan) => {}
+                _ => return Err(String::from("imported function shouldn't have a body")),
+            }
+            return Ok(function);
         }
-        function.result = self.parse_result();
-        while self.parse_var("local", &mut locals_map, n_locals) {
-            function.locals += 1;
-            n_locals += 1;
+        let mut n_locals = function.ty.params.len();
+        while let Some(types) = self.parse_var("local", &mut locals_map, n_locals)? {
+            n_locals += types.len();
+            function.locals.extend(types);
         }
-        while self.parse_instruction(&locals_map) {}
-        self.code_memory.push(RET);
-        //self.parse_instruction2();
+        self.parse_instructions(&locals_map)?;
+        // the implicit return is at the closing parenthesis
+        self.emit(RET, self.scanner.next_position());
         match self.scanner.get_next_token() {
             Some(Token::RParan) => {
             }
-            _ => panic!("should see rparan for end of function"),
+            _ => return Err(String::from("should see rparan for end of function")),
         }
-        return function;
+        Ok(function)
     }
 
-    fn parse_instruction(&mut self, locals_map: &HashMap<String, usize>) -> bool {
-        let token = self.scanner.peek1();
+    /// Parses plain and folded instructions up to the closing parenthesis of the enclosing field
+    fn parse_instructions(&mut self, locals_map: &HashMap<String, usize>) -> Result<(), String> {
+        while self.parse_instruction(locals_map)? || self.parse_folded_instruction(locals_map)? {}
+        Ok(())
+    }
+    fn parse_instruction(&mut self, locals_map: &HashMap<String, usize>) -> Result<bool, String> {
         if let Some(Token::Kwd(inst)) = self.scanner.peek1() {
             let instruction = inst.clone();
             self.scanner.advance();
-            let new_bytecode = self.parse_to_bytecode(&instruction, locals_map);
-            self.code_memory.push(new_bytecode);
-            return true;
+            let position = self.scanner.position();
+            let new_bytecode = self.parse_to_bytecode(&instruction, locals_map)?;
+            self.emit(new_bytecode, position);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+    /// Appends an opcode of the function being parsed written at `position`
+    fn emit(&mut self, op: OP, position: Position) {
+        self.code_memory.push(op);
+        self.code_locs.push(SourceLoc {
+            line: position.line,
+            col: position.col,
+            func: self.functions.len(),
+        });
+    }
+    /// Takes the code from `start` on out of the function code
+    fn split_code(&mut self, start: usize) -> Vec<OP> {
+        self.code_locs.truncate(start);
+        self.code_memory.split_off(start)
+    }
+    /// Parses the label and typeuse of a `block`, `loop` or `if` and opens it
+    fn parse_block(&mut self, is_loop: bool) -> Result<usize, String> {
+        let fn_idx = self.functions.len();
+        let mut blk_id = None;
+        if let Some(Token::Id(id)) = self.scanner.peek1() {
+            blk_id = Some(id.clone());
+            self.scanner.advance();
+        }
+        let ty = self.parse_typeuse(&mut HashMap::new())?;
+        let blk = Block {
+            id: blk_id,
+            is_loop,
+            next_pc: if is_loop { self.code_memory.len() } else { 0 },
+            else_pc: None,
+            ty,
+        };
+        self.blks_table[fn_idx].push(blk);
+        let blk_idx: usize = self.blks_table[fn_idx].len() - 1;
+        self.blks_stack.push(blk_idx);
+        Ok(blk_idx)
+    }
+    /// Resolves the label of a `br` or `br_if` to the block it leaves. Depths count outwards from
+    /// the innermost block and the function body is the outermost label, it resolves to `None`
+    fn parse_branch_target(&mut self, inst: &str) -> Result<Option<usize>, String> {
+        let fn_idx = self.functions.len(); // the function being parsed hasn't been pushed yet
+        let open = self.blks_stack.len();
+        let depth = match self.scanner.get_next_token().cloned() {
+            Some(Token::Id(id)) => {
+                let label = Some(id.clone());
+                let blocks = &self.blks_table[fn_idx];
+                match self.blks_stack.iter().rev().position(|blk_idx| blocks[*blk_idx].id == label) {
+                    Some(depth) => depth,
+                    None => return Err(format!("unknown label ${}", id)),
+                }
+            }
+            Some(Token::Integer(depth)) if depth >= 0 => depth as usize,
+            Some(Token::Integer(depth)) => return Err(format!("unknown label {}", depth)),
+            _ => return Err(format!("need to see reference operand with {} instruction", inst)),
+        };
+        match depth.cmp(&open) {
+            std::cmp::Ordering::Less => Ok(Some(self.blks_stack[open - 1 - depth])),
+            std::cmp::Ordering::Equal => Ok(None),
+            std::cmp::Ordering::Greater => Err(format!("unknown label {}", depth)),
         }
-        return false;
     }
-    fn parse_to_bytecode(&mut self, inst: &str, vars_map: &HashMap<String, usize>) -> OP {
-        match inst {
+    fn parse_to_bytecode(&mut self, inst: &str, vars_map: &HashMap<String, usize>) -> Result<OP, String> {
+        let op = match inst {
             "i32.const" => {
                 if let Some(Token::Integer(n)) = self.scanner.get_next_token() {
-                    I32CONST(*n)
+                    I32CONST(*n as i32)
                 } else {
-                    panic!("should see constant operand with {} instruciton", inst)
+                    return Err(format!("should see constant operand with {} instruciton", inst));
                 }
             }
+            "i64.const" => {
+                if let Some(Token::Integer(n)) = self.scanner.get_next_token() {
+                    I64CONST(*n)
+                } else {
+                    return Err(format!("should see constant operand with {} instruciton", inst));
+                }
+            }
+            "f32.const" => F32CONST(self.parse_float_operand(inst)? as f32),
+            "f64.const" => F64CONST(self.parse_float_operand(inst)?),
+            "i64.add" => I64ADD,
+            "i64.sub" => I64SUB,
+            "i64.mul" => I64MUL,
             "i32.add" => I32ADD,
             "i32.sub" => I32SUB,
             "i32.mul" => I32MUL,
             "i32.lt_s" => I32LTS,
             "i32.rem_u" => I32REMU,
+            "i32.rem_s" => I32REMS,
             "i32.div_s" => I32DIVS,
+            "i32.div_u" => I32DIVU,
             "i32.ne" => I32NE,
             "i32.eq" => I32EQ,
             "i32.eqz" => I32EQZ,
@@ -84,383 +169,690 @@ impl Parser {
                     if let Some(idx) = vars_map.get(id) {
                         LOCGET(*idx)
                     } else {
-                        panic!("no such named local variable for {} instructino", inst)
+                        return Err(format!("no such named local variable for {} instructino", inst));
                     }
                 }
                 Some(Token::Integer(idx)) => LOCGET(*idx as usize),
-                _ => panic!(
-                    "should see local variable reference for {} instruction",
-                    inst
-                ),
+                _ => return Err(format!("should see local variable reference for {} instruction", inst)),
             },
             "local.set" => match self.scanner.get_next_token() {
                 Some(Token::Id(id)) => {
                     if let Some(idx) = vars_map.get(id) {
                         LOCSET(*idx)
                     } else {
-                        panic!("no such named local variable for {} instructino", inst)
+                        return Err(format!("no such named local variable for {} instructino", inst));
                     }
                 }
                 Some(Token::Integer(idx)) => LOCSET(*idx as usize),
-                _ => panic!(
-                    "should see local variable reference for {} instruction",
-                    inst
-                ),
+                _ => return Err(format!("should see local variable reference for {} instruction", inst)),
             },
             "local.tee" => match self.scanner.get_next_token() {
                 Some(Token::Id(id)) => {
                     if let Some(idx) = vars_map.get(id) {
                         LOCTEE(*idx)
                     } else {
-                        panic!("no such named local variable for {} instructino", inst)
+                        return Err(format!("no such named local variable for {} instructino", inst));
                     }
                 }
                 Some(Token::Integer(idx)) => LOCTEE(*idx as usize),
-                _ => panic!(
-                    "should see local variable reference for {} instruction",
-                    inst
-                ),
+                _ => return Err(format!("should see local variable reference for {} instruction", inst)),
             },
             "global.get" => match self.scanner.get_next_token() {
                 Some(Token::Id(id)) => GLOGET(Label::REF(id.clone())),
-                Some(Token::Integer(idx)) => GLOGET((Label::U32(*idx as usize))),
-                _ => panic!(
-                    "should see global variable reference for {} instruction",
-                    inst
-                ),
+                Some(Token::Integer(idx)) => GLOGET(Label::U32(*idx as usize)),
+                _ => return Err(format!("should see global variable reference for {} instruction", inst)),
             },
             "global.set" => match self.scanner.get_next_token() {
                 Some(Token::Id(id)) => GLOSET(Label::REF(id.clone())),
-                Some(Token::Integer(idx)) => GLOSET((Label::U32(*idx as usize))),
-                _ => panic!(
-                    "should see global variable reference for {} instruction",
-                    inst
-                ),
+                Some(Token::Integer(idx)) => GLOSET(Label::U32(*idx as usize)),
+                _ => return Err(format!("should see global variable reference for {} instruction", inst)),
             },
-            "block" => {
+            "block" => BLK(self.parse_block(false)?),
+            "if" => IF(self.parse_block(false)?),
+            "else" => {
                 let fn_idx = self.functions.len();
-                let mut blk_id = None;
-                if let Some(Token::Id(id)) = self.scanner.peek1() {
-                    blk_id = Some(id.clone());
+                let blk_idx = *self.blks_stack.last().ok_or("else outside of an if")?;
+                self.blks_table[fn_idx][blk_idx].else_pc = Some(self.code_memory.len());
+                if let Some(Token::Id(_)) = self.scanner.peek1() {
                     self.scanner.advance();
                 }
-                let has_result = self.parse_result();
-                let blk = Block {
-                    id: blk_id,
-                    is_loop: false,
-                    next_pc: 0,
-                    result: has_result,
-                };
-                self.blks_table[fn_idx].push(blk);
-                let blk_idx: usize = self.blks_table[fn_idx].len() - 1;
-                self.blks_stack.push(blk_idx);
-                BLK(blk_idx)
-            }
-            "br" => {
-                let fn_idx = self.functions.len(); // not -1 because the current function being parsed hasn't been pushed onto the function stack
-                                                    // let token =  self.scanner.get_next_token();
-                                                    // debug!("br operand is {:?}", token);
-                match self.scanner.get_next_token() {
-                    Some(Token::Id(id)) => {
-                        let br_blk = Some(id.clone());
-                        //for (stack_idx, blk_idx) in self.blks_stack.iter().rev().enumerate(){ // nesting starts from in-to-out
-                        for blk_idx in self.blks_stack.iter().rev() {
-                            // nesting starts from in-to-out
-                            if let Some(blk) = self.blks_table[fn_idx].get(*blk_idx) {
-                                if blk.id == br_blk {
-                                    return BR(*blk_idx);
-                                }
-                            }
-                        }
-                        panic!("no matching variable name {:?}", id);
-                    }
-                    Some(Token::Integer(nesting)) => {
-                        let blk_depth = self.blks_stack.len() - (*nesting as usize) - 1; // nesting is from in-to-out
-                        debug!("block depth in stack is {blk_depth}");
-                        let blk_idx = self.blks_stack.get(blk_depth);
-                        match blk_idx {
-                            Some(blk_ref) => {
-                                let blk_ref = *blk_ref;
-                                BR(blk_ref)
-                            }
-                            None => panic!("not a valid block"),
-                        }
-                    }
-                    _ => panic!("need to see reference operand with {} instruction", inst),
-                }
-            }
-            "br_if" => {
-                let fn_idx = self.functions.len();
-                match self.scanner.get_next_token() {
-                    Some(Token::Id(id)) => {
-                        let br_blk = Some(id.clone());
-                        for blk_idx in self.blks_stack.iter().rev() {
-                            // nesting starts from in-to-out
-                            if let Some(blk) = self.blks_table[fn_idx].get(*blk_idx) {
-                                if blk.id == br_blk {
-                                    return BRIF(*blk_idx);
-                                }
-                            }
-                        }
-                        panic!("no matching variable name {:?}", id);
-                    }
-                    Some(Token::Integer(nesting)) => {
-                        let blk_depth = self.blks_stack.len() - (*nesting as usize) - 1; // nesting is from in-to-out
-                        debug!("block depth in stack is {blk_depth}");
-                        let blk_idx = self.blks_stack.get(blk_depth);
-                        match blk_idx {
-                            Some(blk_ref) => {
-                                let blk_ref = *blk_ref;
-                                BRIF(blk_ref)
-                            }
-                            None => panic!("not a valid block"),
-                        }
-                    }
-                    _ => panic!("need to see reference operand with {} instruction", inst),
-                }
-            }
-            "loop" => {
-                let fn_idx = self.functions.len();
-                let mut blk_id = None;
-                if let Some(Token::Id(id)) = self.scanner.peek1() {
-                    blk_id = Some(id.clone());
-                    self.scanner.advance();
-                }
-                let has_result = self.parse_result();
-                let blk = Block {
-                    id: blk_id,
-                    is_loop: true,
-                    next_pc: self.code_memory.len(),
-                    result: has_result,
-                };
-                self.blks_table[fn_idx].push(blk);
-                let blk_idx: usize = self.blks_table[fn_idx].len() - 1;
-                self.blks_stack.push(blk_idx);
-                LOOP(blk_idx)
+                ELSE(blk_idx)
             }
+            "br" => match self.parse_branch_target(inst)? {
+                Some(blk_idx) => BR(blk_idx),
+                None => RET,
+            },
+            "br_if" => match self.parse_branch_target(inst)? {
+                Some(blk_idx) => BRIF(blk_idx),
+                None => return Err(String::from("br_if to the function body isn't supported")),
+            },
+            "loop" => LOOP(self.parse_block(true)?),
             "end" => {
                 let fn_idx = self.functions.len(); // Check why not -1? Not -1 because function symbol has not been added to the function table yet, parsing needs to be completed
-                let blk_idx = self.blks_stack.pop().unwrap(); // assume always valid
+                let blk_idx = self.blks_stack.pop().ok_or("end without an open block")?;
                 let blk = &mut self.blks_table[fn_idx][blk_idx];
                 if !blk.is_loop {
                     blk.next_pc = self.code_memory.len();
                 }
+                if let Some(Token::Id(_)) = self.scanner.peek1() {
+                    self.scanner.advance();
+                }
                 END
             }
             "call" => {
                 match self.scanner.get_next_token() {
                     Some(Token::Id(fn_name)) => CALL(Label::REF(fn_name.clone())),
                     Some(Token::Integer(idx)) => CALL(Label::U32(*idx as usize)),
-                    _ => panic!("should see seen a function reference with {}", inst),
+                    _ => return Err(format!("should see seen a function reference with {}", inst)),
                 }
             }
+            "call_indirect" => {
+                self.parse_index_use("table")?;
+                if let Some(Token::Id(_)) | Some(Token::Integer(_)) = self.scanner.peek1() {
+                    self.scanner.advance(); // table 0 is the only table
+                }
+                let ty = self.parse_typeuse(&mut HashMap::new())?;
+                let type_idx = match self.types.iter().position(|t| *t == ty) {
+                    Some(idx) => idx,
+                    None => {
+                        self.types.push(ty);
+                        self.types.len() - 1
+                    }
+                };
+                CALLIND(type_idx)
+            }
             "return" => RET,
             "drop" => DROP,
             "nop" => NOP,
-            _ => todo!("implement {inst}"),
+            "unreachable" => UNR,
+            _ => return Err(format!("unknown instruction {}", inst)),
+        };
+        Ok(op)
+    }
+    /// Parses `(type x)? (param ...)* (result ...)*`, recording named params in `locals_map`
+    fn parse_typeuse(&mut self, locals_map: &mut HashMap<String, usize>) -> Result<FuncType, String> {
+        let type_ref = self.parse_type_ref()?;
+        let mut ty = FuncType::default();
+        while let Some(types) = self.parse_var("param", locals_map, ty.params.len())? {
+            ty.params.extend(types);
+        }
+        while let Some(types) = self.parse_result()? {
+            ty.results.extend(types);
+        }
+        match type_ref {
+            Some(referenced) => {
+                if (!ty.params.is_empty() || !ty.results.is_empty()) && ty != referenced {
+                    return Err(format!("inline signature {:?} doesn't match type use {:?}", ty, referenced));
+                }
+                Ok(referenced)
+            }
+            None => Ok(ty),
         }
     }
-    fn parse_result(&mut self) -> bool {
+    fn parse_type_ref(&mut self) -> Result<Option<FuncType>, String> {
         if let Some(Token::LParan) = self.scanner.peek1() {
             if let Some(Token::Kwd(kwd)) = self.scanner.peek2() {
-                if kwd.as_str() == "result" {
+                if kwd.as_str() == "type" {
                     self.scanner.advance();
                     self.scanner.advance();
+                    let type_idx = match self.scanner.get_next_token() {
+                        Some(Token::Id(id)) => match self.types_map.get(id) {
+                            Some(idx) => *idx,
+                            None => return Err(format!("no type of the id {}", id)),
+                        },
+                        Some(Token::Integer(idx)) => *idx as usize,
+                        _ => return Err(String::from("should see a type reference with type keyword")),
+                    };
                     match self.scanner.get_next_token() {
-                        Some(Token::Kwd(kwd)) => {
-                            if kwd.as_str() == "i32" {
-                            } else {
-                                panic!("Result can only be of type i32")
-                            }
-                        }
-
-                        _ => {
-                            panic!("should see a type with result keyword")
-                        }
+                        Some(Token::RParan) => {}
+                        _ => return Err(String::from("should see rparan for end of type use")),
                     }
-                    match self.scanner.get_next_token() {
-                        Some(Token::RParan) => return true,
-                        _ => panic!("should see rparan for end of result"),
+                    match self.types.get(type_idx) {
+                        Some(ty) => return Ok(Some(ty.clone())),
+                        None => return Err(format!("type index {} out of range", type_idx)),
                     }
                 }
             }
         }
-        return false;
+        Ok(None)
     }
+    fn parse_float_operand(&mut self, inst: &str) -> Result<f64, String> {
+        match self.scanner.get_next_token() {
+            Some(Token::Float(n)) => Ok(*n),
+            Some(Token::Integer(n)) => Ok(*n as f64),
+            Some(Token::Kwd(kwd)) if kwd.as_str() == "inf" => Ok(f64::INFINITY),
+            Some(Token::Kwd(kwd)) if kwd.as_str() == "nan" => Ok(f64::NAN),
+            _ => Err(format!("should see constant operand with {} instruciton", inst)),
+        }
+    }
+    fn parse_val_types(&mut self, var_kwd: &str) -> Result<Vec<ValType>, String> {
+        let mut types = Vec::new();
+        loop {
+            match self.scanner.get_next_token() {
+                Some(Token::Kwd(kwd)) => match ValType::from_kwd(kwd) {
+                    Some(ty) => types.push(ty),
+                    None => return Err(format!("unknown value type {} for {}", kwd, var_kwd)),
+                },
+                Some(Token::RParan) => return Ok(types),
+                _ => return Err(format!("should see rparan for end of {}", var_kwd)),
+            }
+        }
+    }
+    fn parse_result(&mut self) -> Result<Option<Vec<ValType>>, String> {
+        if let Some(Token::LParan) = self.scanner.peek1() {
+            if let Some(Token::Kwd(kwd)) = self.scanner.peek2() {
+                if kwd.as_str() == "result" {
+                    self.scanner.advance();
+                    self.scanner.advance();
+                    return Ok(Some(self.parse_val_types("result")?));
+                }
+            }
+        }
+        Ok(None)
+    }
+    /// Parses one `(param ...)` or `(local ...)`, a named one declares exactly one type
     fn parse_var(
         &mut self,
         var_kwd: &str,
         locals_map: &mut HashMap<String, usize>,
-        n_locals: u32,
-    ) -> bool {
+        n_locals: usize,
+    ) -> Result<Option<Vec<ValType>>, String> {
         if let Some(Token::LParan) = self.scanner.peek1() {
             if let Some(Token::Kwd(kwd)) = self.scanner.peek2() {
                 if kwd.as_str() == var_kwd {
                     self.scanner.advance();
                     self.scanner.advance();
                     if let Some(Token::Id(id)) = self.scanner.peek1() {
-                        locals_map.insert(id.clone(), n_locals as usize); 
+                        locals_map.insert(id.clone(), n_locals);
                         self.scanner.advance();
-                    }
-                    match self.scanner.get_next_token() {
-                        Some(Token::Kwd(kwd)) => {
-                            if kwd.as_str() == "i32" {
-                            } else {
-                                panic!("Param can only be of type i32")
-                            }
+                        let types = self.parse_val_types(var_kwd)?;
+                        if types.len() != 1 {
+                            return Err(format!("named {} should have exactly one type", var_kwd));
                         }
-                        _ => {
-                            panic!("should see a type with var keyword")
-                        }
-                    }
-                    match self.scanner.get_next_token() {
-                        Some(Token::RParan) => return true,
-                        _ => panic!("should see rparan for end of var"),
+                        return Ok(Some(types));
                     }
+                    return Ok(Some(self.parse_val_types(var_kwd)?));
                 }
             }
         }
-        return false;
+        Ok(None)
     }
-    fn parse_export(&mut self) {
-        let mut export_type: ExportType;
-        let mut export_name: String;
-        let mut export_ref: Label;
+    fn parse_type(&mut self) -> Result<(), String> {
+        if let Some(Token::Id(id)) = self.scanner.peek1() {
+            self.types_map.insert(id.clone(), self.types.len());
+            self.scanner.advance();
+        }
+        match self.scanner.get_next_token() {
+            Some(Token::LParan) => {}
+            _ => return Err(String::from("should see lparen for beginning of function type")),
+        }
+        match self.scanner.get_next_token() {
+            Some(Token::Kwd(kwd)) if kwd.as_str() == "func" => {}
+            _ => return Err(String::from("should see func keyword in type definition")),
+        }
+        let ty = self.parse_typeuse(&mut HashMap::new())?;
+        match self.scanner.get_next_token() {
+            Some(Token::RParan) => {}
+            _ => return Err(String::from("should see rparen to terminate function type")),
+        }
+        match self.scanner.get_next_token() {
+            Some(Token::RParan) => {}
+            _ => return Err(String::from("should see rparen to terminate type definition")),
+        }
+        self.types.push(ty);
+        Ok(())
+    }
+    fn parse_export(&mut self) -> Result<(), String> {
+        let export_type: ExportType;
+        let export_name: String;
         if let Some(Token::String(name)) = self.scanner.get_next_token() {
             export_name = name.clone();
         } else {
-            panic!("should see export name");
+            return Err(String::from("should see export name"));
         }
         match self.scanner.get_next_token() {
             Some(Token::LParan) => {}
-            _ => panic!("should see lparen for beginning of export type "),
+            _ => return Err(String::from("should see lparen for beginning of export type ")),
         }
         match self.scanner.get_next_token() {
             Some(Token::Kwd(kwd)) => match kwd.as_str() {
                 "func" => export_type = ExportType::FUNCTION,
                 "global" => export_type = ExportType::GLOBAL,
                 "memory" => export_type = ExportType::MEMORY,
-                _ => panic!("can't export this type {:?}", kwd),
+                "table" => export_type = ExportType::TABLE,
+                _ => return Err(format!("can't export this type {:?}", kwd)),
             },
-            _ => panic!("should see export type after export"),
-        }
-        match self.scanner.get_next_token() {
-            Some(Token::Id(id)) => export_ref = Label::REF(id.clone()),
-            Some(Token::Integer(idx)) => export_ref = Label::U32(*idx as usize),
-            _ => panic!("should see export reference"),
+            _ => return Err(String::from("should see export type after export")),
         }
+        let export_ref = match self.scanner.get_next_token() {
+            Some(Token::Id(id)) => Label::REF(id.clone()),
+            Some(Token::Integer(idx)) => Label::U32(*idx as usize),
+            _ => return Err(String::from("should see export reference")),
+        };
         match self.scanner.get_next_token() {
             Some(Token::RParan) => {}
-            _ => panic!("should see rparen to terminate export type "),
+            _ => return Err(String::from("should see rparen to terminate export type ")),
         }
         match self.scanner.get_next_token() {
             Some(Token::RParan) => {}
-            _ => panic!("should see rparen to terminate export"),
+            _ => return Err(String::from("should see rparen to terminate export")),
         }
-        self.exports
-            .insert(export_name, Export::new(export_type, export_ref));
+        self.add_export(export_name, Export::new(export_type, export_ref))
     }
 
-    fn parse_global(&mut self) {
-        let mut value: i32;
-        let mut is_mut = false;
-        if let Some(Token::Id(id)) = self.scanner.peek1() {
-            self.globals_map.insert(id.clone(), self.globals.len());
+    /// Parses the `(export "name")*` abbreviations following a definition's id
+    fn parse_inline_exports(&mut self, export_type: ExportType, idx: usize) -> Result<(), String> {
+        while let (Some(Token::LParan), Some(Token::Kwd(kwd))) =
+            (self.scanner.peek1(), self.scanner.peek2())
+        {
+            if kwd.as_str() != "export" {
+                break;
+            }
+            self.scanner.advance();
             self.scanner.advance();
+            let export_name = match self.scanner.get_next_token() {
+                Some(Token::String(name)) => name.clone(),
+                _ => return Err(String::from("should see export name")),
+            };
+            match self.scanner.get_next_token() {
+                Some(Token::RParan) => {}
+                _ => return Err(String::from("should see rparen to terminate inline export")),
+            }
+            self.add_export(export_name, Export::new(export_type.clone(), Label::U32(idx)))?;
         }
-        if let Some(Token::LParan) = self.scanner.peek1() {
-            if let Some(Token::Kwd(kwd)) = self.scanner.peek2() {
-                if kwd.as_str() == "mut" {
-                    is_mut = true;
-                    self.scanner.advance();
-                    self.scanner.advance();
-                    if let Some(Token::Kwd(num_type)) = self.scanner.get_next_token() {
-                        if num_type.as_str() != "i32" {
-                            panic!(
-                                "This implementation only accepts i32 type for globals, saw {}",
-                                num_type
-                            );
-                        }
-                    }
-                    match self.scanner.get_next_token() {
-                        Some(Token::RParan) => {}
-                        _ => panic!("should see right parenthesis to terminate global type"),
-                    }
-                } else {
-                    panic!("should see type for global")
+        Ok(())
+    }
+    /// Parses the `(import "module" "name")` abbreviation following a definition's exports
+    fn parse_inline_import(&mut self) -> Result<Option<(String, String)>, String> {
+        if let (Some(Token::LParan), Some(Token::Kwd(kwd))) =
+            (self.scanner.peek1(), self.scanner.peek2())
+        {
+            if kwd.as_str() == "import" {
+                self.scanner.advance();
+                self.scanner.advance();
+                let names = self.parse_import_names()?;
+                match self.scanner.get_next_token() {
+                    Some(Token::RParan) => {}
+                    _ => return Err(String::from("should see rparen to terminate inline import")),
                 }
+                return Ok(Some(names));
             }
+        }
+        Ok(None)
+    }
+    fn parse_import_names(&mut self) -> Result<(String, String), String> {
+        let module = match self.scanner.get_next_token() {
+            Some(Token::String(name)) => name.clone(),
+            _ => return Err(String::from("should see module name of import")),
+        };
+        let name = match self.scanner.get_next_token() {
+            Some(Token::String(name)) => name.clone(),
+            _ => return Err(String::from("should see field name of import")),
+        };
+        Ok((module, name))
+    }
+    fn add_export(&mut self, export_name: String, export: Export) -> Result<(), String> {
+        if self.exports.contains_key(&export_name) {
+            return Err(format!("duplicate export name {}", export_name));
+        }
+        self.exports.insert(export_name, export);
+        Ok(())
+    }
+    /// Records an import, they have to come before any definition of the same kind
+    fn add_import(&mut self, (module, name): (String, String), import_type: ExportType, idx: usize) -> Result<(), String> {
+        let defined = match import_type {
+            ExportType::FUNCTION => self.functions.iter().any(|f| f.import.is_none()),
+            ExportType::GLOBAL => self.n_imports(ExportType::GLOBAL) != self.globals.len(),
+            ExportType::MEMORY => self.memory.is_some(),
+            ExportType::TABLE => self.table.is_some(),
+        };
+        if defined {
+            return Err(format!("import {}.{} has to occur before any regular definition", module, name));
+        }
+        self.imports.push(Import {
+            module,
+            name,
+            import_type,
+            import_ref: idx,
+        });
+        Ok(())
+    }
+    fn n_imports(&self, import_type: ExportType) -> usize {
+        self.imports
+            .iter()
+            .filter(|import| import.import_type == import_type)
+            .count()
+    }
+    /// Parses `(import "module" "name" (func|global|memory ...))`
+    fn parse_import(&mut self) -> Result<(), String> {
+        let names = self.parse_import_names()?;
+        match self.scanner.get_next_token() {
+            Some(Token::LParan) => {}
+            _ => return Err(String::from("should see lparen for beginning of import description")),
+        }
+        match self.scanner.get_next_token() {
+            Some(Token::Kwd(kwd)) => match kwd.as_str() {
+                "func" => {
+                    self.blks_table.push(Vec::new());
+                    self.parse_func_field(Some(names))?;
+                }
+                "global" => self.parse_global_field(Some(names))?,
+                "memory" => self.parse_memory_field(Some(names))?,
+                "table" => self.parse_table_field(Some(names))?,
+                _ => return Err(format!("can't import this type {:?}", kwd)),
+            },
+            _ => return Err(String::from("should see import description after import names")),
+        }
+        match self.scanner.get_next_token() {
+            Some(Token::RParan) => {}
+            _ => return Err(String::from("should see rparen to terminate import")),
+        }
+        Ok(())
+    }
+    fn parse_func_field(&mut self, import: Option<(String, String)>) -> Result<(), String> {
+        let function = self.parse_fn(import)?;
+        if function.name.is_some() {
+            self.funcs_refs
+                .insert(function.name.clone().unwrap(), self.functions.len());
+        }
+        self.functions.push(function);
+        self.blks_stack.clear();
+        Ok(())
+    }
+    fn parse_global(&mut self) -> Result<(), String> {
+        self.parse_global_field(None)
+    }
+    fn parse_global_field(&mut self, mut import: Option<(String, String)>) -> Result<(), String> {
+        let global_idx = self.globals.len();
+        if let Some(Token::Id(id)) = self.scanner.peek1() {
+            self.globals_map.insert(id.clone(), global_idx);
+            self.scanner.advance();
+        }
+        if import.is_none() {
+            self.parse_inline_exports(ExportType::GLOBAL, global_idx)?;
+            import = self.parse_inline_import()?;
+        }
+        if let Some(names) = import.clone() {
+            self.add_import(names, ExportType::GLOBAL, global_idx)?;
+        }
+        let (ty, mutable) = self.parse_global_type()?;
+        let init = if import.is_none() {
+            self.parse_const_expr()?
         } else {
+            Vec::new()
+        };
+        match self.scanner.get_next_token() {
+            Some(Token::RParan) => {}
+            _ => return Err(String::from("should see right parenthesis to terminate global component")),
+        }
+        self.globals.push(Global { ty, mutable, init });
+        Ok(())
+    }
+    /// Parses `t` or `(mut t)`
+    fn parse_global_type(&mut self) -> Result<(ValType, bool), String> {
+        let mut mutable = false;
+        if let (Some(Token::LParan), Some(Token::Kwd(kwd))) =
+            (self.scanner.peek1(), self.scanner.peek2())
+        {
+            if kwd.as_str() != "mut" {
+                return Err(String::from("should see type for global"));
+            }
+            mutable = true;
+            self.scanner.advance();
+            self.scanner.advance();
+        }
+        let ty = match self.scanner.get_next_token() {
+            Some(Token::Kwd(kwd)) => match ValType::from_kwd(kwd) {
+                Some(ty) => ty,
+                None => return Err(format!("unknown value type {} for global", kwd)),
+            },
+            _ => return Err(String::from("should see type for global")),
+        };
+        if mutable {
             match self.scanner.get_next_token() {
-                Some(Token::Kwd(num_type)) => {}
-                _ => panic!("This implementation only accepts i32 type for globals"),
+                Some(Token::RParan) => {}
+                _ => return Err(String::from("should see right parenthesis to terminate global type")),
+            }
+        }
+        Ok((ty, mutable))
+    }
+    fn parse_const_expr(&mut self) -> Result<ConstExpr, String> {
+        let start = self.code_memory.len();
+        self.parse_instructions(&HashMap::new())?;
+        Ok(self.split_code(start))
+    }
+    /// Whether the next tokens open the clause `(kwd ...)`
+    fn peek_clause(&self, kwd: &str) -> bool {
+        matches!((self.scanner.peek1(), self.scanner.peek2()),
+            (Some(Token::LParan), Some(Token::Kwd(next))) if next.as_str() == kwd)
+    }
+    /// Parses `(instr immediates operand*)`, the operands are emitted before the instruction.
+    /// `(block ...)` and `(loop ...)` hold their body, `(if ...)` its condition operands followed
+    /// by `(then ...)` and an optional `(else ...)`, they are emitted like the plain forms
+    fn parse_folded_instruction(&mut self, locals_map: &HashMap<String, usize>) -> Result<bool, String> {
+        let instruction = match (self.scanner.peek1(), self.scanner.peek2()) {
+            (Some(Token::LParan), Some(Token::Kwd(inst))) => inst.clone(),
+            _ => return Ok(false),
+        };
+        self.scanner.advance();
+        self.scanner.advance();
+        let position = self.scanner.position();
+        let op = self.parse_to_bytecode(&instruction, locals_map)?;
+        let is_block = matches!(op, BLK(_) | LOOP(_) | IF(_));
+        match op {
+            BLK(_) | LOOP(_) => {
+                self.emit(op, position);
+                self.parse_instructions(locals_map)?;
+            }
+            IF(blk_idx) => {
+                // the condition is evaluated outside of the if, its labels don't count it
+                self.blks_stack.pop();
+                while !self.peek_clause("then") && self.parse_folded_instruction(locals_map)? {}
+                self.blks_stack.push(blk_idx);
+                self.emit(op, position);
+                self.parse_clause("then", locals_map)?;
+                if self.peek_clause("else") {
+                    let position = self.scanner.next_position();
+                    let op = self.parse_to_bytecode("else", locals_map)?;
+                    self.emit(op, position);
+                    self.parse_clause("else", locals_map)?;
+                }
             }
+            _ => {
+                while self.parse_folded_instruction(locals_map)? {}
+                self.emit(op, position);
+            }
+        }
+        if is_block {
+            let position = self.scanner.next_position();
+            let end = self.parse_to_bytecode("end", locals_map)?;
+            self.emit(end, position);
         }
         match self.scanner.get_next_token() {
-            Some(Token::LParan) => {
+            Some(Token::RParan) => {}
+            _ => return Err(format!("should see rparen to terminate folded {}", instruction)),
+        }
+        Ok(true)
+    }
+    /// Parses the `(then instr*)` or `(else instr*)` of a folded `if`
+    fn parse_clause(&mut self, kwd: &str, locals_map: &HashMap<String, usize>) -> Result<(), String> {
+        if !self.peek_clause(kwd) {
+            return Err(format!("should see {} in folded if", kwd));
+        }
+        self.scanner.advance();
+        self.scanner.advance();
+        self.parse_instructions(locals_map)?;
+        match self.scanner.get_next_token() {
+            Some(Token::RParan) => Ok(()),
+            _ => Err(format!("should see rparen to terminate {}", kwd)),
+        }
+    }
+    /// Parses the offset of an active segment, `(offset instr*)` or a single folded instruction
+    fn parse_offset(&mut self) -> Result<Option<ConstExpr>, String> {
+        match (self.scanner.peek1(), self.scanner.peek2()) {
+            (Some(Token::LParan), Some(Token::Kwd(kwd))) if kwd.as_str() == "offset" => {
+                self.scanner.advance();
+                self.scanner.advance();
+                let offset = self.parse_const_expr()?;
                 match self.scanner.get_next_token() {
-                    Some(Token::Kwd(kwd)) => {
-                        if kwd.as_str() == "i32.const" {
-                            match self.scanner.get_next_token(){
-                            Some(Token::Integer(n)) => value = *n,
-                            _ =>   panic!("Should integer for i32.const instruction to set initial global value")
-                        }
-                        } else {
-                            panic!("should see ")
-                        }
-                    }
-                    _ => panic!(
-                        "Should to see i32.const instruction to set initial global value"
-                    ),
+                    Some(Token::RParan) => {}
+                    _ => return Err(String::from("should see rparen to terminate offset")),
+                }
+                Ok(Some(offset))
+            }
+            (Some(Token::LParan), Some(Token::Kwd(_))) => {
+                let start = self.code_memory.len();
+                self.parse_folded_instruction(&HashMap::new())?;
+                Ok(Some(self.split_code(start)))
+            }
+            _ => Ok(None),
+        }
+    }
+    /// Skips a `(memory x)` or `(table x)` use, this implementation has only one of each
+    fn parse_index_use(&mut self, kwd: &str) -> Result<(), String> {
+        if let (Some(Token::LParan), Some(Token::Kwd(next))) =
+            (self.scanner.peek1(), self.scanner.peek2())
+        {
+            if next.as_str() == kwd {
+                self.scanner.advance();
+                self.scanner.advance();
+                match self.scanner.get_next_token() {
+                    Some(Token::Integer(0)) | Some(Token::Id(_)) => {}
+                    _ => return Err(format!("only {} 0 exists in this implementation", kwd)),
                 }
                 match self.scanner.get_next_token() {
                     Some(Token::RParan) => {}
-                    _ => panic!("should see right parenthesis to terminate global value"),
+                    _ => return Err(format!("should see rparen to terminate {} use", kwd)),
                 }
             }
-            _ => panic!("should see left parenthesis to start global value"),
+        }
+        Ok(())
+    }
+    /// Parses `(data $id? (memory x)? offset? string*)`
+    fn parse_data(&mut self) -> Result<(), String> {
+        if let Some(Token::Id(_)) = self.scanner.peek1() {
+            self.scanner.advance();
+        }
+        self.parse_index_use("memory")?;
+        let offset = self.parse_offset()?;
+        let mut bytes = Vec::new();
+        loop {
+            match self.scanner.get_next_token() {
+                Some(Token::String(raw)) => bytes.extend(unescape(raw)?),
+                Some(Token::RParan) => break,
+                _ => return Err(String::from("should see strings in data segment")),
+            }
+        }
+        self.datas.push(Data { offset, bytes });
+        Ok(())
+    }
+    /// Parses `(elem $id? (table x)? offset? func? funcidx*)`
+    fn parse_elem(&mut self) -> Result<(), String> {
+        if let Some(Token::Id(_)) = self.scanner.peek1() {
+            self.scanner.advance();
+        }
+        self.parse_index_use("table")?;
+        let offset = self.parse_offset()?;
+        if let Some(Token::Kwd(kwd)) = self.scanner.peek1() {
+            if kwd.as_str() == "func" {
+                self.scanner.advance();
+            }
+        }
+        let mut funcs = Vec::new();
+        loop {
+            match self.scanner.get_next_token() {
+                Some(Token::Id(id)) => funcs.push(Label::REF(id.clone())),
+                Some(Token::Integer(idx)) => funcs.push(Label::U32(*idx as usize)),
+                Some(Token::RParan) => break,
+                _ => return Err(String::from("should see function references in element segment")),
+            }
+        }
+        self.elems.push(Elem { offset, funcs });
+        Ok(())
+    }
+    /// Parses `(table $id? (export "name")* (import "module" "name")? min max? funcref)`
+    fn parse_table(&mut self) -> Result<(), String> {
+        self.parse_table_field(None)
+    }
+    fn parse_table_field(&mut self, mut import: Option<(String, String)>) -> Result<(), String> {
+        let mut name = None;
+        if let Some(Token::Id(id)) = self.scanner.peek1() {
+            name = Some(id.clone());
+            self.scanner.advance();
+        }
+        if self.table.is_some() {
+            return Err(String::from("only one table is allowed per module"));
+        }
+        if import.is_none() {
+            self.parse_inline_exports(ExportType::TABLE, 0)?;
+            import = self.parse_inline_import()?;
+        }
+        if let Some(names) = import {
+            self.add_import(names, ExportType::TABLE, 0)?;
+        }
+        let initial_size = match self.scanner.get_next_token() {
+            Some(Token::Integer(n)) => *n as u32,
+            _ => return Err(String::from("Should see initial table size")),
+        };
+        if let Some(Token::Integer(_)) = self.scanner.peek1() {
+            self.scanner.advance(); // the maximum isn't enforced
+        }
+        match self.scanner.get_next_token() {
+            Some(Token::Kwd(kwd)) if kwd.as_str() == "funcref" || kwd.as_str() == "anyfunc" => {}
+            _ => return Err(String::from("only funcref tables are supported")),
         }
         match self.scanner.get_next_token() {
             Some(Token::RParan) => {}
-            _ => panic!("should see right parenthesis to terminate global component"),
+            _ => return Err(String::from("should see right parenthesis to terminate table declaration")),
         }
-        self.globals.push(Global {
-            mutable: is_mut,
-            value,
-        })
+        self.table = Some(Table { name, initial_size });
+        Ok(())
+    }
+    fn parse_memory(&mut self) -> Result<(), String> {
+        self.parse_memory_field(None)
     }
-    fn parse_memory(&mut self) {
+    fn parse_memory_field(&mut self, mut import: Option<(String, String)>) -> Result<(), String> {
         let mut name = None;
-        let mut initial_capacity: u32;
         if let Some(Token::Id(id)) = self.scanner.peek1() {
             name = Some(id.clone());
             self.scanner.advance();
         }
-        match self.scanner.get_next_token() {
-            Some(Token::Integer(n)) => initial_capacity = *n as u32,
-            _ => panic!("Should see initial memory capacity"),
+        if self.memory.is_some() {
+            return Err(String::from("only one memory is allowed per module"));
         }
+        if import.is_none() {
+            self.parse_inline_exports(ExportType::MEMORY, 0)?;
+            import = self.parse_inline_import()?;
+        }
+        if let Some(names) = import {
+            self.add_import(names, ExportType::MEMORY, 0)?;
+        }
+        let initial_capacity = match self.scanner.get_next_token() {
+            Some(Token::Integer(n)) => *n as u32,
+            _ => return Err(String::from("Should see initial memory capacity")),
+        };
         match self.scanner.get_next_token() {
             Some(Token::RParan) => {}
-            _ => panic!("should see right parenthesis to terminate memory declaration"),
+            _ => return Err(String::from("should see right parenthesis to terminate memory declaration")),
         }
         self.memory = Some(Mem {
             name,
             initial_capacity,
-        })
+        });
+        Ok(())
     }
 }
-pub fn parse_source(source: &str) -> (Mod, Vec<Vec<Block>>) {
-    let mut scanner = Scanner::new(get_tokens(source));
+pub fn parse_source(source: &str) -> Result<(Mod, Vec<Vec<Block>>), String> {
+    let scanner = Scanner::new(get_tokens(source)?);
     let mut parser = Parser::new(scanner);
 
     match parser.scanner.current_debug() {
         Some(Token::LParan) => {}
-        _ => panic!("should see lparan for beginning of  Wasm module"),
+        _ => return Err(String::from("should see lparan for beginning of  Wasm module")),
     }
     if let Some(Token::Kwd(kwd)) = parser.scanner.get_next_token() {
         if "module" != kwd.as_str() {
-            panic!("each wat file should start with a module")
+            return Err(String::from("each wat file should start with a module"));
         }
     }
     loop {
@@ -468,56 +860,79 @@ pub fn parse_source(source: &str) -> (Mod, Vec<Vec<Block>>) {
             Some(Token::LParan) => {
             }
             Some(Token::RParan) => break, // end of wasm
-            _ => panic!("should see terminator or beginnor symbol ), ("),
+            None => return Err(String::from("unexpected end of the module")),
+            _ => return Err(String::from("should see terminator or beginnor symbol ), (")),
         }
         if let Some(Token::Kwd(kwd)) = parser.scanner.peek1() {
             match kwd.as_str() {
                 "memory" => {
                     parser.scanner.advance();
-                    parser.parse_memory();
+                    parser.parse_memory()?;
                 }
                 "global" => {
                     parser.scanner.advance();
-                    parser.parse_global();
+                    parser.parse_global()?;
                 }
                 "export" => {
                     parser.scanner.advance();
-                    parser.parse_export();
+                    parser.parse_export()?;
+                }
+                "type" => {
+                    parser.scanner.advance();
+                    parser.parse_type()?;
+                }
+                "import" => {
+                    parser.scanner.advance();
+                    parser.parse_import()?;
+                }
+                "table" => {
+                    parser.scanner.advance();
+                    parser.parse_table()?;
+                }
+                "data" => {
+                    parser.scanner.advance();
+                    parser.parse_data()?;
+                }
+                "elem" => {
+                    parser.scanner.advance();
+                    parser.parse_elem()?;
                 }
                 "func" => {
                     parser.scanner.advance();
                     parser.blks_table.push(Vec::new());
-                    let function = parser.parse_fn();
-                    if function.name.is_some() {
-                        parser
-                            .funcs_refs
-                            .insert(function.name.clone().unwrap(), parser.functions.len());
-                    }
-                    parser.functions.push(function);
-                    parser.blks_stack.clear();
+                    parser.parse_func_field(None)?;
+                }
+                _ => {
+                    return Err(String::from(
+                        "unknown statement, have to be one of: type, import, func, table, memory, global, export, elem, data",
+                    ))
                 }
-                _ => panic!(
-                    "unknown statement, have to be one of four: func, export, global, memory"
-                ),
             }
         }
     }
     if let Some(x) = parser.scanner.peek1() {
-        panic!(
-            "module definition terminated shouldn't see anything else, saw {:?}",
-            x
-        );
+        return Err(format!("module definition terminated shouldn't see anything else, saw {:?}", x));
     }
     let module = Mod {
         memory: parser.memory,
+        table: parser.table,
+        datas: parser.datas,
+        elems: parser.elems,
+        imports: parser.imports,
         exports: parser.exports,
         funcs: parser.functions,
         funcs_refs: parser.funcs_refs,
         globals_map: parser.globals_map,
         globals: parser.globals,
+        types: parser.types,
         code: parser.code_memory,
+        source_map: SourceMap {
+            file: None,
+            locs: parser.code_locs,
+        },
         start: None,
+        source_hash: source_hash(source.as_bytes()),
     };
-    return (module, parser.blks_table);
+    Ok((module, parser.blks_table))
 }
 
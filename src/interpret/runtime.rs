@@ -1,68 +1,321 @@
-use std::fmt::{self, write};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use log::debug;
-use crate::interpret::ast::ast::{BlockTable, Fn, Mod};
-use super::op::OP;
+use crate::interpret::ast::ast::{BlockTable, Fn, Label, Mod, ValType};
+use super::{host::HostFunc, op::{OpKind, OP}, trace::Tracer, trap::Trap};
 pub const PAGE: u32 = 65536;
-pub type ValueStack = Vec<i32>;
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+pub const DEFAULT_MAX_STACK_SIZE: usize = 1 << 20;
+pub type ValueStack = Vec<Value>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+impl Value {
+    pub fn default_of(ty: ValType) -> Self {
+        match ty {
+            ValType::I32 => Value::I32(0),
+            ValType::I64 => Value::I64(0),
+            ValType::F32 => Value::F32(0.0),
+            ValType::F64 => Value::F64(0.0),
+        }
+    }
+    pub fn ty(&self) -> ValType {
+        match self {
+            Value::I32(_) => ValType::I32,
+            Value::I64(_) => ValType::I64,
+            Value::F32(_) => ValType::F32,
+            Value::F64(_) => ValType::F64,
+        }
+    }
+}
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::I32(n) => write!(f, "{}", n),
+            Value::I64(n) => write!(f, "{}", n),
+            Value::F32(n) => write!(f, "{}", n),
+            Value::F64(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+/// An entered `block`, `loop` or `if`, with the operand stack height below its parameters
+#[derive(Debug, Clone)]
+pub struct BlockFrame {
+    pub blk_idx: usize,
+    pub height: usize,
+}
 
 #[derive(Debug, Clone)]
 pub struct FnFrame {
     pub fn_idx: usize,
-    pub locals: Vec<i32>,
+    pub locals: Vec<Value>,
     pub ret: usize,
+    pub stack_height: usize,
+    pub blocks: Vec<BlockFrame>,
 }
 impl FnFrame {
-    pub fn new(fn_idx: usize, locals: Vec<i32>, ret: usize) -> Self {
+    pub fn new(fn_idx: usize, locals: Vec<Value>, ret: usize, stack_height: usize) -> Self {
         Self {
             fn_idx,
             locals,
             ret,
+            stack_height,
+            blocks: Vec::new(),
         }
     }
 }
 
-pub fn set_fn_variables<'a>(function: &'a Fn, caller_stack: &'a mut ValueStack) -> ValueStack {
-    let mut vars_table: Vec<i32> = Vec::new();
-    let args = function.args;
-    if args > 0 {
-        if caller_stack.len() < args as usize {
-            panic!(
-                "not enough parameters in stack saw {} parameters, function signature needs {}",
-                caller_stack.len(),
-                args
-            )
+/// Where an opcode is in the text format, the file is known for modules read by `Module::from_file`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: Option<String>,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
         }
-        for arg in 0..args {
-            vars_table.push(caller_stack.pop().unwrap());
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// A function activation as shown to a debugger, the callers' `pc` is the call they wait on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub func: usize,
+    pub name: Option<String>, // without the `$`
+    pub pc: usize,
+    pub location: Option<Location>, // of `pc`, for modules parsed from text
+    pub op: String,                 // the opcode at `pc`
+}
+
+impl Frame {
+    /// `$name`, or `function N` for a function without a name
+    pub fn function(&self) -> String {
+        match &self.name {
+            Some(name) => format!("${}", name),
+            None => format!("function {}", self.func),
+        }
+    }
+}
+
+/// `at gcd.wat:17:9 in $gcd`, with the pc when the source isn't known
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "at {} in {}", location, self.function()),
+            None => write!(f, "at pc {} in {}", self.pc, self.function()),
         }
     }
-    for local in 0..function.locals {
-        vars_table.push(0);
+}
+
+pub fn set_fn_variables(function: &Fn, caller_stack: &mut ValueStack) -> Vec<Value> {
+    let args = function.ty.params.len();
+    if caller_stack.len() < args {
+        panic!(
+            "not enough parameters in stack saw {} parameters, function signature needs {}",
+            caller_stack.len(),
+            args
+        )
+    }
+    // the first parameter is the deepest of the arguments on the stack
+    let mut vars_table = caller_stack.split_off(caller_stack.len() - args);
+    for local in function.locals.iter() {
+        vars_table.push(Value::default_of(*local));
     }
     vars_table
 }
+/// Evaluates a validated constant expression against the globals initialised so far
+pub fn eval_const(module: &Mod, expr: &[OP], globals: &[Value]) -> Value {
+    let mut stack: Vec<Value> = Vec::new();
+    for op in expr {
+        let value = match op {
+            OP::I32CONST(n) => Value::I32(*n),
+            OP::I64CONST(n) => Value::I64(*n),
+            OP::F32CONST(n) => Value::F32(*n),
+            OP::F64CONST(n) => Value::F64(*n),
+            OP::GLOGET(label) => globals[module.get_global(label)],
+            _ => {
+                let rhs = stack.pop();
+                let lhs = stack.pop();
+                match (op, lhs, rhs) {
+                    (OP::I32ADD, Some(Value::I32(lhs)), Some(Value::I32(rhs))) => Value::I32(lhs.wrapping_add(rhs)),
+                    (OP::I32SUB, Some(Value::I32(lhs)), Some(Value::I32(rhs))) => Value::I32(lhs.wrapping_sub(rhs)),
+                    (OP::I32MUL, Some(Value::I32(lhs)), Some(Value::I32(rhs))) => Value::I32(lhs.wrapping_mul(rhs)),
+                    (OP::I64ADD, Some(Value::I64(lhs)), Some(Value::I64(rhs))) => Value::I64(lhs.wrapping_add(rhs)),
+                    (OP::I64SUB, Some(Value::I64(lhs)), Some(Value::I64(rhs))) => Value::I64(lhs.wrapping_sub(rhs)),
+                    (OP::I64MUL, Some(Value::I64(lhs)), Some(Value::I64(rhs))) => Value::I64(lhs.wrapping_mul(rhs)),
+                    _ => panic!("{:?} isn't a constant instruction", op),
+                }
+            }
+        };
+        stack.push(value);
+    }
+    match stack.pop() {
+        Some(value) => value,
+        None => panic!("constant expression should produce a value"),
+    }
+}
+
+pub fn eval_offset(module: &Mod, expr: &[OP], globals: &[Value]) -> usize {
+    match eval_const(module, expr, globals) {
+        Value::I32(offset) => offset as u32 as usize,
+        value => panic!("segment offset should be an i32, saw {:?}", value),
+    }
+}
+
+/// Fuel charged for each executed opcode
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostTable {
+    pub default: u64,
+    pub call: u64,   // call and call_indirect
+    pub memory: u64, // loads and stores
+}
+impl Default for CostTable {
+    fn default() -> Self {
+        Self {
+            default: 1,
+            call: 5,
+            memory: 2,
+        }
+    }
+}
+impl CostTable {
+    pub fn cost(&self, op: &OP) -> u64 {
+        match op.kind() {
+            OpKind::Call => self.call,
+            OpKind::Memory => self.memory,
+            OpKind::Control | OpKind::Parametric | OpKind::Variable | OpKind::Numeric => self.default,
+        }
+    }
+}
+
+/// Where a time-sliced evaluation stopped
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunState {
+    Finished(Vec<Value>),
+    /// Out of steps or suspended by a host function, see `Evaluator::is_suspended`
+    Yielded,
+    Trapped(Trap),
+}
+
+/// Stops a running evaluation from another thread or a signal handler,
+/// `run` returns an `Interrupted` trap at the next loop back-edge or call
+#[derive(Debug, Clone)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>,
+}
+impl InterruptHandle {
+    /// A flag no evaluator polls yet, `Evaluator::share_interrupt` connects it to one
+    pub(crate) fn unshared() -> Self {
+        Self {
+            flag: Arc::new(AtomicBool::new(false)),
+        }
+    }
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+}
+
+/// A table element
+#[derive(Debug, Clone)]
+pub enum FuncRef {
+    Local(usize), // a function index of this module
+    /// A function of another instance sharing the table
+    Host(HostFunc),
+}
+
 #[derive(Debug, Clone)]
 pub struct Evaluator {
     pub module: Mod,
-    pub globals: Vec<i32>,
+    pub globals: Vec<Value>,
     pub memory: LinearMemory,
+    pub table: Vec<Option<FuncRef>>, // None for an uninitialised element
     pub stack: ValueStack,
     pub pc: usize,
     pub calls: Vec<FnFrame>,
     pub blks_table: BlockTable,
+    pub max_call_depth: usize,
+    pub max_stack_size: usize, // in values, locals of the active frames don't count
+    pub fuel: Option<u64>,     // None when execution isn't metered
+    pub cost_table: CostTable,
+    interrupted: Arc<AtomicBool>,
+    pub host_funcs: Vec<Option<HostFunc>>, // indexed like `Mod::imports`
+    pub suspended: Option<usize>,          // the host function that yielded
+    pub tracer: Option<Tracer>,
+    pub backtrace: Vec<Frame>, // the active functions when the last run trapped, outermost first
+    /// Where a host function that ran another instance's code trapped, `backtrace` continues with them
+    pub callee_frames: Vec<Frame>,
 }
 impl Evaluator {
-    pub fn add_parameters(&mut self, params: Vec<i32>) {
+    pub fn add_parameters(&mut self, params: Vec<Value>) {
         self.stack.extend(params);
     }
-    fn set_globals(module: &Mod) -> Vec<i32> {
-        let mut globals: Vec<i32> = Vec::new();
+    fn set_globals(module: &Mod, imported: &[Value]) -> Vec<Value> {
+        let mut globals: Vec<Value> = Vec::new();
         for global in module.globals.iter() {
-            globals.push(global.value);
+            if global.init.is_empty() {
+                // imported, zero unless the host provides it
+                let value = imported.get(globals.len()).copied();
+                globals.push(value.unwrap_or(Value::default_of(global.ty)));
+            } else {
+                let value = eval_const(module, &global.init, &globals);
+                globals.push(value);
+            }
         }
         globals
     }
+    /// Copies the active data and element segments into memory and the table,
+    /// a segment that doesn't fit traps and leaves the earlier ones in place
+    pub fn init_segments(&mut self) -> Result<(), Trap> {
+        let module = &self.module;
+        for data in module.datas.iter() {
+            if let Some(offset) = &data.offset {
+                let offset = eval_offset(module, offset, &self.globals);
+                if offset + data.bytes.len() > self.memory.bytes.len() {
+                    return Err(Trap::MemoryOutOfBounds);
+                }
+                self.memory.bytes[offset..offset + data.bytes.len()].copy_from_slice(&data.bytes);
+            }
+        }
+        for elem in module.elems.iter() {
+            if let Some(offset) = &elem.offset {
+                let offset = eval_offset(module, offset, &self.globals);
+                if offset + elem.funcs.len() > self.table.len() {
+                    return Err(Trap::TableOutOfBounds);
+                }
+                for (i, label) in elem.funcs.iter().enumerate() {
+                    let fn_idx = match label {
+                        Label::REF(id) => module.get_fn_idx(id),
+                        Label::U32(idx) => *idx,
+                    };
+                    self.table[offset + i] = Some(FuncRef::Local(fn_idx));
+                }
+            }
+        }
+        Ok(())
+    }
+    #[cfg(test)]
     pub fn new(module: Mod, blks_table: BlockTable) -> Self {
+        let mut evaluator = Evaluator::instantiate(module, blks_table, &[]);
+        if let Err(trap) = evaluator.init_segments() {
+            panic!("instantiation failed: {}", trap)
+        }
+        evaluator
+    }
+    /// Builds the instance state with values for the imported globals, which come first,
+    /// the segments are copied by `init_segments`
+    pub fn instantiate(module: Mod, blks_table: BlockTable, imported_globals: &[Value]) -> Self {
         let mut new_memory: Vec<u8> = Vec::new();
         debug!(" {:?}", module.memory);
         if let Some(mem) = &module.memory {
@@ -70,20 +323,154 @@ impl Evaluator {
             let pages = PAGE * mem.initial_capacity;
             new_memory = vec![0; pages as usize];
         }
-        let globals = Evaluator::set_globals(&module);
+        let globals = Evaluator::set_globals(&module, imported_globals);
+        let host_funcs = vec![None; module.imports.len()];
+        let table = match &module.table {
+            Some(table) => vec![None; table.initial_size as usize],
+            None => Vec::new(),
+        };
         Self {
             module,
             globals,
             memory: LinearMemory { bytes: new_memory },
+            table,
             stack: Vec::new(),
             pc: 0,
             calls: Vec::new(),
             blks_table,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_stack_size: DEFAULT_MAX_STACK_SIZE,
+            fuel: None,
+            cost_table: CostTable::default(),
+            interrupted: Arc::new(AtomicBool::new(false)),
+            host_funcs,
+            suspended: None,
+            tracer: None,
+            backtrace: Vec::new(),
+            callee_frames: Vec::new(),
+        }
+    }
+    /// Meters every following opcode, running dry traps with `OutOfFuel`
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(fuel);
+    }
+    /// Tops up the fuel, which also starts metering if it was off
+    pub fn add_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(self.fuel.unwrap_or(0).saturating_add(fuel));
+    }
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+    pub fn disable_fuel(&mut self) {
+        self.fuel = None;
+    }
+    /// Satisfies the function import `module.name` with a host function of the same type
+    #[cfg(test)]
+    pub fn define_host_fn(&mut self, module: &str, name: &str, func: HostFunc) -> Result<(), String> {
+        use crate::interpret::ast::ast::ExportType;
+        let import_idx = self
+            .module
+            .imports
+            .iter()
+            .position(|import| {
+                import.module == module && import.name == name && import.import_type == ExportType::FUNCTION
+            })
+            .ok_or(format!("no function import {}.{}", module, name))?;
+        let import_ty = &self.module.funcs[self.module.imports[import_idx].import_ref].ty;
+        if *import_ty != func.ty {
+            return Err(format!(
+                "host function {}.{} has type {:?}, the import expects {:?}",
+                module, name, func.ty, import_ty
+            ));
         }
+        self.host_funcs[import_idx] = Some(func);
+        Ok(())
+    }
+    /// Drops the pending call, memory and globals keep what it changed
+    pub fn abandon(&mut self) {
+        if let Some(frame) = self.calls.first() {
+            self.stack.truncate(frame.stack_height);
+        }
+        self.calls.clear();
+        self.pc = 0;
+        self.suspended = None;
+    }
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.is_some()
+    }
+    /// Hands the results of a yielded host call to the guest, `run_for` then carries on
+    pub fn resume(&mut self, results: Vec<Value>) -> Result<(), String> {
+        let fn_idx = match self.suspended {
+            Some(fn_idx) => fn_idx,
+            None => return Err(String::from("the evaluator isn't suspended by a host function")),
+        };
+        let expected = &self.module.funcs[fn_idx].ty.results;
+        let types: Vec<ValType> = results.iter().map(|value| value.ty()).collect();
+        if types != *expected {
+            return Err(format!("resumed with {:?}, the host function returns {:?}", types, expected));
+        }
+        self.stack.extend(results);
+        self.suspended = None;
+        Ok(())
+    }
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle {
+            flag: Arc::clone(&self.interrupted),
+        }
+    }
+    /// Makes `handle` stop this evaluator too, instances calling each other share one handle
+    pub fn share_interrupt(&mut self, handle: &InterruptHandle) {
+        self.interrupted = Arc::clone(&handle.flag);
+    }
+    /// Consumes a pending interrupt request
+    pub fn take_interrupt(&self) -> bool {
+        self.interrupted.swap(false, Ordering::SeqCst)
     }
     pub fn next_opcode(&self) -> &OP {
         &self.module.code[self.pc]
     }
+    /// The active functions, the outermost first
+    pub fn frames(&self) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        for i in 0..self.calls.len() {
+            let pc = match self.calls.get(i + 1) {
+                Some(callee) => callee.ret - 1,
+                None => self.pc,
+            };
+            frames.push(self.frame(i, pc));
+        }
+        frames
+    }
+    /// The `depth`th active function at `pc`
+    pub fn frame(&self, depth: usize, pc: usize) -> Frame {
+        let fn_idx = self.calls[depth].fn_idx;
+        Frame {
+            func: fn_idx,
+            name: self.module.funcs[fn_idx].name.clone(),
+            pc,
+            location: self.location(pc),
+            op: format!("{:?}", self.module.code[pc]),
+        }
+    }
+    /// Where the opcode at `pc` is in the source
+    pub fn location(&self, pc: usize) -> Option<Location> {
+        let source_map = &self.module.source_map;
+        let loc = source_map.get(pc)?;
+        Some(Location {
+            file: source_map.file.clone(),
+            line: loc.line,
+            col: loc.col,
+        })
+    }
+    /// The operand stack of the `frame`th active function, counted like `frames`
+    pub fn frame_stack(&self, frame: usize) -> Option<&[Value]> {
+        let start = self.calls.get(frame)?.stack_height;
+        let end = match self.calls.get(frame + 1) {
+            Some(callee) => callee.stack_height,
+            None => self.stack.len(),
+        };
+        self.stack.get(start..end.max(start))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -94,9 +481,9 @@ pub struct LinearMemory {
 impl fmt::Display for LinearMemory {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for (i, byte) in self.bytes.iter().enumerate() {
-            write!(f, "{:02x} ", byte);
+            write!(f, "{:02x} ", byte)?;
             if (i + 1) % 4 == 0 {
-                writeln!(f);
+                writeln!(f)?;
                 if *byte == 0 {
                     break;
                 }
@@ -105,23 +492,3 @@ impl fmt::Display for LinearMemory {
         Ok(())
     }
 }
-
-impl LinearMemory {
-    pub fn pretty_print_as_integers(&self) {
-        let mut iter = self.bytes.chunks(4);
-        let mut before_is_zero = false;
-        let mut address = 0;
-        while let Some(chunk) = iter.next() {
-            let n = i32::from_le_bytes(chunk.try_into().unwrap()); 
-            if n == 0 && before_is_zero {
-            } else if n == 0 {
-                println!("{address:08x}: 0");
-                before_is_zero = true;
-            } else {
-                println!("{address:08x}: {}", n);
-                before_is_zero = false;
-            }
-            address += 1;
-        }
-    }
-}
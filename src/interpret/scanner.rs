@@ -1,39 +1,37 @@
 use log::debug;
 
-use crate::interpret::lexer::Token;
+use crate::interpret::lexer::{Position, Token};
 #[derive(Debug)]
 pub struct Scanner {
     tokens: Vec<Token>,
+    positions: Vec<Position>, // of each token
     curr: usize,
 }
-#[derive(Debug, Clone)]
-pub enum Errros {
-    NoLeftParan(String),
-    NoRightParan(String),
-}
 impl Scanner {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, curr: 0 }
+    pub fn new((tokens, positions): (Vec<Token>, Vec<Position>)) -> Self {
+        Self {
+            tokens,
+            positions,
+            curr: 0,
+        }
+    }
+    /// Where the current token is
+    pub fn position(&self) -> Position {
+        self.positions.get(self.curr).copied().unwrap_or_default()
+    }
+    /// Where the token after the current one is
+    pub fn next_position(&self) -> Position {
+        self.positions.get(self.curr + 1).copied().unwrap_or_default()
     }
     pub fn current_debug(&self) -> Option<&Token> {
         self.tokens.get(self.curr)
     }
     pub fn get_next_token(&mut self) -> Option<&Token> {
         self.curr += 1;
-        let token = self.tokens.get(self.curr);
-        return token;
-    }
-    pub fn consume_token(&mut self) -> Option<&Token> {
-        let token = self.tokens.get(self.curr);
-        self.curr += 1;
-        return token;
+        self.tokens.get(self.curr)
     }
     pub fn peek1(&self) -> Option<&Token> {
-        if let token = self.tokens.get(self.curr + 1) {
-            return token;
-        } else {
-            return None;
-        }
+        self.tokens.get(self.curr + 1)
     }
     pub fn advance(&mut self) {
         self.curr += 1;
@@ -43,10 +41,6 @@ impl Scanner {
         );
     }
     pub fn peek2(&self) -> Option<&Token> {
-        if let token = self.tokens.get(self.curr + 2) {
-            return token;
-        } else {
-            return None;
-        }
+        self.tokens.get(self.curr + 2)
     }
 }
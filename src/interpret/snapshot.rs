@@ -0,0 +1,240 @@
+use std::fs;
+
+use super::{
+    ast::ast::ValType,
+    runtime::{BlockFrame, Evaluator, FnFrame, Value},
+};
+
+// Layout, all integers little endian:
+//   magic "WSNP", version u32, module hash u64
+//   memory: len u64, bytes
+//   globals: count u32, values
+//   execution flag u8, when 1: stack: count u32, values; pc u64;
+//     calls: count u32, per frame fn_idx u64, ret u64, stack_height u64,
+//     locals: count u32, values; blocks: count u32, per block blk_idx u64, height u64;
+//     suspended flag u8, when 1: fn_idx u64
+// A value is a type tag u8 (0x7f i32, 0x7e i64, 0x7d f32, 0x7c f64) followed by 8 bytes
+const MAGIC: &[u8; 4] = b"WSNP";
+const VERSION: u32 = 2;
+
+/// FNV-1a of the text or binary a module was parsed from, a snapshot only restores into the same source
+pub fn source_hash(source: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in source {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+impl Writer {
+    fn u8(&mut self, n: u8) {
+        self.bytes.push(n);
+    }
+    fn u32(&mut self, n: usize) {
+        self.bytes.extend((n as u32).to_le_bytes());
+    }
+    fn u64(&mut self, n: u64) {
+        self.bytes.extend(n.to_le_bytes());
+    }
+    fn value(&mut self, value: &Value) {
+        let (tag, bits) = match value {
+            Value::I32(n) => (0x7f, *n as u32 as u64),
+            Value::I64(n) => (0x7e, *n as u64),
+            Value::F32(n) => (0x7d, n.to_bits() as u64),
+            Value::F64(n) => (0x7c, n.to_bits()),
+        };
+        self.u8(tag);
+        self.u64(bits);
+    }
+    fn values(&mut self, values: &[Value]) {
+        self.u32(values.len());
+        for value in values {
+            self.value(value);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        match self.bytes.get(self.pos..self.pos + n) {
+            Some(slice) => {
+                self.pos += n;
+                Ok(slice)
+            }
+            None => Err(String::from("snapshot is truncated")),
+        }
+    }
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+    fn u32(&mut self) -> Result<usize, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize)
+    }
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    fn usize(&mut self) -> Result<usize, String> {
+        Ok(self.u64()? as usize)
+    }
+    fn value(&mut self) -> Result<Value, String> {
+        let tag = self.u8()?;
+        let bits = self.u64()?;
+        match tag {
+            0x7f => Ok(Value::I32(bits as u32 as i32)),
+            0x7e => Ok(Value::I64(bits as i64)),
+            0x7d => Ok(Value::F32(f32::from_bits(bits as u32))),
+            0x7c => Ok(Value::F64(f64::from_bits(bits))),
+            _ => Err(format!("unknown value type {:#x} in snapshot", tag)),
+        }
+    }
+    fn values(&mut self) -> Result<Vec<Value>, String> {
+        let count = self.u32()?;
+        (0..count).map(|_| self.value()).collect()
+    }
+}
+
+impl Evaluator {
+    /// Serialises memory and globals, plus the in-flight call when `with_execution` is set
+    pub fn snapshot(&self, with_execution: bool) -> Vec<u8> {
+        let mut writer = Writer { bytes: Vec::new() };
+        writer.bytes.extend(MAGIC);
+        writer.u32(VERSION as usize);
+        writer.u64(self.module.source_hash);
+        writer.u64(self.memory.bytes.len() as u64);
+        writer.bytes.extend(&self.memory.bytes);
+        writer.values(&self.globals);
+        if !with_execution {
+            writer.u8(0);
+            return writer.bytes;
+        }
+        writer.u8(1);
+        writer.values(&self.stack);
+        writer.u64(self.pc as u64);
+        writer.u32(self.calls.len());
+        for frame in self.calls.iter() {
+            writer.u64(frame.fn_idx as u64);
+            writer.u64(frame.ret as u64);
+            writer.u64(frame.stack_height as u64);
+            writer.values(&frame.locals);
+            writer.u32(frame.blocks.len());
+            for block in frame.blocks.iter() {
+                writer.u64(block.blk_idx as u64);
+                writer.u64(block.height as u64);
+            }
+        }
+        match self.suspended {
+            Some(fn_idx) => {
+                writer.u8(1);
+                writer.u64(fn_idx as u64);
+            }
+            None => writer.u8(0),
+        }
+        writer.bytes
+    }
+
+    /// Replaces the mutable state with a snapshot taken from an instance of the same module,
+    /// the evaluator is left untouched when the snapshot is rejected
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(4)? != MAGIC {
+            return Err(String::from("not a snapshot file"));
+        }
+        let version = reader.u32()?;
+        if version != VERSION as usize {
+            return Err(format!("unsupported snapshot version {}", version));
+        }
+        if reader.u64()? != self.module.source_hash {
+            return Err(String::from("snapshot was taken from a different module"));
+        }
+        let memory_len = reader.u64()?;
+        if memory_len != self.memory.bytes.len() as u64 {
+            return Err(format!(
+                "snapshot memory has {} bytes, the module's memory has {}",
+                memory_len,
+                self.memory.bytes.len()
+            ));
+        }
+        let memory = reader.take(self.memory.bytes.len())?.to_vec();
+        let globals = reader.values()?;
+        let types_match = globals.len() == self.globals.len()
+            && globals.iter().zip(self.globals.iter()).all(|(a, b)| a.ty() == b.ty());
+        if !types_match {
+            return Err(String::from("snapshot globals don't match the module's globals"));
+        }
+        let mut execution = (Vec::new(), 0, Vec::new(), None);
+        if reader.u8()? == 1 {
+            let stack = reader.values()?;
+            let pc = reader.usize()?;
+            let n_calls = reader.u32()?;
+            let mut calls = Vec::new();
+            for _ in 0..n_calls {
+                let fn_idx = reader.usize()?;
+                let ret = reader.usize()?;
+                let stack_height = reader.usize()?;
+                let locals = reader.values()?;
+                // a callee returns past the call, `frames` looks at the opcode before `ret`
+                let returns_to_call = calls.is_empty() || ret >= 1;
+                if !returns_to_call || ret > self.module.code.len() || stack_height > stack.len() {
+                    return Err(String::from("snapshot call frames don't match the module"));
+                }
+                let function = match self.module.funcs.get(fn_idx) {
+                    Some(function) => function,
+                    None => return Err(format!("snapshot refers to unknown function {}", fn_idx)),
+                };
+                let types: Vec<ValType> = function.ty.params.iter().chain(function.locals.iter()).copied().collect();
+                if locals.iter().map(Value::ty).ne(types) {
+                    return Err(format!("snapshot locals don't match the type of function {}", fn_idx));
+                }
+                let mut frame = FnFrame::new(fn_idx, locals, ret, stack_height);
+                let n_blocks = reader.u32()?;
+                for _ in 0..n_blocks {
+                    let blk_idx = reader.usize()?;
+                    let height = reader.usize()?;
+                    if self.blks_table.get(fn_idx).is_none_or(|blks| blk_idx >= blks.len()) {
+                        return Err(format!("snapshot refers to unknown block {} of function {}", blk_idx, fn_idx));
+                    }
+                    if height > stack.len() {
+                        return Err(format!("snapshot block {} of function {} is above the stack", blk_idx, fn_idx));
+                    }
+                    frame.blocks.push(BlockFrame { blk_idx, height });
+                }
+                calls.push(frame);
+            }
+            if pc >= self.module.code.len() && !calls.is_empty() {
+                return Err(String::from("snapshot pc is outside the module's code"));
+            }
+            let suspended = match reader.u8()? {
+                1 => Some(reader.usize()?),
+                _ => None,
+            };
+            if suspended.is_some_and(|fn_idx| fn_idx >= self.module.funcs.len()) {
+                return Err(String::from("snapshot is suspended in an unknown function"));
+            }
+            execution = (stack, pc, calls, suspended);
+        }
+        if reader.pos != bytes.len() {
+            return Err(String::from("trailing bytes after snapshot"));
+        }
+        self.memory.bytes = memory;
+        self.globals = globals;
+        (self.stack, self.pc, self.calls, self.suspended) = execution;
+        Ok(())
+    }
+
+    pub fn save_snapshot(&self, path: &str, with_execution: bool) -> Result<(), String> {
+        fs::write(path, self.snapshot(with_execution)).map_err(|error| format!("can't write {}: {}", path, error))
+    }
+
+    pub fn load_snapshot(&mut self, path: &str) -> Result<(), String> {
+        let bytes = fs::read(path).map_err(|error| format!("can't read {}: {}", path, error))?;
+        self.restore(&bytes)
+    }
+}
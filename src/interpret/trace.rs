@@ -0,0 +1,200 @@
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use super::{
+    op::OP,
+    runtime::{Evaluator, Value},
+    trap::Trap,
+};
+
+/// How each executed opcode is written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    /// `[depth] $name pc=N (line:col) OP: [before] -> [after], local N = value`
+    Human,
+    /// Tab separated `depth pc line:col function op before after writes`, one opcode a line, for diffing
+    Compact,
+}
+
+struct TraceState {
+    format: TraceFormat,
+    functions: Vec<String>, // only these are traced, every function when empty
+    sink: Box<dyn Write + Send>,
+}
+
+/// Logs every opcode an instance executes with the operand stack of its frame before and
+/// after it and the locals it writes. Clones share the sink, so one tracer can follow
+/// several instances
+#[derive(Clone)]
+pub struct Tracer {
+    state: Arc<Mutex<TraceState>>,
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        write!(f, "Tracer({:?}, {:?})", state.format, state.functions)
+    }
+}
+
+/// What a traced opcode did
+struct Event<'a> {
+    depth: usize,
+    pc: usize,
+    location: String, // `line:col`, empty for binary modules
+    function: String,
+    op: &'a OP,
+    before: &'a [Value],
+    after: Result<&'a [Value], &'a Trap>,
+    write: Option<(usize, Value)>, // the local set by the opcode
+}
+
+impl Tracer {
+    /// Writes readable lines to `sink`
+    pub fn new(sink: impl Write + Send + 'static) -> Tracer {
+        Tracer::with_format(TraceFormat::Human, sink)
+    }
+
+    /// Writes the compact format to `sink`, which is usually a file
+    pub fn compact(sink: impl Write + Send + 'static) -> Tracer {
+        Tracer::with_format(TraceFormat::Compact, sink)
+    }
+
+    fn with_format(format: TraceFormat, sink: impl Write + Send + 'static) -> Tracer {
+        Tracer {
+            state: Arc::new(Mutex::new(TraceState {
+                format,
+                functions: Vec::new(),
+                sink: Box::new(sink),
+            })),
+        }
+    }
+
+    /// Traces only `function`, given by its name with or without the `$` or by its index,
+    /// calling it again adds another one
+    pub fn only(&mut self, function: &str) -> &mut Self {
+        let function = function.strip_prefix('$').unwrap_or(function);
+        self.state.lock().unwrap().functions.push(function.to_string());
+        self
+    }
+
+    pub fn format(&self) -> TraceFormat {
+        self.state.lock().unwrap().format
+    }
+
+    /// The functions being traced, empty when all of them are
+    pub fn functions(&self) -> Vec<String> {
+        self.state.lock().unwrap().functions.clone()
+    }
+
+    pub fn flush(&self) {
+        let _ = self.state.lock().unwrap().sink.flush();
+    }
+
+    fn traces(&self, name: Option<&str>, fn_idx: usize) -> bool {
+        let state = self.state.lock().unwrap();
+        state.functions.is_empty()
+            || state
+                .functions
+                .iter()
+                .any(|function| Some(function.as_str()) == name || *function == fn_idx.to_string())
+    }
+
+    fn write(&self, event: &Event) {
+        let mut state = self.state.lock().unwrap();
+        let line = match state.format {
+            TraceFormat::Human => human(event),
+            TraceFormat::Compact => compact(event),
+        };
+        // a trace that can't be written must not stop the guest
+        let _ = writeln!(state.sink, "{}", line);
+    }
+}
+
+fn typed(value: &Value) -> String {
+    format!("{}:{}", value.ty(), value)
+}
+
+fn values(values: &[Value], separator: &str) -> String {
+    let values: Vec<String> = values.iter().map(typed).collect();
+    values.join(separator)
+}
+
+fn human(event: &Event) -> String {
+    let mut line = format!("[{}] {} pc={}", event.depth, event.function, event.pc);
+    if !event.location.is_empty() {
+        line.push_str(&format!(" ({})", event.location));
+    }
+    line.push_str(&format!(" {:?}: [{}]", event.op, values(event.before, ", ")));
+    match event.after {
+        Ok(after) => line.push_str(&format!(" -> [{}]", values(after, ", "))),
+        Err(trap) => line.push_str(&format!(" -> trap: {}", trap)),
+    }
+    if let Some((local, value)) = event.write {
+        line.push_str(&format!(", local {} = {}", local, typed(&value)));
+    }
+    line
+}
+
+fn compact(event: &Event) -> String {
+    let after = match event.after {
+        Ok(after) => values(after, ","),
+        Err(trap) => format!("trap:{}", trap),
+    };
+    let write = match event.write {
+        Some((local, value)) => format!("{}={}", local, typed(&value)),
+        None => String::new(),
+    };
+    format!(
+        "{}\t{}\t{}\t{}\t{:?}\t{}\t{}\t{}",
+        event.depth,
+        event.pc,
+        event.location,
+        event.function,
+        event.op,
+        values(event.before, ","),
+        after,
+        write
+    )
+}
+
+impl Evaluator {
+    /// Evaluates the opcode at `pc` like `evaluate_bytecode` and reports it to `tracer`
+    /// when its function is traced
+    pub(crate) fn evaluate_traced(&mut self, tracer: &Tracer, pc: usize, opcode: OP) -> Result<(), Trap> {
+        let frame = self.calls.last().unwrap();
+        let (fn_idx, depth, height) = (frame.fn_idx, self.calls.len(), frame.stack_height);
+        let name = self.module.funcs[fn_idx].name.clone();
+        if !tracer.traces(name.as_deref(), fn_idx) {
+            return self.evaluate_bytecode(opcode);
+        }
+        let before = self.stack[height.min(self.stack.len())..].to_vec();
+        // the tracer isn't locked while evaluating, a host function may run a traced instance
+        let result = self.evaluate_bytecode(opcode.clone());
+        let write = match opcode {
+            OP::LOCSET(local) | OP::LOCTEE(local) if result.is_ok() => {
+                Some((local, self.calls[depth - 1].locals[local]))
+            }
+            _ => None,
+        };
+        let after = &self.stack[height.min(self.stack.len())..];
+        tracer.write(&Event {
+            depth,
+            pc,
+            location: match self.module.source_map.get(pc) {
+                Some(loc) => format!("{}:{}", loc.line, loc.col),
+                None => String::new(),
+            },
+            function: match &name {
+                Some(name) => format!("${}", name),
+                None => fn_idx.to_string(),
+            },
+            op: &opcode,
+            before: &before,
+            after: result.as_ref().map(|_| after),
+            write,
+        });
+        result
+    }
+}
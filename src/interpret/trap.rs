@@ -0,0 +1,47 @@
+use std::fmt;
+
+/// An error raised while executing guest code, it aborts the running call
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trap {
+    Unreachable,
+    IntegerDivideByZero,
+    IntegerOverflow,
+    MemoryOutOfBounds,
+    TableOutOfBounds,
+    UndefinedElement,
+    UninitializedElement,
+    IndirectCallTypeMismatch,
+    CallStackExhausted,
+    OperandStackExhausted,
+    OutOfFuel,
+    Interrupted,
+    Host(String), // raised by a host function
+    /// The guest asked to exit the process, WASI's `proc_exit`
+    Exit(i32),
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trap::Host(message) => return write!(f, "{}", message),
+            Trap::Exit(code) => return write!(f, "exit with code {}", code),
+            _ => {}
+        }
+        let message = match self {
+            Trap::Unreachable => "unreachable",
+            Trap::IntegerDivideByZero => "integer divide by zero",
+            Trap::IntegerOverflow => "integer overflow",
+            Trap::MemoryOutOfBounds => "out of bounds memory access",
+            Trap::TableOutOfBounds => "out of bounds table access",
+            Trap::UndefinedElement => "undefined element",
+            Trap::UninitializedElement => "uninitialized element",
+            Trap::IndirectCallTypeMismatch => "indirect call type mismatch",
+            Trap::CallStackExhausted => "call stack exhausted",
+            Trap::OperandStackExhausted => "operand stack exhausted",
+            Trap::OutOfFuel => "all fuel consumed",
+            Trap::Interrupted => "interrupted",
+            Trap::Host(_) | Trap::Exit(_) => unreachable!(),
+        };
+        write!(f, "{}", message)
+    }
+}
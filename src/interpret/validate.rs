@@ -0,0 +1,496 @@
+use std::fmt;
+
+use crate::interpret::ast::ast::{BlockTable, ExportType, Label, Mod, ValType};
+use crate::interpret::op::OP::{self, *};
+use crate::interpret::op::OpKind;
+
+/// A typing error, located at the instruction of the function that broke the rule
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub fn_idx: Option<usize>,
+    pub fn_name: Option<String>,
+    pub pc: Option<usize>,
+    pub op: Option<OP>,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid module")?;
+        if let Some(fn_idx) = self.fn_idx {
+            write!(f, " in function {}", fn_idx)?;
+            if let Some(name) = &self.fn_name {
+                write!(f, " (${})", name)?;
+            }
+        }
+        if let (Some(pc), Some(op)) = (self.pc, &self.op) {
+            write!(f, " at pc {} {:?}", pc, op)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Checks every function body with the operand and control stack algorithm of the spec's appendix
+pub fn validate(module: &Mod, blks_table: &BlockTable) -> Result<(), ValidationError> {
+    for (name, export) in module.exports.iter() {
+        let found = match (&export.export_type, &export.export_ref) {
+            (ExportType::FUNCTION, Label::U32(idx)) => *idx < module.funcs.len(),
+            (ExportType::FUNCTION, Label::REF(id)) => module.funcs_refs.contains_key(id),
+            (ExportType::GLOBAL, Label::U32(idx)) => *idx < module.globals.len(),
+            (ExportType::GLOBAL, Label::REF(id)) => module.globals_map.contains_key(id),
+            (ExportType::MEMORY, Label::U32(idx)) => *idx == 0 && module.memory.is_some(),
+            (ExportType::MEMORY, Label::REF(id)) => {
+                matches!(&module.memory, Some(mem) if mem.name.as_ref() == Some(id))
+            }
+            (ExportType::TABLE, Label::U32(idx)) => *idx == 0 && module.table.is_some(),
+            (ExportType::TABLE, Label::REF(id)) => {
+                matches!(&module.table, Some(table) if table.name.as_ref() == Some(id))
+            }
+        };
+        if !found {
+            return Err(module_error(format!(
+                "export {} refers to an unknown {:?}",
+                name, export.export_type
+            )));
+        }
+    }
+    for (idx, global) in module.globals.iter().enumerate() {
+        if !global.init.is_empty() {
+            validate_const_expr(module, &global.init, global.ty, &format!("global {}", idx))?;
+        }
+    }
+    for (idx, data) in module.datas.iter().enumerate() {
+        if let Some(offset) = &data.offset {
+            if module.memory.is_none() {
+                return Err(module_error(format!("data segment {} without a memory", idx)));
+            }
+            validate_const_expr(module, offset, ValType::I32, &format!("data segment {}", idx))?;
+        }
+    }
+    for (idx, elem) in module.elems.iter().enumerate() {
+        if let Some(offset) = &elem.offset {
+            if module.table.is_none() {
+                return Err(module_error(format!("element segment {} without a table", idx)));
+            }
+            validate_const_expr(module, offset, ValType::I32, &format!("element segment {}", idx))?;
+        }
+        for label in elem.funcs.iter() {
+            let found = match label {
+                Label::REF(id) => module.funcs_refs.contains_key(id),
+                Label::U32(idx) => *idx < module.funcs.len(),
+            };
+            if !found {
+                return Err(module_error(format!(
+                    "element segment {} refers to an unknown function {:?}",
+                    idx, label
+                )));
+            }
+        }
+    }
+    let defined: Vec<usize> = (0..module.funcs.len())
+        .filter(|idx| module.funcs[*idx].import.is_none())
+        .collect();
+    for (i, fn_idx) in defined.iter().enumerate() {
+        let start = module.funcs[*fn_idx].code_addr;
+        let end = match defined.get(i + 1) {
+            Some(next) => module.funcs[*next].code_addr,
+            None => module.code.len(),
+        };
+        FnValidator::new(module, blks_table, *fn_idx).validate(start, end)?;
+    }
+    Ok(())
+}
+
+fn module_error(message: String) -> ValidationError {
+    ValidationError {
+        fn_idx: None,
+        fn_name: None,
+        pc: None,
+        op: None,
+        message,
+    }
+}
+
+/// Constant expressions may only read imported immutable globals and use extended-const arithmetic
+fn validate_const_expr(
+    module: &Mod,
+    expr: &[OP],
+    expected: ValType,
+    context: &str,
+) -> Result<(), ValidationError> {
+    let mut types: Vec<ValType> = Vec::new();
+    for op in expr {
+        match op {
+            I32CONST(_) => types.push(ValType::I32),
+            I64CONST(_) => types.push(ValType::I64),
+            F32CONST(_) => types.push(ValType::F32),
+            F64CONST(_) => types.push(ValType::F64),
+            GLOGET(label) => {
+                let idx = match label {
+                    Label::REF(id) => module.globals_map.get(id).copied(),
+                    Label::U32(idx) => Some(*idx).filter(|idx| *idx < module.globals.len()),
+                };
+                let imported = |idx: usize| {
+                    module.imports.iter().any(|import| {
+                        import.import_type == ExportType::GLOBAL && import.import_ref == idx
+                    })
+                };
+                match idx {
+                    Some(idx) if imported(idx) && !module.globals[idx].mutable => {
+                        types.push(module.globals[idx].ty)
+                    }
+                    _ => {
+                        return Err(module_error(format!(
+                            "{}: constant expression can only read imported immutable globals, saw {:?}",
+                            context, label
+                        )))
+                    }
+                }
+            }
+            I32ADD | I32SUB | I32MUL | I64ADD | I64SUB | I64MUL => {
+                let ty = if matches!(op, I32ADD | I32SUB | I32MUL) {
+                    ValType::I32
+                } else {
+                    ValType::I64
+                };
+                let rhs = types.pop();
+                let lhs = types.pop();
+                if lhs != Some(ty) || rhs != Some(ty) {
+                    return Err(module_error(format!(
+                        "{}: type mismatch in constant expression at {:?}",
+                        context, op
+                    )));
+                }
+                types.push(ty);
+            }
+            _ => {
+                return Err(module_error(format!(
+                    "{}: {:?} isn't a constant instruction",
+                    context, op
+                )))
+            }
+        }
+    }
+    if types != vec![expected] {
+        return Err(module_error(format!(
+            "{}: constant expression should produce a single {}, produces {:?}",
+            context, expected, types
+        )));
+    }
+    Ok(())
+}
+
+struct CtrlFrame {
+    blk_idx: Option<usize>, // None for the function body itself
+    is_loop: bool,
+    is_if: bool,
+    start_types: Vec<ValType>,
+    end_types: Vec<ValType>,
+    height: usize,
+    unreachable: bool,
+}
+impl CtrlFrame {
+    fn label_types(&self) -> Vec<ValType> {
+        if self.is_loop {
+            self.start_types.clone()
+        } else {
+            self.end_types.clone()
+        }
+    }
+}
+
+struct FnValidator<'a> {
+    module: &'a Mod,
+    blks_table: &'a BlockTable,
+    fn_idx: usize,
+    locals: Vec<ValType>,
+    pc: usize,
+    opds: Vec<Option<ValType>>, // None is a value of unknown type below an unreachable instruction
+    ctrls: Vec<CtrlFrame>,
+}
+
+impl<'a> FnValidator<'a> {
+    fn new(module: &'a Mod, blks_table: &'a BlockTable, fn_idx: usize) -> Self {
+        let function = &module.funcs[fn_idx];
+        let mut locals = function.ty.params.clone();
+        locals.extend(function.locals.iter());
+        Self {
+            module,
+            blks_table,
+            fn_idx,
+            locals,
+            pc: 0,
+            opds: Vec::new(),
+            ctrls: Vec::new(),
+        }
+    }
+
+    fn error(&self, message: String) -> ValidationError {
+        ValidationError {
+            fn_idx: Some(self.fn_idx),
+            fn_name: self.module.funcs[self.fn_idx].name.clone(),
+            pc: Some(self.pc),
+            op: self.module.code.get(self.pc).cloned(),
+            message,
+        }
+    }
+
+    fn push(&mut self, ty: ValType) {
+        self.opds.push(Some(ty));
+    }
+    fn pushes(&mut self, types: &[ValType]) {
+        for ty in types {
+            self.push(*ty);
+        }
+    }
+    fn pop(&mut self) -> Result<Option<ValType>, ValidationError> {
+        let frame = self.ctrls.last().unwrap();
+        if self.opds.len() == frame.height {
+            if frame.unreachable {
+                return Ok(None);
+            }
+            return Err(self.error(String::from("type mismatch, operand stack is empty")));
+        }
+        Ok(self.opds.pop().unwrap())
+    }
+    fn pop_expect(&mut self, expected: ValType) -> Result<(), ValidationError> {
+        match self.pop()? {
+            Some(actual) if actual != expected => Err(self.error(format!(
+                "type mismatch, expected {} but found {}",
+                expected, actual
+            ))),
+            _ => Ok(()),
+        }
+    }
+    fn pops(&mut self, types: &[ValType]) -> Result<(), ValidationError> {
+        for ty in types.iter().rev() {
+            self.pop_expect(*ty)?;
+        }
+        Ok(())
+    }
+    fn push_ctrl(&mut self, blk_idx: Option<usize>, is_if: bool) {
+        let (is_loop, start_types, end_types) = match blk_idx {
+            Some(blk_idx) => {
+                let blk = &self.blks_table[self.fn_idx][blk_idx];
+                (blk.is_loop, blk.ty.params.clone(), blk.ty.results.clone())
+            }
+            None => (false, Vec::new(), self.module.funcs[self.fn_idx].ty.results.clone()),
+        };
+        self.ctrls.push(CtrlFrame {
+            blk_idx,
+            is_loop,
+            is_if,
+            start_types: start_types.clone(),
+            end_types,
+            height: self.opds.len(),
+            unreachable: false,
+        });
+        self.pushes(&start_types);
+    }
+    fn pop_ctrl(&mut self) -> Result<CtrlFrame, ValidationError> {
+        if self.ctrls.is_empty() {
+            return Err(self.error(String::from("end without a matching block")));
+        }
+        let end_types = self.ctrls.last().unwrap().end_types.clone();
+        self.pops(&end_types)?;
+        let frame = self.ctrls.last().unwrap();
+        if self.opds.len() != frame.height {
+            return Err(self.error(format!(
+                "type mismatch, {} values left on the stack at the end of the block",
+                self.opds.len() - frame.height
+            )));
+        }
+        Ok(self.ctrls.pop().unwrap())
+    }
+    fn set_unreachable(&mut self) {
+        let frame = self.ctrls.last_mut().unwrap();
+        self.opds.truncate(frame.height);
+        frame.unreachable = true;
+    }
+    fn label_types(&self, blk_idx: usize) -> Result<Vec<ValType>, ValidationError> {
+        match self.ctrls.iter().rev().find(|frame| frame.blk_idx == Some(blk_idx)) {
+            Some(frame) => Ok(frame.label_types()),
+            None => Err(self.error(format!("branch to block {} which doesn't enclose it", blk_idx))),
+        }
+    }
+    fn global_idx(&self, label: &Label) -> Result<usize, ValidationError> {
+        let idx = match label {
+            Label::REF(id) => self.module.globals_map.get(id).copied(),
+            Label::U32(idx) => Some(*idx).filter(|idx| *idx < self.module.globals.len()),
+        };
+        idx.ok_or_else(|| self.error(format!("unknown global {:?}", label)))
+    }
+    fn check_memory(&self) -> Result<(), ValidationError> {
+        if self.module.memory.is_none() {
+            return Err(self.error(String::from("memory access without a memory")));
+        }
+        Ok(())
+    }
+    fn check_local(&self, idx: usize) -> Result<ValType, ValidationError> {
+        match self.locals.get(idx) {
+            Some(ty) => Ok(*ty),
+            None => Err(self.error(format!("unknown local {}", idx))),
+        }
+    }
+
+    fn validate(mut self, start: usize, end: usize) -> Result<(), ValidationError> {
+        self.pc = start;
+        self.push_ctrl(None, false);
+        while self.pc < end {
+            let op = self.module.code[self.pc].clone();
+            if self.ctrls.is_empty() {
+                return Err(self.error(String::from("instruction after the end of the function")));
+            }
+            self.validate_op(op, self.pc == end - 1)?;
+            self.pc += 1;
+        }
+        if !self.ctrls.is_empty() {
+            self.pc = end - 1;
+            return Err(self.error(String::from("block isn't terminated by end")));
+        }
+        Ok(())
+    }
+
+    fn validate_op(&mut self, op: OP, is_fn_end: bool) -> Result<(), ValidationError> {
+        if op.kind() == OpKind::Memory {
+            self.check_memory()?;
+        }
+        match op {
+            I32CONST(_) => self.push(ValType::I32),
+            I64CONST(_) => self.push(ValType::I64),
+            F32CONST(_) => self.push(ValType::F32),
+            F64CONST(_) => self.push(ValType::F64),
+            I32ADD | I32SUB | I32MUL | I32DIVS | I32DIVU | I32REMS | I32REMU | I32AND | I32OR
+            | I32XOR | I32EQ | I32NE | I32LTS | I32GTS | I32LES | I32GES => {
+                self.pops(&[ValType::I32, ValType::I32])?;
+                self.push(ValType::I32);
+            }
+            I32EQZ => {
+                self.pop_expect(ValType::I32)?;
+                self.push(ValType::I32);
+            }
+            I64ADD | I64SUB | I64MUL => {
+                self.pops(&[ValType::I64, ValType::I64])?;
+                self.push(ValType::I64);
+            }
+            LOCGET(idx) => {
+                let ty = self.check_local(idx)?;
+                self.push(ty);
+            }
+            LOCSET(idx) => {
+                let ty = self.check_local(idx)?;
+                self.pop_expect(ty)?;
+            }
+            LOCTEE(idx) => {
+                let ty = self.check_local(idx)?;
+                self.pop_expect(ty)?;
+                self.push(ty);
+            }
+            GLOGET(label) => {
+                let idx = self.global_idx(&label)?;
+                self.push(self.module.globals[idx].ty);
+            }
+            GLOSET(label) => {
+                let idx = self.global_idx(&label)?;
+                if !self.module.globals[idx].mutable {
+                    return Err(self.error(format!("global {:?} is immutable", label)));
+                }
+                self.pop_expect(self.module.globals[idx].ty)?;
+            }
+            I32LOAD => {
+                self.pop_expect(ValType::I32)?;
+                self.push(ValType::I32);
+            }
+            I32STORE => {
+                self.pops(&[ValType::I32, ValType::I32])?;
+            }
+            BLK(blk_idx) | LOOP(blk_idx) => {
+                let params = self.blks_table[self.fn_idx][blk_idx].ty.params.clone();
+                self.pops(&params)?;
+                self.push_ctrl(Some(blk_idx), false);
+            }
+            IF(blk_idx) => {
+                self.pop_expect(ValType::I32)?;
+                let params = self.blks_table[self.fn_idx][blk_idx].ty.params.clone();
+                self.pops(&params)?;
+                self.push_ctrl(Some(blk_idx), true);
+            }
+            ELSE(blk_idx) => {
+                match self.ctrls.last() {
+                    Some(frame) if frame.is_if && frame.blk_idx == Some(blk_idx) => {}
+                    _ => return Err(self.error(String::from("else without a matching if"))),
+                }
+                self.pop_ctrl()?;
+                self.push_ctrl(Some(blk_idx), false);
+            }
+            END => {
+                if self.ctrls.len() == 1 {
+                    return Err(self.error(String::from("end without a matching block")));
+                }
+                let frame = self.pop_ctrl()?;
+                if frame.is_if && frame.start_types != frame.end_types {
+                    return Err(self.error(String::from(
+                        "if without else has to leave its parameters as its results",
+                    )));
+                }
+                self.pushes(&frame.end_types);
+            }
+            BR(blk_idx) => {
+                let types = self.label_types(blk_idx)?;
+                self.pops(&types)?;
+                self.set_unreachable();
+            }
+            BRIF(blk_idx) => {
+                self.pop_expect(ValType::I32)?;
+                let types = self.label_types(blk_idx)?;
+                self.pops(&types)?;
+                self.pushes(&types);
+            }
+            RET => {
+                let results = self.module.funcs[self.fn_idx].ty.results.clone();
+                if is_fn_end {
+                    // the RET closing the function body acts as its end
+                    if self.ctrls.len() > 1 {
+                        return Err(self.error(String::from("block isn't terminated by end")));
+                    }
+                    self.pop_ctrl()?;
+                } else {
+                    self.pops(&results)?;
+                    self.set_unreachable();
+                }
+            }
+            CALL(label) => {
+                // imported functions are in `funcs` too, with the type their import declares
+                let callee = match &label {
+                    Label::REF(id) => match self.module.funcs_refs.get(id) {
+                        Some(idx) => *idx,
+                        None => return Err(self.error(format!("unknown function ${}", id))),
+                    },
+                    Label::U32(idx) if *idx < self.module.funcs.len() => *idx,
+                    Label::U32(idx) => return Err(self.error(format!("unknown function {}", idx))),
+                };
+                let ty = self.module.funcs[callee].ty.clone();
+                self.pops(&ty.params)?;
+                self.pushes(&ty.results);
+            }
+            CALLIND(type_idx) => {
+                if self.module.table.is_none() {
+                    return Err(self.error(String::from("call_indirect without a table")));
+                }
+                let ty = match self.module.types.get(type_idx) {
+                    Some(ty) => ty.clone(),
+                    None => return Err(self.error(format!("unknown type {}", type_idx))),
+                };
+                self.pop_expect(ValType::I32)?;
+                self.pops(&ty.params)?;
+                self.pushes(&ty.results);
+            }
+            DROP => {
+                self.pop()?;
+            }
+            NOP => {}
+            UNR => self.set_unreachable(),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,18 @@
+// opcodes and export kinds are spelled like the spec's upper case mnemonics
+#![allow(clippy::upper_case_acronyms, clippy::enum_variant_names, clippy::module_inception)]
+mod api;
+mod interpret;
+mod linker;
+mod wasi;
+mod wast;
+#[cfg(test)]
+mod tests;
+
+pub use api::{Caller, Error, ExportDesc, ExternType, Frame, FuncType, Imports, Instance, Module, ValType, Value};
+pub use interpret::host::HostAction;
+pub use interpret::runtime::InterruptHandle;
+pub use interpret::trace::{TraceFormat, Tracer};
+pub use interpret::trap::Trap;
+pub use linker::{InstanceHandle, Linker, Store};
+pub use wasi::{MemoryFs, OutputBuffer, Wasi};
+pub use wast::{run_wast, WastFailure, WastReport};
@@ -1,11 +1,11 @@
 /// file used from Thomas Peters
-pub struct LineReader {
-    rl: rustyline::Editor<()>,
+pub struct LineReader<H: rustyline::Helper> {
+    rl: rustyline::Editor<H>,
     history_file: String,
     prompt: String,
 }
 
-impl Drop for LineReader {
+impl<H: rustyline::Helper> Drop for LineReader<H> {
     fn drop(&mut self) {
         self.rl.save_history(&self.history_file).ok();
     }
@@ -16,9 +16,10 @@ pub enum LineReadStatus {
     Done,
 }
 
-impl LineReader {
-    pub fn new(history_file: &str, prompt: &str) -> LineReader {
-        let mut rl = rustyline::Editor::<()>::new();
+impl<H: rustyline::Helper> LineReader<H> {
+    pub fn new(history_file: &str, prompt: &str, helper: H) -> LineReader<H> {
+        let mut rl = rustyline::Editor::<H>::new();
+        rl.set_helper(Some(helper));
         rl.load_history(history_file).ok();
         LineReader {
             rl,
@@ -27,6 +28,10 @@ impl LineReader {
         }
     }
 
+    pub fn helper_mut(&mut self) -> &mut H {
+        self.rl.helper_mut().unwrap()
+    }
+
     pub fn readline(&mut self) -> LineReadStatus {
         let res = self.rl.readline(&self.prompt);
 
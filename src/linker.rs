@@ -0,0 +1,417 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use crate::api::{export_of, exports_of, fn_index, global_index, invoke_export, trap_error, Error, ExportDesc, ExternType, Imports, Module};
+use crate::interpret::{
+    ast::ast::{ExportType, FuncType, Import, Mod},
+    host::{HostAction, HostFunc},
+    runtime::{Evaluator, FuncRef, InterruptHandle, Value, PAGE},
+    trap::Trap,
+};
+
+/// Where a function is defined: an instance of the store and a function index of its module
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FuncAddr {
+    instance: usize,
+    fn_idx: usize,
+}
+
+type SharedMemory = Arc<Mutex<Vec<u8>>>;
+type SharedGlobal = Arc<Mutex<Value>>;
+type SharedTable = Arc<Mutex<Vec<Option<FuncAddr>>>>;
+
+/// The memory and globals of an instance, which other instances may have imported.
+/// They move into the evaluator while it runs and back out when control leaves it.
+#[derive(Debug, Clone)]
+struct Slots {
+    memory: Option<SharedMemory>,
+    globals: Vec<SharedGlobal>,
+}
+
+impl Slots {
+    fn enter(&self, evaluator: &mut Evaluator) {
+        if let Some(memory) = &self.memory {
+            evaluator.memory.bytes = mem::take(&mut *memory.lock().unwrap());
+        }
+        for (global, slot) in evaluator.globals.iter_mut().zip(self.globals.iter()) {
+            *global = *slot.lock().unwrap();
+        }
+    }
+    fn leave(&self, evaluator: &mut Evaluator) {
+        if let Some(memory) = &self.memory {
+            *memory.lock().unwrap() = mem::take(&mut evaluator.memory.bytes);
+        }
+        for (global, slot) in evaluator.globals.iter().zip(self.globals.iter()) {
+            *slot.lock().unwrap() = *global;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Linked {
+    evaluator: Arc<Mutex<Evaluator>>,
+    slots: Slots,
+    table: Option<SharedTable>, // elements are only written while instantiating
+    funcs: Vec<FuncAddr>,       // per function index, imports point at the exporting instance
+}
+
+/// A host function that runs a function of another instance, handing the shared state over
+fn forward(caller: &Slots, callee: &Linked, fn_idx: usize, ty: FuncType) -> HostFunc {
+    let caller = caller.clone();
+    let callee = callee.clone();
+    HostFunc::new(ty, move |evaluator, args| {
+        let mut target = match callee.evaluator.try_lock() {
+            Ok(target) => target,
+            Err(_) => {
+                return Err(Trap::Host(String::from(
+                    "re-entrant calls between linked instances aren't supported",
+                )))
+            }
+        };
+        caller.leave(evaluator);
+        callee.slots.enter(&mut target);
+        let results = target.invoke(fn_idx, args.to_vec());
+        callee.slots.leave(&mut target);
+        caller.enter(evaluator);
+        if results.is_err() {
+            // the trap's backtrace goes on into the callee
+            evaluator.callee_frames = mem::take(&mut target.backtrace);
+        }
+        results.map(HostAction::Return)
+    })
+}
+
+/// Identifies an instance of a `Store`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InstanceHandle(usize);
+
+/// Owns the instances created by a `Linker`, imported memories and globals are shared with the exporter
+#[derive(Debug)]
+pub struct Store {
+    instances: Vec<Linked>,
+    interrupt: InterruptHandle, // shared by the evaluators of every instance
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Self {
+            instances: Vec::new(),
+            interrupt: InterruptHandle::unshared(),
+        }
+    }
+
+    /// Calls an exported function of `instance` with arguments matching its signature
+    pub fn invoke(&mut self, instance: InstanceHandle, name: &str, args: &[Value]) -> Result<Vec<Value>, Error> {
+        let linked = &self.instances[instance.0];
+        let mut evaluator = linked.evaluator.lock().unwrap();
+        linked.slots.enter(&mut evaluator);
+        let results = invoke_export(&mut evaluator, name, args);
+        linked.slots.leave(&mut evaluator);
+        results
+    }
+
+    pub fn export(&self, instance: InstanceHandle, name: &str) -> Option<ExternType> {
+        export_of(&self.instances[instance.0].evaluator.lock().unwrap().module, name)
+    }
+
+    /// Every export of `instance` sorted by name
+    pub fn exports(&self, instance: InstanceHandle) -> Vec<ExportDesc> {
+        exports_of(&self.instances[instance.0].evaluator.lock().unwrap().module)
+    }
+
+    /// The current value of an exported global
+    pub fn global(&self, instance: InstanceHandle, name: &str) -> Option<Value> {
+        let linked = &self.instances[instance.0];
+        let global_idx = global_index(&linked.evaluator.lock().unwrap().module, name)?;
+        let value = *linked.slots.globals[global_idx].lock().unwrap();
+        Some(value)
+    }
+
+    /// A copy of the linear memory of `instance`, `None` if it has none
+    pub fn memory(&self, instance: InstanceHandle) -> Option<Vec<u8>> {
+        let memory = self.instances[instance.0].slots.memory.as_ref()?;
+        let bytes = memory.lock().unwrap().clone();
+        Some(bytes)
+    }
+
+    /// Stops the running invocation with a trap, whichever instance it is in
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// The table elements as `instance` calls them, its own functions directly and others forwarded
+    fn local_table(&self, instance: usize, table: &[Option<FuncAddr>]) -> Vec<Option<FuncRef>> {
+        let linked = &self.instances[instance];
+        let element = |addr: &FuncAddr| {
+            if let Some(fn_idx) = linked.funcs.iter().position(|func| func == addr) {
+                return FuncRef::Local(fn_idx);
+            }
+            let callee = &self.instances[addr.instance];
+            let ty = callee.evaluator.lock().unwrap().module.funcs[addr.fn_idx].ty.clone();
+            FuncRef::Host(forward(&linked.slots, callee, addr.fn_idx, ty))
+        };
+        table.iter().map(|addr| addr.as_ref().map(element)).collect()
+    }
+
+    /// Rebuilds every instance's copy of its table after an instantiation wrote elements
+    fn refresh_tables(&self) {
+        for (instance, linked) in self.instances.iter().enumerate() {
+            if let Some(table) = &linked.table {
+                let elements = self.local_table(instance, &table.lock().unwrap());
+                linked.evaluator.lock().unwrap().table = elements;
+            }
+        }
+    }
+}
+
+/// An import satisfied by the host or by an instance of the store
+enum Resolved {
+    HostFunc(HostFunc),
+    Func(FuncAddr),
+    Global(SharedGlobal),
+    Memory(SharedMemory),
+    Table(SharedTable),
+}
+
+fn kind(export_type: &ExportType) -> &'static str {
+    match export_type {
+        ExportType::FUNCTION => "function",
+        ExportType::GLOBAL => "global",
+        ExportType::MEMORY => "memory",
+        ExportType::TABLE => "table",
+    }
+}
+
+fn unknown_import(import: &Import) -> Error {
+    Error::Link(format!("unknown import {}.{}", import.module, import.name))
+}
+
+fn incompatible_import(import: &Import, expected: String, saw: String) -> Error {
+    Error::Link(format!(
+        "incompatible import type for {}.{}: expected {}, saw {}",
+        import.module, import.name, expected, saw
+    ))
+}
+
+/// Instantiates modules against the exports of instances registered under a module name,
+/// imports from any other module name are looked up in the host definitions.
+///
+/// Re-entrant calls between linked instances aren't supported: a call that comes back into an
+/// instance already running, e.g. through a shared table, traps instead of running it
+#[derive(Debug, Default)]
+pub struct Linker {
+    instances: HashMap<String, InstanceHandle>,
+    host: Imports,
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds host functions and globals, replacing earlier definitions of the same name
+    pub fn define(&mut self, imports: &Imports) -> &mut Self {
+        self.host.funcs.extend(imports.funcs.clone());
+        self.host.globals.extend(imports.globals.clone());
+        self
+    }
+
+    /// Makes the exports of `instance` importable from the module `name`
+    pub fn register(&mut self, name: &str, instance: InstanceHandle) -> &mut Self {
+        self.instances.insert(name.to_string(), instance);
+        self
+    }
+
+    /// The instance registered under `name`
+    pub fn get(&self, name: &str) -> Option<InstanceHandle> {
+        self.instances.get(name).copied()
+    }
+
+    /// Resolves the imports, initialises globals and segments and runs the start function.
+    /// Segments written before a trap stay in shared memories and tables, as the spec asks.
+    pub fn instantiate(&self, store: &mut Store, module: &Module) -> Result<InstanceHandle, Error> {
+        let parsed = &module.module;
+        let resolved = parsed
+            .imports
+            .iter()
+            .map(|import| self.resolve(store, parsed, import))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let instance = store.instances.len();
+        let mut funcs: Vec<FuncAddr> = (0..parsed.funcs.len()).map(|fn_idx| FuncAddr { instance, fn_idx }).collect();
+        let mut globals: Vec<Option<SharedGlobal>> = vec![None; parsed.globals.len()];
+        let (mut memory, mut table) = (None, None);
+        for (import, resolved) in parsed.imports.iter().zip(resolved.iter()) {
+            match resolved {
+                Resolved::Func(addr) => funcs[import.import_ref] = *addr,
+                Resolved::Global(slot) => globals[import.import_ref] = Some(slot.clone()),
+                Resolved::Memory(slot) => memory = Some(slot.clone()),
+                Resolved::Table(slot) => table = Some(slot.clone()),
+                Resolved::HostFunc(_) => {}
+            }
+        }
+        // imported globals come first
+        let imported_globals: Vec<Value> = globals.iter().map_while(|slot| Some(*slot.as_ref()?.lock().unwrap())).collect();
+        let mut evaluator = Evaluator::instantiate(parsed.clone(), module.blks_table.clone(), &imported_globals);
+        evaluator.share_interrupt(&store.interrupt);
+        let globals = globals
+            .into_iter()
+            .zip(evaluator.globals.iter())
+            .map(|(slot, value)| slot.unwrap_or_else(|| Arc::new(Mutex::new(*value))))
+            .collect();
+        if memory.is_none() && parsed.memory.is_some() {
+            memory = Some(Arc::new(Mutex::new(mem::take(&mut evaluator.memory.bytes))));
+        }
+        if let (None, Some(defined)) = (&table, &parsed.table) {
+            table = Some(Arc::new(Mutex::new(vec![None; defined.initial_size as usize])));
+        }
+        let slots = Slots { memory, globals };
+
+        evaluator.host_funcs = parsed
+            .imports
+            .iter()
+            .zip(resolved)
+            .map(|(import, resolved)| match resolved {
+                Resolved::HostFunc(host_fn) => Some(host_fn),
+                Resolved::Func(addr) => {
+                    let ty = parsed.funcs[import.import_ref].ty.clone();
+                    Some(forward(&slots, &store.instances[addr.instance], addr.fn_idx, ty))
+                }
+                _ => None,
+            })
+            .collect();
+        let linked = Linked {
+            evaluator: Arc::new(Mutex::new(evaluator)),
+            slots,
+            table,
+            funcs,
+        };
+        // a failed instantiation stays in the store, the shared table may refer to its functions
+        store.instances.push(linked.clone());
+
+        let mut evaluator = linked.evaluator.lock().unwrap();
+        linked.slots.enter(&mut evaluator);
+        let mut elements = Vec::new();
+        if let Some(table) = &linked.table {
+            elements = table.lock().unwrap().clone();
+            evaluator.table = store.local_table(instance, &elements);
+        }
+        let initialised = evaluator.init_segments();
+        linked.slots.leave(&mut evaluator);
+        if let Some(table) = &linked.table {
+            let written = evaluator.table.iter().zip(elements).map(|(element, addr)| match element {
+                Some(FuncRef::Local(fn_idx)) => Some(linked.funcs[*fn_idx]),
+                Some(FuncRef::Host(_)) => addr,
+                None => None,
+            });
+            *table.lock().unwrap() = written.collect();
+        }
+        drop(evaluator);
+        store.refresh_tables();
+        initialised.map_err(|trap| Error::Trap(trap, Vec::new()))?;
+
+        if let Some(start) = parsed.start {
+            let mut evaluator = linked.evaluator.lock().unwrap();
+            linked.slots.enter(&mut evaluator);
+            let started = evaluator.invoke(start, Vec::new()).map_err(|trap| trap_error(&mut evaluator, trap));
+            linked.slots.leave(&mut evaluator);
+            started?;
+        }
+        Ok(InstanceHandle(instance))
+    }
+
+    fn resolve(&self, store: &Store, module: &Mod, import: &Import) -> Result<Resolved, Error> {
+        let handle = match self.instances.get(&import.module) {
+            Some(handle) => handle,
+            None => return self.resolve_host(module, import),
+        };
+        let exporter = &store.instances[handle.0];
+        let evaluator = exporter.evaluator.lock().unwrap();
+        let exports = &evaluator.module;
+        let export = exports.exports.get(&import.name).ok_or_else(|| unknown_import(import))?;
+        if export.export_type != import.import_type {
+            let (expected, saw) = (kind(&import.import_type), kind(&export.export_type));
+            return Err(incompatible_import(import, format!("a {}", expected), format!("a {}", saw)));
+        }
+        match import.import_type {
+            ExportType::FUNCTION => {
+                let fn_idx = fn_index(exports, &export.export_ref);
+                let (expected, saw) = (&module.funcs[import.import_ref].ty, &exports.funcs[fn_idx].ty);
+                if expected != saw {
+                    return Err(incompatible_import(import, format!("{:?}", expected), format!("{:?}", saw)));
+                }
+                Ok(Resolved::Func(exporter.funcs[fn_idx]))
+            }
+            ExportType::GLOBAL => {
+                let global_idx = exports.get_global(&export.export_ref);
+                let (expected, saw) = (&module.globals[import.import_ref], &exports.globals[global_idx]);
+                if expected.ty != saw.ty || expected.mutable != saw.mutable {
+                    let describe = |mutable: bool, ty| if mutable { format!("mut {}", ty) } else { format!("{}", ty) };
+                    return Err(incompatible_import(
+                        import,
+                        describe(expected.mutable, expected.ty),
+                        describe(saw.mutable, saw.ty),
+                    ));
+                }
+                Ok(Resolved::Global(exporter.slots.globals[global_idx].clone()))
+            }
+            ExportType::MEMORY => {
+                let slot = exporter.slots.memory.clone().ok_or_else(|| unknown_import(import))?;
+                let pages = slot.lock().unwrap().len() / PAGE as usize;
+                let minimum = module.memory.as_ref().map_or(0, |mem| mem.initial_capacity) as usize;
+                if pages < minimum {
+                    return Err(incompatible_import(
+                        import,
+                        format!("at least {} pages", minimum),
+                        format!("{} pages", pages),
+                    ));
+                }
+                Ok(Resolved::Memory(slot))
+            }
+            ExportType::TABLE => {
+                let slot = exporter.table.clone().ok_or_else(|| unknown_import(import))?;
+                let size = slot.lock().unwrap().len();
+                let minimum = module.table.as_ref().map_or(0, |table| table.initial_size) as usize;
+                if size < minimum {
+                    return Err(incompatible_import(
+                        import,
+                        format!("at least {} elements", minimum),
+                        format!("{} elements", size),
+                    ));
+                }
+                Ok(Resolved::Table(slot))
+            }
+        }
+    }
+
+    fn resolve_host(&self, module: &Mod, import: &Import) -> Result<Resolved, Error> {
+        let key = (import.module.clone(), import.name.clone());
+        match import.import_type {
+            ExportType::FUNCTION => {
+                let host_fn = self.host.funcs.get(&key).ok_or_else(|| unknown_import(import))?;
+                let expected = &module.funcs[import.import_ref].ty;
+                if host_fn.ty != *expected {
+                    return Err(incompatible_import(import, format!("{:?}", expected), format!("{:?}", host_fn.ty)));
+                }
+                Ok(Resolved::HostFunc(host_fn.clone()))
+            }
+            ExportType::GLOBAL => {
+                let value = self.host.globals.get(&key).ok_or_else(|| unknown_import(import))?;
+                let expected = module.globals[import.import_ref].ty;
+                if value.ty() != expected {
+                    return Err(incompatible_import(import, format!("{}", expected), format!("{}", value.ty())));
+                }
+                Ok(Resolved::Global(Arc::new(Mutex::new(*value))))
+            }
+            // only instances export memories and tables
+            ExportType::MEMORY | ExportType::TABLE => Err(unknown_import(import)),
+        }
+    }
+}
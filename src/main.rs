@@ -1,7 +1,38 @@
+mod cli;
 mod line_reader;
-mod interpret;
 mod repl;
-mod tests;
+
+use cli::Command;
+
 fn main() {
-    repl::main::run();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match cli::parse_args(&args) {
+        Ok(Command::Repl {
+            file,
+            script: None,
+            trace,
+        }) => repl::main::run(&file, &trace),
+        Ok(Command::Repl {
+            file,
+            script: Some(script),
+            trace,
+        }) => repl::script::run(&file, &script, &trace),
+        Ok(Command::Run(options)) => std::process::exit(cli::run(&options)),
+        Ok(Command::Wast { files }) => std::process::exit(cli::wast(&files)),
+        Err(usage) => Err(usage),
+    };
+    if let Err(msg) = result {
+        eprintln!("{}", msg);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod test_cli;
+    mod test_helper;
+    mod test_memory;
+    mod test_repl_parser;
+    mod test_script;
+    mod test_session;
 }
@@ -0,0 +1,256 @@
+use std::fs;
+
+use interperter::{Error, Frame, Instance, Value};
+
+use crate::repl::parser::format_results;
+
+/// The frames of a trap's backtrace shown, deep recursion would flood the terminal
+const BACKTRACE_LIMIT: usize = 20;
+
+/// Where the debugger stops a running call
+pub enum Point {
+    /// On entering the function, named without the `$` or by its index
+    Function(String),
+    /// On reaching a line of the `.wat` file
+    Line(usize),
+    /// When an exported global changes, with the value it was last seen with
+    Global(String, Value),
+    /// When bytes of memory change, with the bytes last seen
+    Memory { addr: usize, bytes: Vec<u8> },
+}
+
+impl Point {
+    fn describe(&self) -> String {
+        match self {
+            Point::Function(name) => format!("break at entry of {}", name),
+            Point::Line(line) => format!("break at line {}", line),
+            Point::Global(name, value) => format!("watch global {} = {}", name, typed(value)),
+            Point::Memory { addr, bytes } => format!("watch memory {:#x}..{:#x}", addr, addr + bytes.len()),
+        }
+    }
+}
+
+/// How far a paused call runs on
+#[derive(Clone, Copy)]
+pub enum Until {
+    /// One opcode, into calls
+    Step,
+    /// Until the next opcode of the current function, over calls
+    Next,
+    /// Until the current function returns
+    Finish,
+    /// Until a breakpoint or watchpoint is hit
+    Continue,
+}
+
+/// The breakpoints and watchpoints of a REPL session
+#[derive(Default)]
+pub struct Debugger {
+    pub points: Vec<Point>,
+}
+
+fn typed(value: &Value) -> String {
+    format!("{}:{}", value.ty(), value)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    bytes.join(" ")
+}
+
+fn line(frame: &Frame) -> Option<usize> {
+    frame.location.as_ref().map(|location| location.line)
+}
+
+/// The line of the source file, empty if it can't be read
+fn source_line(path: &str, line: usize) -> String {
+    let source = fs::read_to_string(path).unwrap_or_default();
+    source.lines().nth(line - 1).unwrap_or_default().trim().to_string()
+}
+
+impl Debugger {
+    pub fn is_active(&self) -> bool {
+        !self.points.is_empty()
+    }
+
+    pub fn add(&mut self, point: Point) {
+        println!("{}: {}", self.points.len() + 1, point.describe());
+        self.points.push(point);
+    }
+
+    /// Removes the point numbered like `list` shows, all of them without a number
+    pub fn delete(&mut self, number: Option<usize>) -> Result<(), String> {
+        match number {
+            Some(number) if (1..=self.points.len()).contains(&number) => {
+                let point = self.points.remove(number - 1);
+                println!("deleted {}", point.describe());
+            }
+            Some(number) => return Err(format!("no breakpoint or watchpoint {}", number)),
+            None => {
+                self.points.clear();
+                println!("deleted all breakpoints and watchpoints");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn list(&self) {
+        if self.points.is_empty() {
+            println!("no breakpoints or watchpoints");
+        }
+        for (i, point) in self.points.iter().enumerate() {
+            println!("{}: {}", i + 1, point.describe());
+        }
+    }
+
+    /// Starts a call paused before its first opcode, or running to the first stop when `paused`
+    /// is false
+    pub fn start(
+        &mut self,
+        instance: &mut Instance,
+        path: &str,
+        name: &str,
+        args: &[Value],
+        paused: bool,
+    ) -> Result<(), String> {
+        instance.start(name, args).map_err(report)?;
+        if paused || self.entered(instance) {
+            print_location(instance, path);
+            return Ok(());
+        }
+        self.resume(instance, path, Until::Continue)
+    }
+
+    /// Runs the paused call on, it stays paused where it stops unless it returns or traps
+    pub fn resume(&mut self, instance: &mut Instance, path: &str, until: Until) -> Result<(), String> {
+        let depth = instance.frames().len();
+        loop {
+            let before = instance.frames();
+            match instance.step() {
+                Ok(Some(results)) => {
+                    println!("{}", format_results(&results));
+                    return Ok(());
+                }
+                Ok(None) => {}
+                Err(error) => return Err(report(error)),
+            }
+            let frames = instance.frames();
+            // every watchpoint is checked so that all of them see the new values
+            let watched = self.watched(instance);
+            let arrived = match until {
+                Until::Step => true,
+                Until::Next => frames.len() <= depth,
+                Until::Finish => frames.len() < depth,
+                Until::Continue => false,
+            };
+            if watched || arrived || self.hit(&before, &frames) {
+                print_location(instance, path);
+                return Ok(());
+            }
+        }
+    }
+
+    /// Whether the function the call starts with has a breakpoint
+    fn entered(&self, instance: &Instance) -> bool {
+        self.hit(&[], &instance.frames())
+    }
+
+    /// Whether a breakpoint is reached going from `before` to `after`
+    fn hit(&self, before: &[Frame], after: &[Frame]) -> bool {
+        let top = match after.last() {
+            Some(top) => top,
+            None => return false,
+        };
+        let entered = after.len() > before.len();
+        let moved = entered || before.last().map(line) != Some(line(top));
+        self.points.iter().any(|point| match point {
+            Point::Function(name) => {
+                entered && (top.name.as_deref() == Some(name.as_str()) || *name == top.func.to_string())
+            }
+            Point::Line(number) => moved && line(top) == Some(*number),
+            _ => false,
+        })
+    }
+
+    /// Reports and remembers the watched globals and memory that changed
+    fn watched(&mut self, instance: &Instance) -> bool {
+        let mut changed = false;
+        for (i, point) in self.points.iter_mut().enumerate() {
+            match point {
+                Point::Global(name, last) => {
+                    let value = instance.global(name);
+                    if let Some(value) = value.filter(|value| value != last) {
+                        println!("{}: global {} changed from {} to {}", i + 1, name, typed(last), typed(&value));
+                        *last = value;
+                        changed = true;
+                    }
+                }
+                Point::Memory { addr, bytes } => {
+                    let memory = instance.memory().unwrap_or_default();
+                    let now = memory.get(*addr..*addr + bytes.len()).unwrap_or_default();
+                    let changed_at = |j: &usize| now[*j] != bytes[*j];
+                    let first = (0..now.len()).find(changed_at);
+                    if let (Some(first), Some(last)) = (first, (0..now.len()).rfind(changed_at)) {
+                        println!(
+                            "{}: memory {:#x} changed from {} to {}",
+                            i + 1,
+                            *addr + first,
+                            hex(&bytes[first..=last]),
+                            hex(&now[first..=last])
+                        );
+                        *bytes = now.to_vec();
+                        changed = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        changed
+    }
+}
+
+/// `$fac at fac.wat:15:9, pc 12: CALL(..)`
+fn describe(frame: &Frame) -> String {
+    match &frame.location {
+        Some(location) => format!("{} at {}, pc {}: {}", frame.function(), location, frame.pc, frame.op),
+        None => format!("{} at pc {}: {}", frame.function(), frame.pc, frame.op),
+    }
+}
+
+fn print_location(instance: &Instance, path: &str) {
+    let frames = instance.frames();
+    let top = frames.last().unwrap();
+    println!("paused in {}", describe(top));
+    if let Some(line) = line(top) {
+        println!("{:>5} | {}", line, source_line(path, line));
+    }
+}
+
+/// Prints the active functions, the innermost as #0
+pub fn backtrace(instance: &Instance) {
+    for (i, frame) in instance.frames().iter().rev().enumerate() {
+        println!("#{} {}", i, describe(frame));
+    }
+}
+
+/// The index `Instance::frames` uses for frame `number` of the backtrace
+pub fn frame_index(instance: &Instance, number: usize) -> Result<usize, String> {
+    let depth = instance.frames().len();
+    if number >= depth {
+        return Err(format!("no frame #{}, the call is {} deep", number, depth));
+    }
+    Ok(depth - 1 - number)
+}
+
+/// The message of a failed call followed by the backtrace when it trapped in guest code
+pub fn report(error: Error) -> String {
+    let backtrace = error.backtrace();
+    let mut lines = vec![error.to_string()];
+    for (i, frame) in backtrace.iter().take(BACKTRACE_LIMIT).enumerate() {
+        lines.push(format!("#{} {}", i, describe(frame)));
+    }
+    if backtrace.len() > BACKTRACE_LIMIT {
+        lines.push(format!("... {} more frames", backtrace.len() - BACKTRACE_LIMIT));
+    }
+    lines.join("\n")
+}
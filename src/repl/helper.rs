@@ -0,0 +1,184 @@
+use std::borrow::Cow;
+
+use interperter::{ExportDesc, ExternType, FuncType, ValType};
+use logos::Logos;
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::{Hint, Hinter};
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+use crate::repl::lexer::Token;
+use crate::repl::parser::META_COMMANDS;
+
+/// The commands that take the name of an export between their parentheses
+const NAMED_COMMANDS: [&str; 3] = ["exports.globals.get(", "exports.globals.set(", "exports.memory.get("];
+
+/// The commands that start with an address
+const MEMORY_COMMANDS: [&str; 4] = [
+    "exports.memory.set(",
+    "exports.memory.write(",
+    "exports.memory.dump(",
+    "exports.memory.struct(",
+];
+
+const BOLD: &str = "\x1b[1m";
+const CYAN: &str = "\x1b[36m";
+const YELLOW: &str = "\x1b[33m";
+const GREEN: &str = "\x1b[32m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Completes, hints and highlights REPL commands using the exports of the loaded module
+#[derive(Default)]
+pub struct ReplHelper {
+    pub exports: Vec<ExportDesc>,
+    filenames: FilenameCompleter,
+}
+
+/// The rest of a function's signature, it is only shown and never inserted
+pub struct SignatureHint(String);
+
+impl Hint for SignatureHint {
+    fn display(&self) -> &str {
+        &self.0
+    }
+    fn completion(&self) -> Option<&str> {
+        None
+    }
+}
+
+fn pair(replacement: String) -> Pair {
+    Pair {
+        display: replacement.clone(),
+        replacement,
+    }
+}
+
+impl ReplHelper {
+    fn names(&self, command: &str) -> impl Iterator<Item = &str> {
+        let global = command.starts_with("exports.globals");
+        self.exports
+            .iter()
+            .filter(move |export| match export.ty {
+                ExternType::Global { .. } => global,
+                ExternType::Memory { .. } => !global,
+                _ => false,
+            })
+            .map(|export| export.name.as_str())
+    }
+
+    fn functions(&self) -> impl Iterator<Item = (&str, &FuncType)> {
+        self.exports.iter().filter_map(|export| match &export.ty {
+            ExternType::Func(ty) => Some((export.name.as_str(), ty)),
+            _ => None,
+        })
+    }
+
+    fn signature(&self, name: &str) -> Option<&FuncType> {
+        self.functions().find(|(function, _)| *function == name).map(|(_, ty)| ty)
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before = &line[..pos];
+        if [":open ", ":save ", ":load "].iter().any(|command| before.starts_with(command)) {
+            return self.filenames.complete(line, pos, ctx);
+        }
+        if let Some(partial) = before.strip_prefix(':') {
+            let commands = META_COMMANDS.iter().filter(|command| command.starts_with(partial));
+            return Ok((1, commands.map(|command| pair(command.to_string())).collect()));
+        }
+        if let Some(open) = before.rfind('(') {
+            let (command, partial) = (&before[..open + 1], &before[open + 1..]);
+            if NAMED_COMMANDS.contains(&command) && !partial.contains([',', ')']) {
+                let names = self.names(command).filter(|name| name.starts_with(partial));
+                return Ok((open + 1, names.map(|name| pair(name.to_string())).collect()));
+            }
+            return Ok((pos, Vec::new()));
+        }
+        let commands = NAMED_COMMANDS.iter().chain(MEMORY_COMMANDS.iter()).map(|command| command.to_string());
+        let functions = self.functions().map(|(name, _)| format!("exports.functions.{}(", name));
+        let candidates = commands.chain(functions).filter(|command| command.starts_with(before));
+        Ok((0, candidates.map(pair).collect()))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = SignatureHint;
+
+    /// Shows the parameters still to be passed to a function and its results
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<SignatureHint> {
+        if pos < line.len() || line.contains(')') {
+            return None;
+        }
+        let call = line.strip_prefix("exports.functions.")?;
+        let (name, args) = call.split_once('(').map_or((call, None), |(name, args)| (name, Some(args)));
+        let ty = self.signature(name)?;
+        let results = format!(") -> ({})", join(&ty.results));
+        let args = match args {
+            Some(args) => args,
+            None => return Some(SignatureHint(format!("({}{}", join(&ty.params), results))),
+        };
+        let args: Vec<&str> = args.split(',').collect();
+        let (last, passed) = args.split_last().unwrap();
+        let typing = !last.trim().is_empty();
+        let remaining = ty.params.get(passed.len() + typing as usize..).unwrap_or(&[]);
+        let separator = if remaining.is_empty() {
+            ""
+        } else if typing {
+            ", "
+        } else if last.is_empty() && !passed.is_empty() {
+            " "
+        } else {
+            ""
+        };
+        Some(SignatureHint(format!("{}{}{}", separator, join(remaining), results)))
+    }
+}
+
+fn join(types: &[ValType]) -> String {
+    let types: Vec<String> = types.iter().map(|ty| ty.to_string()).collect();
+    types.join(", ")
+}
+
+impl Highlighter for ReplHelper {
+    /// Commands are bold, names cyan, numbers yellow and strings green
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if let Some(command) = line.strip_prefix(':') {
+            let end = command.find(' ').unwrap_or(command.len()) + 1;
+            return Cow::Owned(format!("{}{}{}{}", BOLD, &line[..end], RESET, &line[end..]));
+        }
+        let mut highlighted = String::new();
+        let mut last = 0;
+        for (token, span) in Token::lexer(line).spanned() {
+            let color = match token {
+                Ok(Token::Keyword(_)) => CYAN,
+                Ok(Token::Number(_)) => YELLOW,
+                Ok(Token::Str(_)) => GREEN,
+                Ok(Token::LParan | Token::RParan | Token::LBracket | Token::RBracket) | Err(_) => continue,
+                Ok(_) => BOLD,
+            };
+            highlighted.push_str(&line[last..span.start]);
+            highlighted.push_str(&format!("{}{}{}", color, &line[span.clone()], RESET));
+            last = span.end;
+        }
+        highlighted.push_str(&line[last..]);
+        Cow::Owned(highlighted)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{}{}{}", DIM, hint, RESET))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        true
+    }
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
@@ -1,10 +1,7 @@
-use std::ops::Deref;
-
 use logos::Logos;
 
 #[derive(Logos, Debug, PartialEq)]
 #[logos(skip r"[\s\t\n\f,]+")]
-
 pub enum Token {
     #[regex(r#"exports.functions.[a-zA-z0-9!#$%&`*+-./:<>=?@\^_'|~]+"#,  |lex| lex.slice()[18..].to_owned() )]
     Function(String),
@@ -15,16 +12,44 @@ pub enum Token {
     #[regex(r#"exports.memory.get\([a-zA-z0-9!#$%&`*+-./:<>=?@\^_'|~]+\)"#,  |lex| lex.slice()[19..lex.slice().len()-1].to_owned() )]
     GetMemory(String),
 
-    #[regex(r#"[a-zA-Z][a-zA-z0-9!#$%&`*+-./:<>=?@\^_'|~]+"#,  |lex| lex.slice()[..].to_owned() )]
+    #[token("exports.globals.set")]
+    SetGlobal,
+
+    #[token("exports.memory.set")]
+    SetMemory,
+
+    #[token("exports.memory.write")]
+    WriteMemory,
+
+    #[token("exports.memory.dump")]
+    DumpMemory,
+
+    #[token("exports.memory.struct")]
+    StructMemory,
+
+    // like a wat id but without the comma that separates arguments
+    #[regex(r#"[a-zA-Z][a-zA-Z0-9!#$%&`*+\-./:<>=?@\^_'|~]*"#,  |lex| lex.slice()[..].to_owned() )]
     Keyword(String),
 
-    #[regex(r#"-?[0-9]+"#, |lex| lex.slice().parse::<i32>().unwrap())]
-    Integer(i32),
+    // the literal's text, it is read according to the type it is used as
+    #[regex(r#"[+-]?(0[xX][0-9a-fA-F_]+|[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?)"#, |lex| lex.slice().to_owned())]
+    #[regex(r#"[+-](inf|nan)"#, |lex| lex.slice().to_owned())]
+    Number(String),
+
+    // the raw text between the quotes, escapes are resolved by the parser
+    #[regex(r#""([^"\\]|\\.)*""#, |lex| lex.slice()[1..lex.slice().len()-1].to_owned())]
+    Str(String),
 
     #[token("(")]
     LParan,
 
     #[token(")")]
     RParan,
+
+    #[token("[")]
+    LBracket,
+
+    #[token("]")]
+    RBracket,
 }
 
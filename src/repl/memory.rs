@@ -0,0 +1,190 @@
+use std::fmt::{self, Write};
+
+/// How a range of memory is read by `exports.memory.dump(addr, len) as view`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum View {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    U64,
+    F32,
+    F64,
+    CString, // up to the first zero byte
+    Utf8,
+}
+
+const VIEWS: [View; 12] = [
+    View::I8,
+    View::U8,
+    View::I16,
+    View::U16,
+    View::I32,
+    View::U32,
+    View::I64,
+    View::U64,
+    View::F32,
+    View::F64,
+    View::CString,
+    View::Utf8,
+];
+
+impl View {
+    pub fn parse(name: &str) -> Result<View, String> {
+        VIEWS.into_iter().find(|view| view.to_string() == name).ok_or(format!(
+            "no such view {}, expected one of i8 u8 i16 u16 i32 u32 i64 u64 f32 f64 cstring utf8",
+            name
+        ))
+    }
+
+    /// The size in bytes of a single value, strings are made of single bytes
+    fn size(self) -> usize {
+        match self {
+            View::I8 | View::U8 | View::CString | View::Utf8 => 1,
+            View::I16 | View::U16 => 2,
+            View::I32 | View::U32 | View::F32 => 4,
+            View::I64 | View::U64 | View::F64 => 8,
+        }
+    }
+
+    fn is_string(self) -> bool {
+        matches!(self, View::CString | View::Utf8)
+    }
+
+    /// Formats `bytes`, which hold exactly one number or a whole string
+    fn format(self, bytes: &[u8]) -> String {
+        match self {
+            View::I8 => (bytes[0] as i8).to_string(),
+            View::U8 => bytes[0].to_string(),
+            View::I16 => i16::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            View::U16 => u16::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            View::I32 => i32::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            View::U32 => u32::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            View::I64 => i64::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            View::U64 => u64::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            View::F32 => f32::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            View::F64 => f64::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            View::CString => {
+                let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+                format!("\"{}\"", bytes[..end].escape_ascii())
+            }
+            View::Utf8 => format!("{:?}", String::from_utf8_lossy(bytes)),
+        }
+    }
+}
+
+impl fmt::Display for View {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            View::I8 => "i8",
+            View::U8 => "u8",
+            View::I16 => "i16",
+            View::U16 => "u16",
+            View::I32 => "i32",
+            View::U32 => "u32",
+            View::I64 => "i64",
+            View::U64 => "u64",
+            View::F32 => "f32",
+            View::F64 => "f64",
+            View::CString => "cstring",
+            View::Utf8 => "utf8",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A field of `exports.memory.struct`, e.g. `x:i32` or `name:cstring[16]`
+#[derive(Debug)]
+pub struct Field {
+    pub name: String,
+    pub view: View,
+    pub count: Option<usize>, // the length of an array field
+}
+
+fn range(memory: &[u8], addr: usize, len: usize) -> Result<&[u8], String> {
+    memory.get(addr..addr.saturating_add(len)).ok_or(format!(
+        "{} bytes at {} are out of bounds of a {} byte memory",
+        len,
+        addr,
+        memory.len()
+    ))
+}
+
+/// A classic hexdump: 16 bytes a row with their address and an ASCII column
+pub fn hexdump(memory: &[u8], addr: usize, len: usize) -> Result<String, String> {
+    let mut dump = String::new();
+    for (row, bytes) in range(memory, addr, len)?.chunks(16).enumerate() {
+        write!(dump, "{:08x} ", addr + 16 * row).unwrap();
+        for i in 0..16 {
+            if i == 8 {
+                dump.push(' ');
+            }
+            match bytes.get(i) {
+                Some(byte) => write!(dump, " {:02x}", byte).unwrap(),
+                None => dump.push_str("   "),
+            }
+        }
+        let ascii: String = bytes
+            .iter()
+            .map(|byte| if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' })
+            .collect();
+        writeln!(dump, "  |{}|", ascii).unwrap();
+    }
+    Ok(dump)
+}
+
+/// The values in `len` bytes at `addr`, numbers take a row for every 16 bytes
+pub fn typed(memory: &[u8], addr: usize, len: usize, view: View) -> Result<String, String> {
+    let bytes = range(memory, addr, len)?;
+    if view.is_string() {
+        return Ok(format!("{:08x}: {}\n", addr, view.format(bytes)));
+    }
+    if !len.is_multiple_of(view.size()) {
+        return Err(format!("{} bytes don't divide into {} values of {} bytes", len, view, view.size()));
+    }
+    let mut rows = String::new();
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        let values: Vec<String> = chunk.chunks(view.size()).map(|value| view.format(value)).collect();
+        writeln!(rows, "{:08x}: {}", addr + 16 * row, values.join(" ")).unwrap();
+    }
+    Ok(rows)
+}
+
+/// Lays the fields out one after another the way C does, each aligned to the size of its
+/// values, and prints every field with its offset from `addr`
+pub fn layout(memory: &[u8], addr: usize, fields: &[Field]) -> Result<String, String> {
+    let mut offset: usize = 0;
+    let mut align = 1;
+    let mut lines = String::new();
+    for field in fields.iter() {
+        let size = field.view.size();
+        let len = match field.count {
+            Some(count) => size * count,
+            None if field.view.is_string() => {
+                return Err(format!("the string field {} needs a length, e.g. {}:cstring[16]", field.name, field.name))
+            }
+            None => size,
+        };
+        offset = offset.next_multiple_of(size);
+        align = align.max(size);
+        let bytes = range(memory, addr + offset, len)?;
+        let value = match field.count {
+            Some(_) if !field.view.is_string() => {
+                let values: Vec<String> = bytes.chunks(size).map(|value| field.view.format(value)).collect();
+                format!("[{}]", values.join(", "))
+            }
+            _ => field.view.format(bytes),
+        };
+        let ty = match field.count {
+            Some(count) => format!("{}[{}]", field.view, count),
+            None => field.view.to_string(),
+        };
+        writeln!(lines, "+{:<4} {}: {} = {}", offset, field.name, ty, value).unwrap();
+        offset += len;
+    }
+    writeln!(lines, "{} bytes", offset.next_multiple_of(align)).unwrap();
+    Ok(lines)
+}
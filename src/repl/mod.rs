@@ -1,3 +1,8 @@
+mod debugger;
+pub(crate) mod helper;
 pub mod lexer;
-mod parser;
-pub mod main;
\ No newline at end of file
+pub(crate) mod parser;
+pub mod main;
+pub(crate) mod memory;
+pub(crate) mod script;
+pub(crate) mod session;
@@ -1,17 +1,14 @@
-use std::{fmt::format, thread::panicking};
+use std::io;
 
+use interperter::{Error, ExternType, Instance, Tracer, ValType, Value};
 use log::debug;
 use logos::{Lexer, Logos};
 
-use crate::{
-    interpret::{
-        ast::ast::{ExportType, Label},
-        runtime::Evaluator,
-    },
-    repl::lexer::Token::{self as ReplToken, *},
-};
-
-type FnCall = (String, Vec<i32>);
+use crate::cli::{parse_integer, parse_value, trace_file};
+use crate::repl::debugger::{backtrace, frame_index, report, Point, Until};
+use crate::repl::lexer::Token::{self as ReplToken, *};
+use crate::repl::memory::{hexdump, layout, typed, Field, View};
+use crate::repl::session::Session;
 
 fn consume_lparan(lexer: &mut Lexer<'_, ReplToken>) -> Result<(), String> {
     if let Some(Ok(LParan)) = lexer.next() {
@@ -25,124 +22,608 @@ fn consume_lparan(lexer: &mut Lexer<'_, ReplToken>) -> Result<(), String> {
         ))
     }
 }
-fn consume_rparan(lexer: &mut Lexer<'_, ReplToken>) -> Result<(), String> {
-    if let Some(Ok(LParan)) = lexer.next() {
-        Ok(())
+/// The names of the commands that start with a colon
+pub const META_COMMANDS: [&str; 21] = [
+    "exports", "open", "reload", "reset", "save", "load", "trace", "break", "watch", "delete", "debug", "step",
+    "next", "finish", "continue", "abort", "backtrace", "locals", "stack", "help", "quit",
+];
+
+const HELP: &str = "\
+exports.functions.NAME(args*)               calls an exported function
+exports.globals.get(NAME)                   prints an exported global
+exports.globals.set(NAME, value)            sets a mutable exported global
+exports.memory.get(NAME)                    prints the non-zero words of memory
+exports.memory.set(addr, value [, width])   stores an integer of 1, 2, 4 or 8 bytes
+exports.memory.write(addr, \"bytes\")         copies a string into memory
+exports.memory.dump(addr, len [as view])    prints memory as a hexdump or typed values
+exports.memory.struct(addr, name:view*)     prints a struct laid out as in C
+:exports                                    lists the exports with their types
+:open FILE                                  switches to another module
+:reload [keep]                              parses the file again, keep carries memory and globals over
+:reset                                      instantiates the module afresh
+:save FILE                                  saves memory, globals and a paused call to a snapshot
+:load FILE                                  restores memory, globals and a paused call from a snapshot
+:trace on [FUNCTION*]                       prints every executed opcode, of all or the given functions
+:trace file FILE [FUNCTION*]                writes a compact trace to FILE for diffing
+:trace off                                  stops tracing
+:break [FUNCTION | LINE]                    stops calls entering a function or reaching a line, lists the stops
+:watch [GLOBAL | addr len]                  stops calls changing a global or memory, lists the stops
+:delete [N]                                 removes breakpoint or watchpoint N, or all of them
+:debug exports.functions.NAME(args*)        starts a call paused before its first opcode
+:step                                       runs the paused call for one opcode
+:next                                       runs the paused call to the next opcode of its function
+:finish                                     runs the paused call until its function returns
+:continue                                   runs the paused call to the next stop
+:abort                                      abandons the paused call
+:backtrace                                  lists the functions of the paused call, innermost first
+:locals [N]                                 prints the locals of frame N of the backtrace, 0 by default
+:stack [N]                                  prints the operand stack of frame N
+:help                                       prints this help
+:quit                                       leaves the REPL";
+
+fn format_extern_type(ty: &ExternType) -> String {
+    match ty {
+        ExternType::Func(ty) => format!("({}) -> ({})", format_types(&ty.params), format_types(&ty.results)),
+        ExternType::Global { ty, mutable: true } => format!("mut {}", ty),
+        ExternType::Global { ty, mutable: false } => ty.to_string(),
+        ExternType::Memory { pages } => format!("{} pages", pages),
+        ExternType::Table { size } => format!("{} elements", size),
+    }
+}
+
+fn parse_trace(words: &[&str], session: &mut Session) -> Result<(), String> {
+    let (mut tracer, functions) = match words {
+        [] => {
+            match &session.tracer {
+                None => println!("tracing is off"),
+                Some(tracer) if tracer.functions().is_empty() => println!("tracing every function"),
+                Some(tracer) => println!("tracing {}", tracer.functions().join(" ")),
+            }
+            return Ok(());
+        }
+        ["off"] => {
+            session.set_tracer(None);
+            println!("tracing off");
+            return Ok(());
+        }
+        ["on", functions @ ..] => (Tracer::new(io::stdout()), functions),
+        ["file", path, functions @ ..] => (trace_file(path)?, functions),
+        _ => return Err(String::from("expected :trace on|off or :trace file FILE")),
+    };
+    for function in functions {
+        tracer.only(function);
+    }
+    session.set_tracer(Some(tracer));
+    println!("tracing on");
+    Ok(())
+}
+
+/// Reads the breakpoint or watchpoint of a `:break` or `:watch` with arguments
+fn parse_point(words: &[&str], instance: &Instance) -> Result<Point, String> {
+    match words {
+        ["break", line] if line.starts_with(|c: char| c.is_ascii_digit()) => match line.parse() {
+            Ok(line) => Ok(Point::Line(line)),
+            Err(_) => Err(format!("{} is not a line number", line)),
+        },
+        ["break", function] => Ok(Point::Function(function.trim_start_matches('$').to_string())),
+        ["watch", name] => match instance.global(name) {
+            Some(value) => Ok(Point::Global(name.to_string(), value)),
+            None => Err(format!("no such exported global {}", name)),
+        },
+        ["watch", addr, len] => match (parse_integer(addr, 32), parse_integer(len, 32)) {
+            (Some(addr), Some(len)) => {
+                let (addr, len) = (addr as u32 as usize, len as u32 as usize);
+                match instance.memory().and_then(|memory| memory.get(addr..addr + len)) {
+                    Some(bytes) => Ok(Point::Memory {
+                        addr,
+                        bytes: bytes.to_vec(),
+                    }),
+                    None => Err(format!("{:#x}..{:#x} is outside the memory", addr, addr + len)),
+                }
+            }
+            _ => Err(format!("expected an address and a length, saw {} {}", addr, len)),
+        },
+        _ => Err(format!("unexpected arguments to :{}, see :help", words[0])),
+    }
+}
+
+/// Handles the debugger's commands, `None` if `words` isn't one
+fn parse_debug_command(words: &[&str], session: &mut Session) -> Option<Result<(), String>> {
+    let debugger = &mut session.debugger;
+    let instance = &mut session.instance;
+    let frame = |number: Option<&&str>, instance: &Instance| {
+        let number = match number {
+            Some(number) => number.parse().map_err(|_| format!("{} is not a frame number", number))?,
+            None => 0,
+        };
+        frame_index(instance, number)
+    };
+    let result = match words {
+        ["break"] | ["watch"] => {
+            debugger.list();
+            Ok(())
+        }
+        ["break" | "watch", ..] => parse_point(words, instance).map(|point| debugger.add(point)),
+        ["delete"] => debugger.delete(None),
+        ["delete", number] => match number.parse() {
+            Ok(number) => debugger.delete(Some(number)),
+            Err(_) => Err(format!("{} is not a breakpoint number", number)),
+        },
+        ["step" | "next" | "finish" | "continue" | "abort" | "backtrace" | "locals" | "stack", ..]
+            if !instance.is_paused() =>
+        {
+            Err(String::from("no call is paused, start one with :debug"))
+        }
+        [command @ ("step" | "next" | "finish" | "continue")] => {
+            let until = match *command {
+                "step" => Until::Step,
+                "next" => Until::Next,
+                "finish" => Until::Finish,
+                _ => Until::Continue,
+            };
+            debugger.resume(instance, &session.path, until)
+        }
+        ["abort"] => {
+            instance.abort();
+            println!("aborted the call");
+            Ok(())
+        }
+        ["backtrace"] => {
+            backtrace(instance);
+            Ok(())
+        }
+        ["locals", number @ ..] if number.len() <= 1 => frame(number.first(), instance).map(|frame| {
+            for (i, value) in instance.frame_locals(frame).unwrap().iter().enumerate() {
+                println!("local {} = {}", i, typed_value(value));
+            }
+        }),
+        ["stack", number @ ..] if number.len() <= 1 => frame(number.first(), instance).map(|frame| {
+            println!("{}", format_results(instance.frame_stack(frame).unwrap()));
+        }),
+        ["delete" | "step" | "next" | "finish" | "continue" | "abort" | "backtrace" | "locals"
+        | "stack", ..] => Err(format!("unexpected arguments to :{}, see :help", words[0])),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// A paused call goes into a snapshot with the memory and globals
+fn snapshot_contents(instance: &Instance) -> &'static str {
+    if instance.is_paused() {
+        "memory, globals and the paused call"
     } else {
-        Err(format!(
-            "didn't see a left paranthesis at {:?}, saw {}",
-            lexer.span(),
-            lexer.slice()
-        ))
+        "memory and globals"
     }
 }
 
-pub fn parse_command(line: &str, evaluator: &mut Evaluator) -> Result<(), String> {
-    let mut lexer = ReplToken::lexer(&line);
+fn typed_value(value: &Value) -> String {
+    format!("{}:{}", value.ty(), value)
+}
+
+fn parse_meta_command(command: &str, session: &mut Session) -> Result<(), String> {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    if let ["trace", rest @ ..] = words.as_slice() {
+        return parse_trace(rest, session);
+    }
+    if let Some(call) = command.trim().strip_prefix("debug ") {
+        let (name, args) = parse_call(call, &session.instance).map_err(|error| error.to_string())?;
+        return session.debugger.start(&mut session.instance, &session.path, &name, &args, true);
+    }
+    if let Some(result) = parse_debug_command(&words, session) {
+        return result;
+    }
+    let mut words = command.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some("help"), None, _) => {
+            println!("{}", HELP);
+            Ok(())
+        }
+        (Some("exports"), None, _) => {
+            for export in session.instance.exports() {
+                let kind = match export.ty {
+                    ExternType::Func(_) => "function",
+                    ExternType::Global { .. } => "global",
+                    ExternType::Memory { .. } => "memory",
+                    ExternType::Table { .. } => "table",
+                };
+                println!("{:<8} {} {}", kind, export.name, format_extern_type(&export.ty));
+            }
+            Ok(())
+        }
+        (Some("open"), Some(path), None) => {
+            session.switch(path)?;
+            println!("opened {}", path);
+            Ok(())
+        }
+        (Some("reload"), keep @ (None | Some("keep")), None) => {
+            let dropped = session.reload(keep.is_some())?;
+            for part in dropped {
+                println!("couldn't keep {}", part);
+            }
+            println!("reloaded {}", session.path);
+            Ok(())
+        }
+        (Some("reset"), None, _) => {
+            session.reset()?;
+            println!("reset {}", session.path);
+            Ok(())
+        }
+        (Some("save"), Some(path), None) => {
+            session.instance.save_snapshot(path)?;
+            println!("saved {} to {}", snapshot_contents(&session.instance), path);
+            Ok(())
+        }
+        (Some("load"), Some(path), None) => {
+            session.instance.load_snapshot(path)?;
+            println!("restored {} from {}", snapshot_contents(&session.instance), path);
+            Ok(())
+        }
+        (Some("quit"), None, _) => {
+            session.quit = true;
+            Ok(())
+        }
+        (Some("save" | "load" | "open"), _, _) => Err(String::from("expected a single file path")),
+        (Some("reload"), _, _) => Err(String::from("expected :reload or :reload keep")),
+        _ => Err(format!("no such command :{}, see :help", command.trim())),
+    }
+}
+pub fn parse_command(line: &str, session: &mut Session) -> Result<(), String> {
+    if let Some(command) = line.trim_start().strip_prefix(':') {
+        return parse_meta_command(command, session);
+    }
+    let instance = &mut session.instance;
+    let mut lexer = ReplToken::lexer(line);
     if let Some(Ok(token)) = lexer.next() {
         match token {
-            Function(name) => {
-                match parse_function(&name, &mut lexer) {
-                    Ok(args) => {
-                        evaluator.add_parameters(args); // Fix
-                        let export = evaluator.module.exports.get(&name);
-                        let mut fn_ref: Label;
-                        if let Some(export) = export {
-                            if export.export_type == ExportType::FUNCTION {
-                                fn_ref = export.export_ref.clone();
-                            } else {
-                                return Err(format!(
-                                    "export is not a function it is of type {:?}",
-                                    export.export_type
-                                ));
-                            }
-                        } else {
-                            debug!("{:?}", evaluator.module.exports);
-                            return Err(format!("no such function export {}", name));
-                        }
-                        evaluator.call(&fn_ref);
-                        let result = evaluator.run();
-                        println!("function result is {result:?}");
-                        return Ok(());
-                    }
-                    Err(msg) => return Err(msg),
-                }
+            Function(_) if session.debugger.is_active() => {
+                let (name, args) = parse_call(line, instance).map_err(|error| error.to_string())?;
+                session.debugger.start(instance, &session.path, &name, &args, false)
             }
-            GetGlobal(name) => {
-                let export = evaluator.module.exports.get(&name);
-                let mut global_ref: Label;
-                if let Some(export) = export {
-                    if export.export_type == ExportType::GLOBAL {
-                        global_ref = export.export_ref.clone();
-                    } else {
-                        return Err(format!(
-                            "export is not a global it is of type {:?}",
-                            export.export_type
-                        ));
-                    }
-                } else {
-                    return Err(format!("no such exported global {}", name));
-                }
-                let global_idx = evaluator.module.get_global(&global_ref);
-                println!("{}", evaluator.globals[global_idx]);
+            Function(_) => {
+                let results = evaluate(line, instance).map_err(report)?;
+                println!("{}", format_results(&results));
+                Ok(())
+            }
+            GetGlobal(_) => {
+                let value = evaluate(line, instance).map_err(|error| error.to_string())?;
+                println!("{}", format_results(&value));
                 Ok(())
             }
-            GetMemory(name) => {
-                let export = evaluator.module.exports.get(&name);
-                match export {
-                    Some(export) => match export.export_type {
-                        ExportType::MEMORY => {
-                            println!("Memory in integers:");
-                            evaluator.memory.pretty_print_as_integers();
-                            Ok(())
-                        }
-                        _ => Err(format!(
-                            "export is not a memory it is of type {:?}",
-                            export.export_type
-                        )),
-                    },
-                    None => Err(format!("no such exported memory {}", name)),
+            SetGlobal => {
+                let (name, text) = parse_set_global(&mut lexer)?;
+                let ty = match instance.export(&name) {
+                    Some(ExternType::Global { ty, .. }) => ty,
+                    Some(ty) => return Err(format!("export is not a global it is of type {:?}", ty)),
+                    None => return Err(format!("no such exported global {}", name)),
+                };
+                let value = parse_value(ty, &text)?;
+                instance.set_global(&name, value).map_err(|error| error.to_string())?;
+                println!("{} = {}", name, typed_value(&value));
+                Ok(())
+            }
+            SetMemory => {
+                let (addr, text, width) = parse_set_memory(&mut lexer)?;
+                let bytes = int_bytes(&text, width)?;
+                write_memory(instance, addr, &bytes)
+            }
+            WriteMemory => {
+                let (addr, bytes) = parse_write_memory(&mut lexer)?;
+                write_memory(instance, addr, &bytes)
+            }
+            DumpMemory => {
+                let (addr, len, view) = parse_dump_memory(&mut lexer)?;
+                let memory = instance.memory().ok_or("the module has no memory")?;
+                match view {
+                    Some(view) => print!("{}", typed(memory, addr, len, view)?),
+                    None => print!("{}", hexdump(memory, addr, len)?),
                 }
+                Ok(())
+            }
+            StructMemory => {
+                let (addr, fields) = parse_struct_memory(&mut lexer)?;
+                let memory = instance.memory().ok_or("the module has no memory")?;
+                print!("{}", layout(memory, addr, &fields)?);
+                Ok(())
             }
-            _ => return Err(format!("no such command {:?}", token)),
+            GetMemory(name) => match instance.export(&name) {
+                Some(ExternType::Memory { .. }) => {
+                    println!("Memory in integers:");
+                    pretty_print_as_integers(instance.memory().unwrap_or(&[]));
+                    Ok(())
+                }
+                Some(ty) => Err(format!("export is not a memory it is of type {:?}", ty)),
+                None => Err(format!("no such exported memory {}", name)),
+            },
+            _ => Err(format!("no such command {:?}, see :help", token)),
         }
     } else {
-        return Err(String::from("no such command"));
+        Err(String::from("no such command, see :help"))
     }
 }
 
-fn next_keyword(lexer: &mut Lexer<'_, ReplToken>) -> Result<String, String> {
-    if let Keyword(string) = lexer.next().unwrap().unwrap() {
-        return Ok(string);
-    } else {
-        Err(String::from("should have seen a keyword"))
+/// Runs a function call or reads a global, the commands that produce values, without printing
+/// the values
+pub fn evaluate(line: &str, instance: &mut Instance) -> Result<Vec<Value>, Error> {
+    let mut lexer = ReplToken::lexer(line);
+    let values = match lexer.next() {
+        Some(Ok(Function(name))) => {
+            let args = parse_args(&name, &mut lexer, instance)?;
+            return instance.invoke(&name, &args);
+        }
+        Some(Ok(GetGlobal(name))) => match instance.export(&name) {
+            Some(ExternType::Global { .. }) => vec![instance.global(&name).unwrap()],
+            Some(ty) => return Err(Error::Invoke(format!("export is not a global it is of type {:?}", ty))),
+            None => return Err(Error::Invoke(format!("no such exported global {}", name))),
+        },
+        _ => return Err(Error::Invoke(format!("expected a function call or a global, saw {}", line.trim()))),
+    };
+    expect_end(&mut lexer).map_err(Error::Invoke)?;
+    Ok(values)
+}
+
+/// Reads the name and the arguments of a function call command without calling it
+pub fn parse_call(line: &str, instance: &Instance) -> Result<(String, Vec<Value>), Error> {
+    let mut lexer = ReplToken::lexer(line);
+    match lexer.next() {
+        Some(Ok(Function(name))) => {
+            let args = parse_args(&name, &mut lexer, instance)?;
+            Ok((name, args))
+        }
+        _ => Err(Error::Invoke(format!("expected a function call, saw {}", line.trim()))),
     }
 }
-pub fn parse_set_global(lexer: &mut Lexer<'_, ReplToken>) -> Result<i32, String> {
-    consume_lparan(lexer);
-    if let Some(Ok(Integer(n))) = lexer.next() {
-        Ok(n)
-    } else {
-        Err(String::from("should have seen a global identifier"))
+
+/// Reads the arguments of a call as the parameter types of the function
+fn parse_args(name: &str, lexer: &mut Lexer<'_, ReplToken>, instance: &Instance) -> Result<Vec<Value>, Error> {
+    let ty = instance.func_type(name)?;
+    let texts = parse_function(lexer).map_err(Error::Invoke)?;
+    if texts.len() != ty.params.len() {
+        return Err(Error::Invoke(format!(
+            "{} expects {} arguments ({}), saw {}",
+            name,
+            ty.params.len(),
+            format_types(&ty.params),
+            texts.len()
+        )));
+    }
+    let args = ty
+        .params
+        .iter()
+        .zip(texts.iter())
+        .enumerate()
+        .map(|(i, (ty, text))| parse_value(*ty, text).map_err(|error| format!("argument {}: {}", i + 1, error)))
+        .collect::<Result<Vec<Value>, String>>()
+        .map_err(Error::Invoke)?;
+    expect_end(lexer).map_err(Error::Invoke)?;
+    Ok(args)
+}
+
+fn expect_end(lexer: &mut Lexer<'_, ReplToken>) -> Result<(), String> {
+    match lexer.next() {
+        None => Ok(()),
+        Some(_) => Err(format!("unexpected {} at {:?}", lexer.slice(), lexer.span())),
+    }
+}
+
+/// Prints memory as little endian i32 words, collapsing runs of zeros
+fn pretty_print_as_integers(memory: &[u8]) {
+    let mut before_is_zero = false;
+    for (address, chunk) in memory.chunks(4).enumerate() {
+        let n = i32::from_le_bytes(chunk.try_into().unwrap());
+        if n == 0 && before_is_zero {
+        } else if n == 0 {
+            println!("{address:08x}: 0");
+            before_is_zero = true;
+        } else {
+            println!("{address:08x}: {}", n);
+            before_is_zero = false;
+        }
+    }
+}
+
+/// Formats function results with their types, e.g. `i32:1, f64:2.5`, or `()` when there are none
+pub fn format_results(results: &[Value]) -> String {
+    if results.is_empty() {
+        return String::from("()");
+    }
+    let values: Vec<String> = results.iter().map(|value| format!("{}:{}", value.ty(), value)).collect();
+    values.join(", ")
+}
+
+fn format_types(types: &[ValType]) -> String {
+    let types: Vec<String> = types.iter().map(|ty| ty.to_string()).collect();
+    types.join(" ")
+}
+
+/// Copies bytes into the exported memory at `addr`
+fn write_memory(instance: &mut Instance, addr: usize, bytes: &[u8]) -> Result<(), String> {
+    let memory = instance.memory_mut().ok_or("the module has no memory")?;
+    match memory.get_mut(addr..addr + bytes.len()) {
+        Some(target) => target.copy_from_slice(bytes),
+        None => {
+            return Err(format!(
+                "{} bytes at {} are out of bounds of a {} byte memory",
+                bytes.len(),
+                addr,
+                memory.len()
+            ))
+        }
+    }
+    println!("wrote {} bytes at {}", bytes.len(), addr);
+    Ok(())
+}
+
+/// The little endian bytes of an integer that fits in `width` bytes, signed or unsigned
+fn int_bytes(text: &str, width: usize) -> Result<Vec<u8>, String> {
+    match parse_integer(text, 8 * width as u32) {
+        Some(n) => Ok(n.to_le_bytes()[..width].to_vec()),
+        None => Err(format!("{} is not an integer that fits in {} bytes", text, width)),
+    }
+}
+
+fn expect_rparan(lexer: &mut Lexer<'_, ReplToken>) -> Result<(), String> {
+    match lexer.next() {
+        Some(Ok(RParan)) => Ok(()),
+        _ => Err(format!("expected ) at {:?}, saw {}", lexer.span(), lexer.slice())),
+    }
+}
+
+/// Reads a non-negative number such as an address, a length or a count
+fn parse_size(lexer: &mut Lexer<'_, ReplToken>, what: &str) -> Result<usize, String> {
+    match lexer.next() {
+        Some(Ok(Number(text))) => match parse_value(ValType::I64, &text)? {
+            Value::I64(n) if n >= 0 => Ok(n as usize),
+            _ => Err(format!("{} is not {}", text, what)),
+        },
+        _ => Err(format!("expected {} at {:?}, saw {}", what, lexer.span(), lexer.slice())),
+    }
+}
+
+/// Parses `(name, value)`, the value is read once the global's type is known
+pub fn parse_set_global(lexer: &mut Lexer<'_, ReplToken>) -> Result<(String, String), String> {
+    consume_lparan(lexer)?;
+    let name = match lexer.next() {
+        Some(Ok(Keyword(name))) => name,
+        _ => return Err(String::from("should have seen a global identifier")),
+    };
+    let value = match lexer.next() {
+        Some(Ok(Number(text))) | Some(Ok(Keyword(text))) => text,
+        _ => return Err(format!("expected a value at {:?}, saw {}", lexer.span(), lexer.slice())),
+    };
+    expect_rparan(lexer)?;
+    Ok((name, value))
+}
+
+/// Parses `(addr, value [, width])`, the width in bytes is 1, 2, 4 or 8 and defaults to 4
+pub(crate) fn parse_set_memory(lexer: &mut Lexer<'_, ReplToken>) -> Result<(usize, String, usize), String> {
+    consume_lparan(lexer)?;
+    let addr = parse_size(lexer, "an address")?;
+    let value = match lexer.next() {
+        Some(Ok(Number(text))) => text,
+        _ => return Err(format!("expected an integer at {:?}, saw {}", lexer.span(), lexer.slice())),
+    };
+    let width = match lexer.next() {
+        Some(Ok(RParan)) => return Ok((addr, value, 4)),
+        Some(Ok(Number(width))) => match width.as_str() {
+            "1" | "2" | "4" | "8" => width.parse().unwrap(),
+            _ => return Err(format!("the width is 1, 2, 4 or 8 bytes, saw {}", width)),
+        },
+        _ => return Err(format!("expected a width or ) at {:?}, saw {}", lexer.span(), lexer.slice())),
+    };
+    expect_rparan(lexer)?;
+    Ok((addr, value, width))
+}
+
+/// Parses `(addr, "bytes")`
+pub(crate) fn parse_write_memory(lexer: &mut Lexer<'_, ReplToken>) -> Result<(usize, Vec<u8>), String> {
+    consume_lparan(lexer)?;
+    let addr = parse_size(lexer, "an address")?;
+    let bytes = match lexer.next() {
+        Some(Ok(Str(raw))) => unescape(&raw)?,
+        _ => return Err(format!("expected a string at {:?}, saw {}", lexer.span(), lexer.slice())),
+    };
+    expect_rparan(lexer)?;
+    Ok((addr, bytes))
+}
+
+/// Parses `(addr, len)` optionally followed by `as view`
+fn parse_dump_memory(lexer: &mut Lexer<'_, ReplToken>) -> Result<(usize, usize, Option<View>), String> {
+    consume_lparan(lexer)?;
+    let addr = parse_size(lexer, "an address")?;
+    let len = parse_size(lexer, "a length")?;
+    expect_rparan(lexer)?;
+    match lexer.next() {
+        None => Ok((addr, len, None)),
+        Some(Ok(Keyword(as_))) if as_ == "as" => match lexer.next() {
+            Some(Ok(Keyword(view))) => Ok((addr, len, Some(View::parse(&view)?))),
+            _ => Err(format!("expected a view after as, saw {}", lexer.slice())),
+        },
+        _ => Err(format!("expected as or the end of the line at {:?}, saw {}", lexer.span(), lexer.slice())),
+    }
+}
+
+/// Parses `(addr, name:view, name:view[count], ...)`
+fn parse_struct_memory(lexer: &mut Lexer<'_, ReplToken>) -> Result<(usize, Vec<Field>), String> {
+    consume_lparan(lexer)?;
+    let addr = parse_size(lexer, "an address")?;
+    let mut fields: Vec<Field> = Vec::new();
+    loop {
+        match lexer.next() {
+            Some(Ok(Keyword(field))) => {
+                let (name, view) = field
+                    .split_once(':')
+                    .ok_or(format!("expected a field like {}:i32, saw {}", field, field))?;
+                fields.push(Field {
+                    name: name.to_string(),
+                    view: View::parse(view)?,
+                    count: None,
+                });
+            }
+            Some(Ok(LBracket)) if !fields.is_empty() => {
+                let count = parse_size(lexer, "a count")?;
+                if lexer.next() != Some(Ok(RBracket)) {
+                    return Err(format!("expected ] at {:?}, saw {}", lexer.span(), lexer.slice()));
+                }
+                fields.last_mut().unwrap().count = Some(count);
+            }
+            Some(Ok(RParan)) if !fields.is_empty() => return Ok((addr, fields)),
+            _ => return Err(format!("expected a field at {:?}, saw {}", lexer.span(), lexer.slice())),
+        }
     }
 }
 
+/// Resolves `\n`, `\t`, `\\`, `\"`, `\'` and two digit hex escapes like `\ff`, as in the text format
+pub(crate) fn unescape(raw: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = raw.bytes();
+    while let Some(byte) = chars.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some(b'n') => b'\n',
+            Some(b't') => b'\t',
+            Some(b'r') => b'\r',
+            Some(quote @ (b'\\' | b'"' | b'\'')) => quote,
+            Some(high) => {
+                let low = chars.next().unwrap_or(b' ');
+                let hex = [high, low];
+                let hex = std::str::from_utf8(&hex).unwrap_or("");
+                u8::from_str_radix(hex, 16).map_err(|_| format!("unknown escape \\{}", hex))?
+            }
+            None => return Err(String::from("a string can't end with \\")),
+        };
+        bytes.push(escaped);
+    }
+    Ok(bytes)
+}
+
+#[allow(dead_code)]
 pub fn parse_get_global(lexer: &mut Lexer<'_, ReplToken>) -> Result<String, String> {
-    consume_lparan(lexer);
+    consume_lparan(lexer)?;
     if let Some(Ok(Keyword(kwd))) = lexer.next() {
         Ok(kwd)
     } else {
         Err(String::from("should have seen a global identifier"))
     }
 }
-pub fn parse_function(name: &str, lexer: &mut Lexer<'_, ReplToken>) -> Result<Vec<i32>, String> {
-    let mut args: Vec<i32> = Vec::new();
-    let mut fn_name = name;
-    consume_lparan(lexer);
-
-    while let Some(Ok(Integer(n))) = lexer.next() {
-        args.push(n);
+/// Parses `(args*)`, the arguments are read once the function's signature is known
+pub fn parse_function(lexer: &mut Lexer<'_, ReplToken>) -> Result<Vec<String>, String> {
+    let mut args: Vec<String> = Vec::new();
+    consume_lparan(lexer)?;
+    loop {
+        match lexer.next() {
+            // inf and nan lex as keywords
+            Some(Ok(Number(text))) | Some(Ok(Keyword(text))) => args.push(text),
+            Some(Ok(RParan)) => return Ok(args),
+            _ => {
+                return Err(format!(
+                    "expected an argument or ) at {:?}, saw {}",
+                    lexer.span(),
+                    lexer.slice()
+                ))
+            }
+        }
     }
-    consume_rparan(lexer);
-    Ok(args)
 }
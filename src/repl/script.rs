@@ -0,0 +1,110 @@
+use std::fs;
+
+use interperter::{Error, ValType, Value};
+
+use crate::cli::{parse_value, TraceOptions};
+use crate::repl::parser::{evaluate, format_results, parse_command};
+use crate::repl::session::Session;
+
+/// Runs the REPL commands of `script` against the module in `file`, one command a line, and
+/// prints a report line for each. Besides the REPL commands a script can hold
+/// `assert_eq COMMAND == VALUES` and `assert_trap COMMAND ["message"]`, blank lines and
+/// comments starting with `#`. Stops at the first failing line
+pub fn run(file: &str, script: &str, trace: &TraceOptions) -> Result<(), String> {
+    let source = fs::read_to_string(script).map_err(|error| format!("can't read {}: {}", script, error))?;
+    let mut session = Session::open(file)?;
+    session.set_tracer(trace.tracer()?);
+    let mut assertions = 0;
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let outcome = if let Some(assertion) = line.strip_prefix("assert_eq ") {
+            assertions += 1;
+            assert_eq(assertion, &mut session)
+        } else if let Some(assertion) = line.strip_prefix("assert_trap ") {
+            assertions += 1;
+            assert_trap(assertion, &mut session)
+        } else {
+            parse_command(line, &mut session)
+        };
+        match outcome {
+            Ok(()) => println!("{}:{}: ok {}", script, number + 1, line),
+            Err(message) => {
+                println!("{}:{}: FAILED {}", script, number + 1, line);
+                return Err(format!("{}:{}: {}", script, number + 1, message));
+            }
+        }
+        if session.quit {
+            break;
+        }
+    }
+    println!("{} assertions passed", assertions);
+    Ok(())
+}
+
+/// Checks that `COMMAND == VALUES` evaluates to the values, see `parse_expected`
+pub(crate) fn assert_eq(text: &str, session: &mut Session) -> Result<(), String> {
+    let (command, expected) = text.split_once("==").ok_or("expected == and the values after the command")?;
+    let results = evaluate(command, &mut session.instance).map_err(|error| error.to_string())?;
+    let expected = parse_expected(expected.trim(), &results)?;
+    let equal = results.len() == expected.len() && results.iter().zip(expected.iter()).all(|(a, b)| same(a, b));
+    if !equal {
+        return Err(format!(
+            "expected {}, saw {}",
+            format_results(&expected),
+            format_results(&results)
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that `COMMAND ["message"]` traps, with a trap containing the message when one is given
+pub(crate) fn assert_trap(text: &str, session: &mut Session) -> Result<(), String> {
+    // the message is the quoted text at the end
+    let (command, message) = match text.strip_suffix('"').and_then(|text| text.rsplit_once('"')) {
+        Some((command, message)) => (command, Some(message)),
+        None => (text, None),
+    };
+    match evaluate(command, &mut session.instance) {
+        Err(Error::Trap(trap, _)) => match message {
+            Some(message) if !trap.to_string().contains(message) => {
+                Err(format!("expected a trap with \"{}\", saw trap: {}", message, trap))
+            }
+            _ => Ok(()),
+        },
+        Err(error) => Err(error.to_string()),
+        Ok(results) => Err(format!("expected a trap, saw {}", format_results(&results))),
+    }
+}
+
+/// Reads `()` or values separated by commas, each may name its type like `i64:1` and otherwise
+/// has the type of the result at its position
+fn parse_expected(text: &str, results: &[Value]) -> Result<Vec<Value>, String> {
+    if text == "()" {
+        return Ok(Vec::new());
+    }
+    let items: Vec<&str> = text.split(',').map(str::trim).collect();
+    let mut values = Vec::new();
+    for (i, item) in items.iter().copied().enumerate() {
+        let (ty, value) = match item.split_once(':') {
+            Some((ty, value)) => (ValType::from_kwd(ty).ok_or(format!("no such type {}", ty))?, value),
+            None => match results.get(i) {
+                Some(result) => (result.ty(), item),
+                None => return Err(format!("expected {} values, saw {}", items.len(), format_results(results))),
+            },
+        };
+        values.push(parse_value(ty, value)?);
+    }
+    Ok(values)
+}
+
+/// Equality where any NaN matches any other, so `nan` can be expected
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::F32(a), Value::F32(b)) => a == b || (a.is_nan() && b.is_nan()),
+        (Value::F64(a), Value::F64(b)) => a == b || (a.is_nan() && b.is_nan()),
+        _ => a == b,
+    }
+}
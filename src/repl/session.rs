@@ -0,0 +1,101 @@
+use std::sync::{Arc, Mutex};
+
+use interperter::{ExternType, Imports, Instance, InterruptHandle, Module, Tracer};
+
+use crate::repl::debugger::Debugger;
+
+/// The module the REPL works on and the instance its commands run against
+pub struct Session {
+    pub path: String,
+    pub module: Module,
+    pub instance: Instance,
+    pub quit: bool,
+    pub tracer: Option<Tracer>, // follows the session to reloaded and reset instances
+    pub debugger: Debugger,
+    // the Ctrl-C handler is installed once, it interrupts whichever instance is current
+    interrupt: Arc<Mutex<InterruptHandle>>,
+}
+
+fn instantiate(path: &str) -> Result<(Module, Instance), String> {
+    let module = Module::from_file(path).map_err(|error| error.to_string())?;
+    let instance = Instance::new(&module, &Imports::new()).map_err(|error| error.to_string())?;
+    Ok((module, instance))
+}
+
+impl Session {
+    pub fn open(path: &str) -> Result<Session, String> {
+        let (module, instance) = instantiate(path)?;
+        Ok(Session {
+            path: path.to_string(),
+            interrupt: Arc::new(Mutex::new(instance.interrupt_handle())),
+            module,
+            instance,
+            quit: false,
+            tracer: None,
+            debugger: Debugger::default(),
+        })
+    }
+
+    /// The handle the Ctrl-C handler stops the running command with
+    pub fn interrupt(&self) -> Arc<Mutex<InterruptHandle>> {
+        Arc::clone(&self.interrupt)
+    }
+
+    /// Switches to the module in `path`, the current one stays when it can't be loaded
+    pub fn switch(&mut self, path: &str) -> Result<(), String> {
+        let (module, instance) = instantiate(path)?;
+        self.path = path.to_string();
+        self.module = module;
+        self.replace_instance(instance);
+        Ok(())
+    }
+
+    /// Instantiates the current module afresh
+    pub fn reset(&mut self) -> Result<(), String> {
+        let instance = Instance::new(&self.module, &Imports::new()).map_err(|error| error.to_string())?;
+        self.replace_instance(instance);
+        Ok(())
+    }
+
+    /// Parses the current file again, when `keep` is set the memory and the mutable exported
+    /// globals that still have the same type are carried over. Returns what couldn't be kept
+    pub fn reload(&mut self, keep: bool) -> Result<Vec<String>, String> {
+        let (module, mut instance) = instantiate(&self.path)?;
+        let mut dropped = Vec::new();
+        if keep {
+            if let (Some(old), Some(new)) = (self.instance.memory(), instance.memory_mut()) {
+                let len = old.len().min(new.len());
+                new[..len].copy_from_slice(&old[..len]);
+                if len < old.len() {
+                    dropped.push(format!("memory past {} bytes", len));
+                }
+            }
+            for export in self.instance.exports() {
+                if let ExternType::Global { mutable: true, .. } = export.ty {
+                    let value = self.instance.global(&export.name).unwrap();
+                    if instance.set_global(&export.name, value).is_err() {
+                        dropped.push(format!("global {}", export.name));
+                    }
+                }
+            }
+        }
+        self.module = module;
+        self.replace_instance(instance);
+        Ok(dropped)
+    }
+
+    /// Traces the commands from now on, `None` stops and flushes the current trace
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        if let Some(old) = self.tracer.take() {
+            old.flush();
+        }
+        self.instance.set_tracer(tracer.clone());
+        self.tracer = tracer;
+    }
+
+    fn replace_instance(&mut self, mut instance: Instance) {
+        *self.interrupt.lock().unwrap() = instance.interrupt_handle();
+        instance.set_tracer(self.tracer.clone());
+        self.instance = instance;
+    }
+}
@@ -1,3 +1,11 @@
 mod test_arithmetic;
 mod test_parser;
-mod test_evaluation;
\ No newline at end of file
+mod test_evaluation;
+mod test_validation;
+mod test_host;
+mod test_snapshot;
+mod test_api;
+mod test_linker;
+mod test_wasi;
+mod test_wast;
+mod test_trace;
@@ -0,0 +1,344 @@
+use std::sync::{Arc, Mutex};
+
+use crate::interpret::decoder::decode_wasm;
+use crate::interpret::runtime::DEFAULT_MAX_CALL_DEPTH;
+use crate::{Error, ExternType, Frame, FuncType, HostAction, Imports, Instance, Module, Trap, ValType, Value};
+
+// (func $sum (export "add") (param i32 i32) (result i32) local.get 0 local.get 1 i32.add)
+// (func (export "five") (result i32) block (result i32) i32.const 5 br 0 i32.const 6 end)
+// with a name section naming the first function "sum"
+const ADD_WASM: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x0b, 0x02, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f, 0x60,
+    0x00, 0x01, 0x7f, 0x03, 0x03, 0x02, 0x00, 0x01, 0x07, 0x0e, 0x02, 0x03, 0x61, 0x64, 0x64, 0x00, 0x00, 0x04,
+    0x66, 0x69, 0x76, 0x65, 0x00, 0x01, 0x0a, 0x15, 0x02, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b, 0x0b,
+    0x00, 0x02, 0x7f, 0x41, 0x05, 0x0c, 0x00, 0x41, 0x06, 0x0b, 0x0b, 0x00, 0x0d, 0x04, 0x6e, 0x61, 0x6d, 0x65,
+    0x01, 0x06, 0x01, 0x00, 0x03, 0x73, 0x75, 0x6d,
+];
+
+// (func (export "f") (param i32 i32) (result i32)
+//   nop local.get 0 local.get 1 i32.div_u local.get 0 local.get 1 i32.rem_s i32.xor i32.const 3 i32.ne)
+const OPCODES_WASM: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x07, 0x01, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f, 0x03,
+    0x02, 0x01, 0x00, 0x07, 0x05, 0x01, 0x01, 0x66, 0x00, 0x00, 0x0a, 0x13, 0x01, 0x11, 0x00, 0x01, 0x20, 0x00,
+    0x20, 0x01, 0x6e, 0x20, 0x00, 0x20, 0x01, 0x6f, 0x73, 0x41, 0x03, 0x47, 0x0b,
+];
+
+// (memory 1) (func (export "f") (result i32) i32.const 7) with a data count section of 0
+const DATA_COUNT_WASM: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f, 0x03, 0x02, 0x01,
+    0x00, 0x05, 0x03, 0x01, 0x00, 0x01, 0x07, 0x05, 0x01, 0x01, 0x66, 0x00, 0x00, 0x0c, 0x01, 0x00, 0x0a, 0x06,
+    0x01, 0x04, 0x00, 0x41, 0x07, 0x0b,
+];
+
+#[test]
+fn test_invoke_wat() {
+    let module = Module::from_wat(include_str!("../../examples/factorial.wat")).unwrap();
+    let mut instance = Instance::new(&module, &Imports::new()).unwrap();
+    let name = match &module.exports()[0] {
+        export if matches!(export.ty, ExternType::Func(_)) => export.name.clone(),
+        export => panic!("unexpected export {:?}", export),
+    };
+    assert_eq!(instance.invoke(&name, &[Value::I32(5)]), Ok(vec![Value::I32(120)]));
+}
+
+#[test]
+fn test_invoke_wasm() {
+    let module = Module::from_wasm(ADD_WASM).unwrap();
+    let mut instance = Instance::new(&module, &Imports::new()).unwrap();
+    assert_eq!(instance.invoke("add", &[Value::I32(2), Value::I32(40)]), Ok(vec![Value::I32(42)]));
+    assert_eq!(instance.invoke("five", &[]), Ok(vec![Value::I32(5)]));
+    let (decoded, _) = decode_wasm(ADD_WASM).unwrap();
+    assert_eq!(decoded.funcs[0].name, Some(String::from("sum")));
+    assert!(matches!(Module::from_wasm(&ADD_WASM[..20]), Err(Error::Parse(_))));
+    assert!(matches!(Module::from_wasm(b"\0asm\x02\0\0\0"), Err(Error::Parse(_))));
+}
+
+#[test]
+fn test_invoke_wasm_opcodes() {
+    let module = Module::from_wasm(OPCODES_WASM).unwrap();
+    let mut instance = Instance::new(&module, &Imports::new()).unwrap();
+    assert_eq!(instance.invoke("f", &[Value::I32(7), Value::I32(2)]), Ok(vec![Value::I32(1)]));
+    assert_eq!(instance.invoke("f", &[Value::I32(7), Value::I32(3)]), Ok(vec![Value::I32(0)]));
+    let trapped = instance.invoke("f", &[Value::I32(7), Value::I32(0)]);
+    assert!(matches!(trapped, Err(Error::Trap(Trap::IntegerDivideByZero, _))));
+}
+
+#[test]
+fn test_decode_data_count_section() {
+    let module = Module::from_wasm(DATA_COUNT_WASM).unwrap();
+    let mut instance = Instance::new(&module, &Imports::new()).unwrap();
+    assert_eq!(instance.invoke("f", &[]), Ok(vec![Value::I32(7)]));
+    // the data count section after the code section
+    let mut swapped = DATA_COUNT_WASM[..31].to_vec();
+    swapped.extend(&DATA_COUNT_WASM[34..]);
+    swapped.extend(&DATA_COUNT_WASM[31..34]);
+    assert_eq!(decode_wasm(&swapped).unwrap_err(), "section 12 is out of order");
+}
+
+#[test]
+fn test_module_errors() {
+    assert!(matches!(Module::from_wat("(module (func $f"), Err(Error::Parse(_))));
+    assert!(matches!(
+        Module::from_wat("(module (func (result i32) nop))"),
+        Err(Error::Invalid(_))
+    ));
+    let module = Module::from_wat(r#"(module (memory (import "env" "memory") 1))"#).unwrap();
+    assert_eq!(
+        Instance::new(&module, &Imports::new()).err(),
+        Some(Error::Link(String::from("unknown import env.memory")))
+    );
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!(
+        Module::from_wat("(module (func i32.const))").err(),
+        Some(Error::Parse(String::from("should see constant operand with i32.const instruciton")))
+    );
+    assert_eq!(
+        Module::from_wat("(module (func i32.popcnt))").err(),
+        Some(Error::Parse(String::from("unknown instruction i32.popcnt")))
+    );
+    assert_eq!(
+        Module::from_wat("(module (func end))").err(),
+        Some(Error::Parse(String::from("end without an open block")))
+    );
+    assert!(matches!(Module::from_wat("(module (func \\))"), Err(Error::Parse(_))));
+}
+
+#[test]
+fn test_invoke_errors() {
+    let module = Module::from_wasm(ADD_WASM).unwrap();
+    let mut instance = Instance::new(&module, &Imports::new()).unwrap();
+    assert!(matches!(instance.invoke("sub", &[]), Err(Error::Invoke(_))));
+    assert!(matches!(instance.invoke("add", &[Value::I32(1)]), Err(Error::Invoke(_))));
+    assert!(matches!(
+        instance.invoke("add", &[Value::I32(1), Value::I64(1)]),
+        Err(Error::Invoke(_))
+    ));
+    let module = Module::from_wat(
+        r#"(module (func (export "div") (param i32 i32) (result i32) local.get 0 local.get 1 i32.div_s))"#,
+    )
+    .unwrap();
+    let mut instance = Instance::new(&module, &Imports::new()).unwrap();
+    let trapped = instance.invoke("div", &[Value::I32(1), Value::I32(0)]).unwrap_err();
+    assert!(matches!(trapped, Error::Trap(Trap::IntegerDivideByZero, _)));
+    assert_eq!(trapped.backtrace().len(), 1);
+    assert_eq!(trapped.to_string(), "trap: integer divide by zero at 1:83 in function 0");
+    assert_eq!(instance.invoke("div", &[Value::I32(9), Value::I32(3)]), Ok(vec![Value::I32(3)]));
+}
+
+#[test]
+fn test_imports_and_state() {
+    let module = Module::from_wat(
+        r#"(module
+        (import "env" "log" (func $log (param i32)))
+        (import "env" "base" (global $base i32))
+        (memory (export "mem") 1)
+        (global $count (export "count") (mut i32) (global.get $base))
+        (func (export "tick") (result i32)
+          global.get $count
+          i32.const 1
+          i32.add
+          global.set $count
+          global.get $count
+          call $log
+          i32.const 0
+          global.get $count
+          i32.store
+          global.get $count)
+    )"#,
+    )
+    .unwrap();
+    let unlinked = Instance::new(&module, &Imports::new()).unwrap_err();
+    assert_eq!(unlinked, Error::Link(String::from("unknown import env.log")));
+
+    let logged = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&logged);
+    let mut imports = Imports::new();
+    imports
+        .func("env", "log", FuncType { params: vec![ValType::I32], results: vec![] }, move |caller, args| {
+            assert_eq!(caller.memory().len(), 65536);
+            sink.lock().unwrap().push(args[0]);
+            Ok(vec![])
+        })
+        .global("env", "base", Value::I32(10));
+    let mut instance = Instance::new(&module, &imports).unwrap();
+    assert_eq!(instance.global("count"), Some(Value::I32(10)));
+    assert_eq!(instance.invoke("tick", &[]), Ok(vec![Value::I32(11)]));
+    assert_eq!(instance.invoke("tick", &[]), Ok(vec![Value::I32(12)]));
+    assert_eq!(*logged.lock().unwrap(), vec![Value::I32(11), Value::I32(12)]);
+    assert_eq!(instance.memory().unwrap()[..4], 12i32.to_le_bytes());
+    assert_eq!(instance.global("tick"), None);
+
+    let mut wrong = Imports::new();
+    wrong
+        .func("env", "log", FuncType::default(), |_, _| Ok(vec![]))
+        .global("env", "base", Value::I32(0));
+    assert!(matches!(Instance::new(&module, &wrong), Err(Error::Link(_))));
+}
+
+#[test]
+fn test_set_global() {
+    let module = Module::from_wat(
+        r#"(module
+        (global (export "count") (mut i32) (i32.const 0))
+        (global (export "limit") i64 (i64.const 10))
+        (func (export "next") (result i32)
+          global.get 0
+          i32.const 1
+          i32.add))"#,
+    )
+    .unwrap();
+    let mut instance = Instance::new(&module, &Imports::new()).unwrap();
+    assert_eq!(instance.set_global("count", Value::I32(41)), Ok(()));
+    assert_eq!(instance.invoke("next", &[]), Ok(vec![Value::I32(42)]));
+    assert_eq!(
+        instance.set_global("limit", Value::I64(1)),
+        Err(Error::Invoke(String::from("global limit is immutable")))
+    );
+    assert_eq!(
+        instance.set_global("count", Value::I64(1)),
+        Err(Error::Invoke(String::from("global count has type i32, saw a i64")))
+    );
+    assert!(matches!(instance.set_global("next", Value::I32(1)), Err(Error::Invoke(_))));
+    assert_eq!(instance.global("limit"), Some(Value::I64(10)));
+}
+
+#[test]
+fn test_stepping() {
+    let module = Module::from_wat(include_str!("../../examples/factorial.wat")).unwrap();
+    let mut instance = Instance::new(&module, &Imports::new()).unwrap();
+    assert!(matches!(instance.step(), Err(Error::Invoke(_))));
+    instance.start("run", &[Value::I32(3)]).unwrap();
+    assert!(instance.is_paused());
+    assert!(matches!(instance.invoke("run", &[Value::I32(3)]), Err(Error::Invoke(_))));
+    let frames = instance.frames();
+    assert_eq!(frames.len(), 1);
+    assert_eq!((frames[0].name.as_deref(), frames[0].pc), (Some("fac"), 0));
+    // up to the recursive call, with the caller waiting on it
+    while instance.frames().len() == 1 {
+        assert_eq!(instance.step(), Ok(None));
+    }
+    let frames = instance.frames();
+    assert_eq!(frames[0].location.as_ref().map(|location| location.line), Some(15));
+    assert_eq!(frames[0].op, "CALL(REF(\"fac\"))");
+    assert_eq!(instance.frame_locals(0), Some(&[Value::I32(3)][..]));
+    assert_eq!(instance.frame_locals(1), Some(&[Value::I32(2)][..]));
+    assert_eq!(instance.frame_stack(0), Some(&[Value::I32(3)][..]));
+    assert_eq!(instance.frame_stack(2), None);
+    let results = loop {
+        if let Some(results) = instance.step().unwrap() {
+            break results;
+        }
+    };
+    assert_eq!(results, vec![Value::I32(6)]);
+    assert!(!instance.is_paused());
+
+    instance.start("run", &[Value::I32(4)]).unwrap();
+    instance.step().unwrap();
+    instance.abort();
+    assert!(instance.frames().is_empty());
+    assert_eq!(instance.invoke("run", &[Value::I32(4)]), Ok(vec![Value::I32(24)]));
+}
+
+#[test]
+fn test_run_for_and_resume() {
+    let module = Module::from_wat(
+        r#"(module
+  (import "env" "ask" (func $ask (param i32) (result i32)))
+  (func (export "run") (param i32) (result i32)
+    local.get 0
+    call $ask
+    i32.const 100
+    i32.mul))"#,
+    )
+    .unwrap();
+    let mut imports = Imports::new();
+    let ty = FuncType { params: vec![ValType::I32], results: vec![ValType::I32] };
+    imports.yielding_func("env", "ask", ty, |_, _| Ok(HostAction::Yield));
+    let mut instance = Instance::new(&module, &imports).unwrap();
+    assert!(matches!(instance.run_for(10), Err(Error::Invoke(_))));
+    instance.start("run", &[Value::I32(7)]).unwrap();
+    assert_eq!(instance.run_for(1), Ok(None));
+    assert!(!instance.is_suspended());
+    assert_eq!(instance.run_for(10), Ok(None));
+    assert!(instance.is_suspended());
+    // stays suspended until the host answers
+    assert_eq!(instance.run_for(10), Ok(None));
+    assert!(matches!(instance.resume(vec![Value::I64(3)]), Err(Error::Invoke(_))));
+    instance.resume(vec![Value::I32(3)]).unwrap();
+    assert_eq!(instance.run_for(10), Ok(Some(vec![Value::I32(300)])));
+    assert!(matches!(instance.resume(vec![Value::I32(3)]), Err(Error::Invoke(_))));
+
+    // a call that can't be suspended traps and leaves the instance usable
+    let trapped = instance.invoke("run", &[Value::I32(7)]).unwrap_err();
+    assert!(matches!(trapped, Error::Trap(Trap::Host(_), _)));
+    assert!(trapped.to_string().contains("run_for"));
+    assert!(!instance.is_paused() && !instance.is_suspended());
+    instance.start("run", &[Value::I32(1)]).unwrap();
+    assert_eq!(instance.run_for(10), Ok(None));
+    instance.resume(vec![Value::I32(5)]).unwrap();
+    assert_eq!(instance.run_for(10), Ok(Some(vec![Value::I32(500)])));
+}
+
+#[test]
+fn test_fuel() {
+    let module = Module::from_wat(include_str!("../../examples/factorial.wat")).unwrap();
+    let mut instance = Instance::new(&module, &Imports::new()).unwrap();
+    assert_eq!(instance.remaining_fuel(), None);
+    instance.set_fuel(10);
+    let trapped = instance.invoke("run", &[Value::I32(5)]).unwrap_err();
+    assert!(matches!(trapped, Error::Trap(Trap::OutOfFuel, _)));
+    assert_eq!(instance.remaining_fuel(), Some(0));
+    // the call waits for more fuel
+    assert!(instance.is_paused());
+    instance.add_fuel(1000);
+    let results = loop {
+        if let Some(results) = instance.run_for(100).unwrap() {
+            break results;
+        }
+    };
+    assert_eq!(results, vec![Value::I32(120)]);
+    instance.disable_fuel();
+    assert_eq!(instance.invoke("run", &[Value::I32(3)]), Ok(vec![Value::I32(6)]));
+    assert_eq!(instance.remaining_fuel(), None);
+}
+
+#[test]
+fn test_trap_backtrace() {
+    let module = Module::from_wat(
+        r#"(module
+  (func $main (export "main") (param i32) (result i32)
+    local.get 0
+    call $fac)
+  (func $fac (param i32) (result i32)
+    local.get 0
+    i32.eqz
+    if
+      unreachable
+    end
+    local.get 0
+    i32.const 1
+    i32.sub
+    call $fac
+    local.get 0
+    i32.mul))"#,
+    )
+    .unwrap();
+    let mut instance = Instance::new(&module, &Imports::new()).unwrap();
+    let trapped = instance.invoke("main", &[Value::I32(3)]).unwrap_err();
+    let backtrace = trapped.backtrace();
+    let functions: Vec<String> = backtrace.iter().map(|frame| frame.function()).collect();
+    assert_eq!(functions, vec!["$fac", "$fac", "$fac", "$fac", "$main"]);
+    let lines: Vec<usize> = backtrace.iter().filter_map(|frame| Some(frame.location.as_ref()?.line)).collect();
+    assert_eq!(lines, vec![9, 14, 14, 14, 4]);
+    assert_eq!(backtrace[0].op, "UNR");
+    assert_eq!(trapped.to_string(), "trap: unreachable at 9:7 in $fac");
+
+    // every active function is kept, also when the call stack is what ran out
+    let trapped = instance.invoke("main", &[Value::I32(-1)]).unwrap_err();
+    assert!(matches!(trapped, Error::Trap(Trap::CallStackExhausted, _)));
+    assert_eq!(trapped.backtrace().len(), DEFAULT_MAX_CALL_DEPTH);
+    assert_eq!(trapped.backtrace().last().map(Frame::function), Some(String::from("$main")));
+}
@@ -1,13 +1,16 @@
 
-use crate::{interpret::{self, lexer::Token, ast::ast::Label, parser::{self, parse_source}, runtime::Evaluator}, repl::main::run};
+use crate::interpret::{ast::ast::Label, parser::parse_source, runtime::{Evaluator, Value}};
 
 
-fn run_src(sc : &str,fn_idx : u32,  params: Vec<i32>) -> Option<i32>{ 
-    let  (mut module, mut blk_table)  = parse_source(sc);
+fn run_src(sc : &str, fn_idx : usize,  params: Vec<i32>) -> Option<i32>{ 
+    let  (module, blk_table)  = parse_source(sc).unwrap();
     let mut evaluator = Evaluator::new(module, blk_table);
-    evaluator.add_parameters(params);
-    evaluator.call(&Label::U32(0));
-    evaluator.run()
+    evaluator.add_parameters(params.into_iter().map(Value::I32).collect());
+    evaluator.call(&Label::U32(fn_idx));
+    match evaluator.run().unwrap().pop() {
+        Some(Value::I32(n)) => Some(n),
+        _ => None,
+    }
 }
 
 #[test]
@@ -119,3 +122,42 @@ fn test_integer_multiplication() {
     assert_eq!(result, Some(-4));
 }
 
+#[test]
+fn test_integer_unsigned_division_and_signed_remainder() {
+    let src =
+    r#"(module
+        (func (param i32 i32) (result i32)
+        local.get 0
+        local.get 1
+        i32.div_u)
+        (func (param i32 i32) (result i32)
+        local.get 0
+        local.get 1
+        i32.rem_s)
+    )"#;
+    assert_eq!(run_src(src, 0, vec![7, 2]), Some(3));
+    assert_eq!(run_src(src, 0, vec![-1, 2]), Some(i32::MAX));
+    assert_eq!(run_src(src, 1, vec![-7, 2]), Some(-1));
+    assert_eq!(run_src(src, 1, vec![7, -2]), Some(1));
+    assert_eq!(run_src(src, 1, vec![i32::MIN, -1]), Some(0));
+}
+
+#[test]
+fn test_integer_not_equal_xor_and_nop() {
+    let src =
+    r#"(module
+        (func (param i32 i32) (result i32)
+        local.get 0
+        local.get 1
+        i32.ne)
+        (func (param i32 i32) (result i32)
+        nop
+        local.get 0
+        local.get 1
+        i32.xor)
+    )"#;
+    assert_eq!(run_src(src, 0, vec![3, 4]), Some(1));
+    assert_eq!(run_src(src, 0, vec![4, 4]), Some(0));
+    assert_eq!(run_src(src, 1, vec![0b1100, 0b1010]), Some(0b0110));
+    assert_eq!(run_src(src, 1, vec![-1, 0]), Some(-1));
+}
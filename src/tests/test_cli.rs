@@ -0,0 +1,100 @@
+use interperter::{ValType, Value};
+
+use crate::cli::{parse_args, parse_integer, parse_value, run, Command, RunOptions};
+
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(String::from).collect()
+}
+
+/// Writes a module to a file of its own in the temporary directory
+fn module_file(name: &str, source: &str) -> String {
+    let path = std::env::temp_dir().join(format!("interperter-cli-{}-{}.wat", std::process::id(), name));
+    std::fs::write(&path, source).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+fn run_options(file: &str, invoke: Option<(&str, &[&str])>) -> RunOptions {
+    match parse_args(&args(&format!("run {}", file))) {
+        Ok(Command::Run(mut options)) => {
+            let invoke = invoke.map(|(name, args)| (name, args.iter().map(|arg| arg.to_string()).collect()));
+            options.invoke = invoke.map(|(name, args)| (name.to_string(), args));
+            options
+        }
+        _ => panic!("run {} should parse", file),
+    }
+}
+
+#[test]
+fn test_parse_args() {
+    match parse_args(&args("fac.wat")) {
+        Ok(Command::Repl { file, script: None, trace }) => {
+            assert_eq!(file, "fac.wat");
+            assert!(!trace.enabled);
+        }
+        _ => panic!("a lone file opens the REPL"),
+    }
+    match parse_args(&args("repl --trace-only fac fac.wat --script check.repl")) {
+        Ok(Command::Repl { file, script, trace }) => {
+            assert_eq!((file.as_str(), script.as_deref()), ("fac.wat", Some("check.repl")));
+            assert_eq!(trace.functions, vec!["fac"]);
+        }
+        _ => panic!("repl with a script should parse"),
+    }
+    match parse_args(&args("run prog.wasm --invoke add 1 -2 --dir /tmp:/work --trace-file t.trace -- -v x")) {
+        Ok(Command::Run(options)) => {
+            assert_eq!(options.file, "prog.wasm");
+            assert_eq!(options.invoke, Some((String::from("add"), vec![String::from("1"), String::from("-2")])));
+            assert_eq!(options.dirs, vec![(String::from("/tmp"), String::from("/work"))]);
+            assert_eq!(options.trace.file.as_deref(), Some("t.trace"));
+            assert_eq!(options.wasi_args, vec!["-v", "x"]);
+        }
+        _ => panic!("run with options should parse"),
+    }
+    assert!(matches!(parse_args(&args("wast a.wast b.wast")), Ok(Command::Wast { files }) if files.len() == 2));
+    for bad in ["", "run", "repl", "run a.wat --invoke", "run a.wat --bogus", "repl a.wat b.wat", "a.wat b.wat"] {
+        assert!(parse_args(&args(bad)).is_err(), "{:?} should be rejected", bad);
+    }
+}
+
+#[test]
+fn test_parse_integer_ranges() {
+    assert_eq!(parse_integer("0xffffffff", 32), Some(0xffff_ffff));
+    assert_eq!(parse_integer("-2147483648", 32), Some(i32::MIN as i128));
+    assert_eq!(parse_integer("-2147483649", 32), None);
+    assert_eq!(parse_integer("4294967296", 32), None);
+    assert_eq!(parse_integer("0xffff_ffff_ffff_ffff", 64), Some(u64::MAX as i128));
+    assert_eq!(parse_integer("-0x8000000000000000", 64), Some(i64::MIN as i128));
+    assert_eq!(parse_integer("+1_000", 32), Some(1000));
+    for bad in ["", "-", "0x", "0xg", "1e3", "abc", "--1"] {
+        assert_eq!(parse_integer(bad, 32), None, "{:?} should be rejected", bad);
+    }
+    assert_eq!(parse_value(ValType::I32, "0xffffffff"), Ok(Value::I32(-1)));
+    assert_eq!(parse_value(ValType::I64, "-1"), Ok(Value::I64(-1)));
+    assert_eq!(parse_value(ValType::F64, "-inf"), Ok(Value::F64(f64::NEG_INFINITY)));
+    assert!(parse_value(ValType::I32, "1.5").is_err());
+}
+
+#[test]
+fn test_run_exit_codes() {
+    let div = module_file(
+        "div",
+        r#"(module (func (export "div") (param i32 i32) (result i32) local.get 0 local.get 1 i32.div_s))"#,
+    );
+    assert_eq!(run(&run_options(&div, Some(("div", &["7", "2"])))), 0);
+    assert_eq!(run(&run_options(&div, Some(("div", &["7", "0"])))), 1);
+    assert_eq!(run(&run_options(&div, Some(("div", &["7"])))), 1);
+    assert_eq!(run(&run_options(&div, Some(("mod", &[])))), 1);
+    // without --invoke the module is a WASI command, which has no _start here
+    assert_eq!(run(&run_options(&div, None)), 1);
+    let exit = module_file(
+        "exit",
+        r#"(module
+          (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+          (memory (export "memory") 1)
+          (func (export "_start") i32.const 3 call $proc_exit))"#,
+    );
+    assert_eq!(run(&run_options(&exit, None)), 3);
+    std::fs::remove_file(&div).unwrap();
+    std::fs::remove_file(&exit).unwrap();
+    assert_eq!(run(&run_options(&div, None)), 1);
+}
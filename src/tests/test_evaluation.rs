@@ -1,13 +1,14 @@
-use log::debug;
+use crate::interpret::{ast::ast::Label, op::OP, parser::parse_source, runtime::{CostTable, Evaluator, Value}, trap::Trap};
 
-use crate::interpret::{ast::ast::Label, parser::parse_source, runtime::Evaluator};
-
-fn run_test_on_evaluator(sc : &str, fn_idx : u32, params: Vec<i32>) -> Option<i32>{
-  let  (module, blk_table)  = parse_source(sc);
+fn run_test_on_evaluator(sc : &str, fn_idx : usize, params: Vec<i32>) -> Option<i32>{
+  let  (module, blk_table)  = parse_source(sc).unwrap();
   let mut evaluator = Evaluator::new(module, blk_table);
-  evaluator.add_parameters(params);
-  evaluator.call(&Label::U32(0));
-  evaluator.run()
+  evaluator.add_parameters(params.into_iter().map(Value::I32).collect());
+  evaluator.call(&Label::U32(fn_idx));
+  match evaluator.run().unwrap().pop() {
+    Some(Value::I32(n)) => Some(n),
+    _ => None,
+  }
 }
 
 
@@ -105,7 +106,7 @@ let source_code = r#"
 fn test_nested_loop(){
 let source_code = r#"
 (module
-  (func
+  (func (result i32)
     (local $i i32) (local $j i32) 
     loop $loop0
       i32.const 0
@@ -294,14 +295,354 @@ let source_code = r#"
 }
 
 #[test]
-fn test_print(){
+fn test_multi_value_results(){
   let source_code = r#"
-(module 
-    (func $sq2 
+(module
+    (func $pair (param i32) (result i32 i64)
+      local.get 0
+      i64.const 7
+    )
+)
+"#;
+  let (module, blk_table) = parse_source(source_code).unwrap();
+  let mut evaluator = Evaluator::new(module, blk_table);
+  evaluator.add_parameters(vec![Value::I32(3)]);
+  evaluator.call(&Label::U32(0));
+  assert_eq!(evaluator.run(), Ok(vec![Value::I32(3), Value::I64(7)]));
+}
+
+#[test]
+fn test_params_in_order(){
+  let source_code = r#"
+(module
+    (func $sub (param $a i32) (param $b i32) (result i32)
+      local.get $a
+      local.get $b
+      i32.sub
+    )
+)
+"#;
+  assert_eq!(run_test_on_evaluator(source_code, 0, vec![10, 3]), Some(7));
+}
+
+#[test]
+fn test_block_params(){
+  let source_code = r#"
+(module
+    (type $binop (func (param i32 i32) (result i32)))
+    (func (result i32)
       i32.const 2
-      call $print
-    )     
+      i32.const 5
+      block $add (type $binop)
+        i32.add
+      end
+      i32.const 1
+      loop $l (param i32) (result i32)
+        i32.add
+      end
+    )
 )
 "#;
-  let result = run_test_on_evaluator(source_code, 0, vec![]);
-}
\ No newline at end of file
+  assert_eq!(run_test_on_evaluator(source_code, 0, vec![]), Some(8));
+}
+
+#[test]
+fn test_if_else(){
+  let source_code = r#"
+(module
+    (func $max (param i32) (param i32) (result i32)
+      local.get 0
+      local.get 1
+      i32.gt_s
+      if (result i32)
+        local.get 0
+      else
+        local.get 1
+      end
+    )
+)
+"#;
+  assert_eq!(run_test_on_evaluator(source_code, 0, vec![4, 9]), Some(9));
+  assert_eq!(run_test_on_evaluator(source_code, 0, vec![9, 4]), Some(9));
+}
+
+#[test]
+fn test_branch_unwinds_block_values(){
+  let source_code = r#"
+(module
+    (func (result i32 i32)
+      block $out (result i32 i32)
+        i32.const 9
+        i32.const 1
+        i32.const 2
+        br $out
+      end
+    )
+)
+"#;
+  let (module, blk_table) = parse_source(source_code).unwrap();
+  let mut evaluator = Evaluator::new(module, blk_table);
+  evaluator.call(&Label::U32(0));
+  assert_eq!(evaluator.run(), Ok(vec![Value::I32(1), Value::I32(2)]));
+}
+
+#[test]
+fn test_global_initializers(){
+  let source_code = r#"
+(module
+    (global $a i64 (i64.mul (i64.const 3) (i64.const 4)))
+    (global $b f64 (f64.const 0.5))
+    (func (result i64 f64)
+      global.get $a
+      global.get $b
+    )
+)
+"#;
+  let (module, blk_table) = parse_source(source_code).unwrap();
+  let mut evaluator = Evaluator::new(module, blk_table);
+  evaluator.call(&Label::U32(0));
+  assert_eq!(evaluator.run(), Ok(vec![Value::I64(12), Value::F64(0.5)]));
+}
+
+#[test]
+fn test_data_segment(){
+  let source_code = r#"
+(module
+    (memory 1)
+    (data (i32.const 4) "\2a\00\00\00")
+    (func (result i32)
+      i32.const 4
+      i32.load
+    )
+)
+"#;
+  assert_eq!(run_test_on_evaluator(source_code, 0, vec![]), Some(42));
+}
+
+#[test]
+fn test_call_indirect(){
+  let source_code = r#"
+(module
+    (type $binop (func (param i32 i32) (result i32)))
+    (table 2 funcref)
+    (elem (i32.const 0) $add $sub)
+    (func (param i32) (result i32)
+      i32.const 10
+      i32.const 3
+      local.get 0
+      call_indirect (type $binop)
+    )
+    (func $add (param i32 i32) (result i32) local.get 0 local.get 1 i32.add)
+    (func $sub (param i32 i32) (result i32) local.get 0 local.get 1 i32.sub)
+)
+"#;
+  assert_eq!(run_test_on_evaluator(source_code, 0, vec![0]), Some(13));
+  assert_eq!(run_test_on_evaluator(source_code, 0, vec![1]), Some(7));
+}
+
+#[test]
+fn test_call_stack_exhausted(){
+  let source_code = r#"
+(module
+    (func $loop (param i32) (result i32)
+      local.get 0
+      call $loop
+    )
+    (func $double (param i32) (result i32)
+      local.get 0
+      local.get 0
+      i32.add
+    )
+)
+"#;
+  let (module, blk_table) = parse_source(source_code).unwrap();
+  let mut evaluator = Evaluator::new(module, blk_table);
+  evaluator.max_call_depth = 100;
+  evaluator.add_parameters(vec![Value::I32(1)]);
+  evaluator.call(&Label::REF(String::from("loop")));
+  assert_eq!(evaluator.run(), Err(Trap::CallStackExhausted));
+  assert!(evaluator.calls.is_empty());
+  assert!(evaluator.stack.is_empty());
+  // the evaluator is still usable after the trap
+  evaluator.add_parameters(vec![Value::I32(21)]);
+  evaluator.call(&Label::REF(String::from("double")));
+  assert_eq!(evaluator.run(), Ok(vec![Value::I32(42)]));
+}
+
+#[test]
+fn test_operand_stack_exhausted(){
+  let source_code = r#"
+(module
+    (func $grow
+      i32.const 1
+      call $grow
+      drop
+    )
+)
+"#;
+  let (module, blk_table) = parse_source(source_code).unwrap();
+  let mut evaluator = Evaluator::new(module, blk_table);
+  evaluator.max_stack_size = 64;
+  evaluator.call(&Label::U32(0));
+  assert_eq!(evaluator.run(), Err(Trap::OperandStackExhausted));
+  assert!(evaluator.stack.is_empty());
+}
+
+#[test]
+fn test_operand_stack_limit_checked_before_push(){
+  let source_code = r#"
+(module
+    (func $three (result i32)
+      i32.const 1
+      i32.const 2
+      i32.const 3
+      drop
+      drop
+    )
+)
+"#;
+  let (module, blk_table) = parse_source(source_code).unwrap();
+  let mut evaluator = Evaluator::new(module, blk_table);
+  evaluator.max_stack_size = 2;
+  evaluator.call(&Label::U32(0));
+  assert_eq!(evaluator.step(), Ok(()));
+  assert_eq!(evaluator.step(), Ok(()));
+  assert_eq!(evaluator.step(), Err(Trap::OperandStackExhausted));
+  assert_eq!(evaluator.stack.len(), 2);
+  assert_eq!(Trap::OperandStackExhausted.to_string(), "operand stack exhausted");
+}
+
+#[test]
+fn test_arithmetic_and_memory_traps(){
+  let source_code = r#"
+(module
+    (memory 1)
+    (func $div (param i32 i32) (result i32) local.get 0 local.get 1 i32.div_s)
+    (func $load (param i32) (result i32) local.get 0 i32.load)
+    (func $trap unreachable)
+    (func $div_u (param i32 i32) (result i32) local.get 0 local.get 1 i32.div_u)
+    (func $rem_s (param i32 i32) (result i32) local.get 0 local.get 1 i32.rem_s)
+)
+"#;
+  let (module, blk_table) = parse_source(source_code).unwrap();
+  let mut evaluator = Evaluator::new(module, blk_table);
+  evaluator.add_parameters(vec![Value::I32(1), Value::I32(0)]);
+  evaluator.call(&Label::U32(0));
+  assert_eq!(evaluator.run(), Err(Trap::IntegerDivideByZero));
+  evaluator.add_parameters(vec![Value::I32(i32::MIN), Value::I32(-1)]);
+  evaluator.call(&Label::U32(0));
+  assert_eq!(evaluator.run(), Err(Trap::IntegerOverflow));
+  evaluator.add_parameters(vec![Value::I32(65534)]);
+  evaluator.call(&Label::U32(1));
+  assert_eq!(evaluator.run(), Err(Trap::MemoryOutOfBounds));
+  evaluator.call(&Label::U32(2));
+  assert_eq!(evaluator.run(), Err(Trap::Unreachable));
+  for fn_idx in [3, 4] {
+    evaluator.add_parameters(vec![Value::I32(1), Value::I32(0)]);
+    evaluator.call(&Label::U32(fn_idx));
+    assert_eq!(evaluator.run(), Err(Trap::IntegerDivideByZero));
+  }
+}
+
+#[test]
+fn test_fuel_resumes_after_refill(){
+  let source_code = r#"
+(module
+    (func $sum (param i32) (result i32) (local i32)
+      block $done
+        loop $l
+          local.get 0
+          i32.eqz
+          br_if $done
+          local.get 1
+          local.get 0
+          i32.add
+          local.set 1
+          local.get 0
+          i32.const 1
+          i32.sub
+          local.set 0
+          br $l
+        end
+      end
+      local.get 1
+    )
+)
+"#;
+  let (module, blk_table) = parse_source(source_code).unwrap();
+  let mut evaluator = Evaluator::new(module, blk_table);
+  evaluator.set_fuel(50);
+  evaluator.add_parameters(vec![Value::I32(100)]);
+  evaluator.call(&Label::U32(0));
+  assert_eq!(evaluator.run(), Err(Trap::OutOfFuel));
+  assert_eq!(evaluator.remaining_fuel(), Some(0));
+  assert_eq!(evaluator.calls.len(), 1);
+  let mut refills = 0;
+  let results = loop {
+    match evaluator.run() {
+      Ok(results) => break results,
+      Err(Trap::OutOfFuel) => {
+        refills += 1;
+        evaluator.add_fuel(50);
+      }
+      Err(trap) => panic!("unexpected trap {}", trap),
+    }
+  };
+  assert!(refills > 10);
+  assert_eq!(results, vec![Value::I32(5050)]);
+}
+
+#[test]
+fn test_fuel_cost_table(){
+  let source_code = r#"
+(module
+    (memory 1)
+    (func $id (param i32) (result i32) local.get 0)
+    (func (result i32)
+      i32.const 0
+      i32.load
+      call $id
+    )
+)
+"#;
+  let (module, blk_table) = parse_source(source_code).unwrap();
+  let mut evaluator = Evaluator::new(module, blk_table);
+  evaluator.cost_table = CostTable { default: 1, call: 100, memory: 10 };
+  evaluator.set_fuel(1000);
+  evaluator.call(&Label::U32(1));
+  assert_eq!(evaluator.run(), Ok(vec![Value::I32(0)]));
+  // const, load, call, return in $id and the caller
+  assert_eq!(evaluator.remaining_fuel(), Some(1000 - 1 - 10 - 100 - 1 - 1 - 1));
+  // every opcode of a kind costs the same
+  let table = &evaluator.cost_table;
+  assert_eq!(table.cost(&OP::I32STORE), 10);
+  assert_eq!(table.cost(&OP::CALLIND(0)), 100);
+  assert_eq!(table.cost(&OP::I32XOR), 1);
+  assert_eq!(table.cost(&OP::LOCTEE(0)), 1);
+}
+
+#[test]
+fn test_interrupt_infinite_loop(){
+  let source_code = r#"
+(module
+    (func $spin
+      loop $l
+        br $l
+      end
+    )
+    (func $one (result i32) i32.const 1)
+)
+"#;
+  let (module, blk_table) = parse_source(source_code).unwrap();
+  let mut evaluator = Evaluator::new(module, blk_table);
+  let interrupt = evaluator.interrupt_handle();
+  let interrupter = std::thread::spawn(move || {
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    interrupt.interrupt();
+  });
+  evaluator.call(&Label::U32(0));
+  assert_eq!(evaluator.run(), Err(Trap::Interrupted));
+  interrupter.join().unwrap();
+  evaluator.call(&Label::U32(1));
+  assert_eq!(evaluator.run(), Ok(vec![Value::I32(1)]));
+}
@@ -0,0 +1,109 @@
+use interperter::{Imports, Instance, Module};
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::{Hint, Hinter};
+use rustyline::history::History;
+use rustyline::Context;
+
+use crate::repl::helper::ReplHelper;
+
+const EXPORTS: &str = r#"(module
+    (memory (export "heap") 1)
+    (global (export "count") (mut i64) (i64.const 0))
+    (global (export "limit") i32 (i32.const 8))
+    (func (export "mix") (param i32 i64 f32 f64) (result i32 i64 f32 f64)
+      local.get 0
+      local.get 1
+      local.get 2
+      local.get 3)
+    (func (export "none"))
+)"#;
+
+fn helper() -> ReplHelper {
+    let instance = Instance::new(&Module::from_wat(EXPORTS).unwrap(), &Imports::new()).unwrap();
+    let mut helper = ReplHelper::default();
+    helper.exports = instance.exports();
+    helper
+}
+
+/// The start of the replaced text and the replacements offered at the end of the line
+fn complete(helper: &ReplHelper, line: &str) -> (usize, Vec<String>) {
+    let history = History::new();
+    let (start, candidates) = helper.complete(line, line.len(), &Context::new(&history)).unwrap();
+    (start, candidates.into_iter().map(|candidate| candidate.replacement).collect())
+}
+
+fn hint(helper: &ReplHelper, line: &str, pos: usize) -> Option<String> {
+    let history = History::new();
+    helper.hint(line, pos, &Context::new(&history)).map(|hint| hint.display().to_string())
+}
+
+#[test]
+fn test_completion() {
+    let helper = helper();
+    assert_eq!(complete(&helper, ":qu"), (1, vec![String::from("quit")]));
+    assert_eq!(
+        complete(&helper, ":re"),
+        (1, vec![String::from("reload"), String::from("reset")])
+    );
+    assert_eq!(complete(&helper, ":op"), (1, vec![String::from("open")]));
+    assert_eq!(complete(&helper, ":zz"), (1, vec![]));
+    assert_eq!(
+        complete(&helper, "exports.f"),
+        (0, vec![String::from("exports.functions.mix("), String::from("exports.functions.none(")])
+    );
+    assert_eq!(
+        complete(&helper, "exports.g"),
+        (0, vec![String::from("exports.globals.get("), String::from("exports.globals.set(")])
+    );
+    assert_eq!(complete(&helper, "exports.memory.d"), (0, vec![String::from("exports.memory.dump(")]));
+
+    // names only complete between the parentheses of the commands that take one
+    assert_eq!(complete(&helper, "exports.globals.get("), (20, vec![String::from("count"), String::from("limit")]));
+    assert_eq!(complete(&helper, "exports.globals.set(l"), (20, vec![String::from("limit")]));
+    assert_eq!(complete(&helper, "exports.memory.get("), (19, vec![String::from("heap")]));
+    assert_eq!(complete(&helper, "exports.globals.set(count, "), (27, vec![]));
+    assert_eq!(complete(&helper, "exports.functions.mix(1"), (23, vec![]));
+
+    // nothing is offered before a module is loaded except the commands themselves
+    let empty = ReplHelper::default();
+    assert_eq!(complete(&empty, "exports.f"), (0, vec![]));
+    assert_eq!(complete(&empty, "exports.globals.get("), (20, vec![]));
+}
+
+#[test]
+fn test_hints() {
+    let helper = helper();
+    let at_end = |line: &str| hint(&helper, line, line.len());
+    let results = " -> (i32, i64, f32, f64)";
+    assert_eq!(at_end("exports.functions.mix"), Some(format!("(i32, i64, f32, f64){}", results)));
+    assert_eq!(at_end("exports.functions.mix("), Some(format!("i32, i64, f32, f64){}", results)));
+    assert_eq!(at_end("exports.functions.mix(1"), Some(format!(", i64, f32, f64){}", results)));
+    assert_eq!(at_end("exports.functions.mix(1,"), Some(format!(" i64, f32, f64){}", results)));
+    assert_eq!(at_end("exports.functions.mix(1, "), Some(format!("i64, f32, f64){}", results)));
+    assert_eq!(at_end("exports.functions.mix(1, 2, 3.0, 4.0"), Some(format!("){}", results)));
+    assert_eq!(at_end("exports.functions.none"), Some(String::from("() -> ()")));
+    assert_eq!(at_end("exports.functions.none("), Some(String::from(") -> ()")));
+
+    // no hint once the call is closed, away from the end of the line or for unknown functions
+    assert_eq!(at_end("exports.functions.mix(1, 2, 3.0, 4.0)"), None);
+    assert_eq!(at_end("exports.functions.missing("), None);
+    assert_eq!(at_end("exports.globals.get("), None);
+    assert_eq!(hint(&helper, "exports.functions.mix(", 5), None);
+}
+
+#[test]
+fn test_highlighting() {
+    let helper = helper();
+    assert_eq!(helper.highlight(":open file.wat", 0), "\x1b[1m:open\x1b[0m file.wat");
+    assert_eq!(helper.highlight(":exports", 0), "\x1b[1m:exports\x1b[0m");
+    assert_eq!(
+        helper.highlight(r#"exports.memory.write(16, "ab")"#, 0),
+        "\x1b[1mexports.memory.write\x1b[0m(\x1b[33m16\x1b[0m, \x1b[32m\"ab\"\x1b[0m)"
+    );
+    assert_eq!(
+        helper.highlight("exports.globals.set(count, 3)", 0),
+        "\x1b[1mexports.globals.set\x1b[0m(\x1b[36mcount\x1b[0m, \x1b[33m3\x1b[0m)"
+    );
+    assert_eq!(helper.highlight_hint(") -> ()"), "\x1b[2m) -> ()\x1b[0m");
+}
@@ -0,0 +1,138 @@
+use crate::interpret::{
+    ast::ast::{FuncType, Label, ValType},
+    host::{HostAction, HostFunc},
+    parser::parse_source,
+    runtime::{Evaluator, RunState, Value},
+    trap::Trap,
+};
+
+fn evaluator_for(sc: &str) -> Evaluator {
+    let (module, blk_table) = parse_source(sc).unwrap();
+    Evaluator::new(module, blk_table)
+}
+
+fn i32_to_i32() -> FuncType {
+    FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    }
+}
+
+const COUNTDOWN: &str = r#"(module
+    (func $count (param i32) (result i32) (local i32)
+      block $done
+        loop $l
+          local.get 0
+          i32.eqz
+          br_if $done
+          local.get 0
+          i32.const 1
+          i32.sub
+          local.set 0
+          local.get 1
+          i32.const 1
+          i32.add
+          local.set 1
+          br $l
+        end
+      end
+      local.get 1)
+)"#;
+
+#[test]
+fn test_run_for_time_slices() {
+    let mut first = evaluator_for(COUNTDOWN);
+    let mut second = evaluator_for(COUNTDOWN);
+    first.add_parameters(vec![Value::I32(30)]);
+    first.call(&Label::U32(0));
+    second.add_parameters(vec![Value::I32(3)]);
+    second.call(&Label::U32(0));
+    let mut results = [None, None];
+    let mut slices = 0;
+    while results.iter().any(|result| result.is_none()) {
+        for (i, evaluator) in [&mut first, &mut second].into_iter().enumerate() {
+            if results[i].is_some() {
+                continue;
+            }
+            match evaluator.run_for(10) {
+                RunState::Finished(values) => results[i] = Some(values),
+                RunState::Yielded => slices += 1,
+                RunState::Trapped(trap) => panic!("unexpected trap {}", trap),
+            }
+        }
+    }
+    assert!(slices > 10);
+    assert_eq!(results[0], Some(vec![Value::I32(30)]));
+    assert_eq!(results[1], Some(vec![Value::I32(3)]));
+}
+
+#[test]
+fn test_host_function_returns() {
+    let mut evaluator = evaluator_for(
+        r#"(module
+        (import "env" "double" (func $double (param i32) (result i32)))
+        (func (param i32) (result i32)
+          local.get 0
+          call $double
+          i32.const 1
+          i32.add)
+    )"#,
+    );
+    let double = HostFunc::new(i32_to_i32(), |_, args| match args {
+        [Value::I32(n)] => Ok(HostAction::Return(vec![Value::I32(n * 2)])),
+        _ => Err(Trap::Host(String::from("bad arguments"))),
+    });
+    evaluator.define_host_fn("env", "double", double).unwrap();
+    evaluator.add_parameters(vec![Value::I32(20)]);
+    evaluator.call(&Label::U32(1));
+    assert_eq!(evaluator.run(), Ok(vec![Value::I32(41)]));
+}
+
+#[test]
+fn test_host_function_yields() {
+    let mut evaluator = evaluator_for(
+        r#"(module
+        (import "env" "ask" (func $ask (param i32) (result i32)))
+        (func (result i32)
+          i32.const 7
+          call $ask
+          i32.const 100
+          i32.mul)
+    )"#,
+    );
+    evaluator
+        .define_host_fn("env", "ask", HostFunc::new(i32_to_i32(), |_, _| Ok(HostAction::Yield)))
+        .unwrap();
+    evaluator.call(&Label::U32(1));
+    assert_eq!(evaluator.run_for(100), RunState::Yielded);
+    assert!(evaluator.is_suspended());
+    // stays suspended until the host answers
+    assert_eq!(evaluator.run_for(100), RunState::Yielded);
+    assert!(evaluator.resume(vec![Value::I64(1)]).is_err());
+    evaluator.resume(vec![Value::I32(3)]).unwrap();
+    assert_eq!(evaluator.run_for(100), RunState::Finished(vec![Value::I32(300)]));
+}
+
+#[test]
+fn test_host_function_errors() {
+    let source_code = r#"(module
+        (import "env" "f" (func $f (param i32) (result i32)))
+        (func (result i32) i32.const 1 call $f)
+    )"#;
+    let mut evaluator = evaluator_for(source_code);
+    evaluator.call(&Label::U32(1));
+    assert_eq!(
+        evaluator.run(),
+        Err(Trap::Host(String::from("unresolved import env.f")))
+    );
+    let wrong_type = HostFunc::new(FuncType::default(), |_, _| Ok(HostAction::Return(vec![])));
+    assert!(evaluator.define_host_fn("env", "f", wrong_type).is_err());
+    assert!(evaluator
+        .define_host_fn("env", "g", HostFunc::new(i32_to_i32(), |_, _| Ok(HostAction::Yield)))
+        .is_err());
+    let failing = HostFunc::new(i32_to_i32(), |_, _| Err(Trap::Host(String::from("host failed"))));
+    evaluator.define_host_fn("env", "f", failing).unwrap();
+    evaluator.call(&Label::U32(1));
+    assert_eq!(evaluator.run(), Err(Trap::Host(String::from("host failed"))));
+    assert!(evaluator.calls.is_empty());
+}
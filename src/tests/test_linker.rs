@@ -0,0 +1,253 @@
+use crate::{Error, FuncType, Imports, Linker, Module, Store, Trap, ValType, Value};
+
+const LIB: &str = r#"(module
+    (memory (export "mem") 1)
+    (global (export "counter") (mut i32) (i32.const 0))
+    (global (export "base") i32 (i32.const 100))
+    (table (export "table") 2 funcref)
+    (elem (i32.const 0) $double)
+    (func $double (export "double") (param i32) (result i32)
+      local.get 0
+      local.get 0
+      i32.add)
+    (func (export "bump") (result i32)
+      global.get 0
+      i32.const 1
+      i32.add
+      global.set 0
+      i32.const 0
+      i32.const 0
+      i32.load
+      i32.const 1
+      i32.add
+      i32.store
+      global.get 0)
+)"#;
+
+fn lib(linker: &mut Linker, store: &mut Store) {
+    let module = Module::from_wat(LIB).unwrap();
+    let instance = linker.instantiate(store, &module).unwrap();
+    linker.register("lib", instance);
+}
+
+#[test]
+fn test_link_functions_and_globals() {
+    let (mut linker, mut store) = (Linker::new(), Store::new());
+    lib(&mut linker, &mut store);
+    let module = Module::from_wat(
+        r#"(module
+        (import "lib" "double" (func $double (param i32) (result i32)))
+        (import "lib" "bump" (func $bump (result i32)))
+        (import "lib" "counter" (global $counter (mut i32)))
+        (import "lib" "base" (global $base i32))
+        (global $start i32 (global.get $base))
+        (func (export "run") (param i32) (result i32)
+          local.get 0
+          call $double
+          global.get $start
+          i32.add)
+        (func (export "twice") (result i32)
+          call $bump
+          drop
+          global.get $counter
+          i32.const 10
+          i32.add
+          global.set $counter
+          call $bump)
+    )"#,
+    )
+    .unwrap();
+    let app = linker.instantiate(&mut store, &module).unwrap();
+    assert_eq!(store.invoke(app, "run", &[Value::I32(21)]), Ok(vec![Value::I32(142)]));
+    assert_eq!(store.invoke(app, "twice", &[]), Ok(vec![Value::I32(12)]));
+    let lib = linker.get("lib").unwrap();
+    assert_eq!(store.global(lib, "counter"), Some(Value::I32(12)));
+    assert_eq!(store.invoke(lib, "bump", &[]), Ok(vec![Value::I32(13)]));
+    assert_eq!(store.memory(lib).unwrap()[..4], 3i32.to_le_bytes());
+}
+
+#[test]
+fn test_link_memory_and_table() {
+    let (mut linker, mut store) = (Linker::new(), Store::new());
+    lib(&mut linker, &mut store);
+    let module = Module::from_wat(
+        r#"(module
+        (import "lib" "mem" (memory 1))
+        (import "lib" "table" (table 1 funcref))
+        (type $unop (func (param i32) (result i32)))
+        (data (i32.const 8) "hi")
+        (elem (i32.const 1) $negate)
+        (func $negate (param i32) (result i32)
+          i32.const 0
+          local.get 0
+          i32.sub)
+        (func (export "apply") (param i32 i32) (result i32)
+          local.get 1
+          local.get 0
+          call_indirect (type $unop))
+        (func (export "load") (param i32) (result i32)
+          local.get 0
+          i32.load)
+    )"#,
+    )
+    .unwrap();
+    let app = linker.instantiate(&mut store, &module).unwrap();
+    let lib = linker.get("lib").unwrap();
+    assert_eq!(store.memory(lib).unwrap()[8..10], *b"hi");
+    assert_eq!(store.invoke(app, "apply", &[Value::I32(0), Value::I32(4)]), Ok(vec![Value::I32(8)]));
+    assert_eq!(store.invoke(app, "apply", &[Value::I32(1), Value::I32(4)]), Ok(vec![Value::I32(-4)]));
+    store.invoke(lib, "bump", &[]).unwrap();
+    assert_eq!(store.invoke(app, "load", &[Value::I32(0)]), Ok(vec![Value::I32(1)]));
+}
+
+#[test]
+fn test_link_errors() {
+    let (mut linker, mut store) = (Linker::new(), Store::new());
+    lib(&mut linker, &mut store);
+    let link = |source: &str, linker: &Linker, store: &mut Store| {
+        let module = Module::from_wat(source).unwrap();
+        linker.instantiate(store, &module).unwrap_err()
+    };
+    assert_eq!(
+        link(r#"(module (import "lib" "triple" (func)))"#, &linker, &mut store),
+        Error::Link(String::from("unknown import lib.triple"))
+    );
+    assert_eq!(
+        link(r#"(module (import "lib" "double" (func (param i64) (result i32))))"#, &linker, &mut store),
+        Error::Link(String::from(
+            "incompatible import type for lib.double: expected FuncType { params: [I64], results: [I32] }, saw FuncType { params: [I32], results: [I32] }"
+        ))
+    );
+    assert_eq!(
+        link(r#"(module (import "lib" "counter" (global i32)))"#, &linker, &mut store),
+        Error::Link(String::from("incompatible import type for lib.counter: expected i32, saw mut i32"))
+    );
+    assert_eq!(
+        link(r#"(module (import "lib" "mem" (func)))"#, &linker, &mut store),
+        Error::Link(String::from("incompatible import type for lib.mem: expected a function, saw a memory"))
+    );
+    assert_eq!(
+        link(r#"(module (import "lib" "mem" (memory 2)))"#, &linker, &mut store),
+        Error::Link(String::from("incompatible import type for lib.mem: expected at least 2 pages, saw 1 pages"))
+    );
+    assert_eq!(
+        link(r#"(module (import "env" "mem" (memory 1)))"#, &linker, &mut store),
+        Error::Link(String::from("unknown import env.mem"))
+    );
+
+    // segments before the one that doesn't fit stay written
+    let failed = link(
+        r#"(module
+        (import "lib" "mem" (memory 1))
+        (data (i32.const 16) "ok")
+        (data (i32.const 65535) "no"))"#,
+        &linker,
+        &mut store,
+    );
+    assert_eq!(failed, Error::Trap(Trap::MemoryOutOfBounds, Vec::new()));
+    assert_eq!(store.memory(linker.get("lib").unwrap()).unwrap()[16..18], *b"ok");
+}
+
+#[test]
+fn test_link_host_imports() {
+    let (mut linker, mut store) = (Linker::new(), Store::new());
+    let mut imports = Imports::new();
+    imports
+        .func("env", "square", FuncType { params: vec![ValType::I32], results: vec![ValType::I32] }, |_, args| {
+            match args[0] {
+                Value::I32(n) => Ok(vec![Value::I32(n * n)]),
+                _ => unreachable!(),
+            }
+        })
+        .global("env", "offset", Value::I32(1));
+    linker.define(&imports);
+    let module = Module::from_wat(
+        r#"(module
+        (import "env" "square" (func $square (param i32) (result i32)))
+        (import "env" "offset" (global $offset i32))
+        (func (export "run") (param i32) (result i32)
+          local.get 0
+          call $square
+          global.get $offset
+          i32.add)
+    )"#,
+    )
+    .unwrap();
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    linker.register("squares", instance);
+    let module = Module::from_wat(
+        r#"(module
+        (import "squares" "run" (func $run (param i32) (result i32)))
+        (func (export "main") (result i32)
+          i32.const 7
+          call $run)
+    )"#,
+    )
+    .unwrap();
+    let main = linker.instantiate(&mut store, &module).unwrap();
+    assert_eq!(store.invoke(main, "main", &[]), Ok(vec![Value::I32(50)]));
+}
+
+#[test]
+fn test_link_trap_backtrace() {
+    let (mut linker, mut store) = (Linker::new(), Store::new());
+    let module = Module::from_wat(
+        r#"(module
+        (func $inner unreachable)
+        (func $boom (export "boom") call $inner))"#,
+    )
+    .unwrap();
+    let lib = linker.instantiate(&mut store, &module).unwrap();
+    linker.register("lib", lib);
+    let module = Module::from_wat(
+        r#"(module
+        (import "lib" "boom" (func $boom))
+        (export "boom" (func $boom))
+        (func $main (export "main") call $boom))"#,
+    )
+    .unwrap();
+    let app = linker.instantiate(&mut store, &module).unwrap();
+    // the caller's frames continue into the instance that trapped, innermost first
+    let trapped = store.invoke(app, "main", &[]).unwrap_err();
+    assert!(matches!(trapped, Error::Trap(Trap::Unreachable, _)));
+    let functions: Vec<String> = trapped.backtrace().iter().map(|frame| frame.function()).collect();
+    assert_eq!(functions, vec!["$inner", "$boom", "$main"]);
+    // also when the import is invoked through the app's export
+    let trapped = store.invoke(app, "boom", &[]).unwrap_err();
+    let functions: Vec<String> = trapped.backtrace().iter().map(|frame| frame.function()).collect();
+    assert_eq!(functions, vec!["$inner", "$boom"]);
+}
+
+#[test]
+fn test_link_reentrant_call_traps() {
+    let (mut linker, mut store) = (Linker::new(), Store::new());
+    let module = Module::from_wat(
+        r#"(module
+        (table (export "table") 1 funcref)
+        (type $ret (func (result i32)))
+        (func (export "go") (result i32)
+          i32.const 0
+          call_indirect (type $ret)))"#,
+    )
+    .unwrap();
+    let lib = linker.instantiate(&mut store, &module).unwrap();
+    linker.register("lib", lib);
+    // the callback in the table belongs to the instance that calls `go`
+    let module = Module::from_wat(
+        r#"(module
+        (import "lib" "table" (table 1 funcref))
+        (import "lib" "go" (func $go (result i32)))
+        (elem (i32.const 0) $callback)
+        (func $callback (result i32) i32.const 7)
+        (func (export "run") (result i32) call $go))"#,
+    )
+    .unwrap();
+    let app = linker.instantiate(&mut store, &module).unwrap();
+    let trapped = store.invoke(app, "run", &[]).unwrap_err();
+    assert_eq!(
+        trapped.to_string().split(" at ").next(),
+        Some("trap: re-entrant calls between linked instances aren't supported")
+    );
+    // called from outside the app, the callback runs
+    assert_eq!(store.invoke(lib, "go", &[]), Ok(vec![Value::I32(7)]));
+}
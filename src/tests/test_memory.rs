@@ -0,0 +1,67 @@
+use crate::repl::memory::{hexdump, layout, typed, Field, View};
+
+fn memory() -> Vec<u8> {
+    let mut memory = b"Hello, world!\0\0\0".to_vec();
+    memory.extend(42i32.to_le_bytes());
+    memory.extend((-1i32).to_le_bytes());
+    memory
+}
+
+#[test]
+fn test_hexdump() {
+    let dump = hexdump(&memory(), 0, 20).unwrap();
+    let rows: Vec<&str> = dump.lines().collect();
+    assert_eq!(rows[0], "00000000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 00 00 00  |Hello, world!...|");
+    // a short row is padded so that the ASCII column lines up
+    assert_eq!(rows[1], format!("00000010  2a 00 00 00{}  |*...|", " ".repeat(12 * 3 + 1)));
+    assert_eq!(rows.len(), 2);
+    assert_eq!(hexdump(&memory(), 4, 3).unwrap(), format!("00000004  6f 2c 20{}  |o, |\n", " ".repeat(13 * 3 + 1)));
+    assert!(hexdump(&memory(), 20, 5).is_err());
+    assert!(hexdump(&memory(), usize::MAX, 2).is_err());
+}
+
+#[test]
+fn test_typed_views() {
+    let memory = memory();
+    assert_eq!(typed(&memory, 16, 8, View::I32), Ok(String::from("00000010: 42 -1\n")));
+    assert_eq!(typed(&memory, 16, 8, View::U32), Ok(String::from("00000010: 42 4294967295\n")));
+    assert_eq!(typed(&memory, 20, 4, View::I8), Ok(String::from("00000014: -1 -1 -1 -1\n")));
+    assert_eq!(typed(&memory, 16, 8, View::I64), Ok(format!("00000010: {}\n", 42 | (0xffff_ffff_i64 << 32))));
+    assert_eq!(typed(&memory, 0, 16, View::CString), Ok(String::from("00000000: \"Hello, world!\"\n")));
+    assert_eq!(typed(&memory, 0, 5, View::Utf8), Ok(String::from("00000000: \"Hello\"\n")));
+    // numbers take a row for every 16 bytes
+    let rows = typed(&memory, 0, 24, View::U8).unwrap();
+    assert_eq!(rows.lines().count(), 2);
+    assert!(rows.ends_with("00000010: 42 0 0 0 255 255 255 255\n"));
+    assert!(typed(&memory, 16, 6, View::I32).is_err());
+    assert!(typed(&memory, 20, 8, View::I32).is_err());
+    assert_eq!(View::parse("f64"), Ok(View::F64));
+    assert_eq!(View::parse("cstring"), Ok(View::CString));
+    assert!(View::parse("i128").is_err());
+}
+
+#[test]
+fn test_struct_layout() {
+    let mut memory = vec![7, 0, 0, 0, 42, 0, 0, 0, b'h', b'i', 0, 0, 0, 0, 1, 0, 2, 0];
+    memory.resize(32, 0);
+    let field = |name: &str, view, count| Field {
+        name: name.to_string(),
+        view,
+        count,
+    };
+    let fields = [
+        field("c", View::U8, None),
+        field("x", View::I32, None),
+        field("name", View::CString, Some(6)),
+        field("pair", View::U16, Some(2)),
+    ];
+    // x is aligned to 4 bytes and the size is rounded up to the largest alignment
+    assert_eq!(
+        layout(&memory, 0, &fields),
+        Ok(String::from(
+            "+0    c: u8 = 7\n+4    x: i32 = 42\n+8    name: cstring[6] = \"hi\"\n+14   pair: u16[2] = [1, 2]\n20 bytes\n"
+        ))
+    );
+    assert!(layout(&memory, 0, &[field("name", View::CString, None)]).is_err());
+    assert!(layout(&memory, 28, &fields).is_err());
+}
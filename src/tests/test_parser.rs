@@ -2,137 +2,139 @@
 mod test_parser {
 use crate::interpret::op::{OP, OP::*};
 use crate::interpret::ast::ast::{self, Export, Global, Label, Mem};
+use crate::interpret::lexer::unescape;
+use crate::{Error, Module};
 use crate::interpret::parser::parse_source;
 #[test]
 fn test_empty_module(){
     let source = "(module)";
-    parse_source(source);
+    parse_source(source).unwrap();
 }
 #[test]
 fn test_empty_function(){
     let source = r#"(module 
                                 (func ))"#;
-    parse_source(source);
+    parse_source(source).unwrap();
 }
 #[test]
 fn test_one_param_function(){
     let source = r#"(module 
                                 (func (param i32) ))"#;
-    parse_source(source);
+    parse_source(source).unwrap();
 }
 #[test]
 fn test_param_id_function(){
     let source = r#"(module 
                                 (func (param $p1 i32) ))"#;
-    parse_source(source);
+    parse_source(source).unwrap();
 }
 #[test]
 fn test_more_than_one_param_function(){
     let source = r#"(module 
                                 (func (param i32) (param i32) (param i32) ))"#;
-    parse_source(source);
+    parse_source(source).unwrap();
 }
 
 #[test]
 fn test_one_result_function(){
     let source = r#"(module 
     (func (result i32) ))"#;
-    parse_source(source);
+    parse_source(source).unwrap();
 }
 #[test]
 fn test_param_and_result_function(){
     let source = r#"(module 
     (func (param i32) (result i32) ))"#;
-    parse_source(source);
+    parse_source(source).unwrap();
 }
 #[test]
 fn test_id_function(){
     let source = r#"(module 
     (func $id ))"#;
-    parse_source(source);
+    parse_source(source).unwrap();
 }
 #[test]
 fn test_id_param_function(){
     let source = r#"(module 
     (func $id (param i32) ))"#;
-    parse_source(source);
+    parse_source(source).unwrap();
 }
 #[test]
 fn test_one_local_function(){
     let source = r#"(module 
     (func $id (local i32) ))"#;
-    parse_source(source);  
+    parse_source(source).unwrap();  
 }
 #[test]
 fn test_return_function(){
     let source = r#"(module 
     (func (result i32) ))"#;
-    parse_source(source);  
+    parse_source(source).unwrap();  
 }
 #[test]
 fn test_function_empty_sig_instruction(){
     let source = r#"(module 
     (func i32.const 5 drop ))"#;
-    parse_source(source);  
+    parse_source(source).unwrap();  
 }
 #[test]
 fn test_function_with_sig_and_instruction(){
     let source = r#"(module 
     (func i32.const 5 drop ))"#;
-    parse_source(source);  
+    parse_source(source).unwrap();  
 }
 
 #[test]
 fn test_function_with_add_instruction(){
     let source = r#"(module 
     (func i32.add ))"#;
-    parse_source(source);  
+    parse_source(source).unwrap();  
 }
 #[test]
 fn test_function_with_more_than_two_arith_instruction(){
     let source = r#"(module 
     (func i32.const 5 i32.const 6 i32.const 6))"#;
-    let (module, blk_table) = parse_source(source);  
+    let (module, _) = parse_source(source).unwrap();  
     assert_eq!(module.code, vec![OP::I32CONST(5), OP::I32CONST(6), OP::I32CONST(6), RET]);
 }
 #[test]
 fn test_function_with_const_instruction(){
     let source = r#"(module 
     (func i32.const 5 ))"#;
-    parse_source(source);  
+    parse_source(source).unwrap();  
 }
 #[test]
 fn test_function_with_var_instruction_n_ref(){
     let source = r#"(module 
     (func local.get 5 ))"#;
-    parse_source(source);  
+    parse_source(source).unwrap();  
 }
 #[test]
 fn test_function_with_var_instruction_id_ref(){
     let source = r#"(module 
     (func (local $x i32) local.get $x ))"#;
-    let (module, blk_table) = parse_source(source);  
+    let (module, _) = parse_source(source).unwrap();  
     assert_eq!(module.code, vec![OP::LOCGET(0), RET]);
 }
 #[test]
 fn test_function_with_block_instruction_id_ref(){
     let source = r#"(module 
     (func block $x ))"#;
-    let (module, blk_table) = parse_source(source);  
+    let (module, _) = parse_source(source).unwrap();  
     assert_eq!(module.code, vec![OP::BLK(0), RET]);
 }
 #[test]
 fn test_function_with_block_instrution_and_br(){
     let source = r#"(module 
     (func block br 0  ))"#;
-    let (module, blk_table) = parse_source(source);  
+    let (module, _) = parse_source(source).unwrap();  
     assert_eq!(module.code, vec![OP::BLK(0), OP::BR(0), RET]);
 }
 #[test]
 fn test_function_with_block_instruction_with_result(){
     let source = r#"(module 
     (func block (result i32) )  )"#;
-    let (module, blk_table) = parse_source(source);  
+    let (module, _) = parse_source(source).unwrap();  
     assert_eq!(module.code, vec![OP::BLK(0), RET]);
 }
 #[test]
@@ -141,14 +143,14 @@ fn test_multiple_empty_functions(){
     (func )
     (func)
     )"#; 
-    let (module, blk_table) = parse_source(source);  
-    // assert_eq!(module.code, vec![OP::BLK(0), OP::BR(0)]);
+    let (module, _) = parse_source(source).unwrap();  
+    assert_eq!(module.funcs.len(), 2);
 }
 #[test]
 fn test_function_with_branch_instruction_id_ref(){
     let source = r#"(module 
     (func block $x br $x ))"#;
-    let (module, blk_table) = parse_source(source);  
+    let (module, _) = parse_source(source).unwrap();  
     assert_eq!(module.code, vec![OP::BLK(0), OP::BR(0), RET]);
 }
 #[test]
@@ -162,7 +164,7 @@ fn test_function_with_multiple_blocks_first_branch(){
             end 
         end
     ))"#;
-    let (module, blk_table) = parse_source(source);  
+    let (module, _) = parse_source(source).unwrap();  
     assert_eq!(module.code, vec![BLK(0), BLK(1), BLK(2), BR(0), END, END, END, RET]);
 }
 #[test]
@@ -176,7 +178,7 @@ fn test_function_with_multiple_blocks_last_branch(){
             end 
         end
     ))"#;
-    let (module, blk_table) = parse_source(source);  
+    let (module, _) = parse_source(source).unwrap();  
     assert_eq!(module.code, vec![BLK(0), BLK(1), BLK(2), BR(2), END, END, END, RET]);
 }
 #[test]
@@ -190,7 +192,7 @@ fn test_function_with_multiple_blocks_middle_branch(){
             end 
         end
     ))"#;
-    let (module, blk_table) = parse_source(source);  
+    let (module, _) = parse_source(source).unwrap();  
     assert_eq!(module.code, vec![BLK(0), BLK(1), BLK(2), BR(1), END, END, END, RET]);
 }
 
@@ -199,7 +201,7 @@ fn test_export_fn(){
     let source = r#"(module 
     (export "fn" (func 0))
     )"#;
-    let (module, blk_table) = parse_source(source);  
+    let (module, _) = parse_source(source).unwrap();  
     assert_eq!(module.exports.get("fn"), Some(&Export{export_type: ast::ExportType::FUNCTION, export_ref: Label::U32(0) }) );
 }
 
@@ -208,7 +210,7 @@ fn test_export_fn_id(){
     let source = r#"(module 
     (export "fn" (func $x))
     )"#;
-    let (module, blk_table) = parse_source(source);  
+    let (module, _) = parse_source(source).unwrap();  
     assert_eq!(module.exports.get("fn"), Some(&Export{export_type: ast::ExportType::FUNCTION, export_ref: Label::REF(String::from("x")) }) );
 }
 #[test]
@@ -216,7 +218,7 @@ fn test_export_memory(){
     let source = r#"(module 
     (export "mem" (memory 0))
     )"#;
-    let (module, blk_table) = parse_source(source);  
+    let (module, _) = parse_source(source).unwrap();  
     assert_eq!(module.exports.get("mem"), Some(&Export{export_type: ast::ExportType::MEMORY, export_ref: Label::U32(0) }) );
 }
 #[test]
@@ -225,7 +227,7 @@ fn test_export_global(){
     (export "g" (global 0))
     (export "fn" (func $x))
     )"#;
-    let (module, blk_table) = parse_source(source);  
+    let (module, _) = parse_source(source).unwrap();  
     assert_eq!(module.exports.get("g"), Some(&Export{export_type: ast::ExportType::GLOBAL, export_ref: Label::U32(0) }) );
     assert_eq!(module.exports.get("fn"), Some(&Export{export_type: ast::ExportType::FUNCTION, export_ref: Label::REF(String::from("x")) }) );
 }
@@ -234,7 +236,7 @@ fn test_multiple_exports(){
     let source = r#"(module 
     (export "g" (global 0))
     )"#;
-    let (module, blk_table) = parse_source(source);  
+    let (module, _) = parse_source(source).unwrap();  
     assert_eq!(module.exports.get("g"), Some(&Export{export_type: ast::ExportType::GLOBAL, export_ref: Label::U32(0) }) );
 }
 #[test]
@@ -242,16 +244,16 @@ fn test_global_no_id(){
     let source = r#"(module 
       (global i32 (i32.const 4))
     )"#;
-    let (module, blk_table) = parse_source(source);  
-    assert_eq!(module.globals.get(0), Some(&Global{mutable: false, value  :4}) );
+    let (module, _) = parse_source(source).unwrap();  
+    assert_eq!(module.globals.first(), Some(&Global{ty: ast::ValType::I32, mutable: false, init: vec![I32CONST(4)]}) );
 }
 #[test]
 fn test_global_id(){
     let source = r#"(module 
       (global $curr i32 (i32.const 4))
     )"#;
-    let (module, blk_table) = parse_source(source);  
-    assert_eq!(module.globals.get(0), Some(&Global{mutable: false, value  :4}) );
+    let (module, _) = parse_source(source).unwrap();  
+    assert_eq!(module.globals.first(), Some(&Global{ty: ast::ValType::I32, mutable: false, init: vec![I32CONST(4)]}) );
     assert_eq!(module.globals_map.get("curr"), Some(&0))
 }
 #[test]
@@ -259,15 +261,199 @@ fn test_global_mut(){
     let source = r#"(module 
       (global (mut i32) (i32.const 4))
     )"#;
-    let (module, blk_table) = parse_source(source);  
-    assert_eq!(module.globals.get(0), Some(&Global{mutable: true, value  :4}) );
+    let (module, _) = parse_source(source).unwrap();  
+    assert_eq!(module.globals.first(), Some(&Global{ty: ast::ValType::I32, mutable: true, init: vec![I32CONST(4)]}) );
 }
 #[test]
 fn test_memory(){
     let source = r#"(module 
       (memory $mem 1)
     )"#;
-    let (module, blk_table) = parse_source(source);  
+    let (module, _) = parse_source(source).unwrap();  
     assert_eq!(module.memory, Some(Mem{name: Some(String::from("mem")), initial_capacity  :1} ));
 }
+#[test]
+fn test_multi_value_signature(){
+    let source = r#"(module 
+      (type $t (func (param i32) (result i32 i64)))
+      (func (param i32 i64) (param $x f32) (result i32) (result i64 f64) (local i32 i64))
+      (func (type $t))
+    )"#;
+    let (module, _) = parse_source(source).unwrap();  
+    assert_eq!(module.funcs[0].ty.params, vec![ast::ValType::I32, ast::ValType::I64, ast::ValType::F32]);
+    assert_eq!(module.funcs[0].ty.results, vec![ast::ValType::I32, ast::ValType::I64, ast::ValType::F64]);
+    assert_eq!(module.funcs[0].locals, vec![ast::ValType::I32, ast::ValType::I64]);
+    assert_eq!(module.funcs[1].ty, module.types[0]);
+}
+#[test]
+fn test_if_else_block_table(){
+    let source = r#"(module 
+    (func (param i32) (result i32)
+      local.get 0
+      if (result i32)
+        i32.const 1
+      else
+        i32.const 2
+      end
+    ))"#;
+    let (module, blk_table) = parse_source(source).unwrap();  
+    assert_eq!(module.code, vec![LOCGET(0), IF(0), I32CONST(1), ELSE(0), I32CONST(2), END, RET]);
+    assert_eq!(blk_table[0][0].else_pc, Some(3));
+    assert_eq!(blk_table[0][0].next_pc, 5);
+}
+#[test]
+fn test_inline_exports(){
+    let source = r#"(module 
+      (memory $mem (export "mem") 1)
+      (global $g (export "g") (export "g2") (mut i32) (i32.const 1))
+      (func $add (export "add") (param i32 i32) (result i32)
+        local.get 0
+        local.get 1
+        i32.add)
+    )"#;
+    let (module, _) = parse_source(source).unwrap();  
+    assert_eq!(module.exports.get("mem"), Some(&Export{export_type: ast::ExportType::MEMORY, export_ref: Label::U32(0) }) );
+    assert_eq!(module.exports.get("g"), Some(&Export{export_type: ast::ExportType::GLOBAL, export_ref: Label::U32(0) }) );
+    assert_eq!(module.exports.get("g2"), Some(&Export{export_type: ast::ExportType::GLOBAL, export_ref: Label::U32(0) }) );
+    assert_eq!(module.exports.get("add"), Some(&Export{export_type: ast::ExportType::FUNCTION, export_ref: Label::U32(0) }) );
+    assert_eq!(module.globals.first(), Some(&Global{ty: ast::ValType::I32, mutable: true, init: vec![I32CONST(1)]}) );
+}
+#[test]
+fn test_imports(){
+    let source = r#"(module 
+      (import "env" "log" (func $log (param i32)))
+      (func $f (export "f") (import "env" "f") (result i32))
+      (global $g (import "env" "g") (mut i32))
+      (memory (import "env" "mem") 1)
+      (func $run i32.const 1 call $log)
+    )"#;
+    let (module, blk_table) = parse_source(source).unwrap();  
+    assert_eq!(module.imports.len(), 4);
+    assert_eq!(module.imports[1], ast::Import{module: String::from("env"), name: String::from("f"), import_type: ast::ExportType::FUNCTION, import_ref: 1});
+    assert_eq!(module.imports[2].import_type, ast::ExportType::GLOBAL);
+    assert_eq!(module.imports[3].import_type, ast::ExportType::MEMORY);
+    assert_eq!(module.funcs[0].import, Some(0));
+    assert_eq!(module.funcs_refs.get("run"), Some(&2));
+    assert_eq!(module.exports.get("f"), Some(&Export{export_type: ast::ExportType::FUNCTION, export_ref: Label::U32(1) }) );
+    assert_eq!(module.memory.map(|mem| mem.initial_capacity), Some(1));
+    assert_eq!(blk_table.len(), 3);
+    assert_eq!(module.code, vec![I32CONST(1), CALL(Label::REF(String::from("log"))), RET]);
+}
+#[test]
+fn test_import_after_definition(){
+    let source = r#"(module 
+      (func $run)
+      (import "env" "log" (func $log (param i32)))
+    )"#;
+    assert_eq!(
+        parse_source(source).err(),
+        Some(String::from("import env.log has to occur before any regular definition"))
+    );
+}
+#[test]
+fn test_global_const_exprs(){
+    let source = r#"(module 
+      (import "env" "base" (global $base i32))
+      (global $a i64 (i64.const 0x10))
+      (global $b f32 (f32.const 1.5))
+      (global $c (mut f64) (f64.const -2.5e3))
+      (global $d i32 (i32.add (global.get $base) (i32.const 4)))
+      (global $e i32 global.get $base i32.const 2 i32.mul)
+    )"#;
+    let (module, _) = parse_source(source).unwrap();  
+    assert_eq!(module.globals[0].init, vec![]);
+    assert_eq!(module.globals[1], Global{ty: ast::ValType::I64, mutable: false, init: vec![I64CONST(16)]});
+    assert_eq!(module.globals[2].init, vec![F32CONST(1.5)]);
+    assert_eq!(module.globals[3], Global{ty: ast::ValType::F64, mutable: true, init: vec![F64CONST(-2500.0)]});
+    assert_eq!(module.globals[4].init, vec![GLOGET(Label::REF(String::from("base"))), I32CONST(4), I32ADD]);
+    assert_eq!(module.globals[5].init, vec![GLOGET(Label::REF(String::from("base"))), I32CONST(2), I32MUL]);
+    assert!(module.code.is_empty());
+}
+#[test]
+fn test_segments(){
+    let source = r#"(module 
+      (memory 1)
+      (table 2 funcref)
+      (data (i32.const 8) "hi\00\ff")
+      (data (offset i32.const 1 i32.const 2 i32.add) "a b")
+      (elem (i32.const 0) $f 0)
+      (func $f)
+    )"#;
+    let (module, _) = parse_source(source).unwrap();  
+    assert_eq!(module.datas[0], ast::Data{offset: Some(vec![I32CONST(8)]), bytes: vec![b'h', b'i', 0, 255]});
+    assert_eq!(module.datas[1].offset, Some(vec![I32CONST(1), I32CONST(2), I32ADD]));
+    assert_eq!(module.datas[1].bytes, b"a b".to_vec());
+    assert_eq!(module.elems[0], ast::Elem{offset: Some(vec![I32CONST(0)]), funcs: vec![Label::REF(String::from("f")), Label::U32(0)]});
+    assert_eq!(module.table.map(|table| table.initial_size), Some(2));
+}
+#[test]
+fn test_source_map(){
+    let source = r#"(module
+      (global $g i32 (i32.const 1))
+      (func $f (result i32)
+        i32.const 2
+        global.get $g i32.add
+      )
+      (global $h i32 (i32.const 4))
+    )"#;
+    let (module, _) = parse_source(source).unwrap();  
+    assert_eq!(module.code, vec![I32CONST(2), GLOGET(Label::REF(String::from("g"))), I32ADD, RET]);
+    let locs: Vec<(usize, usize, usize)> = module.source_map.locs.iter().map(|loc| (loc.line, loc.col, loc.func)).collect();
+    assert_eq!(locs, vec![(4, 9, 0), (5, 9, 0), (5, 23, 0), (6, 7, 0)]);
+}
+#[test]
+fn test_unicode_escapes(){
+    assert_eq!(unescape(r"a\u{41}\u{e9}"), Ok("aA\u{e9}".as_bytes().to_vec()));
+    for malformed in [r"\u", r"\u{41", r"\u41}", r"\u{}", r"\u{zz}", r"\u{d800}", r"x\u"] {
+        assert!(unescape(malformed).is_err(), "{} should be rejected", malformed);
+        let source = format!(r#"(module (memory 1) (data (i32.const 0) "{}"))"#, malformed);
+        assert!(matches!(Module::from_wat(&source), Err(Error::Parse(_))), "{} should fail to lex", malformed);
+    }
+}
+#[test]
+fn test_branch_labels(){
+    let parse_error = |body: &str| match Module::from_wat(&format!("(module (func {}))", body)) {
+        Err(Error::Parse(message)) => message,
+        other => panic!("{} should fail to parse, saw {:?}", body, other),
+    };
+    assert_eq!(parse_error("block br 2 end"), "unknown label 2");
+    assert_eq!(parse_error("block $a block br $b end end"), "unknown label $b");
+    assert_eq!(parse_error("i32.const 1 br_if 0"), "br_if to the function body isn't supported");
+    // the function body is the outermost label, branching to it returns
+    let (module, _) = parse_source("(module (func (result i32) block i32.const 7 br 1 end i32.const 8))").unwrap();
+    assert_eq!(module.code, vec![BLK(0), I32CONST(7), RET, END, I32CONST(8), RET]);
+}
+#[test]
+fn test_folded_instructions(){
+    let folded = r#"(module 
+    (func (param i32 i32) (result i32)
+      (i32.add (local.get 0) (local.get 1))
+      (if $l (result i32) (i32.eqz (local.get 0))
+        (then (i32.const 1) (br_if $l (local.get 1)))
+        (else (block $b (br $b)) (i32.const 2)))
+      (loop (br 1))
+      i32.add
+    ))"#;
+    let flat = r#"(module 
+    (func (param i32 i32) (result i32)
+      local.get 0 local.get 1 i32.add
+      local.get 0 i32.eqz
+      if $l (result i32)
+        i32.const 1 local.get 1 br_if $l
+      else
+        block $b br $b end i32.const 2
+      end
+      loop br 1 end
+      i32.add
+    ))"#;
+    let (module, blk_table) = parse_source(folded).unwrap();
+    let (expected, expected_blk_table) = parse_source(flat).unwrap();
+    assert_eq!(module.code, expected.code);
+    let pcs = |blocks: &Vec<ast::Block>| blocks.iter().map(|blk| (blk.next_pc, blk.else_pc)).collect::<Vec<_>>();
+    assert_eq!(pcs(&blk_table[0]), pcs(&expected_blk_table[0]));
+    let parse_error = |body: &str| parse_source(&format!("(module (func {}))", body)).err();
+    assert_eq!(parse_error("(if (i32.const 1))"), Some(String::from("should see then in folded if")));
+    assert_eq!(parse_error("(i32.add (local.get 0) i32.const 1)"), Some(String::from("should see rparen to terminate folded i32.add")));
+    assert_eq!(parse_error("(then)"), Some(String::from("unknown instruction then")));
+}
 }
@@ -0,0 +1,135 @@
+use interperter::{Instance, Value};
+use logos::Logos;
+
+use crate::repl::lexer::Token;
+use crate::repl::parser::{
+    format_results, parse_command, parse_set_global, parse_set_memory, parse_write_memory, unescape,
+};
+use crate::repl::session::Session;
+
+const TYPED: &str = r#"(module
+    (global $a (export "a") (mut i32) (i32.const 0))
+    (global $b (export "b") (mut i64) (i64.const 0))
+    (global $c (export "c") (mut f32) (f32.const 0))
+    (global $d (export "d") (mut f64) (f64.const 0))
+    (func (export "mix") (param i32 i64 f32 f64)
+      local.get 0
+      global.set $a
+      local.get 1
+      global.set $b
+      local.get 2
+      global.set $c
+      local.get 3
+      global.set $d)
+    (func (export "none"))
+)"#;
+
+fn session() -> Session {
+    let path = std::env::temp_dir().join(format!("interperter-repl-parser-{}-typed.wat", std::process::id()));
+    std::fs::write(&path, TYPED).unwrap();
+    Session::open(path.to_str().unwrap()).unwrap()
+}
+
+/// The arguments `mix` was last called with, it keeps them in the globals
+fn mixed(instance: &Instance) -> Vec<Value> {
+    ["a", "b", "c", "d"].iter().map(|name| instance.global(name).unwrap()).collect()
+}
+
+#[test]
+fn test_typed_arguments() {
+    let mut session = session();
+    assert_eq!(parse_command("exports.functions.mix(0xffffffff, -0x1_0000, 2.5, -inf)", &mut session), Ok(()));
+    assert_eq!(
+        mixed(&session.instance),
+        vec![Value::I32(-1), Value::I64(-65536), Value::F32(2.5), Value::F64(f64::NEG_INFINITY)]
+    );
+    assert_eq!(parse_command("exports.functions.mix(-2147483648 18446744073709551615 1e3 nan)", &mut session), Ok(()));
+    let args = mixed(&session.instance);
+    assert_eq!(args[..3], [Value::I32(i32::MIN), Value::I64(-1), Value::F32(1000.0)]);
+    assert!(matches!(args[3], Value::F64(nan) if nan.is_nan()));
+    assert_eq!(parse_command("exports.functions.none()", &mut session), Ok(()));
+
+    let mut rejected = |line: &str| parse_command(line, &mut session).unwrap_err();
+    assert_eq!(rejected("exports.functions.mix(1, 2)"), "mix expects 4 arguments (i32 i64 f32 f64), saw 2");
+    assert_eq!(rejected("exports.functions.mix(1, 2.5, 3, 4)"), "argument 2: 2.5 is not a valid i64");
+    assert_eq!(rejected("exports.functions.mix(4294967296, 2, 3, 4)"), "argument 1: 4294967296 is not a valid i32");
+    rejected("exports.functions.missing()");
+}
+
+#[test]
+fn test_typed_results() {
+    let results = [Value::I32(7), Value::I64(-1), Value::F32(0.5), Value::F64(25.0)];
+    assert_eq!(format_results(&results), "i32:7, i64:-1, f32:0.5, f64:25");
+    assert_eq!(format_results(&[]), "()");
+}
+
+#[test]
+fn test_parse_set_global() {
+    let parse = |args: &str| parse_set_global(&mut Token::lexer(args));
+    assert_eq!(parse("(a, -0x10)"), Ok((String::from("a"), String::from("-0x10"))));
+    assert_eq!(parse("(d, inf)"), Ok((String::from("d"), String::from("inf"))));
+    assert!(parse("(a)").is_err());
+    assert!(parse("(a, 1, 2)").is_err());
+    assert!(parse("a, 1)").is_err());
+}
+
+#[test]
+fn test_parse_set_memory() {
+    let parse = |args: &str| parse_set_memory(&mut Token::lexer(args));
+    for width in [1, 2, 4, 8] {
+        assert_eq!(parse(&format!("(16, 0x2a, {})", width)), Ok((16, String::from("0x2a"), width)));
+    }
+    assert_eq!(parse("(0x10, -1)"), Ok((16, String::from("-1"), 4)));
+    assert_eq!(parse("(16, 1, 3)"), Err(String::from("the width is 1, 2, 4 or 8 bytes, saw 3")));
+    assert!(parse("(16, 1, 16)").is_err());
+    assert!(parse("(-1, 1)").is_err());
+    assert!(parse("(16, 1, 4, 4)").is_err());
+}
+
+#[test]
+fn test_parse_write_memory() {
+    let parse = |args: &str| parse_write_memory(&mut Token::lexer(args));
+    assert_eq!(parse(r#"(8, "hi\n")"#), Ok((8, b"hi\n".to_vec())));
+    assert_eq!(parse(r#"(0, "")"#), Ok((0, Vec::new())));
+    assert!(parse(r#"(0, "\zz")"#).is_err());
+    assert!(parse("(0, 42)").is_err());
+
+    assert_eq!(unescape(r#"a\tb\\\"c"#), Ok(b"a\tb\\\"c".to_vec()));
+    assert_eq!(unescape(r#"\ff\00\7F"#), Ok(vec![0xff, 0x00, 0x7f]));
+    assert_eq!(unescape(r#"\fg"#), Err(String::from("unknown escape \\fg")));
+    assert!(unescape(r#"\f"#).is_err());
+    assert_eq!(unescape("ends with \\"), Err(String::from("a string can't end with \\")));
+}
+
+#[test]
+fn test_memory_commands() {
+    let path = std::env::temp_dir().join(format!("interperter-repl-parser-{}-memory.wat", std::process::id()));
+    std::fs::write(&path, r#"(module (memory (export "memory") 1))"#).unwrap();
+    let mut session = Session::open(path.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let memory = |session: &Session, at: usize, len: usize| session.instance.memory().unwrap()[at..at + len].to_vec();
+
+    parse_command("exports.memory.set(0, -1, 1)", &mut session).unwrap();
+    parse_command("exports.memory.set(1, 0x1234, 2)", &mut session).unwrap();
+    parse_command("exports.memory.set(3, 1)", &mut session).unwrap();
+    parse_command("exports.memory.set(8, -2, 8)", &mut session).unwrap();
+    assert_eq!(memory(&session, 0, 7), [0xff, 0x34, 0x12, 1, 0, 0, 0]);
+    assert_eq!(memory(&session, 8, 8), (-2i64).to_le_bytes());
+    assert_eq!(
+        parse_command("exports.memory.set(0, 256, 1)", &mut session),
+        Err(String::from("256 is not an integer that fits in 1 bytes"))
+    );
+    parse_command(r#"exports.memory.write(16, "ok\ff")"#, &mut session).unwrap();
+    assert_eq!(memory(&session, 16, 3), [b'o', b'k', 0xff]);
+
+    // nothing is written past the end
+    assert_eq!(
+        parse_command("exports.memory.set(65534, 1, 4)", &mut session),
+        Err(String::from("4 bytes at 65534 are out of bounds of a 65536 byte memory"))
+    );
+    assert_eq!(
+        parse_command(r#"exports.memory.write(65535, "ab")"#, &mut session),
+        Err(String::from("2 bytes at 65535 are out of bounds of a 65536 byte memory"))
+    );
+    assert_eq!(memory(&session, 65534, 2), [0, 0]);
+}
@@ -0,0 +1,106 @@
+use crate::cli::TraceOptions;
+use crate::repl::script::{assert_eq, assert_trap, run};
+use crate::repl::session::Session;
+
+const CHECKED: &str = r#"(module
+    (global (export "count") (mut i32) (i32.const 5))
+    (func (export "div") (param i32 i32) (result i32)
+      local.get 0
+      local.get 1
+      i32.div_s)
+    (func (export "pair") (result i32 i64)
+      i32.const 1
+      i64.const -2)
+    (func (export "nan") (result f32)
+      f32.const nan)
+    (func (export "boom")
+      unreachable)
+)"#;
+
+/// A path in the temporary directory that no other test uses
+fn temp_file(name: &str, contents: &str) -> String {
+    let path = std::env::temp_dir().join(format!("interperter-script-{}-{}", std::process::id(), name));
+    std::fs::write(&path, contents).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+fn session(name: &str) -> Session {
+    Session::open(&temp_file(name, CHECKED)).unwrap()
+}
+
+#[test]
+fn test_assert_eq() {
+    let mut session = session("eq.wat");
+    let mut check = |text: &str| assert_eq(text, &mut session);
+    assert_eq!(check("exports.functions.div(7, 2) == 3"), Ok(()));
+    assert_eq!(check("exports.functions.div(7, 2) == i32:3"), Ok(()));
+    assert_eq!(check("exports.functions.pair() == 1, -2"), Ok(()));
+    assert_eq!(check("exports.functions.pair() == i32:1, i64:-2"), Ok(()));
+    assert!(check("exports.functions.boom() == ()").unwrap_err().starts_with("trap: unreachable"));
+    assert_eq!(check("exports.globals.get(count) == 5"), Ok(()));
+    // any NaN matches any other
+    assert_eq!(check("exports.functions.nan() == nan"), Ok(()));
+
+    assert_eq!(check("exports.functions.div(7, 2) == 4"), Err(String::from("expected i32:4, saw i32:3")));
+    assert_eq!(check("exports.functions.div(7, 2) == i64:3"), Err(String::from("expected i64:3, saw i32:3")));
+    assert_eq!(check("exports.functions.div(7, 2) == ()"), Err(String::from("expected (), saw i32:3")));
+    assert_eq!(check("exports.functions.pair() == 1"), Err(String::from("expected i32:1, saw i32:1, i64:-2")));
+    assert_eq!(
+        check("exports.functions.pair() == 1, -2, 3"),
+        Err(String::from("expected 3 values, saw i32:1, i64:-2"))
+    );
+    assert_eq!(check("exports.functions.div(7, 2) == f16:3"), Err(String::from("no such type f16")));
+    assert_eq!(
+        check("exports.functions.div(7, 2)"),
+        Err(String::from("expected == and the values after the command"))
+    );
+    assert!(check("exports.functions.div(7, 0) == 0").is_err());
+}
+
+#[test]
+fn test_assert_trap() {
+    let mut session = session("trap.wat");
+    let mut check = |text: &str| assert_trap(text, &mut session);
+    assert_eq!(check("exports.functions.boom()"), Ok(()));
+    assert_eq!(check(r#"exports.functions.boom() "unreachable""#), Ok(()));
+    assert_eq!(check(r#"exports.functions.div(1, 0) "divide by zero""#), Ok(()));
+
+    assert_eq!(
+        check(r#"exports.functions.div(1, 0) "overflow""#),
+        Err(String::from("expected a trap with \"overflow\", saw trap: integer divide by zero"))
+    );
+    assert_eq!(check("exports.functions.div(6, 3)"), Err(String::from("expected a trap, saw i32:2")));
+    assert_eq!(check("exports.functions.pair()"), Err(String::from("expected a trap, saw i32:1, i64:-2")));
+    assert!(check("exports.functions.missing()").is_err());
+}
+
+#[test]
+fn test_run_script() {
+    let module = temp_file("run.wat", CHECKED);
+    let trace = TraceOptions::default();
+    let passing = temp_file(
+        "passing.repl",
+        "# comments and blank lines are skipped\n\n\
+         exports.globals.set(count, 9)\n\
+         assert_eq exports.globals.get(count) == 9\n\
+         assert_trap exports.functions.boom() \"unreachable\"\n",
+    );
+    assert_eq!(run(&module, &passing, &trace), Ok(()));
+
+    // the first failing line stops the script and is reported with its line number
+    let failing = temp_file(
+        "failing.repl",
+        "assert_eq exports.functions.div(9, 3) == 3\n\
+         assert_eq exports.functions.div(9, 3) == 4\n\
+         exports.functions.missing()\n",
+    );
+    assert_eq!(run(&module, &failing, &trace), Err(format!("{}:2: expected i32:4, saw i32:3", failing)));
+
+    // nothing after :quit runs
+    let quitting = temp_file("quitting.repl", "assert_eq exports.functions.div(4, 2) == 2\n:quit\nassert_eq bogus\n");
+    assert_eq!(run(&module, &quitting, &trace), Ok(()));
+
+    let missing = temp_file("missing.repl", "");
+    std::fs::remove_file(&missing).unwrap();
+    assert!(run(&module, &missing, &trace).unwrap_err().starts_with(&format!("can't read {}", missing)));
+}
@@ -0,0 +1,120 @@
+use interperter::Value;
+
+use crate::repl::parser::parse_command;
+use crate::repl::session::Session;
+
+const COUNTER: &str = r#"(module
+    (memory (export "memory") 1)
+    (global $x (export "x") (mut i32) (i32.const 1))
+    (func (export "bump") (result i32)
+      global.get $x
+      i32.const 1
+      i32.add
+      global.set $x
+      global.get $x)
+)"#;
+
+/// A path in the temporary directory that no other test uses
+fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("interperter-session-{}-{}", std::process::id(), name));
+    path.to_str().unwrap().to_string()
+}
+
+fn open(name: &str, source: &str) -> Session {
+    let path = temp_path(name);
+    std::fs::write(&path, source).unwrap();
+    Session::open(&path).unwrap()
+}
+
+fn x(session: &Session) -> Option<Value> {
+    session.instance.global("x")
+}
+
+#[test]
+fn test_meta_commands() {
+    let mut session = open("meta.wat", COUNTER);
+    assert_eq!(parse_command(":help", &mut session), Ok(()));
+    assert_eq!(parse_command(":exports", &mut session), Ok(()));
+    assert_eq!(parse_command(":bogus", &mut session), Err(String::from("no such command :bogus, see :help")));
+    assert_eq!(parse_command(":load", &mut session), Err(String::from("expected a single file path")));
+    assert_eq!(parse_command(":open", &mut session), Err(String::from("expected a single file path")));
+    assert_eq!(parse_command(":restore x", &mut session), Err(String::from("no such command :restore x, see :help")));
+    assert_eq!(parse_command(":save a b", &mut session), Err(String::from("expected a single file path")));
+    assert_eq!(parse_command(":reload now", &mut session), Err(String::from("expected :reload or :reload keep")));
+    assert!(parse_command("exports.functions.missing()", &mut session).is_err());
+    assert!(!session.quit);
+    assert_eq!(parse_command(":quit", &mut session), Ok(()));
+    assert!(session.quit);
+    std::fs::remove_file(&session.path).unwrap();
+}
+
+#[test]
+fn test_reset_and_reload() {
+    let mut session = open("reload.wat", COUNTER);
+    parse_command("exports.functions.bump()", &mut session).unwrap();
+    parse_command("exports.memory.set(0, 7)", &mut session).unwrap();
+    assert_eq!(x(&session), Some(Value::I32(2)));
+    parse_command(":reload keep", &mut session).unwrap();
+    assert_eq!(x(&session), Some(Value::I32(2)));
+    assert_eq!(session.instance.memory().map(|memory| memory[0]), Some(7));
+    parse_command(":reload", &mut session).unwrap();
+    assert_eq!(x(&session), Some(Value::I32(1)));
+    parse_command("exports.globals.set(x, 5)", &mut session).unwrap();
+    parse_command(":reset", &mut session).unwrap();
+    assert_eq!(x(&session), Some(Value::I32(1)));
+
+    // a mutable global that changed its type can't be kept
+    parse_command("exports.globals.set(x, 5)", &mut session).unwrap();
+    let retyped = r#"(module (memory (export "memory") 1) (global (export "x") (mut i64) (i64.const 1)))"#;
+    std::fs::write(&session.path, retyped).unwrap();
+    assert_eq!(session.reload(true), Ok(vec![String::from("global x")]));
+    assert_eq!(x(&session), Some(Value::I64(1)));
+    std::fs::remove_file(&session.path).unwrap();
+}
+
+#[test]
+fn test_open_save_and_load() {
+    let mut session = open("load.wat", COUNTER);
+    let first = session.path.clone();
+    parse_command("exports.functions.bump()", &mut session).unwrap();
+    let snapshot = temp_path("load.snap");
+    parse_command(&format!(":save {}", snapshot), &mut session).unwrap();
+    parse_command("exports.functions.bump()", &mut session).unwrap();
+    parse_command(&format!(":load {}", snapshot), &mut session).unwrap();
+    assert_eq!(x(&session), Some(Value::I32(2)));
+
+    // a module that can't be loaded leaves the session as it was
+    assert!(parse_command(&format!(":open {}", temp_path("missing.wat")), &mut session).is_err());
+    assert_eq!(session.path, first);
+    let other = temp_path("other.wat");
+    std::fs::write(&other, r#"(module (global (export "y") i32 (i32.const 9)))"#).unwrap();
+    parse_command(&format!(":open {}", other), &mut session).unwrap();
+    assert_eq!(session.path, other);
+    assert_eq!(session.instance.global("y"), Some(Value::I32(9)));
+    assert!(parse_command(&format!(":load {}", snapshot), &mut session).is_err());
+    for path in [first, other, snapshot] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_save_and_restore_a_paused_call() {
+    let mut session = open("paused.wat", COUNTER);
+    parse_command(":debug exports.functions.bump()", &mut session).unwrap();
+    parse_command(":step", &mut session).unwrap();
+    let snapshot = temp_path("paused.snap");
+    parse_command(&format!(":save {}", snapshot), &mut session).unwrap();
+    parse_command(":continue", &mut session).unwrap();
+    assert_eq!(x(&session), Some(Value::I32(2)));
+    assert!(!session.instance.is_paused());
+
+    parse_command(&format!(":load {}", snapshot), &mut session).unwrap();
+    assert_eq!(x(&session), Some(Value::I32(1)));
+    assert!(session.instance.is_paused());
+    assert_eq!(session.instance.frame_stack(0), Some(&[Value::I32(1)][..]));
+    parse_command(":continue", &mut session).unwrap();
+    assert_eq!(x(&session), Some(Value::I32(2)));
+    for path in [session.path.clone(), snapshot] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
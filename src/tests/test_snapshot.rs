@@ -0,0 +1,145 @@
+use crate::interpret::{
+    ast::ast::Label,
+    parser::parse_source,
+    runtime::{Evaluator, RunState, Value},
+};
+
+const TICTACTOE: &str = include_str!("../../examples/tictactoe.wat");
+
+fn evaluator_for(sc: &str) -> Evaluator {
+    let (module, blk_table) = parse_source(sc).unwrap();
+    Evaluator::new(module, blk_table)
+}
+
+fn invoke(evaluator: &mut Evaluator, name: &str, params: Vec<i32>) -> Vec<Value> {
+    evaluator.add_parameters(params.into_iter().map(Value::I32).collect());
+    evaluator.call(&Label::REF(String::from(name)));
+    evaluator.run().unwrap()
+}
+
+#[test]
+fn test_snapshot_memory_and_globals() {
+    let mut game = evaluator_for(TICTACTOE);
+    invoke(&mut game, "takeTurn", vec![1, 2]);
+    let snapshot = game.snapshot(false);
+
+    let mut restored = evaluator_for(TICTACTOE);
+    restored.restore(&snapshot).unwrap();
+    assert_eq!(restored.memory.bytes, game.memory.bytes);
+    assert_eq!(restored.globals, game.globals);
+    assert_eq!(invoke(&mut restored, "getPiece", vec![1, 2]), vec![Value::I32(1)]);
+    assert_eq!(invoke(&mut restored, "getCurrent", vec![]), vec![Value::I32(2)]);
+}
+
+#[test]
+fn test_snapshot_in_flight_call() {
+    let source_code = r#"(module
+        (global $total (mut i64) (i64.const 0))
+        (func (param i32) (result i64)
+          block $done
+            loop $l
+              local.get 0
+              i32.eqz
+              br_if $done
+              global.get $total
+              i64.const 3
+              i64.add
+              global.set $total
+              local.get 0
+              i32.const 1
+              i32.sub
+              local.set 0
+              br $l
+            end
+          end
+          global.get $total)
+    )"#;
+    let mut evaluator = evaluator_for(source_code);
+    evaluator.add_parameters(vec![Value::I32(50)]);
+    evaluator.call(&Label::U32(0));
+    assert_eq!(evaluator.run_for(200), RunState::Yielded);
+    let snapshot = evaluator.snapshot(true);
+
+    let mut restored = evaluator_for(source_code);
+    restored.restore(&snapshot).unwrap();
+    assert_eq!(restored.run(), Ok(vec![Value::I64(150)]));
+    assert_eq!(evaluator.run(), Ok(vec![Value::I64(150)]));
+}
+
+#[test]
+fn test_snapshot_rejected() {
+    let game = evaluator_for(TICTACTOE);
+    let snapshot = game.snapshot(false);
+    let mut other = evaluator_for(r#"(module (memory 1) (global (mut i32) (i32.const 0)))"#);
+    assert!(other.restore(&snapshot).unwrap_err().contains("different module"));
+    let mut same = evaluator_for(TICTACTOE);
+    assert!(same.restore(&snapshot[..snapshot.len() - 3]).is_err());
+    assert!(same.restore(b"not a snapshot").is_err());
+    assert_eq!(same.globals, game.globals);
+
+    // tied to the source, also where it parses to the same module
+    let mut edited = evaluator_for(&format!("{}\n;; edited", TICTACTOE));
+    assert!(edited.restore(&snapshot).unwrap_err().contains("different module"));
+
+    // the memory length is a u64 after the magic, version and hash
+    assert_eq!(snapshot[16..24], (game.memory.bytes.len() as u64).to_le_bytes());
+    let mut too_long = snapshot.clone();
+    too_long[16..24].copy_from_slice(&(u32::MAX as u64 + 1).to_le_bytes());
+    assert!(same.restore(&too_long).unwrap_err().contains("4294967296 bytes"));
+}
+
+#[test]
+fn test_snapshot_file() {
+    let path = std::env::temp_dir().join(format!("interperter-snapshot-{}.bin", std::process::id()));
+    let path = path.to_str().unwrap();
+    let mut game = evaluator_for(TICTACTOE);
+    invoke(&mut game, "takeTurn", vec![0, 0]);
+    game.save_snapshot(path, false).unwrap();
+    let mut restored = evaluator_for(TICTACTOE);
+    restored.load_snapshot(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(invoke(&mut restored, "getPiece", vec![0, 0]), vec![Value::I32(1)]);
+    assert!(restored.load_snapshot(path).is_err());
+}
+
+#[test]
+fn test_snapshot_corrupted_frames() {
+    let source_code = r#"(module
+        (func $outer (param i32) (result i32)
+          block $b
+            local.get 0
+            call $inner
+            drop
+          end
+          local.get 0)
+        (func $inner (param i32) (result i32) (local i64)
+          local.get 0)
+    )"#;
+    // paused in $inner, called from the block of $outer
+    let paused = || {
+        let mut evaluator = evaluator_for(source_code);
+        evaluator.add_parameters(vec![Value::I32(5)]);
+        evaluator.call(&Label::U32(0));
+        while evaluator.calls.len() < 2 {
+            evaluator.step().unwrap();
+        }
+        evaluator
+    };
+    let corruptions: [fn(&mut Evaluator); 5] = [
+        |evaluator| evaluator.calls[1].locals.truncate(1),
+        |evaluator| evaluator.calls[1].locals[1] = Value::I32(0),
+        |evaluator| evaluator.calls[1].ret = 0,
+        |evaluator| evaluator.calls[0].blocks[0].height = evaluator.stack.len() + 1,
+        |evaluator| evaluator.suspended = Some(2),
+    ];
+    for corrupt in corruptions {
+        let mut corrupted = paused();
+        corrupt(&mut corrupted);
+        let mut restored = evaluator_for(source_code);
+        assert!(restored.restore(&corrupted.snapshot(true)).is_err());
+        assert!(restored.calls.is_empty());
+    }
+    let mut restored = evaluator_for(source_code);
+    restored.restore(&paused().snapshot(true)).unwrap();
+    assert_eq!(restored.run(), Ok(vec![Value::I32(5)]));
+}
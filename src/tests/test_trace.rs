@@ -0,0 +1,54 @@
+use crate::{Imports, Instance, Module, OutputBuffer, Tracer, Value};
+
+const CALLS: &str = r#"(module
+  (func $double (param i32) (result i32)
+    local.get 0
+    i32.const 2
+    i32.mul)
+  (func $quotient (param i32) (param i32) (result i32) (local i32)
+    local.get 0
+    call $double
+    local.set 2
+    local.get 2
+    local.get 1
+    i32.div_s)
+  (export "quotient" (func $quotient)))"#;
+
+fn traced(tracer: &Tracer, args: &[Value]) {
+    let module = Module::from_wat(CALLS).unwrap();
+    let mut instance = Instance::new(&module, &Imports::new()).unwrap();
+    instance.set_tracer(Some(tracer.clone()));
+    let _ = instance.invoke("quotient", args);
+}
+
+fn lines(output: &OutputBuffer) -> Vec<String> {
+    String::from_utf8(output.contents()).unwrap().lines().map(String::from).collect()
+}
+
+#[test]
+fn test_trace_human() {
+    let output = OutputBuffer::new();
+    traced(&Tracer::new(output.clone()), &[Value::I32(7), Value::I32(2)]);
+    let lines = lines(&output);
+    assert_eq!(lines.len(), 11);
+    assert_eq!(lines[0], "[1] $quotient pc=4 (7:5) LOCGET(0): [] -> [i32:7]");
+    assert_eq!(lines[2], "[2] $double pc=0 (3:5) LOCGET(0): [] -> [i32:7]");
+    assert_eq!(lines[5], "[2] $double pc=3 (5:12) RET: [i32:14] -> [i32:14]");
+    assert_eq!(lines[6], "[1] $quotient pc=6 (9:5) LOCSET(2): [i32:14] -> [], local 2 = i32:14");
+    assert_eq!(lines[10], "[1] $quotient pc=10 (12:14) RET: [i32:7] -> [i32:7]");
+}
+
+#[test]
+fn test_trace_compact_filtered() {
+    let output = OutputBuffer::new();
+    let mut tracer = Tracer::compact(output.clone());
+    tracer.only("$quotient");
+    traced(&tracer, &[Value::I32(7), Value::I32(0)]);
+    let lines = lines(&output);
+    assert!(lines.iter().all(|line| line.contains("\t$quotient\t")));
+    assert_eq!(lines[2], "1\t6\t9:5\t$quotient\tLOCSET(2)\ti32:14\t\t2=i32:14");
+    assert_eq!(
+        lines.last().unwrap(),
+        "1\t9\t12:5\t$quotient\tI32DIVS\ti32:14,i32:0\ttrap:integer divide by zero\t"
+    );
+}
@@ -0,0 +1,216 @@
+use crate::interpret::{op::OP, parser::parse_source, validate::validate};
+
+fn validate_src(sc: &str) -> Result<(), String> {
+    let (module, blk_table) = parse_source(sc).unwrap();
+    validate(&module, &blk_table).map_err(|error| error.to_string())
+}
+
+#[test]
+fn test_examples_are_valid() {
+    for source in [
+        include_str!("../../examples/factorial.wat"),
+        include_str!("../../examples/gcd.wat"),
+        include_str!("../../examples/tictactoe.wat"),
+    ] {
+        assert_eq!(validate_src(source), Ok(()));
+    }
+}
+
+#[test]
+fn test_add_on_empty_stack() {
+    let src = r#"(module
+        (func $f (result i32)
+            i32.const 1
+            i32.add)
+    )"#;
+    let (module, blk_table) = parse_source(src).unwrap();
+    let error = validate(&module, &blk_table).unwrap_err();
+    assert_eq!(error.fn_idx, Some(0));
+    assert_eq!(error.fn_name, Some(String::from("f")));
+    assert_eq!(error.pc, Some(1));
+    assert_eq!(error.op, Some(OP::I32ADD));
+}
+
+#[test]
+fn test_missing_result() {
+    let src = r#"(module
+        (func (result i32) nop)
+    )"#;
+    assert!(validate_src(src).is_err());
+}
+
+#[test]
+fn test_extra_value_left() {
+    let src = r#"(module
+        (func i32.const 1)
+    )"#;
+    assert!(validate_src(src).is_err());
+}
+
+#[test]
+fn test_result_type_mismatch() {
+    let src = r#"(module
+        (func (result i64) i32.const 1)
+    )"#;
+    assert!(validate_src(src).is_err());
+}
+
+#[test]
+fn test_immutable_global_set() {
+    let src = r#"(module
+        (global $x i32 (i32.const 4))
+        (func i32.const 3 global.set $x)
+    )"#;
+    assert!(validate_src(src).unwrap_err().contains("immutable"));
+}
+
+#[test]
+fn test_unknown_local_and_global() {
+    assert!(validate_src("(module (func (result i32) local.get 2))").is_err());
+    assert!(validate_src("(module (func (result i32) global.get 0))").is_err());
+}
+
+#[test]
+fn test_memory_access_without_memory() {
+    let src = r#"(module
+        (func (result i32) i32.const 0 i32.load)
+    )"#;
+    assert!(validate_src(src).unwrap_err().contains("memory"));
+    let src = r#"(module
+        (func i32.const 0 i32.const 1 i32.store)
+    )"#;
+    assert!(validate_src(src).unwrap_err().contains("memory access without a memory"));
+}
+
+#[test]
+fn test_branch_arity() {
+    let valid = r#"(module
+        (func (result i32)
+            block $b (result i32)
+                i32.const 1
+                i32.const 2
+                br_if $b
+                drop
+                i32.const 3
+            end)
+    )"#;
+    assert_eq!(validate_src(valid), Ok(()));
+    let invalid = r#"(module
+        (func (result i32)
+            block $b (result i32)
+                br $b
+            end)
+    )"#;
+    assert!(validate_src(invalid).is_err());
+}
+
+#[test]
+fn test_unreachable_code_is_polymorphic() {
+    let src = r#"(module
+        (func (result i32)
+            block $b (result i32)
+                i32.const 1
+                br $b
+                i32.add
+            end)
+    )"#;
+    assert_eq!(validate_src(src), Ok(()));
+}
+
+#[test]
+fn test_if_without_else() {
+    let valid = r#"(module
+        (func (param i32) (result i32)
+            i32.const 5
+            local.get 0
+            if (param i32) (result i32)
+                i32.const 1
+                i32.add
+            end)
+    )"#;
+    assert_eq!(validate_src(valid), Ok(()));
+    let invalid = r#"(module
+        (func (param i32) (result i32)
+            local.get 0
+            if (result i32)
+                i32.const 1
+            end)
+    )"#;
+    assert!(validate_src(invalid).is_err());
+}
+
+#[test]
+fn test_call_signature() {
+    let src = r#"(module
+        (func $two (result i32 i32) i32.const 1 i32.const 2)
+        (func (result i32) call $two i32.add)
+        (func (result i32) call $two)
+    )"#;
+    let error = validate_src(src).unwrap_err();
+    assert!(error.contains("function 2"), "{}", error);
+
+    // print is an ordinary import, checked against the type it is imported with
+    let src = r#"(module
+        (func i32.const 2 call $print)
+    )"#;
+    assert!(validate_src(src).unwrap_err().contains("unknown function $print"));
+    let src = r#"(module
+        (import "env" "print" (func $print (param i64)))
+        (func i32.const 2 call $print)
+    )"#;
+    assert!(validate_src(src).unwrap_err().contains("type mismatch"));
+    let src = r#"(module
+        (import "env" "print" (func $print (param i32)))
+        (func i32.const 2 call $print)
+    )"#;
+    assert_eq!(validate_src(src), Ok(()));
+}
+
+#[test]
+fn test_unknown_export() {
+    assert!(validate_src(r#"(module (export "f" (func 1)) (func))"#).is_err());
+}
+
+#[test]
+fn test_const_expr_globals() {
+    let valid = r#"(module
+        (import "env" "base" (global $base i32))
+        (global i32 (i32.add (global.get $base) (i32.const 1)))
+    )"#;
+    assert_eq!(validate_src(valid), Ok(()));
+    let not_imported = r#"(module
+        (global $x i32 (i32.const 1))
+        (global i32 (global.get $x))
+    )"#;
+    assert!(validate_src(not_imported).is_err());
+    let mutable = r#"(module
+        (import "env" "base" (global $base (mut i32)))
+        (global i32 (global.get $base))
+    )"#;
+    assert!(validate_src(mutable).is_err());
+    let wrong_type = r#"(module
+        (global i32 (i64.const 1))
+    )"#;
+    assert!(validate_src(wrong_type).is_err());
+}
+
+#[test]
+fn test_non_constant_offsets() {
+    let data = r#"(module
+        (memory 1)
+        (data (i32.eqz (i32.const 1)) "a")
+    )"#;
+    assert!(validate_src(data).unwrap_err().contains("isn't a constant instruction"));
+    let elem = r#"(module
+        (table 1 funcref)
+        (elem (offset i32.const 0 nop) $f)
+        (func $f)
+    )"#;
+    assert!(validate_src(elem).is_err());
+}
+
+#[test]
+fn test_segments_need_memory_and_table() {
+    assert!(validate_src(r#"(module (data (i32.const 0) "a"))"#).is_err());
+    assert!(validate_src(r#"(module (elem (i32.const 0) 0) (func))"#).is_err());
+}
@@ -0,0 +1,399 @@
+use std::io::Cursor;
+
+use crate::{Error, Imports, Instance, MemoryFs, Module, OutputBuffer, Value, Wasi};
+
+const HELLO: &str = r#"(module
+    (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    (memory (export "memory") 1)
+    (data (i32.const 16) "hello\n")
+    (func (export "_start")
+      i32.const 0
+      i32.const 16
+      i32.store
+      i32.const 4
+      i32.const 6
+      i32.store
+      i32.const 1
+      i32.const 0
+      i32.const 1
+      i32.const 8
+      call $fd_write
+      drop)
+)"#;
+
+#[test]
+fn test_wasi_hello() {
+    let module = Module::from_wat(HELLO).unwrap();
+    let stdout = OutputBuffer::new();
+    let mut wasi = Wasi::new();
+    wasi.stdout(stdout.clone());
+    assert_eq!(wasi.run(&module, &Imports::new()), Ok(0));
+    assert_eq!(stdout.contents(), b"hello\n");
+}
+
+// copies stdin to stderr, then exits with the number of arguments plus environment variables
+const ECHO: &str = r#"(module
+    (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "args_sizes_get" (func $args_sizes_get (param i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "args_get" (func $args_get (param i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "environ_sizes_get" (func $environ_sizes_get (param i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+    (memory (export "memory") 1)
+    (func (export "_start")
+      i32.const 0
+      i32.const 100
+      i32.store
+      i32.const 4
+      i32.const 64
+      i32.store
+      i32.const 0
+      i32.const 0
+      i32.const 1
+      i32.const 8
+      call $fd_read
+      drop
+      i32.const 4
+      i32.const 8
+      i32.load
+      i32.store
+      i32.const 2
+      i32.const 0
+      i32.const 1
+      i32.const 8
+      call $fd_write
+      drop
+      i32.const 16
+      i32.const 20
+      call $args_sizes_get
+      drop
+      i32.const 200
+      i32.const 300
+      call $args_get
+      drop
+      i32.const 24
+      i32.const 28
+      call $environ_sizes_get
+      drop
+      i32.const 16
+      i32.load
+      i32.const 24
+      i32.load
+      i32.add
+      call $proc_exit
+      unreachable)
+)"#;
+
+#[test]
+fn test_wasi_args_stdin_and_exit() {
+    let module = Module::from_wat(ECHO).unwrap();
+    let stderr = OutputBuffer::new();
+    let mut wasi = Wasi::new();
+    wasi.arg("echo")
+        .arg("-n")
+        .env("HOME", "/home/guest")
+        .stdin(Cursor::new(b"ping".to_vec()))
+        .stderr(stderr.clone());
+    assert_eq!(wasi.run(&module, &Imports::new()), Ok(3));
+    assert_eq!(stderr.contents(), b"ping");
+}
+
+#[test]
+fn test_wasi_clock_and_random() {
+    let module = Module::from_wat(
+        r#"(module
+        (import "wasi_snapshot_preview1" "clock_time_get" (func $clock (param i32 i64 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "random_get" (func $random (param i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (func (export "now") (result i32)
+          i32.const 0
+          i64.const 1
+          i32.const 0
+          call $clock
+          drop
+          i32.const 4
+          i32.load)
+        (func (export "bad_clock") (result i32)
+          i32.const 9
+          i64.const 1
+          i32.const 0
+          call $clock)
+        (func (export "random") (result i32)
+          i32.const 8
+          i32.const 4
+          call $random
+          drop
+          i32.const 8
+          i32.load)
+        (func (export "random_out_of_bounds") (result i32)
+          i32.const 65534
+          i32.const 4
+          call $random)
+    )"#,
+    )
+    .unwrap();
+    let mut wasi = Wasi::new();
+    wasi.random_seed(7);
+    let mut imports = Imports::new();
+    wasi.add_to_imports(&mut imports);
+    let mut instance = Instance::new(&module, &imports).unwrap();
+    // the upper half of the nanoseconds since 1970 is nonzero
+    assert!(matches!(instance.invoke("now", &[]).unwrap()[..], [Value::I32(n)] if n != 0));
+    assert_eq!(instance.invoke("bad_clock", &[]), Ok(vec![Value::I32(28)]));
+    let first = instance.invoke("random", &[]).unwrap();
+    assert_ne!(instance.invoke("random", &[]).unwrap(), first);
+    assert_eq!(instance.invoke("random_out_of_bounds", &[]), Ok(vec![Value::I32(21)]));
+
+    let missing = Module::from_wat("(module (memory 1))").unwrap();
+    assert!(matches!(Wasi::new().run(&missing, &Imports::new()), Err(Error::Invoke(_))));
+}
+
+// exports each filesystem function unchanged so tests can call them with raw pointers
+const FS: &str = r#"(module
+    (import "wasi_snapshot_preview1" "path_open"
+      (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_seek" (func $fd_seek (param i32 i64 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_tell" (func $fd_tell (param i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_close" (func $fd_close (param i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_readdir" (func $fd_readdir (param i32 i32 i32 i64 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_filestat_get" (func $fd_filestat_get (param i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_prestat_get" (func $fd_prestat_get (param i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_prestat_dir_name" (func $fd_prestat_dir_name (param i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "path_create_directory" (func $path_create_directory (param i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "path_unlink_file" (func $path_unlink_file (param i32 i32 i32) (result i32)))
+    (memory (export "memory") 1)
+    (func (export "path_open") (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)
+      local.get 0 local.get 1 local.get 2 local.get 3 local.get 4
+      local.get 5 local.get 6 local.get 7 local.get 8
+      call $path_open)
+    (func (export "fd_read") (param i32 i32 i32 i32) (result i32)
+      local.get 0 local.get 1 local.get 2 local.get 3 call $fd_read)
+    (func (export "fd_write") (param i32 i32 i32 i32) (result i32)
+      local.get 0 local.get 1 local.get 2 local.get 3 call $fd_write)
+    (func (export "fd_seek") (param i32 i64 i32 i32) (result i32)
+      local.get 0 local.get 1 local.get 2 local.get 3 call $fd_seek)
+    (func (export "fd_tell") (param i32 i32) (result i32)
+      local.get 0 local.get 1 call $fd_tell)
+    (func (export "fd_close") (param i32) (result i32)
+      local.get 0 call $fd_close)
+    (func (export "fd_readdir") (param i32 i32 i32 i64 i32) (result i32)
+      local.get 0 local.get 1 local.get 2 local.get 3 local.get 4 call $fd_readdir)
+    (func (export "fd_filestat_get") (param i32 i32) (result i32)
+      local.get 0 local.get 1 call $fd_filestat_get)
+    (func (export "fd_prestat_get") (param i32 i32) (result i32)
+      local.get 0 local.get 1 call $fd_prestat_get)
+    (func (export "fd_prestat_dir_name") (param i32 i32 i32) (result i32)
+      local.get 0 local.get 1 local.get 2 call $fd_prestat_dir_name)
+    (func (export "path_create_directory") (param i32 i32 i32) (result i32)
+      local.get 0 local.get 1 local.get 2 call $path_create_directory)
+    (func (export "path_unlink_file") (param i32 i32 i32) (result i32)
+      local.get 0 local.get 1 local.get 2 call $path_unlink_file)
+)"#;
+
+const RIGHTS_READ: i64 = 1 << 1;
+const RIGHTS_WRITE: i64 = 1 << 6;
+const PATH: i32 = 1024; // where the tests put path strings
+const IOV: i32 = 0;
+const OUT: i32 = 16;
+const DATA: i32 = 2048;
+
+struct Guest {
+    instance: Instance,
+}
+
+impl Guest {
+    fn new(wasi: &Wasi) -> Self {
+        let mut imports = Imports::new();
+        wasi.add_to_imports(&mut imports);
+        let instance = Instance::new(&Module::from_wat(FS).unwrap(), &imports).unwrap();
+        Guest { instance }
+    }
+
+    fn call(&mut self, name: &str, args: &[Value]) -> i32 {
+        match self.instance.invoke(name, args).unwrap()[..] {
+            [Value::I32(errno)] => errno,
+            ref results => panic!("unexpected results {:?}", results),
+        }
+    }
+
+    fn poke(&mut self, addr: i32, bytes: &[u8]) {
+        let addr = addr as usize;
+        self.instance.memory_mut().unwrap()[addr..addr + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn peek(&self, addr: i32, len: usize) -> Vec<u8> {
+        self.instance.memory().unwrap()[addr as usize..addr as usize + len].to_vec()
+    }
+
+    fn u32_at(&self, addr: i32) -> u32 {
+        u32::from_le_bytes(self.peek(addr, 4).try_into().unwrap())
+    }
+
+    /// Opens `path` relative to `dir`, returning the errno or the new descriptor
+    fn open(&mut self, dir: i32, path: &str, oflags: i32, rights: i64) -> Result<i32, i32> {
+        self.poke(PATH, path.as_bytes());
+        let args = [
+            Value::I32(dir),
+            Value::I32(0),
+            Value::I32(PATH),
+            Value::I32(path.len() as i32),
+            Value::I32(oflags),
+            Value::I64(rights),
+            Value::I64(0),
+            Value::I32(0),
+            Value::I32(OUT),
+        ];
+        match self.call("path_open", &args) {
+            0 => Ok(self.u32_at(OUT) as i32),
+            errno => Err(errno),
+        }
+    }
+
+    fn write(&mut self, fd: i32, bytes: &[u8]) -> i32 {
+        self.poke(DATA, bytes);
+        self.poke(IOV, &[DATA.to_le_bytes(), (bytes.len() as i32).to_le_bytes()].concat());
+        self.call("fd_write", &[Value::I32(fd), Value::I32(IOV), Value::I32(1), Value::I32(OUT)])
+    }
+
+    fn read(&mut self, fd: i32, len: i32) -> Result<Vec<u8>, i32> {
+        self.poke(IOV, &[DATA.to_le_bytes(), len.to_le_bytes()].concat());
+        match self.call("fd_read", &[Value::I32(fd), Value::I32(IOV), Value::I32(1), Value::I32(OUT)]) {
+            0 => Ok(self.peek(DATA, self.u32_at(OUT) as usize)),
+            errno => Err(errno),
+        }
+    }
+
+    fn seek(&mut self, fd: i32, pos: i64) -> i32 {
+        self.call("fd_seek", &[Value::I32(fd), Value::I64(pos), Value::I32(0), Value::I32(OUT)])
+    }
+
+    fn path_call(&mut self, name: &str, dir: i32, path: &str) -> i32 {
+        self.poke(PATH, path.as_bytes());
+        self.call(name, &[Value::I32(dir), Value::I32(PATH), Value::I32(path.len() as i32)])
+    }
+
+    /// The names listed by fd_readdir
+    fn list(&mut self, fd: i32) -> Vec<String> {
+        let args = [Value::I32(fd), Value::I32(DATA), Value::I32(1024), Value::I64(0), Value::I32(OUT)];
+        assert_eq!(self.call("fd_readdir", &args), 0);
+        let entries = self.peek(DATA, self.u32_at(OUT) as usize);
+        let mut names = Vec::new();
+        let mut at = 0;
+        while at < entries.len() {
+            let len = u32::from_le_bytes(entries[at + 16..at + 20].try_into().unwrap()) as usize;
+            names.push(String::from_utf8(entries[at + 24..at + 24 + len].to_vec()).unwrap());
+            at += 24 + len;
+        }
+        names
+    }
+}
+
+#[test]
+fn test_wasi_memory_fs() {
+    let memory = MemoryFs::new();
+    memory.write_file("docs/readme.txt", b"read me").unwrap();
+    let mut wasi = Wasi::new();
+    wasi.preopen_memory(&memory, "/sandbox");
+    let mut guest = Guest::new(&wasi);
+
+    assert_eq!(guest.call("fd_prestat_get", &[Value::I32(3), Value::I32(OUT)]), 0);
+    assert_eq!(guest.u32_at(OUT + 4), 8);
+    assert_eq!(guest.call("fd_prestat_dir_name", &[Value::I32(3), Value::I32(DATA), Value::I32(8)]), 0);
+    assert_eq!(guest.peek(DATA, 8), b"/sandbox");
+    assert_eq!(guest.call("fd_prestat_get", &[Value::I32(4), Value::I32(OUT)]), 8);
+
+    let readme = guest.open(3, "docs/./readme.txt", 0, RIGHTS_READ).unwrap();
+    assert_eq!(readme, 4);
+    assert_eq!(guest.read(readme, 4), Ok(b"read".to_vec()));
+    assert_eq!(guest.call("fd_tell", &[Value::I32(readme), Value::I32(OUT)]), 0);
+    assert_eq!(guest.u32_at(OUT), 4);
+    let seek = [Value::I32(readme), Value::I64(-2), Value::I32(2), Value::I32(OUT)];
+    assert_eq!(guest.call("fd_seek", &seek), 0);
+    assert_eq!(guest.read(readme, 10), Ok(b"me".to_vec()));
+    assert_eq!(guest.write(readme, b"no"), 8);
+    assert_eq!(guest.call("fd_filestat_get", &[Value::I32(readme), Value::I32(DATA)]), 0);
+    assert_eq!(guest.peek(DATA + 16, 1), [4]);
+    assert_eq!(guest.u32_at(DATA + 32), 7);
+    assert_eq!(guest.call("fd_close", &[Value::I32(readme)]), 0);
+    assert_eq!(guest.call("fd_close", &[Value::I32(readme)]), 8);
+
+    assert_eq!(guest.path_call("path_create_directory", 3, "out"), 0);
+    assert_eq!(guest.path_call("path_create_directory", 3, "out"), 20);
+    let log = guest.open(3, "out/log.txt", 1 | 8, RIGHTS_WRITE).unwrap();
+    assert_eq!(guest.write(log, b"line 1\n"), 0);
+    assert_eq!(guest.read(log, 4), Err(8));
+    assert_eq!(memory.read_file("out/log.txt"), Some(b"line 1\n".to_vec()));
+    assert_eq!(guest.open(3, "out/log.txt", 1 | 4, RIGHTS_WRITE), Err(20));
+    assert_eq!(guest.open(3, "missing.txt", 0, RIGHTS_READ), Err(44));
+    assert_eq!(guest.open(3, "docs/readme.txt", 2, RIGHTS_READ), Err(54));
+    assert_eq!(guest.list(3), ["docs", "out"]);
+
+    assert_eq!(guest.open(3, "docs", 0, RIGHTS_READ), Err(31));
+    let docs = guest.open(3, "docs", 2, RIGHTS_READ).unwrap();
+    assert_eq!(guest.list(docs), ["readme.txt"]);
+    assert_eq!(guest.open(docs, "../out/log.txt", 0, RIGHTS_READ).map(|_| ()), Ok(()));
+    assert_eq!(guest.path_call("path_unlink_file", 3, "out"), 31);
+    assert_eq!(guest.path_call("path_unlink_file", 3, "out/log.txt"), 0);
+    assert_eq!(memory.read_file("out/log.txt"), None);
+    assert!(memory.is_dir("out"));
+
+    assert_eq!(memory.write_file("docs/readme.txt/x", b""), Err(String::from("docs/readme.txt is a file")));
+    assert_eq!(memory.write_file("docs", b""), Err(String::from("docs is a directory")));
+    assert!(memory.write_file("", b"").is_err());
+    assert_eq!(memory.read_file("docs/readme.txt"), Some(b"read me".to_vec()));
+}
+
+#[test]
+fn test_wasi_sandbox() {
+    let memory = MemoryFs::new();
+    memory.write_file("inside.txt", b"").unwrap();
+    let mut wasi = Wasi::new();
+    wasi.preopen_memory(&memory, ".");
+    let mut guest = Guest::new(&wasi);
+    assert_eq!(guest.open(3, "../secret", 0, RIGHTS_READ), Err(76));
+    assert_eq!(guest.open(3, "a/../../secret", 1, RIGHTS_WRITE), Err(76));
+    assert_eq!(guest.open(3, "/etc/passwd", 0, RIGHTS_READ), Err(76));
+    assert_eq!(guest.path_call("path_create_directory", 3, ".."), 76);
+    assert_eq!(guest.open(0, "inside.txt", 0, RIGHTS_READ), Err(54));
+    assert_eq!(guest.open(9, "inside.txt", 0, RIGHTS_READ), Err(8));
+    // writing far past the end can't grow an in-memory file without bound
+    let file = guest.open(3, "inside.txt", 0, RIGHTS_WRITE).unwrap();
+    assert_eq!(guest.seek(file, 1 << 62), 0);
+    assert_eq!(guest.write(file, b"x"), 22);
+    assert_eq!(memory.read_file("inside.txt"), Some(Vec::new()));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_wasi_host_dir() {
+    let host = std::env::temp_dir().join(format!("interperter-wasi-{}", std::process::id()));
+    std::fs::create_dir_all(host.join("data")).unwrap();
+    std::fs::write(host.join("data/input.txt"), b"from the host").unwrap();
+    std::os::unix::fs::symlink("/etc", host.join("etc")).unwrap();
+    std::os::unix::fs::symlink("data/input.txt", host.join("link.txt")).unwrap();
+
+    let mut wasi = Wasi::new();
+    wasi.preopen_dir(host.to_str().unwrap(), "/work").unwrap();
+    assert!(Wasi::new().preopen_dir("/no/such/dir", "/").is_err());
+    let mut guest = Guest::new(&wasi);
+    let input = guest.open(3, "data/input.txt", 0, RIGHTS_READ).unwrap();
+    assert_eq!(guest.read(input, 64), Ok(b"from the host".to_vec()));
+    let output = guest.open(3, "data/output.txt", 1, RIGHTS_WRITE).unwrap();
+    assert_eq!(guest.write(output, b"from the guest"), 0);
+    assert_eq!(std::fs::read(host.join("data/output.txt")).unwrap(), b"from the guest");
+    // links are refused wherever they are in the path
+    assert_eq!(guest.open(3, "etc/passwd", 0, RIGHTS_READ), Err(76));
+    assert_eq!(guest.open(3, "etc", 2, RIGHTS_READ), Err(76));
+    assert_eq!(guest.path_call("path_create_directory", 3, "etc/new"), 76);
+    assert_eq!(guest.open(3, "link.txt", 0, RIGHTS_READ), Err(76));
+    assert_eq!(guest.open(3, "../", 2, RIGHTS_READ), Err(76));
+    assert_eq!(guest.open(3, "data", 0, RIGHTS_READ), Err(31));
+    assert_eq!(guest.list(3), ["data", "etc", "link.txt"]);
+    // a missing directory in the middle of a path isn't created in place of the last name
+    assert_eq!(guest.path_call("path_create_directory", 3, "missing/new"), 44);
+    assert_eq!(guest.open(3, "missing/x", 1, RIGHTS_WRITE), Err(44));
+    assert!(!host.join("missing").exists());
+    std::fs::remove_dir_all(&host).unwrap();
+}
@@ -0,0 +1,98 @@
+use crate::{run_wast, WastFailure};
+
+const SCRIPT: &str = r#";; modules, actions and assertions
+(module $M
+  (import "spectest" "global_i32" (global $base i32))
+  (import "spectest" "print_i32" (func $log (param i32)))
+  (global (export "base") i32 (global.get $base))
+  (func (export "add") (param i32 i32) (result i32) local.get 0 local.get 1 i32.add)
+  (func (export "div") (param i32 i32) (result i32) local.get 0 local.get 1 i32.div_s)
+  (func (export "id") (param f32) (result f32) local.get 0)
+  (func (export "nan") (result f64) f64.const nan)
+  (func $loop (export "loop") call $loop)
+  (func (export "log") i32.const 1 call $log)
+)
+(assert_return (invoke "add" (i32.const 1) (i32.const 0x2)) (i32.const 3))
+(assert_return (invoke $M "add" (i32.const -1) (i32.const 1)) (i32.const 0))
+(assert_return (invoke "id" (f32.const -0x1p-149)) (f32.const -0x1p-149))
+(assert_return (invoke "nan") (f64.const nan:canonical))
+(assert_return (get "base") (i32.const 666))
+(invoke "log")
+(assert_trap (invoke "div" (i32.const 1) (i32.const 0)) "integer divide by zero")
+(assert_exhaustion (invoke "loop") "call stack exhausted")
+(register "m" $M)
+(module (import "m" "add" (func $add (param i32 i32) (result i32)))
+  (func (export "twice") (param i32) (result i32) local.get 0 local.get 0 call $add))
+(assert_return (invoke "twice" (i32.const 21)) (i32.const 42))
+(assert_invalid (module (func (result i32))) "type mismatch")
+(assert_malformed (module quote "(func") "unexpected end")
+(assert_unlinkable (module (import "m" "sub" (func))) "unknown import")
+(module binary "\00asm" "\01\00\00\00")
+"#;
+
+#[test]
+fn test_wast_passes() {
+    let report = run_wast(SCRIPT).unwrap();
+    assert_eq!(report.failures, vec![]);
+    assert_eq!(report.passed, 16);
+}
+
+#[test]
+fn test_wast_failures() {
+    let report = run_wast(
+        r#"(module (func (export "id") (param f32) (result f32) local.get 0))
+        (assert_return (invoke "id" (f32.const -0)) (f32.const 0))
+        (assert_trap (invoke "id" (f32.const 1)) "unreachable")
+        (assert_return (invoke "missing"))
+        (assert_invalid (module (func (export "id"))) "type mismatch")
+        (assert_return (invoke $other "id" (f32.const 1)) (f32.const 1))
+        (assert_return (invoke "id" (f32.const nan:0x1)) (f32.const nan:arithmetic))
+        (frobnicate)
+        (assert_invalid (module (func (result i32))) "unknown local")
+        (assert_invalid (module (func i32.popcnt)) "type mismatch")
+        (assert_malformed (module quote "(func (result i32))") "type mismatch")"#,
+    )
+    .unwrap();
+    let lines: Vec<usize> = report.failures.iter().map(|failure| failure.line).collect();
+    assert_eq!(lines, vec![2, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+    assert_eq!(
+        report.failures[0],
+        WastFailure {
+            line: 2,
+            message: String::from("expected [F32(0.0)], saw [F32(-0.0)]")
+        }
+    );
+    assert_eq!(report.failures[6].message, "unsupported command frobnicate");
+    assert!(report.failures[7].message.starts_with("expected an invalid module: unknown local, saw invalid module"));
+    assert_eq!(
+        report.failures[8].message,
+        "expected an invalid module: type mismatch, saw parse error: unknown instruction i32.popcnt"
+    );
+    assert!(report.failures[9].message.starts_with("expected a malformed module: type mismatch, saw invalid module"));
+    assert_eq!(report.passed, 1);
+    assert!(run_wast("(module").is_err());
+}
+
+#[test]
+fn test_wast_failed_module() {
+    let report = run_wast(
+        r#"(module (func (export "one") (result i32) (i32.const 1)))
+        (module $M (func (export "one") (result i32) i32.popcnt))
+        (assert_return (invoke "one") (i32.const 1))
+        (assert_return (invoke $M "one") (i32.const 1))
+        (module (func (export "two") (result i32) (i32.add (i32.const 1) (i32.const 1))))
+        (assert_return (invoke "two") (i32.const 2))"#,
+    )
+    .unwrap();
+    let failures: Vec<(usize, &str)> =
+        report.failures.iter().map(|failure| (failure.line, failure.message.as_str())).collect();
+    assert_eq!(
+        failures,
+        vec![
+            (2, "parse error: unknown instruction i32.popcnt"),
+            (3, "the module at line 2 didn't load"),
+            (4, "the module at line 2 didn't load"),
+        ]
+    );
+    assert_eq!(report.passed, 3);
+}
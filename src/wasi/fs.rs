@@ -0,0 +1,328 @@
+use std::collections::BTreeMap;
+#[cfg(unix)]
+use std::fs::File;
+use std::io::{self, ErrorKind};
+#[cfg(unix)]
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+
+#[cfg(unix)]
+use super::host::HostDir;
+use super::{EACCES, EEXIST, EFBIG, EIO, EISDIR, ENOENT, ENOTCAPABLE, ENOTDIR, ENOTEMPTY};
+
+pub const FILETYPE_UNKNOWN: u8 = 0;
+pub const FILETYPE_CHARACTER_DEVICE: u8 = 2;
+pub const FILETYPE_DIRECTORY: u8 = 3;
+pub const FILETYPE_REGULAR_FILE: u8 = 4;
+pub const FILETYPE_SYMBOLIC_LINK: u8 = 7;
+
+/// The largest in-memory file, a guest seeking far ahead and writing can't exhaust the host
+const MAX_MEMORY_FILE_SIZE: usize = 1 << 28;
+
+#[derive(Debug)]
+enum Node {
+    File(Vec<u8>),
+    Dir(BTreeMap<String, Node>),
+}
+
+/// An in-memory directory tree that can be preopened instead of a host directory,
+/// for deterministic tests of guests that use files
+#[derive(Debug, Clone)]
+pub struct MemoryFs {
+    root: Arc<Mutex<Node>>,
+}
+
+impl Default for MemoryFs {
+    fn default() -> Self {
+        Self {
+            root: Arc::new(Mutex::new(Node::Dir(BTreeMap::new()))),
+        }
+    }
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates or replaces a file, along with any missing parent directories.
+    /// Fails when a parent is a file or the path names a directory
+    pub fn write_file(&self, path: &str, contents: &[u8]) -> Result<(), String> {
+        let path = components(path);
+        let (name, parents) = path.split_last().ok_or("a file path can't be empty")?;
+        let mut root = self.root.lock().unwrap();
+        let mut dir = &mut *root;
+        for (i, parent) in parents.iter().enumerate() {
+            dir = match dir {
+                Node::Dir(entries) => entries.entry(parent.clone()).or_insert(Node::Dir(BTreeMap::new())),
+                Node::File(_) => return Err(format!("{} is a file", parents[..i].join("/"))),
+            };
+        }
+        let entries = match dir {
+            Node::Dir(entries) => entries,
+            Node::File(_) => return Err(format!("{} is a file", parents.join("/"))),
+        };
+        if let Some(Node::Dir(_)) = entries.get(name) {
+            return Err(format!("{} is a directory", path.join("/")));
+        }
+        entries.insert(name.clone(), Node::File(contents.to_vec()));
+        Ok(())
+    }
+
+    /// The contents of a file, `None` if there is no such file
+    pub fn read_file(&self, path: &str) -> Option<Vec<u8>> {
+        let mut root = self.root.lock().unwrap();
+        match lookup(&mut root, &components(path)) {
+            Ok(Node::File(contents)) => Some(contents.clone()),
+            _ => None,
+        }
+    }
+
+    /// Whether `path` names a directory
+    pub fn is_dir(&self, path: &str) -> bool {
+        let mut root = self.root.lock().unwrap();
+        matches!(lookup(&mut root, &components(path)), Ok(Node::Dir(_)))
+    }
+}
+
+fn components(path: &str) -> Vec<String> {
+    path.split('/').filter(|name| !name.is_empty() && *name != ".").map(String::from).collect()
+}
+
+fn lookup<'a>(root: &'a mut Node, path: &[String]) -> Result<&'a mut Node, i32> {
+    let mut node = root;
+    for name in path {
+        node = match node {
+            Node::Dir(entries) => entries.get_mut(name).ok_or(ENOENT)?,
+            Node::File(_) => return Err(ENOTDIR),
+        };
+    }
+    Ok(node)
+}
+
+/// The entries of the directory containing `path`, and the last name of `path`
+fn parent<'a>(root: &'a mut Node, path: &'a [String]) -> Result<(&'a mut BTreeMap<String, Node>, &'a String), i32> {
+    let (name, parents) = path.split_last().ok_or(ENOTCAPABLE)?;
+    match lookup(root, parents)? {
+        Node::Dir(entries) => Ok((entries, name)),
+        Node::File(_) => Err(ENOTDIR),
+    }
+}
+
+/// Joins a guest path onto a directory of a preopen, `..` can't climb above the preopen
+/// and absolute paths are refused
+pub fn resolve(base: &[String], path: &str) -> Result<Vec<String>, i32> {
+    if path.starts_with('/') {
+        return Err(ENOTCAPABLE);
+    }
+    let mut resolved = base.to_vec();
+    for name in path.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => {
+                resolved.pop().ok_or(ENOTCAPABLE)?;
+            }
+            name => resolved.push(name.to_string()),
+        }
+    }
+    Ok(resolved)
+}
+
+pub(super) fn errno(error: io::Error) -> i32 {
+    match error.kind() {
+        ErrorKind::NotFound => ENOENT,
+        ErrorKind::PermissionDenied => EACCES,
+        ErrorKind::AlreadyExists => EEXIST,
+        ErrorKind::DirectoryNotEmpty => ENOTEMPTY,
+        ErrorKind::IsADirectory => EISDIR,
+        ErrorKind::NotADirectory => ENOTDIR,
+        ErrorKind::FileTooLarge => EFBIG,
+        _ => EIO,
+    }
+}
+
+pub struct Stat {
+    pub filetype: u8,
+    pub size: u64,
+    pub modified: u64, // nanoseconds since 1970
+}
+
+/// The file of an open descriptor, in-memory files are looked up by path on every access
+#[derive(Debug)]
+pub enum Handle {
+    #[cfg(unix)]
+    Host(File),
+    Memory(Vec<String>),
+}
+
+/// Where the files of a preopened directory live
+#[derive(Debug, Clone)]
+pub enum Backend {
+    #[cfg(unix)]
+    Host(HostDir),
+    Memory(MemoryFs),
+}
+
+impl Backend {
+    pub fn stat(&self, path: &[String]) -> Result<Stat, i32> {
+        match self {
+            #[cfg(unix)]
+            Backend::Host(dir) => dir.stat(path),
+            Backend::Memory(memory) => {
+                let mut root = memory.root.lock().unwrap();
+                let (filetype, size) = match lookup(&mut root, path)? {
+                    Node::File(contents) => (FILETYPE_REGULAR_FILE, contents.len() as u64),
+                    Node::Dir(_) => (FILETYPE_DIRECTORY, 0),
+                };
+                Ok(Stat {
+                    filetype,
+                    size,
+                    modified: 0,
+                })
+            }
+        }
+    }
+
+    /// The names and file types in a directory, sorted by name
+    pub fn read_dir(&self, path: &[String]) -> Result<Vec<(String, u8)>, i32> {
+        let mut entries = match self {
+            #[cfg(unix)]
+            Backend::Host(dir) => dir.read_dir(path)?,
+            Backend::Memory(memory) => match lookup(&mut memory.root.lock().unwrap(), path)? {
+                Node::Dir(entries) => entries
+                    .iter()
+                    .map(|(name, node)| {
+                        let filetype = match node {
+                            Node::File(_) => FILETYPE_REGULAR_FILE,
+                            Node::Dir(_) => FILETYPE_DIRECTORY,
+                        };
+                        (name.clone(), filetype)
+                    })
+                    .collect(),
+                Node::File(_) => return Err(ENOTDIR),
+            },
+        };
+        entries.sort();
+        Ok(entries)
+    }
+
+    pub fn create_dir(&self, path: &[String]) -> Result<(), i32> {
+        match self {
+            #[cfg(unix)]
+            Backend::Host(dir) => dir.create_dir(path),
+            Backend::Memory(memory) => {
+                let mut root = memory.root.lock().unwrap();
+                let (entries, name) = parent(&mut root, path)?;
+                if entries.contains_key(name) {
+                    return Err(EEXIST);
+                }
+                entries.insert(name.clone(), Node::Dir(BTreeMap::new()));
+                Ok(())
+            }
+        }
+    }
+
+    pub fn unlink_file(&self, path: &[String]) -> Result<(), i32> {
+        if self.stat(path)?.filetype == FILETYPE_DIRECTORY {
+            return Err(EISDIR);
+        }
+        match self {
+            #[cfg(unix)]
+            Backend::Host(dir) => dir.unlink_file(path),
+            Backend::Memory(memory) => {
+                let mut root = memory.root.lock().unwrap();
+                let (entries, name) = parent(&mut root, path)?;
+                entries.remove(name);
+                Ok(())
+            }
+        }
+    }
+
+    /// Opens a regular file, creating it when `create` is set. A directory fails with `EISDIR`,
+    /// `path_open` gives those a directory descriptor instead
+    pub fn open(&self, path: &[String], create: bool, truncate: bool, write: bool) -> Result<Handle, i32> {
+        match self {
+            #[cfg(unix)]
+            Backend::Host(dir) => Ok(Handle::Host(dir.open(path, create, truncate, write)?)),
+            Backend::Memory(memory) => {
+                let mut root = memory.root.lock().unwrap();
+                let (entries, name) = parent(&mut root, path)?;
+                match entries.get_mut(name) {
+                    Some(Node::File(contents)) if truncate => contents.clear(),
+                    Some(Node::File(_)) => {}
+                    Some(Node::Dir(_)) => return Err(EISDIR),
+                    None if create => {
+                        entries.insert(name.clone(), Node::File(Vec::new()));
+                    }
+                    None => return Err(ENOENT),
+                }
+                Ok(Handle::Memory(path.to_vec()))
+            }
+        }
+    }
+
+    pub fn read(&self, handle: &mut Handle, pos: u64, buf: &mut [u8]) -> Result<usize, i32> {
+        match handle {
+            #[cfg(unix)]
+            Handle::Host(file) => {
+                file.seek(SeekFrom::Start(pos)).map_err(errno)?;
+                file.read(buf).map_err(errno)
+            }
+            Handle::Memory(path) => {
+                let mut root = self.memory_root().lock().unwrap();
+                let contents = file_contents(&mut root, path)?;
+                let start = (pos as usize).min(contents.len());
+                let n = buf.len().min(contents.len() - start);
+                buf[..n].copy_from_slice(&contents[start..start + n]);
+                Ok(n)
+            }
+        }
+    }
+
+    pub fn write(&self, handle: &mut Handle, pos: u64, data: &[u8]) -> Result<(), i32> {
+        match handle {
+            #[cfg(unix)]
+            Handle::Host(file) => {
+                file.seek(SeekFrom::Start(pos)).map_err(errno)?;
+                file.write_all(data).map_err(errno)
+            }
+            Handle::Memory(path) => {
+                let mut root = self.memory_root().lock().unwrap();
+                let contents = file_contents(&mut root, path)?;
+                let start = usize::try_from(pos).map_err(|_| EFBIG)?;
+                let end = start.checked_add(data.len()).filter(|end| *end <= MAX_MEMORY_FILE_SIZE).ok_or(EFBIG)?;
+                if contents.len() < end {
+                    contents.resize(end, 0);
+                }
+                contents[start..end].copy_from_slice(data);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn size(&self, handle: &mut Handle) -> Result<u64, i32> {
+        match handle {
+            #[cfg(unix)]
+            Handle::Host(file) => Ok(file.metadata().map_err(errno)?.len()),
+            Handle::Memory(path) => {
+                let mut root = self.memory_root().lock().unwrap();
+                Ok(file_contents(&mut root, path)?.len() as u64)
+            }
+        }
+    }
+
+    fn memory_root(&self) -> &Mutex<Node> {
+        match self {
+            Backend::Memory(memory) => &memory.root,
+            #[cfg(unix)]
+            Backend::Host(_) => unreachable!("host files are accessed through their handle"),
+        }
+    }
+}
+
+fn file_contents<'a>(root: &'a mut Node, path: &[String]) -> Result<&'a mut Vec<u8>, i32> {
+    match lookup(root, path)? {
+        Node::File(contents) => Ok(contents),
+        Node::Dir(_) => Err(EISDIR),
+    }
+}
@@ -0,0 +1,178 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd};
+use std::path::Path;
+use std::sync::Arc;
+
+use super::fs::{
+    errno, Stat, FILETYPE_CHARACTER_DEVICE, FILETYPE_DIRECTORY, FILETYPE_REGULAR_FILE, FILETYPE_SYMBOLIC_LINK,
+    FILETYPE_UNKNOWN,
+};
+use super::{EINVAL, EISDIR, ENOTCAPABLE, ENOTDIR};
+
+/// A preopened host directory. Paths are walked a component at a time with `openat` from the
+/// preopen's descriptor and symbolic links are never followed, so no path, nor a link swapped
+/// in while it is walked, leads out of the preopen
+#[derive(Debug, Clone)]
+pub struct HostDir {
+    fd: Arc<OwnedFd>,
+}
+
+/// The errno of the last failed call, a link met with `O_NOFOLLOW` is outside the sandbox
+fn last_errno() -> i32 {
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::ELOOP) => ENOTCAPABLE,
+        _ => errno(error),
+    }
+}
+
+fn is_link(dir: &OwnedFd, name: &CString) -> bool {
+    let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+    let result = unsafe { libc::fstatat(dir.as_raw_fd(), name.as_ptr(), &mut stat, libc::AT_SYMLINK_NOFOLLOW) };
+    result == 0 && stat.st_mode & libc::S_IFMT == libc::S_IFLNK
+}
+
+fn c_name(name: &str) -> Result<CString, i32> {
+    CString::new(name).map_err(|_| EINVAL)
+}
+
+fn filetype(mode: libc::mode_t) -> u8 {
+    match mode & libc::S_IFMT {
+        libc::S_IFDIR => FILETYPE_DIRECTORY,
+        libc::S_IFREG => FILETYPE_REGULAR_FILE,
+        libc::S_IFLNK => FILETYPE_SYMBOLIC_LINK,
+        libc::S_IFCHR => FILETYPE_CHARACTER_DEVICE,
+        _ => FILETYPE_UNKNOWN,
+    }
+}
+
+fn to_stat(stat: &libc::stat) -> Stat {
+    Stat {
+        filetype: filetype(stat.st_mode),
+        size: stat.st_size as u64,
+        modified: (stat.st_mtime as u64).saturating_mul(1_000_000_000).saturating_add(stat.st_mtime_nsec as u64),
+    }
+}
+
+impl HostDir {
+    pub fn new(path: &Path) -> io::Result<HostDir> {
+        let fd = File::open(path)?;
+        if !fd.metadata()?.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotADirectory, "not a directory"));
+        }
+        Ok(HostDir {
+            fd: Arc::new(fd.into()),
+        })
+    }
+
+    /// Opens the directory `path` names, one component at a time
+    fn walk(&self, path: &[String]) -> Result<OwnedFd, i32> {
+        let mut dir = self.fd.try_clone().map_err(errno)?;
+        for name in path {
+            let name = c_name(name)?;
+            let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+            let fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags) };
+            if fd < 0 {
+                let errno = last_errno();
+                // O_DIRECTORY reports a link as ENOTDIR before O_NOFOLLOW gets to it
+                if errno == ENOTDIR && is_link(&dir, &name) {
+                    return Err(ENOTCAPABLE);
+                }
+                return Err(errno);
+            }
+            dir = unsafe { OwnedFd::from_raw_fd(fd) };
+        }
+        Ok(dir)
+    }
+
+    /// The directory holding the last name of `path`, and that name
+    fn parent(&self, path: &[String]) -> Result<(OwnedFd, CString), i32> {
+        let (name, parents) = path.split_last().ok_or(ENOTCAPABLE)?;
+        Ok((self.walk(parents)?, c_name(name)?))
+    }
+
+    pub fn stat(&self, path: &[String]) -> Result<Stat, i32> {
+        let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+        let result = match path.split_last() {
+            None => unsafe { libc::fstat(self.fd.as_raw_fd(), &mut stat) },
+            Some(_) => {
+                let (dir, name) = self.parent(path)?;
+                unsafe { libc::fstatat(dir.as_raw_fd(), name.as_ptr(), &mut stat, libc::AT_SYMLINK_NOFOLLOW) }
+            }
+        };
+        if result < 0 {
+            return Err(last_errno());
+        }
+        Ok(to_stat(&stat))
+    }
+
+    /// The names and file types in a directory
+    pub fn read_dir(&self, path: &[String]) -> Result<Vec<(String, u8)>, i32> {
+        let dir = unsafe { libc::fdopendir(self.walk(path)?.into_raw_fd()) };
+        if dir.is_null() {
+            return Err(last_errno());
+        }
+        let mut entries = Vec::new();
+        loop {
+            let entry = unsafe { libc::readdir(dir) };
+            if entry.is_null() {
+                break;
+            }
+            let entry = unsafe { &*entry };
+            let name = unsafe { std::ffi::CStr::from_ptr(entry.d_name.as_ptr()) }.to_string_lossy().into_owned();
+            if name == "." || name == ".." {
+                continue;
+            }
+            let filetype = match entry.d_type {
+                libc::DT_DIR => FILETYPE_DIRECTORY,
+                libc::DT_REG => FILETYPE_REGULAR_FILE,
+                libc::DT_LNK => FILETYPE_SYMBOLIC_LINK,
+                libc::DT_CHR => FILETYPE_CHARACTER_DEVICE,
+                _ => FILETYPE_UNKNOWN,
+            };
+            entries.push((name, filetype));
+        }
+        unsafe { libc::closedir(dir) };
+        Ok(entries)
+    }
+
+    pub fn create_dir(&self, path: &[String]) -> Result<(), i32> {
+        let (dir, name) = self.parent(path)?;
+        if unsafe { libc::mkdirat(dir.as_raw_fd(), name.as_ptr(), 0o777) } < 0 {
+            return Err(last_errno());
+        }
+        Ok(())
+    }
+
+    pub fn unlink_file(&self, path: &[String]) -> Result<(), i32> {
+        let (dir, name) = self.parent(path)?;
+        if unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), 0) } < 0 {
+            return Err(last_errno());
+        }
+        Ok(())
+    }
+
+    /// Opens a regular file, a directory fails with `EISDIR`
+    pub fn open(&self, path: &[String], create: bool, truncate: bool, write: bool) -> Result<File, i32> {
+        let (dir, name) = self.parent(path)?;
+        let mut flags = libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        flags |= if write || truncate { libc::O_RDWR } else { libc::O_RDONLY };
+        if create {
+            flags |= libc::O_CREAT;
+        }
+        if truncate {
+            flags |= libc::O_TRUNC;
+        }
+        let fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags, 0o666 as libc::c_uint) };
+        if fd < 0 {
+            return Err(last_errno());
+        }
+        let file = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        if file.metadata().map_err(errno)?.is_dir() {
+            return Err(EISDIR);
+        }
+        Ok(file)
+    }
+}
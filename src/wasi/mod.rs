@@ -0,0 +1,625 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::api::{Caller, Error, FuncType, Imports, Instance, Module, ValType, Value};
+use crate::interpret::trap::Trap;
+
+mod fs;
+#[cfg(unix)]
+mod host;
+use fs::{resolve, Backend, Handle, FILETYPE_CHARACTER_DEVICE, FILETYPE_DIRECTORY, FILETYPE_SYMBOLIC_LINK};
+pub use fs::MemoryFs;
+
+const MODULE: &str = "wasi_snapshot_preview1";
+
+// errno values of wasi_snapshot_preview1
+const SUCCESS: i32 = 0;
+const EACCES: i32 = 2;
+const EBADF: i32 = 8;
+const EEXIST: i32 = 20;
+const EFAULT: i32 = 21;
+const EFBIG: i32 = 22;
+const EILSEQ: i32 = 25;
+const EINVAL: i32 = 28;
+const EIO: i32 = 29;
+const EISDIR: i32 = 31;
+const ENOENT: i32 = 44;
+const ENOTDIR: i32 = 54;
+const ENOTEMPTY: i32 = 55;
+const ESPIPE: i32 = 70;
+const ENOTCAPABLE: i32 = 76;
+
+// path_open flags and rights
+const OFLAGS_CREAT: u32 = 1;
+const OFLAGS_DIRECTORY: u32 = 2;
+const OFLAGS_EXCL: u32 = 4;
+const OFLAGS_TRUNC: u32 = 8;
+const FDFLAGS_APPEND: u32 = 1;
+const RIGHTS_FD_READ: u64 = 1 << 1;
+const RIGHTS_FD_WRITE: u64 = 1 << 6;
+
+/// A writer whose output can be read back, for capturing what a guest prints
+#[derive(Debug, Clone, Default)]
+pub struct OutputBuffer {
+    bytes: Arc<Mutex<Vec<u8>>>,
+}
+
+impl OutputBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.bytes.lock().unwrap().clone()
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Random {
+    Os,
+    Seeded(u64), // xorshift64* state
+}
+
+/// What a file descriptor refers to
+enum Descriptor {
+    Stdin,
+    Stdout,
+    Stderr,
+    Dir {
+        preopen: Option<String>, // the guest path of a preopened directory
+        backend: Backend,
+        path: Vec<String>, // relative to the preopened directory
+    },
+    File {
+        backend: Backend,
+        path: Vec<String>,
+        handle: Handle,
+        pos: u64,
+        append: bool,
+        readable: bool,
+        writable: bool,
+    },
+}
+
+struct WasiState {
+    args: Vec<String>,
+    env: Vec<String>, // as KEY=value
+    stdin: Box<dyn Read + Send>,
+    stdout: Box<dyn Write + Send>,
+    stderr: Box<dyn Write + Send>,
+    random: Random,
+    started: Instant,
+    fds: Vec<Option<Descriptor>>, // None for a closed descriptor
+}
+
+impl WasiState {
+    fn descriptor(&mut self, fd: u32) -> Result<&mut Descriptor, i32> {
+        self.fds.get_mut(fd as usize).and_then(Option::as_mut).ok_or(EBADF)
+    }
+
+    /// The backend and path of a directory descriptor
+    fn dir(&mut self, fd: u32) -> Result<(Backend, Vec<String>), i32> {
+        match self.descriptor(fd)? {
+            Descriptor::Dir { backend, path, .. } => Ok((backend.clone(), path.clone())),
+            _ => Err(ENOTDIR),
+        }
+    }
+
+    /// Puts a descriptor in the lowest free slot
+    fn insert(&mut self, descriptor: Descriptor) -> u32 {
+        match self.fds.iter().position(Option::is_none) {
+            Some(fd) => {
+                self.fds[fd] = Some(descriptor);
+                fd as u32
+            }
+            None => {
+                self.fds.push(Some(descriptor));
+                self.fds.len() as u32 - 1
+            }
+        }
+    }
+
+    fn write(&mut self, fd: u32, data: &[u8]) -> Result<(), i32> {
+        let (stdout, stderr) = (&mut self.stdout, &mut self.stderr);
+        match self.fds.get_mut(fd as usize).and_then(Option::as_mut).ok_or(EBADF)? {
+            Descriptor::Stdout => stdout.write_all(data).map_err(|_| EIO),
+            Descriptor::Stderr => stderr.write_all(data).map_err(|_| EIO),
+            Descriptor::File {
+                backend,
+                handle,
+                pos,
+                append,
+                writable: true,
+                ..
+            } => {
+                if *append {
+                    *pos = backend.size(handle)?;
+                }
+                backend.write(handle, *pos, data)?;
+                *pos += data.len() as u64;
+                Ok(())
+            }
+            _ => Err(EBADF),
+        }
+    }
+
+    fn read(&mut self, fd: u32, buf: &mut [u8]) -> Result<usize, i32> {
+        let stdin = &mut self.stdin;
+        match self.fds.get_mut(fd as usize).and_then(Option::as_mut).ok_or(EBADF)? {
+            Descriptor::Stdin => stdin.read(buf).map_err(|_| EIO),
+            Descriptor::File {
+                backend,
+                handle,
+                pos,
+                readable: true,
+                ..
+            } => {
+                let n = backend.read(handle, *pos, buf)?;
+                *pos += n as u64;
+                Ok(n)
+            }
+            Descriptor::Dir { .. } => Err(EISDIR),
+            _ => Err(EBADF),
+        }
+    }
+
+    fn fill_random(&mut self, buf: &mut [u8]) -> Result<(), i32> {
+        match &mut self.random {
+            Random::Os => File::open("/dev/urandom").and_then(|mut file| file.read_exact(buf)).map_err(|_| EIO),
+            Random::Seeded(state) => {
+                for byte in buf.iter_mut() {
+                    *state ^= *state >> 12;
+                    *state ^= *state << 25;
+                    *state ^= *state >> 27;
+                    *byte = (state.wrapping_mul(0x2545f4914f6cdd1d) >> 56) as u8;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The wasi_snapshot_preview1 functions a command module needs, with their stdio, arguments and environment
+#[derive(Clone)]
+pub struct Wasi {
+    state: Arc<Mutex<WasiState>>,
+}
+
+impl Default for Wasi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Wasi {
+    /// Inherits the process's stdio, with no arguments or environment variables
+    pub fn new() -> Self {
+        let state = WasiState {
+            args: Vec::new(),
+            env: Vec::new(),
+            stdin: Box::new(io::stdin()),
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+            random: Random::Os,
+            started: Instant::now(),
+            fds: vec![Some(Descriptor::Stdin), Some(Descriptor::Stdout), Some(Descriptor::Stderr)],
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Appends a command line argument, the first one is the program name by convention
+    pub fn arg(&mut self, arg: &str) -> &mut Self {
+        self.state.lock().unwrap().args.push(arg.to_string());
+        self
+    }
+
+    pub fn env(&mut self, key: &str, value: &str) -> &mut Self {
+        self.state.lock().unwrap().env.push(format!("{}={}", key, value));
+        self
+    }
+
+    pub fn stdin(&mut self, reader: impl Read + Send + 'static) -> &mut Self {
+        self.state.lock().unwrap().stdin = Box::new(reader);
+        self
+    }
+
+    pub fn stdout(&mut self, writer: impl Write + Send + 'static) -> &mut Self {
+        self.state.lock().unwrap().stdout = Box::new(writer);
+        self
+    }
+
+    pub fn stderr(&mut self, writer: impl Write + Send + 'static) -> &mut Self {
+        self.state.lock().unwrap().stderr = Box::new(writer);
+        self
+    }
+
+    /// Makes a host directory visible to the guest as `guest`, nothing outside it is reachable.
+    /// Only unix hosts can walk paths without following links, elsewhere this fails
+    pub fn preopen_dir(&mut self, host: &str, guest: &str) -> Result<&mut Self, String> {
+        #[cfg(unix)]
+        {
+            let root = host::HostDir::new(Path::new(host)).map_err(|error| format!("can't preopen {}: {}", host, error))?;
+            Ok(self.preopen(Backend::Host(root), guest))
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = guest;
+            Err(format!("can't preopen {}: host directories need a unix host", host))
+        }
+    }
+
+    /// Makes an in-memory directory tree visible to the guest as `guest`
+    pub fn preopen_memory(&mut self, memory: &MemoryFs, guest: &str) -> &mut Self {
+        self.preopen(Backend::Memory(memory.clone()), guest)
+    }
+
+    fn preopen(&mut self, backend: Backend, guest: &str) -> &mut Self {
+        let preopen = Descriptor::Dir {
+            preopen: Some(guest.to_string()),
+            backend,
+            path: Vec::new(),
+        };
+        self.state.lock().unwrap().insert(preopen);
+        self
+    }
+
+    /// Makes `random_get` deterministic instead of reading the operating system's generator
+    pub fn random_seed(&mut self, seed: u64) -> &mut Self {
+        self.state.lock().unwrap().random = Random::Seeded(seed.max(1));
+        self
+    }
+
+    /// Instantiates a command module and runs its `_start` export, returning the exit code
+    /// it passed to `proc_exit`, or 0 when `_start` returns
+    pub fn run(&self, module: &Module, imports: &Imports) -> Result<i32, Error> {
+        let mut imports = imports.clone();
+        self.add_to_imports(&mut imports);
+        match Instance::new(module, &imports) {
+            Ok(mut instance) => self.start(&mut instance),
+            Err(error) => self.finish(Err(error)),
+        }
+    }
+
+    /// Runs `_start` of an instance made with these imports, like `run` does
+    pub fn start(&self, instance: &mut Instance) -> Result<i32, Error> {
+        let finished = instance.invoke("_start", &[]);
+        self.finish(finished)
+    }
+
+    fn finish(&self, finished: Result<Vec<Value>, Error>) -> Result<i32, Error> {
+        let mut state = self.state.lock().unwrap();
+        let _ = state.stdout.flush();
+        let _ = state.stderr.flush();
+        match finished {
+            Ok(_) => Ok(0),
+            Err(Error::Trap(Trap::Exit(code), _)) => Ok(code),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Defines the wasi_snapshot_preview1 functions, the guest's memory holds their buffers
+    pub fn add_to_imports(&self, imports: &mut Imports) {
+        use ValType::{I32, I64};
+        self.define(imports, "fd_write", vec![I32; 4], |state, memory, args| {
+            let (fd, iovs, iovs_len, nwritten) = (int(&args[0]), int(&args[1]), int(&args[2]), int(&args[3]));
+            let mut data = Vec::new();
+            for (start, len) in iovecs(memory, iovs, iovs_len)? {
+                data.extend_from_slice(&memory[start..start + len]);
+            }
+            state.write(fd, &data)?;
+            write_u32(memory, nwritten, data.len() as u32)
+        });
+        self.define(imports, "fd_read", vec![I32; 4], |state, memory, args| {
+            let (fd, iovs, iovs_len, nread) = (int(&args[0]), int(&args[1]), int(&args[2]), int(&args[3]));
+            let mut read = 0;
+            for (start, len) in iovecs(memory, iovs, iovs_len)? {
+                let n = state.read(fd, &mut memory[start..start + len])?;
+                read += n;
+                if n < len {
+                    break;
+                }
+            }
+            write_u32(memory, nread, read as u32)
+        });
+        self.define(imports, "args_sizes_get", vec![I32; 2], |state, memory, args| {
+            sizes_get(&state.args, memory, int(&args[0]), int(&args[1]))
+        });
+        self.define(imports, "args_get", vec![I32; 2], |state, memory, args| {
+            strings_get(&state.args, memory, int(&args[0]), int(&args[1]))
+        });
+        self.define(imports, "environ_sizes_get", vec![I32; 2], |state, memory, args| {
+            sizes_get(&state.env, memory, int(&args[0]), int(&args[1]))
+        });
+        self.define(imports, "environ_get", vec![I32; 2], |state, memory, args| {
+            strings_get(&state.env, memory, int(&args[0]), int(&args[1]))
+        });
+        // the precision argument is a hint and ignored
+        self.define(imports, "clock_time_get", vec![I32, I64, I32], |state, memory, args| {
+            let nanos = match int(&args[0]) {
+                0 => SystemTime::now().duration_since(UNIX_EPOCH).map_err(|_| EIO)?.as_nanos(),
+                1..=3 => state.started.elapsed().as_nanos(), // monotonic, process and thread cputime
+                _ => return Err(EINVAL),
+            };
+            write_bytes(memory, int(&args[2]), &(nanos as u64).to_le_bytes())
+        });
+        self.define(imports, "random_get", vec![I32; 2], |state, memory, args| {
+            let (start, len) = range(memory, int(&args[0]), int(&args[1]))?;
+            state.fill_random(&mut memory[start..start + len])
+        });
+        self.add_fs_to_imports(imports);
+        let ty = FuncType {
+            params: vec![I32],
+            results: vec![],
+        };
+        imports.func(MODULE, "proc_exit", ty, |_, args| Err(Trap::Exit(int(&args[0]) as i32)));
+    }
+
+    /// The descriptor and path functions of preopened directories
+    fn add_fs_to_imports(&self, imports: &mut Imports) {
+        use ValType::{I32, I64};
+        self.define(imports, "fd_prestat_get", vec![I32; 2], |state, memory, args| {
+            let name = match state.descriptor(int(&args[0]))? {
+                Descriptor::Dir {
+                    preopen: Some(name), ..
+                } => name,
+                _ => return Err(EBADF),
+            };
+            // a prestat is a directory tag and the length of its name
+            let mut prestat = [0; 8];
+            prestat[4..].copy_from_slice(&(name.len() as u32).to_le_bytes());
+            write_bytes(memory, int(&args[1]), &prestat)
+        });
+        self.define(imports, "fd_prestat_dir_name", vec![I32; 3], |state, memory, args| {
+            let name = match state.descriptor(int(&args[0]))? {
+                Descriptor::Dir {
+                    preopen: Some(name), ..
+                } => name,
+                _ => return Err(EBADF),
+            };
+            if (int(&args[2]) as usize) < name.len() {
+                return Err(EINVAL);
+            }
+            write_bytes(memory, int(&args[1]), name.as_bytes())
+        });
+        self.define(imports, "fd_close", vec![I32], |state, _, args| {
+            state.descriptor(int(&args[0]))?;
+            state.fds[int(&args[0]) as usize] = None;
+            Ok(())
+        });
+        self.define(imports, "fd_seek", vec![I32, I64, I32, I32], |state, memory, args| {
+            let (offset, whence) = (long(&args[1]) as i64, int(&args[2]));
+            let new_pos = match state.descriptor(int(&args[0]))? {
+                Descriptor::File {
+                    backend, handle, pos, ..
+                } => {
+                    let base = match whence {
+                        0 => 0,
+                        1 => *pos,
+                        2 => backend.size(handle)?,
+                        _ => return Err(EINVAL),
+                    };
+                    let new_pos = (base as i64).checked_add(offset).filter(|pos| *pos >= 0).ok_or(EINVAL)?;
+                    *pos = new_pos as u64;
+                    *pos
+                }
+                Descriptor::Dir { .. } => return Err(EBADF),
+                _ => return Err(ESPIPE),
+            };
+            write_bytes(memory, int(&args[3]), &new_pos.to_le_bytes())
+        });
+        self.define(imports, "fd_tell", vec![I32; 2], |state, memory, args| {
+            let pos = match state.descriptor(int(&args[0]))? {
+                Descriptor::File { pos, .. } => *pos,
+                Descriptor::Dir { .. } => return Err(EBADF),
+                _ => return Err(ESPIPE),
+            };
+            write_bytes(memory, int(&args[1]), &pos.to_le_bytes())
+        });
+        self.define(imports, "fd_filestat_get", vec![I32; 2], |state, memory, args| {
+            let (filetype, size, modified) = match state.descriptor(int(&args[0]))? {
+                Descriptor::Stdin | Descriptor::Stdout | Descriptor::Stderr => (FILETYPE_CHARACTER_DEVICE, 0, 0),
+                Descriptor::Dir { backend, path, .. } | Descriptor::File { backend, path, .. } => {
+                    let stat = backend.stat(path)?;
+                    (stat.filetype, stat.size, stat.modified)
+                }
+            };
+            // dev, ino, filetype, nlink, size, atim, mtim, ctim
+            let mut filestat = [0; 64];
+            filestat[16] = filetype;
+            filestat[24..32].copy_from_slice(&1u64.to_le_bytes());
+            filestat[32..40].copy_from_slice(&size.to_le_bytes());
+            for time in [40, 48, 56] {
+                filestat[time..time + 8].copy_from_slice(&modified.to_le_bytes());
+            }
+            write_bytes(memory, int(&args[1]), &filestat)
+        });
+        self.define(imports, "fd_readdir", vec![I32, I32, I32, I64, I32], |state, memory, args| {
+            let (buf, buf_len, cookie, bufused) = (int(&args[1]), int(&args[2]), long(&args[3]), int(&args[4]));
+            let (backend, path) = state.dir(int(&args[0]))?;
+            // a dirent is the cookie of the next entry, an inode, the name's length and a file type
+            let mut entries = Vec::new();
+            for (i, (name, filetype)) in backend.read_dir(&path)?.iter().enumerate().skip(cookie as usize) {
+                entries.extend_from_slice(&(i as u64 + 1).to_le_bytes());
+                entries.extend_from_slice(&0u64.to_le_bytes());
+                entries.extend_from_slice(&(name.len() as u32).to_le_bytes());
+                entries.extend_from_slice(&[*filetype, 0, 0, 0]);
+                entries.extend_from_slice(name.as_bytes());
+            }
+            // a full buffer tells the guest to call again from its last complete entry
+            entries.truncate(buf_len as usize);
+            write_bytes(memory, buf, &entries)?;
+            write_u32(memory, bufused, entries.len() as u32)
+        });
+        self.define(imports, "path_create_directory", vec![I32; 3], |state, memory, args| {
+            let (backend, dir) = state.dir(int(&args[0]))?;
+            let path = resolve(&dir, &read_string(memory, int(&args[1]), int(&args[2]))?)?;
+            backend.create_dir(&path)
+        });
+        self.define(imports, "path_unlink_file", vec![I32; 3], |state, memory, args| {
+            let (backend, dir) = state.dir(int(&args[0]))?;
+            let path = resolve(&dir, &read_string(memory, int(&args[1]), int(&args[2]))?)?;
+            backend.unlink_file(&path)
+        });
+        let params = vec![I32, I32, I32, I32, I32, I64, I64, I32, I32];
+        self.define(imports, "path_open", params, |state, memory, args| {
+            let (backend, dir) = state.dir(int(&args[0]))?;
+            let path = resolve(&dir, &read_string(memory, int(&args[2]), int(&args[3]))?)?;
+            let (oflags, rights, fdflags) = (int(&args[4]), long(&args[5]), int(&args[7]));
+            let create = oflags & OFLAGS_CREAT != 0;
+            let exclusive = oflags & OFLAGS_EXCL != 0;
+            let truncate = oflags & OFLAGS_TRUNC != 0;
+            let writable = rights & RIGHTS_FD_WRITE != 0 || truncate;
+            let descriptor = match backend.stat(&path) {
+                Ok(_) if create && exclusive => return Err(EEXIST),
+                Ok(stat) if stat.filetype == FILETYPE_SYMBOLIC_LINK => return Err(ENOTCAPABLE),
+                // without O_DIRECTORY a directory is opened as a file below, which fails with EISDIR
+                Ok(stat) if stat.filetype == FILETYPE_DIRECTORY && oflags & OFLAGS_DIRECTORY != 0 => {
+                    if writable {
+                        return Err(EISDIR);
+                    }
+                    Descriptor::Dir {
+                        preopen: None,
+                        backend,
+                        path,
+                    }
+                }
+                Ok(_) if oflags & OFLAGS_DIRECTORY != 0 => return Err(ENOTDIR),
+                Err(ENOENT) if create && oflags & OFLAGS_DIRECTORY == 0 => {
+                    let handle = backend.open(&path, true, truncate, writable)?;
+                    file_descriptor(backend, path, handle, rights, fdflags, writable)
+                }
+                Err(errno) => return Err(errno),
+                Ok(_) => {
+                    let handle = backend.open(&path, false, truncate, writable)?;
+                    file_descriptor(backend, path, handle, rights, fdflags, writable)
+                }
+            };
+            let fd = state.insert(descriptor);
+            write_u32(memory, int(&args[8]), fd)
+        });
+    }
+
+    /// Defines a function returning an errno, `func` gets the guest's memory
+    fn define<F>(&self, imports: &mut Imports, name: &str, params: Vec<ValType>, func: F)
+    where
+        F: Fn(&mut WasiState, &mut [u8], &[Value]) -> Result<(), i32> + Send + Sync + 'static,
+    {
+        let state = Arc::clone(&self.state);
+        let ty = FuncType {
+            params,
+            results: vec![ValType::I32],
+        };
+        imports.func(MODULE, name, ty, move |caller: &mut Caller, args| {
+            let errno = match func(&mut state.lock().unwrap(), caller.memory_mut(), args) {
+                Ok(()) => SUCCESS,
+                Err(errno) => errno,
+            };
+            Ok(vec![Value::I32(errno)])
+        });
+    }
+}
+
+/// An i32 argument read as the unsigned pointer or length it is
+fn int(value: &Value) -> u32 {
+    match value {
+        Value::I32(n) => *n as u32,
+        value => unreachable!("WASI pointers and lengths are i32, saw {:?}", value),
+    }
+}
+
+/// An i64 argument such as an offset or a set of rights
+fn long(value: &Value) -> u64 {
+    match value {
+        Value::I64(n) => *n as u64,
+        value => unreachable!("expected an i64 argument, saw {:?}", value),
+    }
+}
+
+/// A freshly opened file, readable unless only write rights were asked for
+fn file_descriptor(backend: Backend, path: Vec<String>, handle: Handle, rights: u64, fdflags: u32, writable: bool) -> Descriptor {
+    Descriptor::File {
+        backend,
+        path,
+        handle,
+        pos: 0,
+        append: fdflags & FDFLAGS_APPEND != 0,
+        readable: rights & RIGHTS_FD_READ != 0 || !writable,
+        writable,
+    }
+}
+
+fn read_string(memory: &[u8], addr: u32, len: u32) -> Result<String, i32> {
+    let (start, len) = range(memory, addr, len)?;
+    String::from_utf8(memory[start..start + len].to_vec()).map_err(|_| EILSEQ)
+}
+
+/// Checks that `len` bytes at `addr` are inside memory
+fn range(memory: &[u8], addr: u32, len: u32) -> Result<(usize, usize), i32> {
+    let (start, len) = (addr as usize, len as usize);
+    if start + len > memory.len() {
+        return Err(EFAULT);
+    }
+    Ok((start, len))
+}
+
+fn read_u32(memory: &[u8], addr: u32) -> Result<u32, i32> {
+    let (start, len) = range(memory, addr, 4)?;
+    Ok(u32::from_le_bytes(memory[start..start + len].try_into().unwrap()))
+}
+
+fn write_bytes(memory: &mut [u8], addr: u32, bytes: &[u8]) -> Result<(), i32> {
+    let (start, len) = range(memory, addr, bytes.len() as u32)?;
+    memory[start..start + len].copy_from_slice(bytes);
+    Ok(())
+}
+
+fn write_u32(memory: &mut [u8], addr: u32, n: u32) -> Result<(), i32> {
+    write_bytes(memory, addr, &n.to_le_bytes())
+}
+
+/// The buffers of an array of `(buf: u32, len: u32)` iovecs
+fn iovecs(memory: &[u8], iovs: u32, iovs_len: u32) -> Result<Vec<(usize, usize)>, i32> {
+    (0..iovs_len)
+        .map(|i| {
+            let iov = iovs.checked_add(i * 8).ok_or(EFAULT)?;
+            let buf = read_u32(memory, iov)?;
+            let len = read_u32(memory, iov.checked_add(4).ok_or(EFAULT)?)?;
+            range(memory, buf, len)
+        })
+        .collect()
+}
+
+/// The count and the total size of NUL terminated strings, for `args_sizes_get` and `environ_sizes_get`
+fn sizes_get(strings: &[String], memory: &mut [u8], count_ptr: u32, size_ptr: u32) -> Result<(), i32> {
+    let size: usize = strings.iter().map(|string| string.len() + 1).sum();
+    write_u32(memory, count_ptr, strings.len() as u32)?;
+    write_u32(memory, size_ptr, size as u32)
+}
+
+/// Writes NUL terminated strings to `buf` and pointers to them to `ptrs`
+fn strings_get(strings: &[String], memory: &mut [u8], ptrs: u32, buf: u32) -> Result<(), i32> {
+    let mut next = buf;
+    for (i, string) in strings.iter().enumerate() {
+        let ptr = ptrs.checked_add(4 * i as u32).ok_or(EFAULT)?;
+        write_u32(memory, ptr, next)?;
+        let mut bytes = string.as_bytes().to_vec();
+        bytes.push(0);
+        write_bytes(memory, next, &bytes)?;
+        next = next.checked_add(bytes.len() as u32).ok_or(EFAULT)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,376 @@
+mod sexpr;
+
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::api::{Error, Module};
+use crate::interpret::ast::ast::ValType;
+use crate::interpret::lexer::{parse_integer, unescape};
+use crate::interpret::runtime::Value;
+use crate::interpret::trap::Trap;
+use crate::linker::{InstanceHandle, Linker, Store};
+use sexpr::{line_of, parse, SExpr};
+
+/// The `spectest` module the testsuite imports from. The print functions discard their arguments
+const SPECTEST: &str = r#"(module
+  (global (export "global_i32") i32 (i32.const 666))
+  (global (export "global_i64") i64 (i64.const 666))
+  (global (export "global_f32") f32 (f32.const 666.6))
+  (global (export "global_f64") f64 (f64.const 666.6))
+  (table (export "table") 10 20 funcref)
+  (memory (export "memory") 1)
+  (func (export "print"))
+  (func (export "print_i32") (param i32))
+  (func (export "print_i64") (param i64))
+  (func (export "print_f32") (param f32))
+  (func (export "print_f64") (param f64))
+  (func (export "print_i32_f32") (param i32 f32))
+  (func (export "print_f64_f64") (param f64 f64))
+)"#;
+
+/// A command of a script that didn't pass
+#[derive(Debug, Clone, PartialEq)]
+pub struct WastFailure {
+    pub line: usize,
+    pub message: String,
+}
+
+/// How the commands of a `.wast` script went
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WastReport {
+    pub passed: usize,
+    pub failures: Vec<WastFailure>,
+}
+
+/// A result `assert_return` expects
+enum Expected {
+    Value(Value),
+    CanonicalNan(ValType),
+    ArithmeticNan(ValType),
+}
+
+/// Runs the commands of a spec test script: modules, `register`, `invoke`, `get` and the
+/// assertions. Every command passes or fails on its own, the error is a script that can't be read.
+/// Actions after a module that didn't load fail naming it rather than run an earlier module.
+/// A module rejected by validation passes `assert_invalid` and one the parser or decoder rejects
+/// passes `assert_malformed` when the error contains the expected message, a trap passes
+/// `assert_trap` when its message starts with the expected one
+pub fn run_wast(source: &str) -> Result<WastReport, String> {
+    let mut runner = Runner::new();
+    let mut report = WastReport::default();
+    for command in parse(source)? {
+        let line = line_of(source, command.span.start);
+        // a panic fails only the command
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| runner.run(source, line, &command)))
+            .unwrap_or_else(|payload| Err(format!("panicked: {}", panic_message(payload))));
+        match outcome {
+            Ok(()) => report.passed += 1,
+            Err(message) => report.failures.push(WastFailure { line, message }),
+        }
+    }
+    Ok(report)
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => payload.downcast_ref::<String>().cloned().unwrap_or_default(),
+    }
+}
+
+struct Runner {
+    linker: Linker,
+    store: Store,
+    current: Result<InstanceHandle, String>,
+    named: HashMap<String, Result<InstanceHandle, String>>,
+}
+
+impl Runner {
+    fn new() -> Runner {
+        let (mut linker, mut store) = (Linker::new(), Store::new());
+        let spectest = Module::from_wat(SPECTEST).unwrap();
+        let instance = linker.instantiate(&mut store, &spectest).unwrap();
+        linker.register("spectest", instance);
+        Runner {
+            linker,
+            store,
+            current: Err(String::from("no module has been instantiated")),
+            named: HashMap::new(),
+        }
+    }
+
+    fn run(&mut self, source: &str, line: usize, command: &SExpr) -> Result<(), String> {
+        let items = command.list().ok_or("expected a command in parentheses")?;
+        let head = command.head().ok_or("expected a command")?;
+        match head {
+            "module" => {
+                let (id, module) = read_module(source, command)?;
+                let loaded = module.and_then(|module| self.instantiate(id.clone(), &module));
+                if loaded.is_err() {
+                    let failed = Err(format!("the module at line {} didn't load", line));
+                    self.current = failed.clone();
+                    if let Some(id) = id {
+                        self.named.insert(id, failed);
+                    }
+                }
+                loaded.map_err(|error| error.to_string())
+            }
+            "register" => {
+                let name = items.get(1).and_then(SExpr::string).ok_or("expected a name to register")?;
+                let instance = self.instance(items.get(2).and_then(SExpr::atom))?;
+                self.linker.register(name, instance);
+                Ok(())
+            }
+            "invoke" | "get" => self.action(command).map(|_| ()).map_err(|error| error.to_string()),
+            "assert_return" => {
+                let action = items.get(1).ok_or("expected an action")?;
+                let expected = items[2..].iter().map(read_expected).collect::<Result<Vec<_>, String>>()?;
+                let results = self.action(action).map_err(|error| error.to_string())?;
+                let matches = results.len() == expected.len()
+                    && results.iter().zip(expected.iter()).all(|(result, expected)| expected.matches(result));
+                if !matches {
+                    return Err(format!("expected {}, saw {:?}", describe(&expected), results));
+                }
+                Ok(())
+            }
+            "assert_trap" | "assert_exhaustion" | "assert_uninstantiable" => {
+                let target = items.get(1).ok_or("expected an action or a module")?;
+                let message = items.get(2).and_then(SExpr::string).unwrap_or("");
+                let outcome = match target.head() {
+                    Some("module") => {
+                        let (id, module) = read_module(source, target)?;
+                        let module = module.map_err(|error| error.to_string())?;
+                        self.instantiate(id, &module).map(|_| Vec::new())
+                    }
+                    _ => self.action(target),
+                };
+                match outcome {
+                    Err(Error::Trap(trap, _))
+                        if head == "assert_exhaustion"
+                            && !matches!(trap, Trap::CallStackExhausted | Trap::OperandStackExhausted) =>
+                    {
+                        Err(format!("expected the stack to be exhausted, saw trap: {}", trap))
+                    }
+                    Err(Error::Trap(trap, _)) if !trap.to_string().starts_with(message) => {
+                        Err(format!("expected trap: {}, saw trap: {}", message, trap))
+                    }
+                    Err(Error::Trap(..)) => Ok(()),
+                    Err(error) => Err(error.to_string()),
+                    Ok(results) => Err(format!("expected trap: {}, saw {:?}", message, results)),
+                }
+            }
+            "assert_invalid" | "assert_malformed" => {
+                let target = items.get(1).ok_or("expected a module")?;
+                let message = items.get(2).and_then(SExpr::string).unwrap_or("");
+                let kind = if head == "assert_invalid" { "an invalid" } else { "a malformed" };
+                match read_module(source, target)?.1 {
+                    Err(Error::Invalid(error)) if head == "assert_invalid" && error.contains(message) => Ok(()),
+                    Err(Error::Parse(error)) if head == "assert_malformed" && error.contains(message) => Ok(()),
+                    Err(error) => Err(format!("expected {} module: {}, saw {}", kind, message, error)),
+                    Ok(_) => Err(format!("expected {} module: {}", kind, message)),
+                }
+            }
+            "assert_unlinkable" => {
+                let target = items.get(1).ok_or("expected a module")?;
+                let module = read_module(source, target)?.1.map_err(|error| error.to_string())?;
+                match self.linker.instantiate(&mut self.store, &module) {
+                    Err(Error::Link(_)) => Ok(()),
+                    Err(error) => Err(format!("expected a link error, saw {}", error)),
+                    Ok(_) => Err(String::from("expected a link error, the module linked")),
+                }
+            }
+            _ => Err(format!("unsupported command {}", head)),
+        }
+    }
+
+    /// Instantiates a module and makes it the one actions without a module name refer to
+    fn instantiate(&mut self, id: Option<String>, module: &Module) -> Result<(), Error> {
+        let instance = self.linker.instantiate(&mut self.store, module)?;
+        self.current = Ok(instance);
+        if let Some(id) = id {
+            self.named.insert(id, Ok(instance));
+        }
+        Ok(())
+    }
+
+    /// The instance named by `$id`, otherwise the last one instantiated
+    fn instance(&self, id: Option<&str>) -> Result<InstanceHandle, String> {
+        match id {
+            Some(id) => self.named.get(id).cloned().unwrap_or(Err(format!("no module {}", id))),
+            None => self.current.clone(),
+        }
+    }
+
+    /// Runs `(invoke $id? "name" const*)` or reads `(get $id? "name")`
+    fn action(&mut self, action: &SExpr) -> Result<Vec<Value>, Error> {
+        let items = action.list().unwrap_or(&[]);
+        let id = items.get(1).and_then(SExpr::atom);
+        let rest = &items[1 + id.is_some() as usize..];
+        let name = rest.first().and_then(SExpr::string).ok_or(Error::Invoke(String::from("expected an export name")))?;
+        let name = String::from_utf8_lossy(&unescape(name).map_err(Error::Invoke)?).into_owned();
+        let instance = self.instance(id).map_err(Error::Invoke)?;
+        match action.head() {
+            Some("invoke") => {
+                let args = rest[1..].iter().map(read_const).collect::<Result<Vec<Value>, String>>().map_err(Error::Invoke)?;
+                self.store.invoke(instance, &name, &args)
+            }
+            Some("get") => match self.store.global(instance, &name) {
+                Some(value) => Ok(vec![value]),
+                None => Err(Error::Invoke(format!("no such global export {}", name))),
+            },
+            _ => Err(Error::Invoke(String::from("expected invoke or get"))),
+        }
+    }
+}
+
+/// Reads `(module $id? ...)`, `(module $id? binary "..."*)` or `(module $id? quote "..."*)`.
+/// The outer error is a script that isn't a module, the inner one a module that can't be loaded
+fn read_module(source: &str, expr: &SExpr) -> Result<(Option<String>, Result<Module, Error>), String> {
+    if expr.head() != Some("module") {
+        return Err(String::from("expected a module"));
+    }
+    let items = expr.list().unwrap();
+    let id = items.get(1).and_then(SExpr::atom).filter(|atom| atom.starts_with('$')).map(String::from);
+    let fields = &items[1 + id.is_some() as usize..];
+    let strings = || {
+        let strings = fields[1..].iter().filter_map(SExpr::string).map(unescape);
+        strings.collect::<Result<Vec<Vec<u8>>, String>>().map(|strings| strings.concat()).map_err(Error::Parse)
+    };
+    let module = match fields.first().and_then(SExpr::atom) {
+        Some("binary") => strings().and_then(|bytes| Module::from_wasm(&bytes)),
+        Some("quote") => {
+            strings().and_then(|text| Module::from_wat(&format!("(module {})", String::from_utf8_lossy(&text))))
+        }
+        // the text parser doesn't take an id after `module`
+        _ => match fields.first() {
+            Some(first) => Module::from_wat(&format!("(module {}", &source[first.span.start..expr.span.end])),
+            None => Module::from_wat("(module)"),
+        },
+    };
+    Ok((id, module))
+}
+
+/// Reads `(i32.const 1)` and the other constants of the number types
+fn read_const(expr: &SExpr) -> Result<Value, String> {
+    let items = expr.list().unwrap_or(&[]);
+    let literal = items.get(1).and_then(SExpr::atom);
+    let value = match (expr.head(), literal) {
+        (Some("i32.const"), Some(literal)) => parse_integer(literal).map(|n| Value::I32(n as i32)),
+        (Some("i64.const"), Some(literal)) => parse_integer(literal).map(Value::I64),
+        (Some("f32.const"), Some(literal)) => float_bits(literal, 23, 8).map(|bits| Value::F32(f32::from_bits(bits as u32))),
+        (Some("f64.const"), Some(literal)) => float_bits(literal, 52, 11).map(|bits| Value::F64(f64::from_bits(bits))),
+        _ => return Err(format!("unsupported constant {:?}", expr.head().unwrap_or("?"))),
+    };
+    value.ok_or(format!("malformed constant {}", literal.unwrap()))
+}
+
+fn read_expected(expr: &SExpr) -> Result<Expected, String> {
+    let literal = expr.list().and_then(|items| items.get(1)).and_then(SExpr::atom);
+    let ty = match expr.head() {
+        Some("f32.const") => ValType::F32,
+        Some("f64.const") => ValType::F64,
+        _ => return read_const(expr).map(Expected::Value),
+    };
+    match literal {
+        Some("nan:canonical") => Ok(Expected::CanonicalNan(ty)),
+        Some("nan:arithmetic") => Ok(Expected::ArithmeticNan(ty)),
+        _ => read_const(expr).map(Expected::Value),
+    }
+}
+
+impl Expected {
+    /// Floats compare by their bits, so `-0.0` doesn't match `0.0` and NaN payloads count
+    fn matches(&self, result: &Value) -> bool {
+        match (self, result) {
+            (Expected::Value(Value::F32(a)), Value::F32(b)) => a.to_bits() == b.to_bits(),
+            (Expected::Value(Value::F64(a)), Value::F64(b)) => a.to_bits() == b.to_bits(),
+            (Expected::Value(expected), result) => expected == result,
+            (Expected::CanonicalNan(ValType::F32), Value::F32(n)) => n.to_bits() & 0x7fff_ffff == 0x7fc0_0000,
+            (Expected::CanonicalNan(ValType::F64), Value::F64(n)) => {
+                n.to_bits() & 0x7fff_ffff_ffff_ffff == 0x7ff8_0000_0000_0000
+            }
+            (Expected::ArithmeticNan(ValType::F32), Value::F32(n)) => n.is_nan() && n.to_bits() & 0x0040_0000 != 0,
+            (Expected::ArithmeticNan(ValType::F64), Value::F64(n)) => {
+                n.is_nan() && n.to_bits() & 0x0008_0000_0000_0000 != 0
+            }
+            _ => false,
+        }
+    }
+}
+
+fn describe(expected: &[Expected]) -> String {
+    let values: Vec<String> = expected
+        .iter()
+        .map(|expected| match expected {
+            Expected::Value(value) => format!("{:?}", value),
+            Expected::CanonicalNan(ty) => format!("{}:nan:canonical", ty),
+            Expected::ArithmeticNan(ty) => format!("{}:nan:arithmetic", ty),
+        })
+        .collect();
+    format!("[{}]", values.join(", "))
+}
+
+/// Reads a float literal of the text format as the bits of a float with `fraction` mantissa bits
+/// and `exponent` exponent bits: decimal, hex like `0x1.8p3`, `inf`, `nan` and `nan:0x200000`
+fn float_bits(literal: &str, fraction: u32, exponent: u32) -> Option<u64> {
+    let literal = literal.replace('_', "");
+    let (sign, magnitude) = match literal.strip_prefix('-') {
+        Some(magnitude) => (1u64 << (fraction + exponent), magnitude),
+        None => (0, literal.strip_prefix('+').unwrap_or(&literal)),
+    };
+    let infinity = ((1u64 << exponent) - 1) << fraction;
+    let bits = match magnitude {
+        "inf" => infinity,
+        "nan" => infinity | 1 << (fraction - 1),
+        _ => match magnitude.strip_prefix("nan:0x") {
+            Some(payload) => match u64::from_str_radix(payload, 16).ok()? {
+                0 => return None,
+                payload if payload >= 1 << fraction => return None,
+                payload => infinity | payload,
+            },
+            None => {
+                let single = fraction == 23;
+                match magnitude.strip_prefix("0x") {
+                    Some(hex) if single => (hex_float(hex)? as f32).to_bits() as u64,
+                    Some(hex) => hex_float(hex)?.to_bits(),
+                    None if single => magnitude.parse::<f32>().ok()?.to_bits() as u64,
+                    None => magnitude.parse::<f64>().ok()?.to_bits(),
+                }
+            }
+        },
+    };
+    Some(sign | bits)
+}
+
+/// The value of the digits of a hex float after `0x`, e.g. `1.8p3` is 12
+fn hex_float(hex: &str) -> Option<f64> {
+    let (digits, mut exponent) = match hex.split_once(['p', 'P']) {
+        Some((digits, exponent)) => (digits, exponent.parse::<i32>().ok()?),
+        None => (hex, 0),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    let mut mantissa: u64 = 0;
+    for (i, digit) in whole.chars().chain(fraction.chars()).enumerate() {
+        let digit = digit.to_digit(16)? as u64;
+        if mantissa >> 60 == 0 {
+            mantissa = mantissa * 16 + digit;
+            if i >= whole.len() {
+                exponent -= 4;
+            }
+        } else if i < whole.len() {
+            // digits past the precision of a u64 only scale the whole part
+            exponent += 4;
+        }
+    }
+    let mut value = mantissa as f64;
+    // scale in steps that stay within the range of an f64
+    while exponent > 1000 {
+        value *= 2f64.powi(1000);
+        exponent -= 1000;
+    }
+    while exponent < -1000 {
+        value *= 2f64.powi(-1000);
+        exponent += 1000;
+    }
+    Some(value * 2f64.powi(exponent))
+}
@@ -0,0 +1,137 @@
+use std::ops::Range;
+
+/// An s-expression of a `.wast` script with the byte range of the source it was read from
+#[derive(Debug, Clone)]
+pub struct SExpr {
+    pub kind: Kind,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Kind {
+    List(Vec<SExpr>),
+    Atom(String),
+    Str(String), // the raw text between the quotes
+}
+
+impl SExpr {
+    pub fn list(&self) -> Option<&[SExpr]> {
+        match &self.kind {
+            Kind::List(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn atom(&self) -> Option<&str> {
+        match &self.kind {
+            Kind::Atom(atom) => Some(atom),
+            _ => None,
+        }
+    }
+
+    pub fn string(&self) -> Option<&str> {
+        match &self.kind {
+            Kind::Str(raw) => Some(raw),
+            _ => None,
+        }
+    }
+
+    /// The keyword a list starts with, e.g. `module` for `(module ...)`
+    pub fn head(&self) -> Option<&str> {
+        self.list()?.first()?.atom()
+    }
+}
+
+/// The line of a byte offset, counting from 1
+pub fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset].matches('\n').count() + 1
+}
+
+/// Reads the top level s-expressions of a script, skipping `;;` and nested `(; ;)` comments
+pub fn parse(source: &str) -> Result<Vec<SExpr>, String> {
+    let bytes = source.as_bytes();
+    // the lists being read and where they started
+    let mut open: Vec<(usize, Vec<SExpr>)> = Vec::new();
+    let mut top = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let expr = match bytes[i] {
+            b if b.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b';' if bytes.get(i + 1) == Some(&b';') => {
+                i = source[i..].find('\n').map_or(bytes.len(), |end| i + end);
+                continue;
+            }
+            b'(' if bytes.get(i + 1) == Some(&b';') => {
+                let mut depth = 0;
+                while i < bytes.len() {
+                    match (bytes[i], bytes.get(i + 1)) {
+                        (b'(', Some(b';')) => depth += 1,
+                        (b';', Some(b')')) => depth -= 1,
+                        _ => {
+                            i += 1;
+                            continue;
+                        }
+                    }
+                    i += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                if depth != 0 {
+                    return Err(format!("line {}: unterminated block comment", line_of(source, start)));
+                }
+                continue;
+            }
+            b'(' => {
+                open.push((i, Vec::new()));
+                i += 1;
+                continue;
+            }
+            b')' => {
+                let (start, items) = open
+                    .pop()
+                    .ok_or_else(|| format!("line {}: unbalanced )", line_of(source, i)))?;
+                i += 1;
+                SExpr {
+                    kind: Kind::List(items),
+                    span: start..i,
+                }
+            }
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                if i >= bytes.len() {
+                    return Err(format!("line {}: unterminated string", line_of(source, start)));
+                }
+                i += 1;
+                SExpr {
+                    kind: Kind::Str(source[start + 1..i - 1].to_string()),
+                    span: start..i,
+                }
+            }
+            _ => {
+                while i < bytes.len() && !bytes[i].is_ascii_whitespace() && !b"()\";".contains(&bytes[i]) {
+                    i += 1;
+                }
+                SExpr {
+                    kind: Kind::Atom(source[start..i].to_string()),
+                    span: start..i,
+                }
+            }
+        };
+        match open.last_mut() {
+            Some((_, items)) => items.push(expr),
+            None => top.push(expr),
+        }
+    }
+    match open.last() {
+        Some((start, _)) => Err(format!("line {}: unbalanced (", line_of(source, *start))),
+        None => Ok(top),
+    }
+}